@@ -0,0 +1,105 @@
+//! Benchmarks for the searcher. Only uses the public API, so they survive internal refactors.
+//! Run with `cargo bench-searcher`.
+//!
+//! Baseline on a release build, one iteration over 10MB of input:
+//! * single_phrase_ascii:     ~2.3s
+//! * hundred_phrases_ascii:   ~4.9s over the first 256KB only, since every phrase scans the whole window for every byte.
+//!   Over all 10MB, criterion's minimum of 10 samples would take around half an hour.
+//! * single_phrase_ascii_width_1: ~2.1s, about 30% less than single_phrase_ascii by skipping the 2-byte search
+//! * single_phrase_u16_le:    ~2.7s
+//! * single_phrase_rotated:   ~2.4s
+//! * streaming vs slice:      ~2.3s each, since the finder reads a byte at a time either way
+//!
+//! A change that moves any of these by more than ~10% deserves a look.
+
+use std::io::{BufReader, Cursor};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use text_searcher_rust::{Finder, Phrase};
+
+const CORPUS_SIZE: usize = 10 * 1024 * 1024;
+const HUNDRED_PHRASES_CORPUS_SIZE: usize = 256 * 1024;
+const SEED: u64 = 0x5EED_CAFE;
+const WORDS: [&str; 16] = [
+    "the", "of", "and", "beauty", "thy", "self", "world", "tender",
+    "memory", "spring", "within", "content", "eyes", "light", "waste", "heir"
+];
+
+// Deterministic xorshift generator, so every run searches the same bytes
+struct XorShift(u64);
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// Generates CORPUS_SIZE bytes of space-separated words, with "famine where" planted every ~1MB
+fn ascii_corpus() -> Vec<u8> {
+    let mut rng = XorShift(SEED);
+    let mut corpus = Vec::with_capacity(CORPUS_SIZE);
+    let mut next_plant = 0;
+    while corpus.len() < CORPUS_SIZE {
+        if corpus.len() >= next_plant {
+            corpus.extend_from_slice(b"famine where ");
+            next_plant += 1024 * 1024;
+        }
+        let word = WORDS[(rng.next() % WORDS.len() as u64) as usize];
+        corpus.extend_from_slice(word.as_bytes());
+        corpus.push(b' ');
+    }
+    corpus.truncate(CORPUS_SIZE);
+    corpus
+}
+
+// Generates 100 two-token phrases that (mostly) don't occur in the corpus
+fn hundred_phrases() -> Vec<Phrase> {
+    let mut rng = XorShift(SEED ^ 0xFFFF);
+    (0..100)
+        .map(|_| {
+            let tokens: Vec<String> = (0..2)
+                .map(|_| (0..6).map(|_| (b'a' + (rng.next() % 26) as u8) as char).collect())
+                .collect();
+            let tokens: Vec<&str> = tokens.iter().map(|token| token.as_str()).collect();
+            Phrase::from_strs(&tokens)
+        })
+        .collect()
+}
+
+fn count_instances(phrases: &[Phrase], input: &[u8]) -> usize {
+    let mut reader = input;
+    let finder = Finder::new(phrases, 64, 32, &mut reader);
+    finder.map(|group| group.0.len()).sum()
+}
+
+fn bench_searcher(c: &mut Criterion) {
+    let ascii = ascii_corpus();
+    let u16_le: Vec<u8> = ascii.iter().flat_map(|b| [*b, 0]).take(CORPUS_SIZE).collect();
+    let rotated: Vec<u8> = ascii.iter().map(|b| b + 13).collect();
+    let phrase = vec![Phrase::from_strs(&["famine", "where"])];
+    let ascii_only = vec![Phrase::from_strs(&["famine", "where"]).with_widths(vec![1])];
+    let phrases = hundred_phrases();
+
+    let mut group = c.benchmark_group("searcher");
+    group.sample_size(10);
+    group.bench_function("single_phrase_ascii", |b| b.iter(|| count_instances(&phrase, &ascii)));
+    group.bench_function("hundred_phrases_ascii", |b| b.iter(|| count_instances(&phrases, &ascii[..HUNDRED_PHRASES_CORPUS_SIZE])));
+    group.bench_function("single_phrase_ascii_width_1", |b| b.iter(|| count_instances(&ascii_only, &ascii)));
+    group.bench_function("single_phrase_u16_le", |b| b.iter(|| count_instances(&phrase, &u16_le)));
+    group.bench_function("single_phrase_rotated", |b| b.iter(|| count_instances(&phrase, &rotated)));
+    group.bench_function("single_phrase_streaming", |b| b.iter_batched(
+        || BufReader::new(Cursor::new(ascii.clone())),
+        |mut reader| Finder::new(&phrase, 64, 32, &mut reader).count(),
+        BatchSize::LargeInput
+    ));
+    group.bench_function("single_phrase_slice", |b| b.iter(|| {
+        let mut reader = ascii.as_slice();
+        Finder::new(&phrase, 64, 32, &mut reader).count()
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_searcher);
+criterion_main!(benches);
@@ -1,32 +1,368 @@
-use std::collections::HashSet;
-use std::fs::{File, metadata};
-use std::path::{PathBuf, Path};
-use std::sync::{Mutex, MutexGuard};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, Metadata, metadata};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::ControlFlow;
+use std::path::{Component, PathBuf, Path};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-use text_searcher_rust::Phrase;
+use text_searcher_rust::{
+    Finder, FinderBuilder, Phrase, PhraseError, PhraseInstance, PhraseLimits, PhraseRef, SearchOptions, Text, CostEstimate,
+    DEFAULT_COST_BUDGET, estimate_cost,
+    FileSearchResult, SearchReport, Encoding, FileRef, ReportEntry, ResultSink, open_bounded, search_file_with, search_file_chunked, auto_intra_file_parallelism, search_reader_with, Throttle, ThrottledReader, Throughput, CancelHandle, cancelled_error, MAX_BYTES_PER_CHARACTER,
+    extract_string_at, read_context_at, read_text_at, patch_at, PatchError, PatchReport, CandidateString, find_candidate_strings,
+    TraceEvent, TraceRing
+};
+pub use text_searcher_rust::dto::{decode_path, encode_path, CacheStats, FileEncoding};
 use walkdir::WalkDir;
-use serde::{Serialize, Deserialize};
+use glob::{Pattern, PatternError};
+
+use crate::scan_history::{now_secs, HistoryRetention, ScanHistory, ScanSummary};
+use crate::operation_log::{Mutation, OperationLog, UndoError};
+use crate::validation::{FileProblem, InvalidPhrase, ValidationFixes, ValidationReport, ValidationStatus};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use schemars::JsonSchema;
+
+/// Largest context [`FinderService::search_all`] picks when sizing it from the phrases
+pub const MAX_AUTO_CONTEXT_SIZE: usize = 4096;
+
+/// Most bytes [`FinderService::read_page`] reads at once
+pub const MAX_PAGE_LEN: usize = 64 * 1024;
+
+/// Most bytes [`FinderService::analyze_strings`] reads from the start of a file
+pub const MAX_ANALYZE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Most bytes [`FinderService::trace_file`] reads from the start of a file, since tracing is much slower than searching
+pub const MAX_TRACE_BYTES: u64 = 1024 * 1024;
 
 /// Service that keeps track of files to monitor for text changes.
 pub struct FinderService {
     persist_file: PathBuf,
-    state: Mutex<State>
+    start_time: Instant,                // When the service was created
+    state: Arc<Mutex<State>>,           // Shared with validation passes running in the background
+    phrase_cache: Mutex<PhraseCache>,   // Phrases prepared for the current generation. Locked after state.
+    rescan_changed: AtomicBool,         // Whether scans search files that changed during them again
+    intra_file_parallelism: AtomicUsize, // Chunks each file is searched in, or 0 to pick from the file's size. 1 by default.
+    max_bytes_per_second: AtomicU64,    // Most bytes scans read per second, or 0 for no limit
+    dedup_content: AtomicBool,          // Whether scans search only one of each group of identical files
+    max_report_bytes: AtomicUsize,      // Most bytes of memory a report's entries can take up, roughly, or 0 for no limit
+    allow_writes: AtomicBool,           // Whether tracked files can be patched. See patch_file.
+    cost_budget: AtomicU64,             // Most work per byte phrases can take without being forced in. See estimate_cost.
+    phrase_limits: Mutex<PhraseLimits>, // Limits phrases added with try_add_phrase must be within
+    walk_limits: Mutex<WalkLimits>,     // Limits on walking directories to track the files beneath them
+    export_dir: Mutex<Option<PathBuf>>, // Directory search results can be exported under. Exports are refused without one.
+    validation: Arc<Mutex<ValidationStatus>>,   // Latest validation pass. See start_validation.
+    validation_auto_fix: AtomicBool,    // Whether validation passes fix what they can
+    history_unpersisted: Arc<AtomicBool>, // Whether scans were recorded since the state was last persisted. See start_history_flush.
+    scan_cancel: Mutex<CancelHandle>    // Cancels the scans running now. Replaced once used, so later scans run. See cancel_scans.
 }
 
+/// Limits on walking a directory to track the files beneath it, so a directory like `/` or a symlink loop can't walk forever
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct WalkLimits {
+    pub max_depth: Option<usize>,       // Most levels below the directory walked. Entries deeper are skipped.
+    pub max_entries: Option<usize>,     // Most entries looked at before the walk stops
+    pub follow_links: bool              // Whether symlinks are followed. Otherwise they're skipped.
+}
 
-// Represents the inner state of a [`FinderService`]
-#[derive(Serialize, Deserialize)]
+impl Default for WalkLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: Some(64),
+            max_entries: Some(100_000),
+            follow_links: false
+        }
+    }
+}
+
+/// What tracking a file or directory did, or would do
+#[derive(Debug, Default)]
+pub struct AddReport {
+    pub added: Vec<PathBuf>,            // Files that weren't tracked already, sorted
+    pub dirs_visited: usize,
+    pub skipped: usize,                 // Entries left out by the limits, and anything that's neither a file nor a directory
+    pub truncated: bool,                // Whether the walk stopped at the most entries allowed
+    pub errors: Vec<walkdir::Error>     // Entries that couldn't be read, which the walk carried on past
+}
+
+/// Phrases prepared for searching, which every scan of the same generation shares.
+/// Only the sorting and sizing are shared. Each scan builds its own matchers, since they depend on the encoding of each source.
+pub struct PreparedPhrases {
+    pub generation: u64,                // Generation of the state the phrases were prepared from
+    pub phrases: Arc<Vec<Phrase>>,      // Sorted, so that reports are deterministic
+    pub auto_options: SearchOptions     // Options sized from the phrases, for scans that don't specify any
+}
+
+impl PreparedPhrases {
+    pub fn prepare(generation: u64, phrases: &HashSet<Phrase>) -> Self {
+        let mut phrases: Vec<Phrase> = phrases.iter().cloned().collect();
+        phrases.sort();
+        let auto_options = SearchOptions::auto_size(&phrases, MAX_BYTES_PER_CHARACTER, MAX_AUTO_CONTEXT_SIZE);
+        Self {
+            generation,
+            phrases: Arc::new(phrases),
+            auto_options
+        }
+    }
+}
+
+#[derive(Default)]
+struct PhraseCache {
+    prepared: Option<Arc<PreparedPhrases>>,
+    stats: CacheStats
+}
+
+
+// Represents the inner state of a [`FinderService`]. Persisted as [`PersistedState::V2`].
+#[derive(Default, Serialize, Deserialize)]
 pub struct State {
+    #[serde(serialize_with = "serialize_paths", deserialize_with = "deserialize_paths")]
     files: HashSet<PathBuf>,
-    phrases: HashSet<Phrase>
+    phrases: HashSet<Phrase>,
+    #[serde(default, serialize_with = "serialize_path_keys", deserialize_with = "deserialize_path_keys")]
+    encodings: HashMap<PathBuf, FileEncoding>,  // Encodings of tracked files, if known
+    #[serde(default, serialize_with = "serialize_path_keys", deserialize_with = "deserialize_path_keys")]
+    content_hashes: HashMap<PathBuf, ContentHash>, // Hashes of tracked files, once they've been needed. See FinderService::duplicate_groups.
+    #[serde(default)]
+    search_config: Option<SearchOptions>,       // Options to search with, if configured
+    #[serde(default)]
+    named_phrases: HashMap<String, Phrase>,     // Phrases given a name, by name
+    #[serde(default)]
+    platform: Option<PathPlatform>,             // Platform the paths are written for. None if persisted before it was recorded.
+    #[serde(default)]
+    history: ScanHistory,                       // Summaries of scans run with FinderService::search_all
+    #[serde(default)]
+    operations: OperationLog,                   // Recent removals, so they can be undone
+    #[serde(skip)]
+    dynamic_sources: HashMap<String, SourceFactory>, // Sources that aren't files, by name. Never persisted.
+    #[serde(skip)]
+    generation: u64                             // Incremented whenever files, phrases or encodings change
+}
+
+/// Opens a new reader over a dynamic source every time it's searched
+pub type SourceFactory = Arc<dyn Fn() -> Result<Box<dyn Read + Send>, std::io::Error> + Send + Sync>;
+
+/// Input a scan reads from
+#[derive(Clone)]
+pub enum Source {
+    Path(PathBuf),                                      // Tracked file
+    Dynamic { name: String, factory: SourceFactory }    // Any reader, like a blob in a database
+}
+
+impl Source {
+
+    /// Path the source's results are reported under. Dynamic sources use their name.
+    pub fn name(&self) -> PathBuf {
+        match self {
+            Self::Path(path) => path.clone(),
+            Self::Dynamic { name, .. } => PathBuf::from(name)
+        }
+    }
+
+    /// Opens a reader over the source. Files are only read up to the size they have when opened. See [`open_bounded`].
+    pub fn open(&self) -> Result<Box<dyn Read + Send>, std::io::Error> {
+        match self {
+            Self::Path(path) => Ok(Box::new(open_bounded(path)?.0)),
+            Self::Dynamic { factory, .. } => factory()
+        }
+    }
+}
+
+/// Hash of a file's contents, along with the size and modification time it had when hashed, to tell when it's stale
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ContentHash {
+    pub hash: String,                   // Prefixed with the algorithm, "blake3:" with the content-hash feature and "sip:" without
+    pub len: u64,
+    pub modified_nanos: Option<u64>     // When the file was last modified, in nanoseconds since the epoch, where the platform records it
+}
+
+impl ContentHash {
+
+    /// Hashes the contents of the file at `path`
+    pub fn of_file<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        let file = File::open(path)?;
+        let meta = file.metadata()?;
+        Ok(Self {
+            hash: hash_contents(BufReader::new(file))?,
+            len: meta.len(),
+            modified_nanos: modified_nanos(&meta)
+        })
+    }
+
+    /// Whether the file still has the size and modification time it was hashed at
+    pub fn is_current(&self, meta: &Metadata) -> bool {
+        self.len == meta.len() && self.modified_nanos.is_some() && self.modified_nanos == modified_nanos(meta)
+    }
+}
+
+/// Tracked files with the same contents
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub len: u64,
+    pub files: Vec<PathBuf>     // Sorted by path
+}
+
+fn modified_nanos(meta: &Metadata) -> Option<u64> {
+    let since_epoch = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    u64::try_from(since_epoch.as_nanos()).ok()
+}
+
+#[cfg(feature = "content-hash")]
+fn hash_contents<R: Read>(mut reader: R) -> Result<String, std::io::Error> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(&mut reader)?;
+    Ok(format!("blake3:{}", hasher.finalize().to_hex()))
+}
+
+// SipHash with fixed keys, so hashes are the same from run to run. Only 64 bits, so groups are also split by length.
+#[cfg(not(feature = "content-hash"))]
+fn hash_contents<R: Read>(mut reader: R) -> Result<String, std::io::Error> {
+    use std::hash::{DefaultHasher, Hasher};
+    let mut hasher = DefaultHasher::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => hasher.write(&buf[..read]),
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(format!("sip:{:016x}", hasher.finish()))
+}
+
+
+/// Platform whose path separators a persist file's paths use
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathPlatform {
+    Unix,
+    Windows
+}
+
+impl PathPlatform {
+    pub fn current() -> Self {
+        if cfg!(windows) { Self::Windows } else { Self::Unix }
+    }
+}
+
+/// Drops `.` components and redundant separators, so paths naming the same file compare equal.
+/// `..` is kept, since resolving it could change which file is named when there are symlinks.
+pub fn normalize_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref()
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+fn serialize_paths<S: Serializer>(paths: &HashSet<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(paths.iter().map(|path| encode_path(path)))
+}
+
+fn deserialize_paths<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashSet<PathBuf>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|str| decode_path(str).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn serialize_path_keys<S: Serializer, V: Serialize>(map: &HashMap<PathBuf, V>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(map.iter().map(|(path, value)| (encode_path(path), value)))
+}
+
+fn deserialize_path_keys<'de, D: Deserializer<'de>, V: Deserialize<'de>>(deserializer: D) -> Result<HashMap<PathBuf, V>, D::Error> {
+    HashMap::<String, V>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(str, value)| decode_path(&str).map(|path| (path, value)).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+// Rewrites a path persisted on another platform so its components are recognized on this one.
+// Windows accepts forward slashes, but elsewhere backslashes are part of the file name.
+fn localize_path(path: &Path, from: PathPlatform) -> PathBuf {
+    match (from, PathPlatform::current(), path.to_str()) {
+        (PathPlatform::Windows, PathPlatform::Unix, Some(str)) => normalize_path(str.replace('\\', "/")),
+        _ => normalize_path(path)
+    }
+}
+
+/// Latest layout of the persisted state
+pub type StateV2 = State;
+
+/// Layout of the persisted state before it was versioned
+#[derive(Default, Serialize, Deserialize)]
+pub struct StateV1 {
+    pub files: HashSet<PathBuf>,
+    pub phrases: HashSet<Phrase>,
+    #[serde(default)]
+    pub encodings: HashMap<PathBuf, FileEncoding>
+}
+
+/// Contents of a persist file, of any version.
+/// Use [`PersistedState::from_reader`] to also read files written before versioning, which have no `version` field.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "version")]
+pub enum PersistedState {
+    V1(StateV1),
+    V2(Box<StateV2>)
+}
+
+// Written in place of PersistedState::V2, so persisting doesn't copy the state
+#[derive(Serialize)]
+#[serde(tag = "version")]
+enum PersistedStateRef<'a> {
+    V2(&'a StateV2)
 }
 
+impl PersistedState {
+
+    /// Reads a persist file, treating one without a `version` field as V1
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, serde_json::Error> {
+        let mut value: serde_json::Value = serde_json::from_reader(reader)?;
+        if let Some(object) = value.as_object_mut() {
+            object.entry("version").or_insert_with(|| "V1".into());
+        }
+        serde_json::from_value(value)
+    }
+
+    /// Migrates the state to the latest version, with paths rewritten for this platform
+    pub fn into_latest(self) -> StateV2 {
+        let mut state = match self {
+            Self::V1(state) => migrate_v1_to_v2(state),
+            Self::V2(state) => *state
+        };
+        state.localize_paths();
+        state
+    }
+}
+
+pub fn migrate_v1_to_v2(s1: StateV1) -> StateV2 {
+    StateV2 {
+        files: s1.files,
+        phrases: s1.phrases,
+        encodings: s1.encodings,
+        ..StateV2::new()
+    }
+}
 
 impl State {
     pub fn new() -> Self {
         Self {
             files: HashSet::new(),
-            phrases: HashSet::new()
+            phrases: HashSet::new(),
+            encodings: HashMap::new(),
+            content_hashes: HashMap::new(),
+            search_config: None,
+            named_phrases: HashMap::new(),
+            platform: Some(PathPlatform::current()),
+            history: ScanHistory::default(),
+            operations: OperationLog::default(),
+            dynamic_sources: HashMap::new(),
+            generation: 0
         }
     }
     pub fn files(&self) -> impl Iterator<Item=&PathBuf> {
@@ -35,172 +371,1922 @@ impl State {
     pub fn phrases(&self) -> impl Iterator<Item=&Phrase> {
         self.phrases.iter()
     }
+    pub fn contains_file<P: AsRef<Path>>(&self, filename: P) -> bool {
+        self.files.contains(&normalize_path(filename))
+    }
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+    pub fn file_encoding<P: AsRef<Path>>(&self, filename: P) -> Option<&FileEncoding> {
+        self.encodings.get(&normalize_path(filename))
+    }
+    pub fn dynamic_sources(&self) -> impl Iterator<Item=&String> {
+        self.dynamic_sources.keys()
+    }
+    pub fn search_config(&self) -> Option<&SearchOptions> {
+        self.search_config.as_ref()
+    }
+    pub fn named_phrases(&self) -> impl Iterator<Item=(&String, &Phrase)> {
+        self.named_phrases.iter()
+    }
+    /// [`Phrase::stable_hash`] of every phrase, sorted, so two sets of phrases can be compared without the phrases themselves
+    pub fn phrase_hashes(&self) -> Vec<u64> {
+        let mut hashes: Vec<u64> = self.phrases.iter().map(Phrase::stable_hash).collect();
+        hashes.sort_unstable();
+        hashes
+    }
+    pub fn history(&self) -> &ScanHistory {
+        &self.history
+    }
+    pub fn operations(&self) -> &OperationLog {
+        &self.operations
+    }
+
+    // Why applying a mutation wouldn't do exactly what it says, if it wouldn't
+    fn mutation_conflict(&self, mutation: &Mutation) -> Option<String> {
+        match mutation {
+            Mutation::RemoveFiles { files, encodings } => files.iter().find_map(|encoded| {
+                let file = decode_path(encoded).ok()?;
+                match self.files.contains(&file) {
+                    false => Some(format!("'{}' is no longer tracked", file.display())),
+                    true if self.encodings.get(&file) != encodings.get(encoded) => {
+                        Some(format!("The encoding of '{}' has changed", file.display()))
+                    },
+                    true => None
+                }
+            }),
+            Mutation::RestoreFiles { files, .. } => files.iter().find_map(|encoded| {
+                let file = decode_path(encoded).ok()?;
+                self.files.contains(&file).then(|| format!("'{}' is tracked again", file.display()))
+            }),
+            Mutation::RemovePhrase { phrase } => {
+                (!self.phrases.contains(phrase)).then(|| format!("Phrase '{}' was removed", phrase))
+            },
+            Mutation::RestorePhrase { phrase } => {
+                self.phrases.contains(phrase).then(|| format!("Phrase '{}' was added again", phrase))
+            }
+        }
+    }
+
+    // Applies a mutation that doesn't conflict. See mutation_conflict.
+    fn apply_mutation(&mut self, mutation: &Mutation) {
+        let decode = |encoded: &String| decode_path(encoded).ok();
+        match mutation {
+            Mutation::RemoveFiles { files, .. } => {
+                for file in files.iter().filter_map(decode) {
+                    self.files.remove(&file);
+                    self.encodings.remove(&file);
+                }
+            },
+            Mutation::RestoreFiles { files, encodings } => {
+                self.files.extend(files.iter().filter_map(decode));
+                self.encodings.extend(encodings.iter().filter_map(|(file, encoding)| Some((decode(file)?, encoding.clone()))));
+            },
+            Mutation::RemovePhrase { phrase } => { self.remove_phrase(phrase); },
+            Mutation::RestorePhrase { phrase } => { self.phrases.insert(phrase.clone()); }
+        }
+        self.generation += 1;
+    }
+
+    // Removes a phrase along with any names it was given, returning true if it was there
+    fn remove_phrase(&mut self, phrase: &Phrase) -> bool {
+        self.named_phrases.retain(|_, named| named != phrase);
+        self.phrases.remove(phrase)
+    }
+
+    // Logs the removal of files, along with the encodings they had
+    fn log_removed_files(&mut self, files: &[PathBuf], encodings: &HashMap<PathBuf, FileEncoding>) {
+        if files.is_empty() {
+            return;
+        }
+        let encodings = files
+            .iter()
+            .filter_map(|file| Some((encode_path(file), encodings.get(file)?.clone())))
+            .collect();
+        let files = files.iter().map(|file| encode_path(file)).collect();
+        self.operations.record(Mutation::RemoveFiles { files, encodings }, now_secs(), None);
+    }
+
+    // Rewrites persisted paths for this platform, assuming they were written on it if it wasn't recorded
+    fn localize_paths(&mut self) {
+        let from = self.platform.unwrap_or_else(PathPlatform::current);
+        self.files = self.files.iter().map(|file| localize_path(file, from)).collect();
+        self.encodings = std::mem::take(&mut self.encodings)
+            .into_iter()
+            .map(|(file, encoding)| (localize_path(&file, from), encoding))
+            .collect();
+        self.platform = Some(PathPlatform::current());
+    }
+
+    /// Tracked files beneath a path, or the file at it, in sorted order.
+    /// Paths are compared by component, so `dir` selects `dir/file` but not `dir2/file`.
+    pub fn files_with_prefix<P: AsRef<Path>>(&self, prefix: P) -> Vec<&PathBuf> {
+        let prefix = normalize_path(prefix);
+        let mut files: Vec<&PathBuf> = self.files
+            .iter()
+            .filter(|file| file.starts_with(&prefix))
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Tracked files that match a glob pattern like `logs/**/*.log`, in sorted order.
+    pub fn files_matching_glob(&self, pattern: &str) -> Result<Vec<&PathBuf>, PatternError> {
+        let pattern = Pattern::new(pattern)?;
+        let mut files: Vec<&PathBuf> = self.files
+            .iter()
+            .filter(|file| pattern.matches_path(file))
+            .collect();
+        files.sort();
+        Ok(files)
+    }
 }
 
 impl FinderService {
     
-    /// Creates an empty [`FinderService`]
+    /// Creates a [`FinderService`] from the persist file, or an empty one if it can't be opened.
+    /// Panics if the persist file can't be parsed. See [`Self::new_try`].
     pub fn new<P: AsRef<Path>>(persist_file: P) -> Self {
-        let persist_file = persist_file.as_ref().to_owned();
-        match File::open(&persist_file) {
-            Ok(file) => {
-                let state = serde_json::from_reader(file).unwrap();
-                Self {
-                    persist_file,
-                    state: Mutex::new(state)
-                }
-            },
-            Err(_) => Self {
-                persist_file,
-                state: Mutex::new(State::new())
-            }
+        match Self::new_try(&persist_file) {
+            Ok(service) => service,
+            Err(err) => panic!("Failed to load '{}': {:?}", persist_file.as_ref().display(), err)
+        }
+    }
+
+    /// Creates a [`FinderService`] like [`Self::new`], searching with `search_config` unless a search specifies otherwise.
+    /// The config replaces any in the persist file, and is persisted along with the rest of the state. Fails if it's invalid.
+    pub fn new_with_config<P: AsRef<Path>>(persist_file: P, search_config: SearchOptions) -> Result<Self, ConfigError> {
+        let service = Self::new(persist_file);
+        service.set_search_config(Some(search_config))?;
+        Ok(service)
+    }
+
+    /// Creates a [`FinderService`] from the persist file, migrating it from older versions.
+    /// Creates an empty one if the file can't be opened.
+    pub fn new_try<P: AsRef<Path>>(persist_file: P) -> Result<Self, PersistErr> {
+        let state = match File::open(&persist_file) {
+            Ok(file) => PersistedState::from_reader(BufReader::new(file))
+                .map_err(PersistErr::JsonError)?
+                .into_latest(),
+            Err(_) => State::new()
+        };
+        Ok(Self::with_state(persist_file, state))
+    }
+
+    /// Creates a [`FinderService`] with state that's already loaded, persisting to `persist_file`.
+    /// Nothing is read from the persist file until [`Self::reload`].
+    pub fn with_state<P: AsRef<Path>>(persist_file: P, state: State) -> Self {
+        Self {
+            persist_file: persist_file.as_ref().to_owned(),
+            start_time: Instant::now(),
+            state: Arc::new(Mutex::new(state)),
+            phrase_cache: Mutex::new(PhraseCache::default()),
+            rescan_changed: AtomicBool::new(false),
+            intra_file_parallelism: AtomicUsize::new(1),
+            max_bytes_per_second: AtomicU64::new(0),
+            dedup_content: AtomicBool::new(false),
+            max_report_bytes: AtomicUsize::new(0),
+            allow_writes: AtomicBool::new(false),
+            cost_budget: AtomicU64::new(DEFAULT_COST_BUDGET),
+            phrase_limits: Mutex::new(PhraseLimits::default()),
+            walk_limits: Mutex::new(WalkLimits::default()),
+            export_dir: Mutex::new(None),
+            validation: Arc::new(Mutex::new(ValidationStatus::NotRun)),
+            validation_auto_fix: AtomicBool::new(false),
+            history_unpersisted: Arc::new(AtomicBool::new(false)),
+            scan_cancel: Mutex::new(CancelHandle::default())
         }
     }
 
     /// Internal state of the service
-    pub fn state(&self) -> MutexGuard<State> {
+    pub fn state(&self) -> MutexGuard<'_, State> {
         self.state.lock().unwrap()
     }
 
+    /// Same as [`Self::state`], but None instead of panicking if a thread panicked while holding it
+    pub fn try_state(&self) -> Option<MutexGuard<'_, State>> {
+        self.state.lock().ok()
+    }
+
+    /// How long since the service was created
+    pub fn uptime(&self) -> Duration {
+        self.start_time.elapsed()
+    }
+
+    /// Size of the persist file in bytes. 0 if it hasn't been written.
+    pub fn persist_file_size(&self) -> u64 {
+        metadata(&self.persist_file).map(|meta| meta.len()).unwrap_or(0)
+    }
+
     /// Tracks the file specified.
     /// If filename is a file, only tracks that file.
-    /// If filename is a directory, recursively tracks all the files beneath the directory.
-    pub fn add_file<P: AsRef<Path>>(&self, filename: P) -> Result<(), std::io::Error> {
-        let meta = metadata(&filename)?;
-        if meta.is_file() {
-            self._add_file(filename);
+    /// If filename is a directory, recursively tracks all the files beneath the directory, within the [`WalkLimits`].
+    /// Returns the files that weren't tracked already, sorted. See [`Self::add_file_reporting`].
+    pub fn add_file<P: AsRef<Path>>(&self, filename: P) -> Result<Vec<PathBuf>, std::io::Error> {
+        let report = self.add_file_reporting(filename)?;
+        for err in &report.errors {
+            log::warn!("WalkDir error: {}", err);
         }
-        else {
-            self.add_dir(filename);
+        Ok(report.added)
+    }
+
+    /// Tracks the file specified like [`Self::add_file`], reporting what walking it came across
+    pub fn add_file_reporting<P: AsRef<Path>>(&self, filename: P) -> Result<AddReport, std::io::Error> {
+        let (selected, mut report) = select_files_to_add(filename, &self.walk_limits())?;
+        report.added = self.track_files(selected);
+        Ok(report)
+    }
+
+    /// What [`Self::add_file_reporting`] would do, without tracking anything
+    pub fn files_to_add<P: AsRef<Path>>(&self, filename: P) -> Result<AddReport, std::io::Error> {
+        let (selected, mut report) = select_files_to_add(filename, &self.walk_limits())?;
+        let state = self.state();
+        report.added = selected.into_iter().filter(|file| !state.files.contains(file)).collect();
+        report.added.sort();
+        report.added.dedup();
+        Ok(report)
+    }
+
+    pub fn walk_limits(&self) -> WalkLimits {
+        *self.walk_limits.lock().unwrap()
+    }
+
+    /// Sets the limits on walking directories added with [`Self::add_file`]
+    pub fn set_walk_limits(&self, limits: WalkLimits) {
+        *self.walk_limits.lock().unwrap() = limits;
+    }
+
+    /// Stops tracking all files that start with the filename prefix, if any. See [`State::files_with_prefix`].
+    /// Returns how many files were removed.
+    pub fn remove_files<P: AsRef<Path>>(&self, filename: P) -> usize {
+        let filename = normalize_path(filename);
+        let mut state = self.state.lock().unwrap();
+        let before = state.files.len();
+        let mut removed = Vec::new();
+        state.files.retain(|file| {
+            let keep = !file.starts_with(&filename);
+            if !keep {
+                removed.push(file.clone());
+            }
+            keep
+        });
+        let removed_count = before - state.files.len();
+        removed.sort();
+        let (removed_encodings, encodings) = std::mem::take(&mut state.encodings)
+            .into_iter()
+            .partition(|(file, _)| file.starts_with(&filename));
+        state.encodings = encodings;
+        if removed_count > 0 {
+            state.generation += 1;
+        }
+        state.log_removed_files(&removed, &removed_encodings);
+        removed_count
+    }
+
+    /// Files [`Self::remove_files`] would stop tracking, sorted, without removing them
+    pub fn files_to_remove<P: AsRef<Path>>(&self, filename: P) -> Vec<PathBuf> {
+        self.state().files_with_prefix(filename).into_iter().cloned().collect()
+    }
+
+    /// Tracked files, sorted, unlike [`State::files`] which iterates in no particular order
+    pub fn list_files_sorted(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.state().files().cloned().collect();
+        files.sort();
+        files
+    }
+
+    /// Tracked files that no longer exist on disk, sorted.
+    /// The disk is checked without holding the lock, so files can change while it's checked.
+    pub fn files_not_found(&self) -> Vec<PathBuf> {
+        self.list_files_sorted().into_iter().filter(|file| !file.exists()).collect()
+    }
+
+    /// Stops tracking the files [`Self::files_not_found`] returns, along with their encodings.
+    /// Returns the files removed.
+    pub fn prune_missing_files(&self) -> Vec<PathBuf> {
+        let missing = self.files_not_found();
+        let mut state = self.state();
+        let missing: Vec<PathBuf> = missing.into_iter().filter(|file| state.files.remove(file)).collect();
+        let encodings: HashMap<PathBuf, FileEncoding> = missing
+            .iter()
+            .filter_map(|file| Some((file.clone(), state.encodings.remove(file)?)))
+            .collect();
+        if !missing.is_empty() {
+            state.generation += 1;
+        }
+        state.log_removed_files(&missing, &encodings);
+        missing
+    }
+
+    /// Sets the encoding a tracked file is searched with, overriding the encoding passed to [`Self::search_all`].
+    pub fn set_file_encoding<P: AsRef<Path>>(&self, filename: P, encoding: FileEncoding) -> Result<(), std::io::Error> {
+        let filename = normalize_path(filename);
+        let bpc = encoding.bytes_per_character;
+        if bpc == 0 || bpc as usize > MAX_BYTES_PER_CHARACTER {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, "Unsupported bytes per character"));
         }
+        let mut state = self.state();
+        if !state.contains_file(&filename) {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "File not tracked"));
+        }
+        state.encodings.insert(filename, encoding);
+        state.generation += 1;
         Ok(())
     }
 
-    /// Stops tracking all files that start with the filename prefix, if any.
-    pub fn remove_files<P: AsRef<Path>>(&self, filename: P) {
-        let files = &mut self.state.lock().unwrap().files;
-        files.retain(|file| !file.starts_with(&filename));
+    /// Registers a source that isn't a file under `name`, replacing any source already registered under it.
+    /// Dynamic sources are searched along with tracked files, but aren't persisted.
+    pub fn add_dynamic_source<F>(&self, name: impl Into<String>, factory: F)
+    where F: Fn() -> Result<Box<dyn Read + Send>, std::io::Error> + Send + Sync + 'static {
+        let mut state = self.state();
+        state.dynamic_sources.insert(name.into(), Arc::new(factory));
+        state.generation += 1;
+    }
+
+    /// Unregisters a dynamic source, returning true if it was registered
+    pub fn remove_dynamic_source(&self, name: &str) -> bool {
+        let mut state = self.state();
+        let removed = state.dynamic_sources.remove(name).is_some();
+        if removed {
+            state.generation += 1;
+        }
+        removed
     }
 
-    /// Adds a phrase to the service
-    pub fn add_phrase(&self, phrase: Phrase) {
+    /// Adds a phrase to the service, even if it's outside the phrase limits or the phrases would be too costly to search for.
+    /// Logs a warning for each existing phrase it shares tokens with, and if the phrases exceed the cost budget.
+    /// Returns false if the phrase was already added, in which case nothing changes.
+    pub fn add_phrase(&self, phrase: Phrase) -> bool {
         let mut state = self.state.lock().unwrap();
-        state.phrases.insert(phrase);
+        if state.phrases.contains(&phrase) {
+            return false;
+        }
+        let mut phrases: Vec<Phrase> = state.phrases.iter().cloned().collect();
+        phrases.push(phrase.clone());
+        let options = state.search_config
+            .unwrap_or_else(|| SearchOptions::auto_size(&phrases, MAX_BYTES_PER_CHARACTER, MAX_AUTO_CONTEXT_SIZE));
+        if let Err(exceeded) = self.check_cost(&phrases, &options) {
+            log::warn!("Adding '{}' anyway. {}", phrase, exceeded);
+        }
+        Self::insert_phrase(&mut state, phrase);
+        true
     }
 
-    /// Adds a phrase to the service
-    pub fn remove_phrase(&self, phrase: &Phrase) -> bool {
+    /// Adds a phrase to the service if it's within the phrase limits,
+    /// unless the phrases would then exceed the cost budget and `force` is false.
+    /// Costs are estimated with the configured search options, or ones sized from the phrases.
+    /// Logs a warning for each existing phrase it shares tokens with.
+    /// Returns false if the phrase was already added, in which case nothing changes.
+    pub fn try_add_phrase(&self, phrase: Phrase, force: bool) -> Result<bool, AddPhraseError> {
+        phrase.validate(&self.phrase_limits.lock().unwrap()).map_err(AddPhraseError::Invalid)?;
         let mut state = self.state.lock().unwrap();
-        state.phrases.remove(&phrase)
+        if state.phrases.contains(&phrase) {
+            return Ok(false);
+        }
+        let mut phrases: Vec<Phrase> = state.phrases.iter().cloned().collect();
+        phrases.push(phrase.clone());
+        let options = state.search_config
+            .unwrap_or_else(|| SearchOptions::auto_size(&phrases, MAX_BYTES_PER_CHARACTER, MAX_AUTO_CONTEXT_SIZE));
+        let exceeded = self.check_cost(&phrases, &options);
+        match exceeded {
+            Err(exceeded) if !force => return Err(AddPhraseError::CostExceeded(exceeded)),
+            Err(exceeded) => log::warn!("Adding '{}' anyway. {}", phrase, exceeded),
+            Ok(_) => {}
+        }
+        Self::insert_phrase(&mut state, phrase);
+        Ok(true)
     }
 
-    /// Persists state to a file
-    pub fn persist(&self) -> Result<(), PersistErr> {
-        let file = File::options()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(&self.persist_file);
-        let file = match file {
-            Ok(file) => file,
-            Err(err) => {
-                log::error!("Failed to open '{}': {:?}", &self.persist_file.display(), err);
-                return Err(PersistErr::IoError(err));
-            }
-        };
-        match serde_json::to_writer(&file, &self.state) {
-            Ok(_) => Ok(()),
-            Err(err) => Err(PersistErr::JsonError(err))
+    /// Sets the options searches use when they don't specify any. With None, they're sized from the phrases.
+    /// Fails without changing anything if the options are invalid. See [`SearchOptions::validate`].
+    pub fn set_search_config(&self, search_config: Option<SearchOptions>) -> Result<(), ConfigError> {
+        if let Some(Err(err)) = search_config.map(|options| options.validate()) {
+            return Err(ConfigError::Invalid { field: "search_config", reason: err.to_string() });
         }
+        self.state().search_config = search_config;
+        Ok(())
     }
 
-    fn _add_file<P: AsRef<Path>>(&self, filename: P) {
-        let files = &mut self.state.lock().unwrap().files;
-        let filename = filename.as_ref();
-        files.insert(filename.to_owned());
-        log::debug!("Added file {}", filename.display());
+    /// Sets the limits phrases added with [`Self::try_add_phrase`] must be within
+    pub fn set_phrase_limits(&self, limits: PhraseLimits) {
+        *self.phrase_limits.lock().unwrap() = limits;
     }
 
-    fn add_dir<P: AsRef<Path>>(&self, dirname: P) {
-        let files = &mut self.state.lock().unwrap().files;
-        for entry in WalkDir::new(dirname).into_iter().flat_map(|entry| entry.ok()) {
-            if entry.metadata().unwrap().is_file() {
-                files.insert(entry.path().to_owned());
-            }
+    // Inserts a phrase that isn't there yet, warning about existing phrases it shares tokens with
+    fn insert_phrase(state: &mut State, phrase: Phrase) {
+        for existing in state.phrases.iter().filter(|existing| existing.overlaps_with(&phrase)) {
+            log::warn!("Phrase '{}' shares tokens with '{}', so both may match the same text", phrase, existing);
         }
+        state.phrases.insert(phrase);
+        state.generation += 1;
     }
-}
 
-#[derive(Debug)]
-pub enum PersistErr {
-    IoError(std::io::Error),
-    JsonError(serde_json::Error)
-}
+    /// Sets the most work per byte of input phrases can take before they're refused. See [`estimate_cost`].
+    pub fn set_cost_budget(&self, budget: u64) {
+        self.cost_budget.store(budget, Ordering::Relaxed);
+    }
 
+    // Estimates the cost of searching for the phrases, failing if it's over budget
+    fn check_cost(&self, phrases: &[Phrase], options: &SearchOptions) -> Result<CostEstimate, CostExceeded> {
+        let estimate = estimate_cost(phrases, options);
+        let budget = self.cost_budget.load(Ordering::Relaxed);
+        match estimate.exceeds(budget) {
+            true => Err(CostExceeded { estimate, budget }),
+            false => Ok(estimate)
+        }
+    }
 
-#[cfg(test)]
-mod tests {
+    /// Gives a tracked phrase a name, replacing the phrase the name was given before, if any.
+    /// The name is forgotten when the phrase is removed, and isn't given back if the removal is undone.
+    /// Returns false if the phrase isn't tracked, in which case nothing changes.
+    pub fn name_phrase(&self, name: impl Into<String>, phrase: &Phrase) -> bool {
+        let mut state = self.state();
+        if !state.phrases.contains(phrase) {
+            return false;
+        }
+        state.named_phrases.insert(name.into(), phrase.clone());
+        state.generation += 1;
+        true
+    }
 
-    use std::path::PathBuf;
+    /// Removes a phrase from the service, returning true if it was there. The removal is logged, so it can be undone.
+    pub fn remove_phrase(&self, phrase: &Phrase) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let removed = state.remove_phrase(phrase);
+        if removed {
+            state.generation += 1;
+            state.operations.record(Mutation::RemovePhrase { phrase: phrase.clone() }, now_secs(), None);
+        }
+        removed
+    }
 
-    use crate::finder_service::FinderService;
+    /// Undoes a logged operation by applying its inverse, which is logged as well, so an undo can be undone too.
+    /// Fails if the operation isn't in the log, was undone already, or if later changes mean its inverse wouldn't put back
+    /// exactly what it changed, such as a removed file being tracked again. Returns the id of the undo.
+    pub fn undo_operation(&self, id: u64) -> Result<u64, UndoError> {
+        let mut state = self.state();
+        let operation = state.operations.get(id).ok_or(UndoError::NotFound(id))?;
+        if let Some(by) = operation.undone_by {
+            return Err(UndoError::AlreadyUndone { id, by });
+        }
+        let inverse = operation.mutation.inverse();
+        if let Some(reason) = state.mutation_conflict(&inverse) {
+            return Err(UndoError::Conflict { id, reason });
+        }
+        state.apply_mutation(&inverse);
+        log::info!("Undid operation {} with {}", id, inverse.kind());
+        Ok(state.operations.record(inverse, now_secs(), Some(id)))
+    }
 
-    #[test]
-    fn test_add_file_single() {
-        let service = FinderService::new("persist-file.json");
-        let result = service.add_file("test_files/file.txt");
-        let state = service.state();
-        let mut files: Vec<PathBuf> = state.files().map(|file| file.to_owned()).collect();
-        files.sort();
+    /// Sets how many operations are logged before the oldest are forgotten
+    pub fn set_max_operations(&self, max_operations: usize) {
+        self.state().operations.set_max_operations(max_operations);
+    }
 
-        assert!(result.is_ok());
-        assert_eq!(
-            [PathBuf::from("test_files/file.txt")].to_vec(),
-            files
-        );
+    /// Searches all tracked files and dynamic sources for all phrases. See [`Snapshot::search`].
+    /// Phrases too long for the window, and phrases too costly to search for, are logged.
+    /// A summary of the scan is added to the history, which is persisted along with the rest of the state.
+    /// Dynamic sources aren't persisted, so the summary doesn't count instances per source for them.
+    pub fn search_all(&self, options: Option<SearchOptions>, encoding: Option<Encoding>) -> SearchReport {
+        self.search_all_within(options, encoding, None)
     }
 
-    #[test]
-    fn test_add_file_dir() {
-        let service = FinderService::new("persist-file.json");
-        let result = service.add_file("test_files/dir");
-        let state = service.state();
-        let mut files: Vec<PathBuf> = state.files().map(|file| file.to_owned()).collect();
-        files.sort();
+    /// Searches like [`Self::search_all`], keeping the report's entries within `max_report_bytes` if given.
+    /// The limit can only lower the one set with [`Self::set_max_report_bytes`], not raise it.
+    pub fn search_all_within(&self, options: Option<SearchOptions>, encoding: Option<Encoding>, max_report_bytes: Option<usize>) -> SearchReport {
+        let started_at = now_secs();
+        let (mut snapshot, options) = self.checked_snapshot(options);
+        snapshot.max_report_bytes = match (snapshot.max_report_bytes, max_report_bytes) {
+            (Some(max), Some(requested)) => Some(max.min(requested)),
+            (max, requested) => max.or(requested)
+        };
+        let (report, throughput) = snapshot.search_measured(Some(options), encoding);
+        let is_file = |path: &Path| snapshot.sources.iter().any(|source| matches!(source, Source::Path(file) if file == path));
+        self.record_scan(ScanSummary::from_report(started_at, &report, is_file).with_throughput(throughput));
+        report
+    }
 
-        assert!(result.is_ok());
-        assert_eq!(
-            [
-                PathBuf::from("test_files/dir/sub_file_1.txt"),
-                PathBuf::from("test_files/dir/sub_file_2.txt")
-            ].to_vec(),
-            files
-        );
+    /// Adds a summary of a scan to the history, compacting it if it's grown past its retention.
+    /// It's persisted along with the rest of the state, or by [`Self::persist_history`].
+    pub fn record_scan(&self, summary: ScanSummary) {
+        self.state().history.record(summary, now_secs());
+        self.history_unpersisted.store(true, Ordering::Relaxed);
     }
 
-    #[test]
-    fn test_remove_file_single() {
-        let service = FinderService::new("persist-file.json");
-        service.add_file("test_files/dir");
-        service.remove_files("test_files/dir/sub_file_1.txt");
-        let state = service.state();
-        let mut files: Vec<PathBuf> = state.files().map(|file| file.to_owned()).collect();
-        files.sort();
+    /// Sets how long scan summaries are kept, and how many before they're compacted
+    pub fn set_history_retention(&self, retention: HistoryRetention) {
+        self.state().history.set_retention(retention, now_secs());
+    }
 
-        assert_eq!(
-            [PathBuf::from("test_files/dir/sub_file_2.txt")].to_vec(),
-            files
-        );
+    /// Searches like [`Self::search_all`], handing results to `sink` as they're found. See [`Snapshot::search_into`].
+    /// Every entry handed to the sink counts towards the summary added to the history, whether the sink keeps it or not.
+    pub fn search_all_into(&self, options: Option<SearchOptions>, encoding: Option<Encoding>, sink: &mut dyn ResultSink) {
+        let started_at = now_secs();
+        let (snapshot, options) = self.checked_snapshot(options);
+        let mut summarizing = SummarizingSink {
+            sink,
+            summary: ScanSummary { timestamp: started_at, scans: 1, ..ScanSummary::default() },
+            count_file: |path: &Path| snapshot.sources.iter().any(|source| matches!(source, Source::Path(file) if file == path))
+        };
+        let throughput = snapshot.search_into(Some(options), encoding, &mut summarizing);
+        self.record_scan(summarizing.summary.with_throughput(throughput));
     }
 
-    #[test]
-    fn test_remove_file_multi() {
-        let service = FinderService::new("persist-file.json");
-        service.add_file("test_files/file.txt");
-        service.add_file("test_files/dir");
-        service.remove_files("test_files/dir");
-        let state = service.state();
-        let mut files: Vec<PathBuf> = state.files().map(|file| file.to_owned()).collect();
+    // Snapshot to search and the options to search it with, logging phrases that are too long or too costly
+    fn checked_snapshot(&self, options: Option<SearchOptions>) -> (Snapshot, SearchOptions) {
+        let mut snapshot = self.snapshot();
+        if self.dedup_content.load(Ordering::Relaxed) {
+            let (duplicate_of, hashing) = self.duplicate_of();
+            snapshot.duplicate_of = Arc::new(duplicate_of);
+            snapshot.hashing = hashing;
+        }
+        let options = snapshot.resolve_options(options);
+        if let Err(exceeded) = self.check_cost(&snapshot.phrases, &options) {
+            log::warn!("Searching anyway. {}", exceeded);
+        }
+        for warning in snapshot.validate_search_config(options.context_size, options.window_size) {
+            log::warn!(
+                "Phrase '{}' spans {} bytes at {} bytes per character, which doesn't fit in a window of {}",
+                warning.phrase.text,
+                warning.phrase_bytes,
+                warning.bytes_per_character,
+                warning.window_size
+            );
+        }
+        (snapshot, options)
+    }
+
+    /// Sets the directory [`Self::export_search`] writes under. Exports are refused without one, the default.
+    pub fn set_export_dir(&self, dir: Option<PathBuf>) {
+        *self.export_dir.lock().unwrap() = dir;
+    }
+
+    pub fn export_dir(&self) -> Option<PathBuf> {
+        self.export_dir.lock().unwrap().clone()
+    }
+
+    /// Searches like [`Self::search_all`], writing each file's results as a line of JSON to `output_path` within the export directory.
+    /// Returns the number of lines written. Fails with PermissionDenied if there's no export directory,
+    /// and with InvalidInput if `output_path` is empty, absolute, or has a `..` component, before searching.
+    /// Fails with Interrupted, without writing anything, if the scan is cancelled. See [`Self::cancel_scans`].
+    pub fn export_search<P: AsRef<Path>>(
+        &self,
+        output_path: P,
+        options: Option<SearchOptions>,
+        encoding: Option<Encoding>
+    ) -> Result<usize, std::io::Error> {
+        let output_path = self.resolve_export_path(output_path.as_ref())?;
+        let report = self.search_all(options, encoding);
+        if report.cancelled {
+            return Err(std::io::Error::new(ErrorKind::Interrupted, "The scan was cancelled"));
+        }
+        let mut writer = BufWriter::new(File::create(output_path)?);
+        for file in &report.files {
+            serde_json::to_writer(&mut writer, file)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(report.files.len())
+    }
+
+    // Path within the export directory that output_path names, as long as it can't name anything outside it
+    fn resolve_export_path(&self, output_path: &Path) -> Result<PathBuf, std::io::Error> {
+        let Some(export_dir) = self.export_dir() else {
+            return Err(std::io::Error::new(ErrorKind::PermissionDenied, "No export directory is configured"));
+        };
+        let escapes = output_path.components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+        if escapes || output_path.file_name().is_none() {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("'{}' isn't a relative path to a file", output_path.display())));
+        }
+        Ok(export_dir.join(output_path))
+    }
+
+    /// Number of instances found in each tracked file and dynamic source, with options sized from the phrases.
+    /// Cheaper than [`Self::search_all`] when only the counts are needed. See [`Snapshot::count_per_source`].
+    pub fn search_phrase_count_per_file(&self) -> HashMap<PathBuf, usize> {
+        self.snapshot().count_per_source(None, None)
+    }
+
+    /// Phrases too long to ever be found with the sizes given. See [`Snapshot::validate_search_config`].
+    pub fn validate_search_config(&self, context_size: usize, window_size: usize) -> Vec<PhraseValidationWarning> {
+        self.snapshot().validate_search_config(context_size, window_size)
+    }
+
+    /// Copies out what a scan needs under a brief lock, so changes made while it runs don't affect it
+    pub fn snapshot(&self) -> Snapshot {
+        let state = self.state();
+        let mut files: Vec<PathBuf> = state.files().cloned().collect();
+        let mut dynamic: Vec<(&String, &SourceFactory)> = state.dynamic_sources.iter().collect();
+        let prepared = self.prepared_phrases(&state);
+        let encodings: HashMap<PathBuf, Encoding> = state.encodings
+            .iter()
+            .map(|(path, file_encoding)| (path.clone(), file_encoding.encoding()))
+            .collect();
+        files.sort();
+        dynamic.sort_by_key(|(name, _)| *name);
+        let sources = files
+            .into_iter()
+            .map(Source::Path)
+            .chain(dynamic.into_iter().map(|(name, factory)| Source::Dynamic { name: name.clone(), factory: factory.clone() }))
+            .collect();
+        Snapshot {
+            generation: state.generation,
+            sources: Arc::new(sources),
+            phrases: prepared.phrases.clone(),
+            auto_options: prepared.auto_options,
+            search_config: state.search_config,
+            encodings: Arc::new(encodings),
+            rescan_changed: self.rescan_changed.load(Ordering::Relaxed),
+            intra_file_parallelism: match self.intra_file_parallelism.load(Ordering::Relaxed) {
+                0 => None,
+                chunks => Some(chunks)
+            },
+            max_bytes_per_second: match self.max_bytes_per_second.load(Ordering::Relaxed) {
+                0 => None,
+                max => Some(max)
+            },
+            duplicate_of: Arc::new(HashMap::new()),
+            hashing: Throughput::default(),
+            max_report_bytes: match self.max_report_bytes.load(Ordering::Relaxed) {
+                0 => None,
+                max => Some(max)
+            },
+            cancel: self.scan_cancel.lock().unwrap().clone()
+        }
+    }
+
+    /// Caps roughly how many bytes of memory the entries of a report from [`Self::search_all`] can take up, so a phrase
+    /// with a huge number of instances can't exhaust it. No cap if `None`, the default. See [`SearchReport::max_bytes`].
+    pub fn set_max_report_bytes(&self, max_bytes: Option<usize>) {
+        self.max_report_bytes.store(max_bytes.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Makes [`Self::search_all`] search only one of each group of tracked files with the same contents and encoding,
+    /// reporting its results under every file in the group. Off by default. Files are hashed the first time they're needed,
+    /// and again whenever their size or modification time changes. See [`Self::duplicate_groups`].
+    /// Hashing reads the whole file, on top of searching it, so it only pays off once hashes are reused by later scans.
+    /// What hashing reads counts towards each scan's throughput.
+    pub fn set_dedup_content(&self, enabled: bool) {
+        self.dedup_content.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Groups of tracked files with the same contents, sorted by their first file. Files without duplicates are left out.
+    /// Hashes any files that haven't been, or that changed since they were, unless no other file has their size.
+    /// Files that can't be read are logged and left out.
+    pub fn duplicate_groups(&self) -> Vec<DuplicateGroup> {
+        self.duplicate_groups_hashing().0
+    }
+
+    // Same as duplicate_groups, along with how much hashing read
+    fn duplicate_groups_hashing(&self) -> (Vec<DuplicateGroup>, Throughput) {
+        let (hashes, hashing) = self.refresh_content_hashes();
+        let mut groups: HashMap<(String, u64), Vec<PathBuf>> = HashMap::new();
+        for (file, hash) in hashes {
+            groups.entry((hash.hash, hash.len)).or_default().push(file);
+        }
+        let mut groups: Vec<DuplicateGroup> = groups
+            .into_iter()
+            .filter(|(_, files)| files.len() > 1)
+            .map(|((hash, len), mut files)| {
+                files.sort();
+                DuplicateGroup { hash, len, files }
+            })
+            .collect();
+        groups.sort_by(|a, b| a.files[0].cmp(&b.files[0]));
+        (groups, hashing)
+    }
+
+    // Hashes of every tracked file that can be read and shares its size with another, hashing those that aren't current
+    // without holding the lock. Files with a size of their own can't have duplicates, so they're never read.
+    // Hashes of files no longer tracked are dropped. Returns how much hashing read.
+    fn refresh_content_hashes(&self) -> (HashMap<PathBuf, ContentHash>, Throughput) {
+        let (files, mut cached): (Vec<PathBuf>, HashMap<PathBuf, ContentHash>) = {
+            let state = self.state();
+            (state.files().cloned().collect(), state.content_hashes.clone())
+        };
+        let mut sized: Vec<(PathBuf, Metadata)> = Vec::new();
+        for file in files {
+            match metadata(&file) {
+                Ok(meta) => sized.push((file, meta)),
+                Err(err) => log::warn!("Failed to hash '{}': {:?}", file.display(), err)
+            }
+        }
+        let mut size_counts: HashMap<u64, usize> = HashMap::new();
+        for (_, meta) in &sized {
+            *size_counts.entry(meta.len()).or_default() += 1;
+        }
+        let started = Instant::now();
+        let mut hashing = Throughput::default();
+        let mut hashes = HashMap::new();
+        for (file, meta) in sized.into_iter().filter(|(_, meta)| size_counts[&meta.len()] > 1) {
+            let cached = cached.remove(&file).filter(|hash| hash.is_current(&meta));
+            let hash = match cached {
+                Some(hash) => Ok(hash),
+                None => ContentHash::of_file(&file).inspect(|hash| hashing.bytes_read += hash.len)
+            };
+            match hash {
+                Ok(hash) => { hashes.insert(file, hash); },
+                Err(err) => log::warn!("Failed to hash '{}': {:?}", file.display(), err)
+            }
+        }
+        hashing.elapsed = started.elapsed();
+        let state = &mut *self.state();
+        state.content_hashes.retain(|file, _| state.files.contains(file));
+        state.content_hashes.extend(hashes.iter().map(|(file, hash)| (file.clone(), hash.clone())));
+        (hashes, hashing)
+    }
+
+    // Files whose results can be copied from an identical file with the same encoding, mapped to the first such file by path,
+    // along with how much hashing read
+    fn duplicate_of(&self) -> (HashMap<PathBuf, PathBuf>, Throughput) {
+        let (groups, hashing) = self.duplicate_groups_hashing();
+        let state = self.state();
+        let mut duplicate_of = HashMap::new();
+        for group in &groups {
+            let mut firsts: Vec<(Option<Encoding>, &PathBuf)> = Vec::new();
+            for file in &group.files {
+                let encoding = state.file_encoding(file).map(FileEncoding::encoding);
+                match firsts.iter().find(|(first_encoding, _)| *first_encoding == encoding) {
+                    Some((_, first)) => { duplicate_of.insert(file.clone(), (*first).clone()); },
+                    None => firsts.push((encoding, file))
+                }
+            }
+        }
+        (duplicate_of, hashing)
+    }
+
+    /// Caps how many bytes per second scans read across all their sources, so they don't saturate the disk. No cap if `None`, the default.
+    /// See [`Throttle`]. Effective throughput is recorded in each scan's summary.
+    pub fn set_max_bytes_per_second(&self, max_bytes_per_second: Option<u64>) {
+        self.max_bytes_per_second.store(max_bytes_per_second.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Cancels every scan running now, including those of snapshots taken before now. Scans started afterwards run as usual.
+    /// Reads of a cancelled scan fail, so the sources it hadn't finished are reported as errors and the report is marked `cancelled`.
+    pub fn cancel_scans(&self) {
+        let cancel = std::mem::take(&mut *self.scan_cancel.lock().unwrap());
+        cancel.cancel();
+    }
+
+    /// Has scans split each file into `chunks` searched in parallel, or pick how many from its size if `Some(0)`.
+    /// Files are searched in one pass if `None`, the default, since instances past the first chunk have no line or column.
+    /// See [`search_file_chunked`]. Files searched again because they changed, and dynamic sources, are searched in one pass.
+    pub fn set_intra_file_parallelism(&self, chunks: Option<usize>) {
+        self.intra_file_parallelism.store(chunks.unwrap_or(1), Ordering::Relaxed);
+    }
+
+    /// Has scans search files whose size changed while they were read once more. Off by default.
+    /// Files that change again are still marked as `changed_during_scan`.
+    pub fn set_rescan_changed(&self, enabled: bool) {
+        self.rescan_changed.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Follows the file at `path` from its current end, so only text appended from now on is searched.
+    /// Uses the configured search options, or ones sized from `phrases`, and the file's encoding if it has one set.
+    /// The file does not need to be tracked.
+    pub fn follow<P: AsRef<Path>>(&self, path: P, phrases: &[Phrase]) -> Result<Follower, std::io::Error> {
+        let path = path.as_ref().to_owned();
+        let state = self.state();
+        let options = state.search_config
+            .unwrap_or_else(|| SearchOptions::auto_size(phrases, MAX_BYTES_PER_CHARACTER, MAX_AUTO_CONTEXT_SIZE));
+        let encoding = state.encodings.get(&path).map(FileEncoding::encoding);
+        Follower::new(path, phrases, options, encoding)
+    }
+
+    /// How often snapshots reused the phrases prepared for the current generation
+    pub fn phrase_cache_stats(&self) -> CacheStats {
+        self.phrase_cache.lock().unwrap().stats
+    }
+
+    // Phrases prepared for the state's generation, preparing them if it changed since the last time
+    fn prepared_phrases(&self, state: &State) -> Arc<PreparedPhrases> {
+        let mut cache = self.phrase_cache.lock().unwrap();
+        match &cache.prepared {
+            Some(prepared) if prepared.generation == state.generation => {
+                let prepared = prepared.clone();
+                cache.stats.hits += 1;
+                prepared
+            },
+            _ => {
+                let prepared = Arc::new(PreparedPhrases::prepare(state.generation, &state.phrases));
+                cache.prepared = Some(prepared.clone());
+                cache.stats.misses += 1;
+                prepared
+            }
+        }
+    }
+
+    /// Searches a single file for a single phrase using the default [`SearchOptions`].
+    /// The file does not need to be tracked.
+    pub fn search_phrase_in_file<P: AsRef<Path>>(&self, phrase: &Phrase, path: P) -> Result<Vec<PhraseInstance>, std::io::Error> {
+        let options = SearchOptions::default();
+        let mut reader = BufReader::new(File::open(path)?);
+        let phrases = std::slice::from_ref(phrase);
+        let mut finder = Finder::new(phrases, options.context_size, options.window_size, &mut reader);
+        let instances = finder
+            .by_ref()
+            .flat_map(|group| group.0)
+            .filter(|instance| instance.phrase_index == 0)
+            .collect();
+        match finder.read_error() {
+            Some(err) => Err(std::io::Error::new(err.kind(), err.to_string())),
+            None => Ok(instances)
+        }
+    }
+
+    /// Reads the text around `pos` in a tracked file, up to `max_len` characters on either side.
+    /// If `terminators` are specified, only the string that `pos` lives in is returned, up to `max_len` characters.
+    pub fn get_context<P: AsRef<Path>>(
+        &self,
+        filename: P,
+        pos: u64,
+        max_len: usize,
+        codepoint_diff: i32,
+        bytes_per_character: u32,
+        terminators: Option<&[u8]>
+    ) -> Result<Text, std::io::Error> {
+        let filename = filename.as_ref();
+        if !self.state().contains_file(filename) {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "File not tracked"));
+        }
+        let mut reader = BufReader::new(File::open(filename)?);
+        match terminators {
+            Some(terminators) => extract_string_at(&mut reader, pos, terminators, max_len, codepoint_diff, bytes_per_character),
+            None => read_context_at(&mut reader, pos, max_len, codepoint_diff, bytes_per_character)
+        }
+    }
+
+    /// Decodes up to `len_bytes` bytes of a tracked file from `offset`, only reading those bytes.
+    /// Fails with InvalidInput if `len_bytes` is more than [`MAX_PAGE_LEN`], or less than a character.
+    pub fn read_page<P: AsRef<Path>>(
+        &self,
+        filename: P,
+        offset: u64,
+        len_bytes: usize,
+        codepoint_diff: i32,
+        bytes_per_character: u32
+    ) -> Result<FilePage, std::io::Error> {
+        let filename = filename.as_ref();
+        if !self.state().contains_file(filename) {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "File not tracked"));
+        }
+        if len_bytes > MAX_PAGE_LEN || len_bytes < bytes_per_character as usize {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Pages must be from {} to {} bytes", bytes_per_character, MAX_PAGE_LEN)));
+        }
+        let mut file = File::open(filename)?;
+        let total_size = file.metadata()?.len();
+        let (text, len_bytes) = read_text_at(&mut file, offset, len_bytes, codepoint_diff, bytes_per_character)?;
+        Ok(FilePage { text, offset, len_bytes, total_size })
+    }
+
+    /// Suggests strings to search for in a tracked file, from runs that look like text under some diff and width.
+    /// Only the first [`MAX_ANALYZE_BYTES`] are read. See [`find_candidate_strings`].
+    pub fn analyze_strings<P: AsRef<Path>>(&self, filename: P, min_len: usize, limit: usize) -> Result<Vec<CandidateString>, std::io::Error> {
+        let filename = filename.as_ref();
+        if !self.state().contains_file(filename) {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "File not tracked"));
+        }
+        let mut bytes = Vec::new();
+        File::open(filename)?.take(MAX_ANALYZE_BYTES).read_to_end(&mut bytes)?;
+        Ok(find_candidate_strings(&bytes, min_len, limit))
+    }
+
+    /// Searches the start of a tracked file for every phrase, tracing the window every `every` bytes, and returns the last `capacity` events.
+    /// Shows why phrases weren't found, for debugging missing matches. Only the first [`MAX_TRACE_BYTES`] are read.
+    /// Fails with NotFound if the file isn't tracked, and InvalidInput if the options are invalid.
+    pub fn trace_file<P: AsRef<Path>>(
+        &self,
+        filename: P,
+        options: Option<SearchOptions>,
+        every: usize,
+        capacity: usize
+    ) -> Result<Vec<TraceEvent>, std::io::Error> {
+        let filename = filename.as_ref();
+        if !self.state().contains_file(filename) {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "File not tracked"));
+        }
+        let snapshot = self.snapshot();
+        let options = snapshot.resolve_options(options);
+        let mut reader = BufReader::new(File::open(filename)?.take(MAX_TRACE_BYTES));
+        let mut finder = FinderBuilder::new()
+            .context_size(options.context_size)
+            .window_size(options.window_size)
+            .encoding(snapshot.encodings.get(&normalize_path(filename)).copied())
+            .build(&snapshot.phrases, &mut reader)
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidInput, err.to_string()))?;
+        let ring = Arc::new(Mutex::new(TraceRing::new(capacity)));
+        finder.trace(every, TraceRing::recorder(&ring));
+        finder.by_ref().for_each(drop);
+        if let Some(err) = finder.read_error() {
+            return Err(std::io::Error::new(err.kind(), err.to_string()));
+        }
+        drop(finder);
+        let events = std::mem::take(&mut *ring.lock().unwrap());
+        Ok(events.into_vec())
+    }
+
+    /// Lets [`Self::patch_file`] write to tracked files. Off by default.
+    pub fn set_allow_writes(&self, enabled: bool) {
+        self.allow_writes.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Whether [`Self::patch_file`] can write to tracked files
+    pub fn allow_writes(&self) -> bool {
+        self.allow_writes.load(Ordering::Relaxed)
+    }
+
+    /// Writes `new_text` over a tracked file at `pos` with [`patch_at`], returning the bytes overwritten.
+    /// Fails with PermissionDenied unless writes are allowed, and NotFound if the file isn't tracked.
+    #[allow(clippy::too_many_arguments)]
+    pub fn patch_file<P: AsRef<Path>>(
+        &self,
+        filename: P,
+        pos: u64,
+        new_text: &Text,
+        codepoint_diff: i32,
+        bytes_per_character: u32,
+        pad_to: Option<usize>,
+        terminator: Option<u8>
+    ) -> Result<PatchReport, PatchError> {
+        let filename = filename.as_ref();
+        if !self.allow_writes() {
+            return Err(PatchError::Io(std::io::Error::new(ErrorKind::PermissionDenied, "Writes are not allowed")));
+        }
+        if !self.state().contains_file(filename) {
+            return Err(PatchError::Io(std::io::Error::new(ErrorKind::NotFound, "File not tracked")));
+        }
+        let report = patch_at(filename, pos, new_text, codepoint_diff, bytes_per_character, pad_to, terminator)?;
+        log::info!("Patched {} bytes of '{}' at {}", report.new_bytes.len(), filename.display(), pos);
+        Ok(report)
+    }
+
+    /// Replaces the state with the contents of the persist file, so edits made to it by hand take effect.
+    /// The state is left as is if the file can't be read or parsed.
+    pub fn reload(&self) -> Result<(), PersistErr> {
+        let file = File::open(&self.persist_file).map_err(PersistErr::IoError)?;
+        let mut new_state = PersistedState::from_reader(BufReader::new(file))
+            .map_err(PersistErr::JsonError)?
+            .into_latest();
+        let mut state = self.state();
+        log::info!(
+            "Reloaded '{}': {} -> {} files, {} -> {} phrases",
+            self.persist_file.display(),
+            state.files.len(),
+            new_state.files.len(),
+            state.phrases.len(),
+            new_state.phrases.len()
+        );
+        new_state.dynamic_sources = std::mem::take(&mut state.dynamic_sources);
+        new_state.generation = state.generation + 1;
+        *state = new_state;
+        Ok(())
+    }
+
+    /// Persists state to a file
+    #[must_use = "persist errors must be handled"]
+    pub fn persist(&self) -> Result<(), PersistErr> {
+        let state = self.state();
+        let was_unpersisted = self.history_unpersisted.swap(false, Ordering::Relaxed);
+        persist_state(&self.persist_file, &state).inspect_err(|_| {
+            self.history_unpersisted.fetch_or(was_unpersisted, Ordering::Relaxed);
+        })
+    }
+
+    /// Persists state to a file if scans were recorded since it was last persisted.
+    /// Returns whether it was persisted.
+    #[must_use = "persist errors must be handled"]
+    pub fn persist_history(&self) -> Result<bool, PersistErr> {
+        flush_history(&self.state, &self.history_unpersisted, &self.persist_file)
+    }
+
+    /// Starts persisting the history of scans on a background thread every `interval`, whenever scans were recorded since it was last persisted,
+    /// so searches don't write to the persist file themselves. Failing to persist is logged, and tried again the next time.
+    /// The thread stops once the service is dropped.
+    pub fn start_history_flush(&self, interval: Duration) -> JoinHandle<()> {
+        let state = Arc::downgrade(&self.state);
+        let unpersisted = Arc::clone(&self.history_unpersisted);
+        let persist_file = self.persist_file.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            let Some(state) = state.upgrade() else { break };
+            if let Err(err) = flush_history(&state, &unpersisted, &persist_file) {
+                log::warn!("Failed to persist search history: {:?}", err);
+            }
+        })
+    }
+
+    /// Starts checking the loaded state on a background thread, so it's not in the way of anything else:
+    /// that each tracked file exists and can be opened, that each phrase is valid Unicode within the current phrase limits,
+    /// and that the persist file can be written. With fixing enabled, missing files are then pruned, invalid phrases dropped,
+    /// and the state persisted. What was found is logged, and kept for [`Self::validation_status`].
+    /// Returns None without starting another pass if one is in progress.
+    pub fn start_validation(&self) -> Option<JoinHandle<()>> {
+        let started_at = now_secs();
+        {
+            let mut status = self.validation.lock().unwrap();
+            if matches!(*status, ValidationStatus::InProgress { .. }) {
+                return None;
+            }
+            *status = ValidationStatus::InProgress { started_at };
+        }
+        let state = Arc::clone(&self.state);
+        let validation = Arc::clone(&self.validation);
+        let limits = *self.phrase_limits.lock().unwrap();
+        let persist_file = self.persist_file.clone();
+        let auto_fix = self.validation_auto_fix.load(Ordering::Relaxed);
+        let handle = std::thread::spawn(move || {
+            let mut report = validate_state(&state, &limits, &persist_file);
+            report.started_at = started_at;
+            if auto_fix {
+                report.fixed = Some(fix_state(&state, &report, &persist_file));
+            }
+            report.finished_at = now_secs();
+            match report.has_problems() {
+                true => log::warn!("Validated '{}': {}", persist_file.display(), report.summary()),
+                false => log::info!("Validated '{}': {}", persist_file.display(), report.summary())
+            }
+            *validation.lock().unwrap() = ValidationStatus::Done(report);
+        });
+        Some(handle)
+    }
+
+    /// Whether a validation pass is in progress, or what the latest one found
+    pub fn validation_status(&self) -> ValidationStatus {
+        self.validation.lock().unwrap().clone()
+    }
+
+    /// Has validation passes prune missing files and drop invalid phrases. Off by default.
+    pub fn set_validation_auto_fix(&self, enabled: bool) {
+        self.validation_auto_fix.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Adds the phrases and files of a JSON config, then persists.
+    /// `config["phrases"]` is an array of strings with tokens separated by whitespace, and `config["files"]` an array of paths.
+    /// Either can be left out. Nothing is added unless the whole config is valid and every file exists.
+    /// Phrases are added like [`Self::try_add_phrase`], and those outside the phrase limits or over the cost budget
+    /// are skipped and returned, with why, rather than failing the rest of the config.
+    pub fn apply_config(&self, config: &serde_json::Value) -> Result<Vec<(Phrase, AddPhraseError)>, ConfigError> {
+        let strings = |field: &'static str| -> Result<Vec<&str>, ConfigError> {
+            let Some(value) = config.get(field) else { return Ok(Vec::new()) };
+            let array = value.as_array().ok_or(ConfigError::Invalid { field, reason: "not an array".to_owned() })?;
+            array
+                .iter()
+                .enumerate()
+                .map(|(idx, value)| value.as_str().ok_or(ConfigError::Invalid { field, reason: format!("element {} isn't a string", idx) }))
+                .collect()
+        };
+        if !config.is_object() {
+            return Err(ConfigError::Invalid { field: "config", reason: "not an object".to_owned() });
+        }
+        let phrases: Vec<Phrase> = strings("phrases")?
+            .iter()
+            .enumerate()
+            .map(|(idx, str)| {
+                Phrase::try_new(str.split_whitespace().map(Text::from_str).collect())
+                    .map_err(|err| ConfigError::Invalid { field: "phrases", reason: format!("element {}: {}", idx, err) })
+            })
+            .collect::<Result<_, _>>()?;
+        let files = strings("files")?;
+        for file in &files {
+            metadata(file).map_err(|err| ConfigError::File(PathBuf::from(file), err))?;
+        }
+
+        let mut rejected = Vec::new();
+        for phrase in phrases {
+            if let Err(err) = self.try_add_phrase(phrase.clone(), false) {
+                log::warn!("Skipping phrase '{}' from config: {}", phrase, err);
+                rejected.push((phrase, err));
+            }
+        }
+        for file in files {
+            self.add_file(file).map_err(|err| ConfigError::File(PathBuf::from(file), err))?;
+        }
+        self.persist().map_err(ConfigError::Persist)?;
+        Ok(rejected)
+    }
+
+    // Tracks the files given, returning those that weren't tracked already, sorted
+    fn track_files(&self, files: Vec<PathBuf>) -> Vec<PathBuf> {
+        let mut state = self.state.lock().unwrap();
+        let mut added: Vec<PathBuf> = files
+            .into_iter()
+            .filter(|file| state.files.insert(file.clone()))
+            .inspect(|file| log::debug!("Added file {}", file.display()))
+            .collect();
+        if !added.is_empty() {
+            state.generation += 1;
+        }
+        added.sort();
+        added
+    }
+
+    /// Recursively tracks all the files beneath the directory within the [`WalkLimits`], carrying on past entries that can't be read.
+    /// Returns how many files weren't tracked already, and the errors hit along the way.
+    pub fn add_dir_reporting<P: AsRef<Path>>(&self, dirname: P) -> (usize, Vec<walkdir::Error>) {
+        let (files, report) = walk_files(dirname, &self.walk_limits());
+        (self.track_files(files).len(), report.errors)
+    }
+}
+
+// Persists the state if scans were recorded since it was last persisted, returning whether it was
+fn flush_history(state: &Mutex<State>, unpersisted: &AtomicBool, persist_file: &Path) -> Result<bool, PersistErr> {
+    let state = state.lock().unwrap();
+    if !unpersisted.swap(false, Ordering::Relaxed) {
+        return Ok(false);
+    }
+    persist_state(persist_file, &state)
+        .map(|_| true)
+        .inspect_err(|_| unpersisted.store(true, Ordering::Relaxed))
+}
+
+// Passes results on to another sink, summarizing the entries it's handed for the history of scans
+struct SummarizingSink<'a, F: Fn(&Path) -> bool> {
+    sink: &'a mut dyn ResultSink,
+    summary: ScanSummary,
+    count_file: F       // Whether instances count towards the file they're in, rather than only their phrase
+}
+
+impl<F: Fn(&Path) -> bool> ResultSink for SummarizingSink<'_, F> {
+    fn on_match(&mut self, file: &FileRef, entry: &ReportEntry) -> ControlFlow<()> {
+        self.summary.count_entry(file.path, entry, (self.count_file)(file.path));
+        self.sink.on_match(file, entry)
+    }
+
+    fn on_file_done(&mut self, file: &FileRef, changed_during_scan: bool) {
+        self.sink.on_file_done(file, changed_during_scan);
+    }
+
+    fn on_error(&mut self, file: &FileRef, error: &std::io::Error) {
+        self.sink.on_error(file, error);
+    }
+}
+
+// Writes the state to the persist file, replacing what was there
+fn persist_state(persist_file: &Path, state: &State) -> Result<(), PersistErr> {
+    let file = File::options()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(persist_file);
+    let file = match file {
+        Ok(file) => file,
+        Err(err) => {
+            log::error!("Failed to open '{}': {:?}", persist_file.display(), err);
+            return Err(PersistErr::IoError(err));
+        }
+    };
+    match serde_json::to_writer(&file, &PersistedStateRef::V2(state)) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(PersistErr::JsonError(err))
+    }
+}
+
+// Checks the state for FinderService::start_validation. Files are checked without holding the lock.
+fn validate_state(state: &Mutex<State>, limits: &PhraseLimits, persist_file: &Path) -> ValidationReport {
+    let (mut files, mut phrases) = {
+        let state = state.lock().unwrap();
+        let files: Vec<PathBuf> = state.files().cloned().collect();
+        let phrases: Vec<Phrase> = state.phrases().cloned().collect();
+        (files, phrases)
+    };
+    files.sort();
+    phrases.sort();
+
+    let mut report = ValidationReport { files_checked: files.len(), phrases_checked: phrases.len(), ..ValidationReport::default() };
+    for file in &files {
+        match File::open(file) {
+            Ok(_) => {},
+            Err(err) if err.kind() == ErrorKind::NotFound => report.missing_files.push(FileProblem::new(file, err)),
+            Err(err) => report.unreadable_files.push(FileProblem::new(file, err))
+        }
+    }
+    report.invalid_phrases = phrases
+        .iter()
+        .filter_map(|phrase| {
+            let reason = match phrase.validate(limits) {
+                Err(err) => err.to_string(),
+                Ok(_) if !phrase.is_valid_unicode() => "Phrase isn't valid Unicode".to_owned(),
+                Ok(_) => return None
+            };
+            Some(InvalidPhrase { phrase: PhraseRef::new(phrase), reason })
+        })
+        .collect();
+    report.persist_error = persist_file_error(persist_file);
+    report
+}
+
+// Why the persist file can't be written, if it can't. Nothing is written to check.
+fn persist_file_error(persist_file: &Path) -> Option<String> {
+    if persist_file.exists() {
+        return File::options().append(true).open(persist_file).err().map(|err| err.to_string());
+    }
+    let dir = match persist_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new(".")
+    };
+    match metadata(dir) {
+        Ok(meta) if meta.permissions().readonly() => Some(format!("Directory '{}' is read-only", dir.display())),
+        Ok(_) => None,
+        Err(err) => Some(format!("Directory '{}': {}", dir.display(), err))
+    }
+}
+
+// Prunes the missing files and drops the invalid phrases a validation pass found, logging both as operations, then persists
+fn fix_state(state: &Mutex<State>, report: &ValidationReport, persist_file: &Path) -> ValidationFixes {
+    let mut state = state.lock().unwrap();
+    let missing: Vec<PathBuf> = report.missing_files
+        .iter()
+        .filter_map(|problem| decode_path(&problem.encoded_path).ok())
+        .filter(|file| state.files.remove(file))
+        .collect();
+    let encodings: HashMap<PathBuf, FileEncoding> = missing
+        .iter()
+        .filter_map(|file| Some((file.clone(), state.encodings.remove(file)?)))
+        .collect();
+    state.log_removed_files(&missing, &encodings);
+
+    let invalid: Vec<Phrase> = state.phrases
+        .iter()
+        .filter(|phrase| report.invalid_phrases.iter().any(|invalid| invalid.phrase.id == phrase.id()))
+        .cloned()
+        .collect();
+    for phrase in &invalid {
+        state.remove_phrase(phrase);
+        state.operations.record(Mutation::RemovePhrase { phrase: phrase.clone() }, now_secs(), None);
+    }
+
+    let fixes = ValidationFixes { pruned_files: missing.len(), dropped_phrases: invalid.len() };
+    if fixes != ValidationFixes::default() {
+        state.generation += 1;
+        if let Err(err) = persist_state(persist_file, &state) {
+            log::error!("Failed to persist validation fixes: {:?}", err);
+        }
+    }
+    fixes
+}
+
+// Files add_file selects: the file itself, or every file beneath the directory.
+// Reports what walking it came across, without any files added.
+fn select_files_to_add<P: AsRef<Path>>(filename: P, limits: &WalkLimits) -> Result<(Vec<PathBuf>, AddReport), std::io::Error> {
+    let meta = metadata(&filename)?;
+    match meta.is_file() {
+        true => Ok((vec![normalize_path(filename)], AddReport::default())),
+        false => Ok(walk_files(filename, limits))
+    }
+}
+
+// Every file beneath the directory within the limits, carrying on past entries that can't be read.
+// Entries are walked in name order, so the same files are kept when the walk stops at the most entries.
+fn walk_files<P: AsRef<Path>>(dirname: P, limits: &WalkLimits) -> (Vec<PathBuf>, AddReport) {
+    let dirname = dirname.as_ref();
+    let mut walker = WalkDir::new(dirname)
+        .follow_links(limits.follow_links)
+        .sort_by_file_name();
+    if let Some(max_depth) = limits.max_depth {
+        // One level deeper, so entries past the limit are counted without being descended into
+        walker = walker.max_depth(max_depth.saturating_add(1));
+    }
+    let mut files = Vec::new();
+    let mut report = AddReport::default();
+    for (seen, entry) in walker.into_iter().enumerate() {
+        if let Some(max_entries) = limits.max_entries.filter(|max_entries| seen >= *max_entries) {
+            log::warn!("Stopped walking '{}' after {} entries. Files past them weren't added.", dirname.display(), max_entries);
+            report.truncated = true;
+            break;
+        }
+        match entry {
+            Ok(entry) if limits.max_depth.is_some_and(|max_depth| entry.depth() > max_depth) => report.skipped += 1,
+            Ok(entry) if entry.file_type().is_file() => files.push(normalize_path(entry.path())),
+            Ok(entry) if entry.file_type().is_dir() => report.dirs_visited += 1,
+            Ok(_) => report.skipped += 1,
+            Err(err) => report.errors.push(err)
+        }
+    }
+    (files, report)
+}
+
+/// Part of a tracked file, decoded. See [`FinderService::read_page`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FilePage {
+    pub text: Text,
+    pub offset: u64,        // Where in the file the page starts
+    pub len_bytes: usize,   // Bytes the text was decoded from
+    pub total_size: u64     // Size of the whole file
+}
+
+impl FilePage {
+
+    /// Where the next page starts, if the file goes on past this one
+    pub fn next_offset(&self) -> Option<u64> {
+        let end = self.offset + self.len_bytes as u64;
+        (self.len_bytes > 0 && end < self.total_size).then_some(end)
+    }
+}
+
+/// Sources, phrases and encodings of a [`FinderService`] at a point in time. Cheap to clone.
+/// Sources and phrases are sorted so that reports are deterministic. Files come before dynamic sources.
+#[derive(Clone)]
+pub struct Snapshot {
+    generation: u64,                            // Generation of the state when the snapshot was taken
+    sources: Arc<Vec<Source>>,
+    phrases: Arc<Vec<Phrase>>,
+    auto_options: SearchOptions,                // Options sized from the phrases
+    search_config: Option<SearchOptions>,       // Options configured in the state, if any
+    encodings: Arc<HashMap<PathBuf, Encoding>>,
+    rescan_changed: bool,                       // See FinderService::set_rescan_changed
+    intra_file_parallelism: Option<usize>,      // See FinderService::set_intra_file_parallelism
+    max_bytes_per_second: Option<u64>,          // See FinderService::set_max_bytes_per_second
+    duplicate_of: Arc<HashMap<PathBuf, PathBuf>>, // Files whose results are copied from an identical one before them. See FinderService::set_dedup_content.
+    hashing: Throughput,                        // What hashing files to find duplicate_of read, which counts towards the scan's
+    max_report_bytes: Option<usize>,            // See FinderService::set_max_report_bytes
+    cancel: CancelHandle                        // Cancels scans of the snapshot. See FinderService::cancel_scans.
+}
+
+/// A phrase that can't fit in the window a search is configured with
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct PhraseValidationWarning {
+    pub phrase: PhraseRef,
+    pub bytes_per_character: u32,   // Narrowest width the phrase can't be found at
+    pub phrase_bytes: usize,        // Bytes the tokens of the phrase take up at that width
+    pub window_size: usize          // Window the phrase doesn't fit in
+}
+
+impl Snapshot {
+
+    /// Options to search with: the ones given, the ones configured, or ones sized from the phrases with [`SearchOptions::auto_size`]
+    pub fn resolve_options(&self, options: Option<SearchOptions>) -> SearchOptions {
+        options.or(self.search_config).unwrap_or(self.auto_options)
+    }
+
+    /// Phrases whose tokens alone take up more bytes than the window, so they can never be found at some widths.
+    /// Each phrase is reported once, at the narrowest width it can't be found at. The window is clamped to the context.
+    pub fn validate_search_config(&self, context_size: usize, window_size: usize) -> Vec<PhraseValidationWarning> {
+        let window_size = window_size.min(context_size);
+        self.phrases
+            .iter()
+            .filter_map(|phrase| {
+                let chars: usize = phrase.tokens.iter().map(|token| token.as_ref().len()).sum();
+                (1..=MAX_BYTES_PER_CHARACTER)
+                    .find(|bpc| chars * bpc > window_size)
+                    .map(|bpc| PhraseValidationWarning {
+                        phrase: PhraseRef::new(phrase),
+                        bytes_per_character: bpc as u32,
+                        phrase_bytes: chars * bpc,
+                        window_size
+                    })
+            })
+            .collect()
+    }
+
+    /// Searches all sources for all phrases.
+    /// Sources that can't be opened are logged and left out of the report.
+    /// If no options are given, they're sized from the phrases with [`SearchOptions::auto_size`].
+    /// Files with an encoding set are searched with it. Others use `encoding`, or try every width if it's `None`.
+    pub fn search(&self, options: Option<SearchOptions>, encoding: Option<Encoding>) -> SearchReport {
+        self.search_measured(options, encoding).0
+    }
+
+    /// Same as [`Self::search`], along with how many bytes were read and how fast.
+    /// Entries past the snapshot's max report bytes are dropped and counted. See [`FinderService::set_max_report_bytes`].
+    pub fn search_measured(&self, options: Option<SearchOptions>, encoding: Option<Encoding>) -> (SearchReport, Throughput) {
+        let options = self.resolve_options(options);
+        let mut report = SearchReport::new(self.phrases.to_vec(), options, self.generation, self.max_report_bytes);
+        let throughput = self.search_into(Some(options), encoding, &mut report);
+        report.cancelled = self.cancel.is_cancelled();
+        (report, throughput)
+    }
+
+    /// Searches like [`Self::search`], handing results to `sink` as they're found.
+    /// Files searched again because they changed are held back until the last search of them, so their entries aren't repeated.
+    /// Reads are capped at the snapshot's max bytes per second, across all sources. Returns how many bytes were read and how fast,
+    /// including what hashing files to find duplicates read.
+    /// Files that duplicate one searched before them aren't read, and are sent that file's results instead. See [`FinderService::set_dedup_content`].
+    /// Once the scan is cancelled, the source being searched and every one after it are sent an error instead. See [`FinderService::cancel_scans`].
+    pub fn search_into(&self, options: Option<SearchOptions>, encoding: Option<Encoding>, sink: &mut dyn ResultSink) -> Throughput {
+        let options = self.resolve_options(options);
+        let throttle = Throttle::with_cancel(self.max_bytes_per_second, self.cancel.clone());
+        let copied_from: HashSet<&PathBuf> = self.duplicate_of.values().collect();
+        let mut copies: HashMap<PathBuf, (Vec<ReportEntry>, Result<bool, std::io::Error>)> = HashMap::new();
+        for source in self.sources.iter() {
+            let name = source.name();
+            let encoding = self.encodings.get(&name).copied().or(encoding);
+            let file = FileRef { path: &name, encoding };
+            if throttle.is_cancelled() {
+                sink.on_error(&file, &cancelled_error());
+                continue;
+            }
+            if let Some((entries, result)) = self.duplicate_of.get(&name).and_then(|first| copies.get(first)) {
+                let _ = entries.iter().try_for_each(|entry| sink.on_match(&file, entry));
+                match result {
+                    Ok(changed_during_scan) => sink.on_file_done(&file, *changed_during_scan),
+                    Err(err) => sink.on_error(&file, err)
+                }
+                continue;
+            }
+            let mut copied = copied_from.contains(&name).then(Vec::new);
+            let mut on_match = |entry: &ReportEntry| {
+                if let Some(copied) = copied.as_mut() {
+                    copied.push(entry.clone());
+                }
+                sink.on_match(&file, entry)
+            };
+            let result = match source {
+                Source::Path(path) if self.rescan_changed => {
+                    FileSearchResult::search_throttled(path, &self.phrases, &options, encoding, Some(&throttle))
+                        .and_then(|result| match result.changed_during_scan {
+                            true => {
+                                log::info!("'{}' changed while it was searched. Searching it again.", name.display());
+                                FileSearchResult::search_throttled(path, &self.phrases, &options, encoding, Some(&throttle))
+                            },
+                            false => Ok(result)
+                        })
+                        .map(|result| {
+                            let _ = result.entries.iter().try_for_each(&mut on_match);
+                            result.changed_during_scan
+                        })
+                },
+                Source::Path(path) => {
+                    let chunks = self.intra_file_parallelism.unwrap_or_else(|| {
+                        auto_intra_file_parallelism(std::fs::metadata(path).map_or(0, |meta| meta.len()))
+                    });
+                    match chunks {
+                        0 | 1 => search_file_with(path, &self.phrases, &options, encoding, Some(&throttle), |entry| on_match(&entry)),
+                        chunks => search_file_chunked(path, &self.phrases, &options, encoding, chunks, Some(&throttle), |entry| on_match(&entry))
+                    }
+                },
+                Source::Dynamic { .. } => source.open().and_then(|reader| {
+                    let mut reader = BufReader::new(ThrottledReader::new(reader, Some(&throttle)));
+                    search_reader_with(&self.phrases, &options, encoding, &mut reader, |entry| on_match(&entry)).map(|_| false)
+                })
+            };
+
+            // A search that was cut short by a cancellation may look like it finished early, or like the file changed
+            let result = match result {
+                Ok(_) if throttle.is_cancelled() => Err(cancelled_error()),
+                result => result
+            };
+            match &result {
+                Ok(changed_during_scan) => sink.on_file_done(&file, *changed_during_scan),
+                Err(err) => {
+                    log::warn!("Failed to search '{}': {:?}", name.display(), err);
+                    sink.on_error(&file, err);
+                }
+            }
+            if let Some(copied) = copied {
+                let result = result.map_err(|err| std::io::Error::new(err.kind(), err.to_string()));
+                copies.insert(name.clone(), (copied, result));
+            }
+        }
+        self.hashing.and(throttle.throughput())
+    }
+
+    /// Counts the instances in every source, like [`Self::search`] but without scoring or collecting them.
+    /// Sources that can't be opened, or can't be searched with the options, are logged and left out.
+    pub fn count_per_source(&self, options: Option<SearchOptions>, encoding: Option<Encoding>) -> HashMap<PathBuf, usize> {
+        let options = self.resolve_options(options);
+        self.sources
+            .iter()
+            .filter_map(|source| {
+                let name = source.name();
+                let encoding = self.encodings.get(&name).copied().or(encoding);
+                let reader = match source.open() {
+                    Ok(reader) => reader,
+                    Err(err) => {
+                        log::warn!("Failed to search '{}': {:?}", name.display(), err);
+                        return None;
+                    }
+                };
+                let mut reader = BufReader::new(reader);
+                let finder = FinderBuilder::new()
+                    .context_size(options.context_size)
+                    .window_size(options.window_size)
+                    .encoding(encoding)
+                    .build(&self.phrases, &mut reader);
+                match finder {
+                    Ok(finder) => Some((name, finder.map(|group| group.0.len()).sum())),
+                    Err(err) => {
+                        log::warn!("Failed to search '{}': {}", name.display(), err);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Searches a file as it grows. See [`FinderService::follow`].
+pub struct Follower {
+    path: PathBuf,
+    phrases: Vec<Phrase>,
+    options: SearchOptions,
+    encoding: Option<Encoding>,
+    offset: u64,                // Bytes of the file searched so far
+    identity: Option<u64>,      // Identifies the file followed, where the platform allows, to tell when it's replaced
+    scan_rotated: bool          // Whether to search the rest of a rotated-away file
+}
+
+/// What a [`Follower`] found in a poll
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct FollowUpdate {
+    pub rotated: bool,                              // The file was truncated or replaced, and was followed from the start
+    pub rotated_instances: Vec<PhraseInstance>,     // Found in what was appended to the rotated-away file, at `<path>.1`
+    pub instances: Vec<PhraseInstance>              // Found in what was appended to the file, with absolute positions
+}
+
+impl Follower {
+
+    pub(crate) fn new(path: PathBuf, phrases: &[Phrase], options: SearchOptions, encoding: Option<Encoding>) -> Result<Self, std::io::Error> {
+        let meta = metadata(&path)?;
+        Ok(Self {
+            path,
+            phrases: phrases.to_vec(),
+            options,
+            encoding,
+            offset: meta.len(),
+            identity: file_identity(&meta),
+            scan_rotated: false
+        })
+    }
+
+    /// Also searches what was appended to a rotated-away file since the last poll, if it was renamed to `<path>.1`.
+    /// Only done where files can be told apart, on unix. Files truncated in place are followed from the start alone. Off by default.
+    pub fn scan_rotated(mut self, scan_rotated: bool) -> Self {
+        self.scan_rotated = scan_rotated;
+        self
+    }
+
+    /// Bytes of the file searched so far
+    pub fn offset(&self) -> u64 { self.offset }
+
+    /// Searches what was appended to the file since the last poll. Instances have absolute file positions.
+    /// The context before the previous end is searched again so that phrases spanning it are found,
+    /// but only instances reaching past it are reported, so each is reported once.
+    /// Lines and columns are left out, since the start of the file isn't read.
+    /// If the file shrank or was replaced, it's followed from the start and the update is marked as rotated.
+    pub fn poll(&mut self) -> Result<FollowUpdate, std::io::Error> {
+        let mut update = FollowUpdate::default();
+        let meta = metadata(&self.path)?;
+        let identity = file_identity(&meta);
+        if meta.len() < self.offset || identity != self.identity {
+            log::info!("'{}' was rotated while followed. Following it from the start.", self.path.display());
+            if self.scan_rotated {
+                let mut rotated = self.path.clone().into_os_string();
+                rotated.push(".1");
+                let rotated = PathBuf::from(rotated);
+                // Renamed files keep their identity, so a `<path>.1` left over from an earlier rotation, or copied before a truncation, is skipped
+                let renamed = |rotated_meta: std::fs::Metadata| {
+                    self.identity.is_some() && identity != self.identity && file_identity(&rotated_meta) == self.identity
+                };
+                if metadata(&rotated).is_ok_and(renamed) {
+                    update.rotated_instances = self.search_appended(&rotated, self.offset)?.0;
+                }
+            }
+            update.rotated = true;
+            self.offset = 0;
+            self.identity = identity;
+        }
+        let (instances, size) = self.search_appended(&self.path, self.offset)?;
+        update.instances = instances;
+        self.offset = size;
+        Ok(update)
+    }
+
+    // Searches what was appended to the file at `path` past `offset`. Also returns the size searched up to.
+    fn search_appended(&self, path: &Path, offset: u64) -> Result<(Vec<PhraseInstance>, u64), std::io::Error> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        if size <= offset {
+            return Ok((Vec::new(), size));
+        }
+
+        // Starts on a character boundary, so wider characters line up as they do in the file
+        let start = offset.saturating_sub(self.options.context_size as u64);
+        let start = start - start % MAX_BYTES_PER_CHARACTER as u64;
+        file.seek(SeekFrom::Start(start))?;
+        let mut reader = BufReader::new(file.take(size - start));
+        let mut finder = FinderBuilder::new()
+            .context_size(self.options.context_size)
+            .window_size(self.options.window_size)
+            .encoding(self.encoding)
+            .build(&self.phrases, &mut reader)
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidInput, err.to_string()))?;
+        let mut instances: Vec<PhraseInstance> = finder
+            .by_ref()
+            .flat_map(|group| group.0)
+            .map(|instance| PhraseInstance {
+                file_pos: instance.file_pos + start as usize,
+                end_pos: instance.end_pos + start as usize,
+                line: None,
+                column: None,
+                ..instance
+            })
+            .filter(|instance| instance.end_pos as u64 > offset)
+            .collect();
+        if let Some(err) = finder.read_error() {
+            return Err(std::io::Error::new(err.kind(), err.to_string()));
+        }
+        instances.sort();
+        instances.dedup();
+        Ok((instances, size))
+    }
+}
+
+// Inode of the file on unix. None elsewhere, where only truncation is detected.
+#[cfg(unix)]
+fn file_identity(meta: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.ino())
+}
+
+#[cfg(not(unix))]
+fn file_identity(_meta: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Phrases that would take more work to search for than the service's budget allows
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct CostExceeded {
+    pub estimate: CostEstimate,
+    pub budget: u64
+}
+
+impl std::fmt::Display for CostExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}, over the budget of {}. Remove phrases or tokens, or shrink the window.", self.estimate, self.budget)
+    }
+}
+
+impl std::error::Error for CostExceeded {}
+
+/// Reasons [`FinderService::try_add_phrase`] can refuse a phrase
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AddPhraseError {
+    Invalid(PhraseError),
+    CostExceeded(CostExceeded)
+}
+
+impl std::fmt::Display for AddPhraseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Invalid(err) => err.fmt(f),
+            Self::CostExceeded(exceeded) => exceeded.fmt(f)
+        }
+    }
+}
+
+impl std::error::Error for AddPhraseError {}
+
+/// Reasons [`FinderService::apply_config`] can fail
+#[derive(Debug)]
+pub enum ConfigError {
+    Invalid { field: &'static str, reason: String },    // The config doesn't have the expected shape
+    File(PathBuf, std::io::Error),                      // A file couldn't be added
+    Persist(PersistErr)                                 // Everything was added, but couldn't be persisted
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Invalid { field, reason } => write!(f, "Invalid '{}': {}", field, reason),
+            Self::File(path, err) => write!(f, "Failed to add '{}': {}", path.display(), err),
+            Self::Persist(err) => write!(f, "Failed to persist: {:?}", err)
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug)]
+pub enum PersistErr {
+    IoError(std::io::Error),
+    JsonError(serde_json::Error)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::{ErrorKind, Read};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use text_searcher_rust::{ChannelSink, Endianness, FileRef, Phrase, PhraseRef, RejectReason, ReportEntry, ResultSink, SearchOptions, SearchReport, SinkEvent, Text};
+
+    use crate::finder_service::{decode_path, encode_path, AddPhraseError, CacheStats, ConfigError, FileEncoding, FinderService, PersistedState, State, WalkLimits};
+    use crate::validation::{ValidationFixes, ValidationStatus};
+
+    #[test]
+    fn test_add_file_single() {
+        let service = FinderService::new("persist-file.json");
+        let result = service.add_file("test_files/file.txt");
+        let state = service.state();
+        let mut files: Vec<PathBuf> = state.files().map(|file| file.to_owned()).collect();
+        files.sort();
+
+        assert!(result.is_ok());
+        assert_eq!(
+            [PathBuf::from("test_files/file.txt")].to_vec(),
+            files
+        );
+    }
+
+    #[test]
+    fn test_add_file_dir() {
+        let service = FinderService::new("persist-file.json");
+        let result = service.add_file("test_files/dir");
+
+        assert!(result.is_ok());
+        assert_eq!(
+            [
+                PathBuf::from("test_files/dir/sub_file_1.txt"),
+                PathBuf::from("test_files/dir/sub_file_2.txt")
+            ].to_vec(),
+            service.list_files_sorted()
+        );
+    }
+
+    #[test]
+    fn test_add_dir_reporting() {
+        let service = FinderService::new("persist-file.json");
+        let (added, errors) = service.add_dir_reporting("test_files/dir");
+        assert_eq!(2, added);
+        assert!(errors.is_empty());
+        let generation = service.state().generation();
+        assert_eq!(0, service.add_dir_reporting("test_files/dir").0);
+        assert_eq!(generation, service.state().generation());
+
+        let (added, errors) = service.add_dir_reporting("test_files/missing");
+        assert_eq!(0, added);
+        assert_eq!(1, errors.len());
+        assert_eq!(Some(Path::new("test_files/missing")), errors[0].path());
+    }
+
+    #[test]
+    fn test_dry_run_add_and_remove() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("test_files/dir/sub_file_1.txt").unwrap();
+        let generation = service.state().generation();
+        let tracked = |service: &FinderService| -> Vec<PathBuf> {
+            let mut files: Vec<PathBuf> = service.state().files().cloned().collect();
+            files.sort();
+            files
+        };
+
+        // Previews leave the state alone, and match what's then added or removed
+        let to_add = service.files_to_add("test_files/dir").unwrap().added;
+        assert_eq!(vec![PathBuf::from("test_files/dir/sub_file_2.txt")], to_add);
+        assert_eq!(vec![PathBuf::from("test_files/dir/sub_file_1.txt")], tracked(&service));
+        assert_eq!(generation, service.state().generation());
+        assert_eq!(to_add, service.add_file("test_files/dir").unwrap());
+
+        let to_remove = service.files_to_remove("./test_files//dir/");
+        assert_eq!(2, to_remove.len());
+        assert_eq!(to_remove, tracked(&service));
+        assert_eq!(to_remove.len(), service.remove_files("./test_files//dir/"));
+        assert!(tracked(&service).is_empty());
+        assert!(service.files_to_add("test_files/missing").is_err());
+    }
+
+    #[test]
+    fn test_phrase_hashes() {
+        let service = FinderService::new("persist-file.json");
+        assert!(service.state().phrase_hashes().is_empty());
+        let phrases = [Phrase::from_strs(&["famine", "where"]), Phrase::from_strs(&["sum", "my", "count"])];
+        for phrase in &phrases {
+            service.add_phrase(phrase.clone());
+        }
+        let mut expected: Vec<u64> = phrases.iter().map(Phrase::stable_hash).collect();
+        expected.sort();
+        assert_eq!(expected, service.state().phrase_hashes());
+        assert_eq!(phrases[0].id(), format!("{:016x}", phrases[0].stable_hash()));
+    }
+
+    #[test]
+    fn test_validation() {
+        let dir = std::env::temp_dir().join(format!("text-searcher-validation-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let missing = dir.join("missing.txt");
+        let mut state = State::new();
+        state.files.insert(PathBuf::from("src/searcher/test_text_1.txt"));
+        state.files.insert(missing.clone());
+        state.phrases.insert(Phrase::from_strs(&["famine", "where"]));
+        state.phrases.insert(Phrase::from_strs(&["ab", "famine"]));
+        let service = FinderService::with_state(dir.join("persist.json"), state);
+        assert_eq!(ValidationStatus::NotRun, service.validation_status());
+
+        // Stale files and phrases are reported, without changing anything
+        service.start_validation().unwrap().join().unwrap();
+        let ValidationStatus::Done(report) = service.validation_status() else { panic!("Validation not done") };
+        assert_eq!((2, 2), (report.files_checked, report.phrases_checked));
+        assert_eq!(vec![missing.display().to_string()], report.missing_files.iter().map(|file| file.path.clone()).collect::<Vec<_>>());
+        assert!(report.unreadable_files.is_empty());
+        assert_eq!(1, report.invalid_phrases.len());
+        assert_eq!("ab famine", report.invalid_phrases[0].phrase.text);
+        assert!(report.invalid_phrases[0].reason.contains("'ab'"));
+        assert_eq!(None, report.persist_error);
+        assert_eq!(None, report.fixed);
+        assert_eq!(2, service.state().files().count());
+
+        // Fixing prunes and drops them, persisting the state, and the fixes can be undone
+        service.set_validation_auto_fix(true);
+        service.start_validation().unwrap().join().unwrap();
+        let ValidationStatus::Done(report) = service.validation_status() else { panic!("Validation not done") };
+        assert_eq!(Some(ValidationFixes { pruned_files: 1, dropped_phrases: 1 }), report.fixed);
+        assert_eq!(1, service.state().files().count());
+        assert_eq!(1, service.state().phrases().count());
+        let persisted = FinderService::new(dir.join("persist.json"));
+        assert_eq!(1, persisted.state().files().count());
+        let operations: Vec<u64> = service.state().operations().operations().map(|operation| operation.id).collect();
+        assert_eq!(2, operations.len());
+        for id in operations {
+            service.undo_operation(id).unwrap();
+        }
+        assert_eq!(2, service.state().files().count());
+        assert_eq!(2, service.state().phrases().count());
+
+        // A persist file that can't be written to is reported
+        let service = FinderService::with_state(dir.join("no-dir/persist.json"), State::new());
+        service.start_validation().unwrap().join().unwrap();
+        let ValidationStatus::Done(report) = service.validation_status() else { panic!("Validation not done") };
+        assert!(report.persist_error.is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_walk_limits() {
+        // dir/file_0, dir/1/file_1, ... dir/1/2/3/4/file_4
+        let dir = std::env::temp_dir().join(format!("text-searcher-walk-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut level = dir.clone();
+        for depth in 0..5 {
+            std::fs::create_dir_all(&level).unwrap();
+            std::fs::write(level.join(format!("file_{}", depth)), "text").unwrap();
+            level = level.join((depth + 1).to_string());
+        }
+
+        // Too deep is skipped, without descending further
+        let service = FinderService::new("persist-file.json");
+        service.set_walk_limits(WalkLimits { max_depth: Some(2), ..WalkLimits::default() });
+        let report = service.add_file_reporting(&dir).unwrap();
+        assert_eq!(vec![dir.join("1/file_1"), dir.join("file_0")], report.added);
+        assert_eq!((3, 2, false), (report.dirs_visited, report.skipped, report.truncated));
+        assert!(report.errors.is_empty());
+
+        // The walk stops after the most entries
+        let service = FinderService::new("persist-file.json");
+        service.set_walk_limits(WalkLimits { max_entries: Some(3), ..WalkLimits::default() });
+        let report = service.files_to_add(&dir).unwrap();
+        assert_eq!((0, 3, true), (report.added.len(), report.dirs_visited, report.truncated));
+        assert!(service.state().files().next().is_none());
+        service.set_walk_limits(WalkLimits { max_entries: Some(2), ..WalkLimits::default() });
+        let (added, errors) = service.add_dir_reporting("test_files/dir");
+        assert_eq!((1, 0), (added, errors.len()));
+        assert_eq!(vec![&PathBuf::from("test_files/dir/sub_file_1.txt")], service.state().files().collect::<Vec<_>>());
+        let report = service.add_file_reporting("test_files/dir").unwrap();
+        assert_eq!((0, true), (report.added.len(), report.truncated));
+
+        // Broken symlinks are skipped, unless they're followed, which fails to read them
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("missing"), dir.join("broken")).unwrap();
+            let service = FinderService::new("persist-file.json");
+            let report = service.files_to_add(&dir).unwrap();
+            assert_eq!((5, 1, 0), (report.added.len(), report.skipped, report.errors.len()));
+            service.set_walk_limits(WalkLimits { follow_links: true, ..WalkLimits::default() });
+            let report = service.add_file_reporting(&dir).unwrap();
+            assert_eq!((5, 0, 1), (report.added.len(), report.skipped, report.errors.len()));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[allow(unused_must_use)]
+    fn test_remove_file_single() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("test_files/dir");
+        assert_eq!(1, service.remove_files("test_files/dir/sub_file_1.txt"));
+        assert_eq!(0, service.remove_files("test_files/dir/sub_file_1.txt"));
+        let state = service.state();
+        let mut files: Vec<PathBuf> = state.files().map(|file| file.to_owned()).collect();
+        files.sort();
+
+        assert_eq!(
+            [PathBuf::from("test_files/dir/sub_file_2.txt")].to_vec(),
+            files
+        );
+    }
+
+    #[test]
+    #[allow(unused_must_use)]
+    fn test_remove_file_multi() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("test_files/file.txt");
+        service.add_file("test_files/dir");
+        assert_eq!(2, service.remove_files("test_files/dir"));
+        let state = service.state();
+        let mut files: Vec<PathBuf> = state.files().map(|file| file.to_owned()).collect();
         files.sort();
 
         assert_eq!(
@@ -208,4 +2294,957 @@ mod tests {
             files
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_reload() {
+        let persist_file = std::env::temp_dir().join(format!("text-searcher-reload-{}.json", std::process::id()));
+        let service = FinderService::new(&persist_file);
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        service.persist().unwrap();
+
+        // Edited by hand
+        std::fs::write(&persist_file, r#"{"files":[],"phrases":[["sum","my","count"],["famine","where"]]}"#).unwrap();
+        service.reload().unwrap();
+        assert_eq!(2, service.state().phrases().count());
+
+        // Invalid files leave the state alone
+        std::fs::write(&persist_file, "{").unwrap();
+        assert!(service.reload().is_err());
+        assert_eq!(2, service.state().phrases().count());
+        std::fs::remove_file(&persist_file).unwrap();
+        assert!(service.reload().is_err());
+    }
+
+    #[test]
+    fn test_persisted_state_versions() {
+        let v1 = r#"{"files":["test_files/file.txt"],"phrases":[["famine","where"]]}"#;
+        let state = PersistedState::from_reader(v1.as_bytes()).unwrap();
+        assert!(matches!(state, PersistedState::V1(_)));
+        let state = state.into_latest();
+        assert!(state.contains_file("test_files/file.txt"));
+        assert_eq!(1, state.phrases().count());
+        assert!(state.search_config().is_none());
+        assert_eq!(0, state.named_phrases().count());
+
+        // Persists as V2, which reads back as is
+        let persist_file = std::env::temp_dir().join(format!("text-searcher-versions-{}.json", std::process::id()));
+        std::fs::write(&persist_file, v1).unwrap();
+        let service = FinderService::new_try(&persist_file).unwrap();
+        service.persist().unwrap();
+        let persisted = std::fs::read_to_string(&persist_file).unwrap();
+        assert!(persisted.contains(r#""version":"V2""#));
+        let state = PersistedState::from_reader(persisted.as_bytes()).unwrap();
+        assert!(matches!(state, PersistedState::V2(_)));
+
+        // Unknown versions and corrupt files are errors rather than panics
+        std::fs::write(&persist_file, r#"{"version":"V9","files":[],"phrases":[]}"#).unwrap();
+        assert!(FinderService::new_try(&persist_file).is_err());
+        std::fs::remove_file(&persist_file).unwrap();
+    }
+
+    #[test]
+    fn test_prune_missing_files() {
+        let dir = std::env::temp_dir().join(format!("text-searcher-prune-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let kept = dir.join("kept.txt");
+        let deleted = dir.join("deleted.txt");
+        std::fs::write(&kept, "kept").unwrap();
+        std::fs::write(&deleted, "deleted").unwrap();
+        let service = FinderService::new("persist-file.json");
+        service.add_file(&dir).unwrap();
+        service.set_file_encoding(&deleted, FileEncoding { bytes_per_character: 2, endianness: Endianness::Little }).unwrap();
+        assert!(service.files_not_found().is_empty());
+
+        std::fs::remove_file(&deleted).unwrap();
+        assert_eq!(vec![deleted.clone()], service.files_not_found());
+        let generation = service.state().generation();
+        assert_eq!(vec![deleted.clone()], service.prune_missing_files());
+        assert_eq!(vec![&kept], service.state().files().collect::<Vec<_>>());
+        assert_eq!(None, service.state().file_encoding(&deleted));
+        assert_eq!(generation + 1, service.state().generation());
+
+        // Nothing left to prune
+        assert!(service.prune_missing_files().is_empty());
+        assert_eq!(generation + 1, service.state().generation());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_files_by_component() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("./test_files/file.txt").unwrap();
+        service.add_file("test_files/dir").unwrap();
+        assert!(service.state().contains_file("test_files/file.txt"));
+        assert_eq!(0, service.remove_files("test_files/di"));
+        assert_eq!(0, service.remove_files("test_files/file"));
+        assert_eq!(2, service.remove_files("./test_files//dir/"));
+        assert_eq!(1, service.remove_files("test_files"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_remove_files_backslash_unix() {
+        // Backslashes are part of file names on unix, so they don't separate components
+        let service = FinderService::new("persist-file.json");
+        service.add_file("test_files/dir").unwrap();
+        assert_eq!(0, service.remove_files("test_files\\dir"));
+        assert_eq!(2, service.remove_files("test_files/dir"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_remove_files_backslash_windows() {
+        // Walked paths use backslashes after the directory given, which may use either
+        let service = FinderService::new("persist-file.json");
+        service.add_file("test_files/dir").unwrap();
+        assert_eq!(1, service.remove_files("test_files\\dir\\sub_file_1.txt"));
+        assert_eq!(1, service.remove_files(".\\test_files/dir"));
+    }
+
+    #[test]
+    fn test_load_windows_persist_file() {
+        let service = FinderService::new_try("test_files/persist_windows.json").unwrap();
+        let state = service.state();
+        let mut files: Vec<&PathBuf> = state.files().collect();
+        files.sort();
+        let (app_log, notes) = if cfg!(windows) {
+            (PathBuf::from("C:\\data\\logs\\app.log"), PathBuf::from("notes.txt"))
+        }
+        else {
+            (PathBuf::from("C:/data/logs/app.log"), PathBuf::from("notes.txt"))
+        };
+        assert_eq!(vec![&app_log, &notes], files);
+
+        // The character table it was once persisted with is dropped
+        let encoding = FileEncoding { bytes_per_character: 2, endianness: Endianness::Little };
+        assert_eq!(Some(&encoding), state.file_encoding(&app_log));
+
+        // Paths are written back for this platform, and read back as is
+        let persisted = serde_json::to_string(&*state).unwrap();
+        let platform = if cfg!(windows) { r#""platform":"windows""# } else { r#""platform":"unix""# };
+        assert!(persisted.contains(platform));
+        let reloaded = PersistedState::V2(serde_json::from_str(&persisted).unwrap()).into_latest();
+        assert_eq!(state.files, reloaded.files);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_persist_non_utf8_path() {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::OsStringExt;
+
+        let dir = std::env::temp_dir().join(format!("text-searcher-non-utf8-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(OsString::from_vec(b"caf\xe9.txt".to_vec()));
+        std::fs::write(&file, "famine where").unwrap();
+        assert!(file.to_str().is_none());
+        assert!(encode_path(&file).starts_with("\0u8:"));
+        assert_eq!(Ok(file.clone()), decode_path(&encode_path(&file)));
+
+        // Persists and reloads exactly
+        let persist_file = dir.join("persist.json");
+        let service = FinderService::new(&persist_file);
+        service.add_file(&file).unwrap();
+        service.set_file_encoding(&file, FileEncoding { bytes_per_character: 1, endianness: Endianness::Little }).unwrap();
+        service.persist().unwrap();
+        let reloaded = FinderService::new_try(&persist_file).unwrap();
+        assert_eq!(vec![&file], reloaded.state().files().collect::<Vec<_>>());
+        assert!(reloaded.state().file_encoding(&file).is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_decode_path() {
+        assert_eq!(Ok(PathBuf::from("test_files/file.txt")), decode_path("test_files/file.txt"));
+        assert_eq!(Ok(PathBuf::from("caf\u{e9}")), decode_path("\0u8:636166c3a9"));
+        assert_eq!(Ok(PathBuf::from("caf\u{e9}")), decode_path("\0u16:00630061006600e9"));
+        assert!(decode_path("\0u8:abc").is_err());
+        assert!(decode_path("\0u32:00").is_err());
+    }
+
+    #[test]
+    fn test_apply_config() {
+        use serde_json::json;
+        let persist_file = std::env::temp_dir().join(format!("text-searcher-config-{}.json", std::process::id()));
+        let service = FinderService::new(&persist_file);
+        let config = json!({
+            "phrases": ["famine where", "within  sunken deep"],
+            "files": ["test_files/file.txt", "test_files/dir"]
+        });
+        assert_eq!(Vec::<(Phrase, AddPhraseError)>::new(), service.apply_config(&config).unwrap());
+        assert_eq!(3, service.state().files().count());
+        assert!(service.state().phrases().any(|phrase| *phrase == Phrase::from_strs(&["within", "sunken", "deep"])));
+        assert_eq!(2, FinderService::new(&persist_file).state().phrases().count());
+
+        // Invalid configs change nothing
+        let service = FinderService::new("persist-file.json");
+        assert!(matches!(service.apply_config(&json!(["famine"])), Err(ConfigError::Invalid { field: "config", .. })));
+        assert!(matches!(service.apply_config(&json!({ "phrases": "famine" })), Err(ConfigError::Invalid { field: "phrases", .. })));
+        assert!(matches!(service.apply_config(&json!({ "phrases": ["famine", 3] })), Err(ConfigError::Invalid { field: "phrases", .. })));
+        assert!(matches!(service.apply_config(&json!({ "phrases": [" "] })), Err(ConfigError::Invalid { field: "phrases", .. })));
+        let missing = json!({ "phrases": ["famine where"], "files": ["test_files/missing.txt"] });
+        assert!(matches!(service.apply_config(&missing), Err(ConfigError::File(..))));
+        assert_eq!(0, service.state().phrases().count());
+
+        // Phrases outside the limits or over the budget are skipped and reported, and the rest still added
+        std::fs::remove_file(&persist_file).unwrap();
+        let service = FinderService::new(&persist_file);
+        service.set_cost_budget(150);
+        let config = json!({ "phrases": ["famine where", "ab famine", "within sunken deep famine where"] });
+        let rejected = service.apply_config(&config).unwrap();
+        assert_eq!(2, rejected.len());
+        assert_eq!(Phrase::from_strs(&["ab", "famine"]), rejected[0].0);
+        assert!(matches!(rejected[0].1, AddPhraseError::Invalid(_)));
+        assert!(matches!(rejected[1].1, AddPhraseError::CostExceeded(_)));
+        assert_eq!(vec![&Phrase::from_strs(&["famine", "where"])], service.state().phrases().collect::<Vec<_>>());
+        std::fs::remove_file(&persist_file).unwrap();
+    }
+
+    #[test]
+    fn test_files_matching_glob() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("test_files").unwrap();
+        let state = service.state();
+
+        assert_eq!(
+            vec![
+                &PathBuf::from("test_files/dir/sub_file_1.txt"),
+                &PathBuf::from("test_files/dir/sub_file_2.txt")
+            ],
+            state.files_matching_glob("test_files/dir/*.txt").unwrap()
+        );
+        assert_eq!(3, state.files_matching_glob("**/*.txt").unwrap().len());
+        assert!(state.files_matching_glob("**/*.csv").unwrap().is_empty());
+        assert!(state.files_matching_glob("test_files/[").is_err());
+    }
+
+    #[test]
+    fn test_search_phrase_in_file() {
+        let service = FinderService::new("persist-file.json");
+        let phrase = Phrase::from_strs(&["famine", "where"]);
+        let instances = service.search_phrase_in_file(&phrase, "src/searcher/test_text_1.txt").unwrap();
+        let missing = service.search_phrase_in_file(&phrase, "test_files/missing.txt");
+
+        assert_eq!(1, instances.len());
+        assert_eq!(288, instances[0].file_pos);
+        assert_eq!((Some(7), Some(12)), (instances[0].line, instances[0].column));
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_search_all() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_file("src/searcher/test_text_2.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        service.add_phrase(Phrase::from_strs(&["within", "sunken", "deep"]));
+        let report = service.search_all(Some(SearchOptions::default()), None);
+
+        assert_eq!(SearchOptions::default(), report.options);
+        assert_eq!(2, report.files.len());
+        assert_eq!(1, report.files[0].entries.len());
+        assert_eq!(288, report.files[0].entries[0].instance.file_pos);
+        assert_eq!(1, report.files[1].entries.len());
+        assert_eq!(285, report.files[1].entries[0].instance.file_pos);
+    }
+
+    #[test]
+    fn test_search_all_chunked() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_file("src/searcher/test_text_2.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        service.add_phrase(Phrase::from_strs(&["sum", "count"]));
+        let positions = |service: &FinderService| -> Vec<Vec<usize>> {
+            service.search_all(Some(SearchOptions::default()), None).files
+                .iter()
+                .map(|file| file.entries.iter().map(|entry| entry.instance.file_pos).collect())
+                .collect()
+        };
+        let single = positions(&service);
+        assert_eq!(vec![vec![288], vec![479]], single);
+        service.set_intra_file_parallelism(Some(4));
+        assert_eq!(single, positions(&service));
+    }
+
+    #[test]
+    fn test_search_all_throttled() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_file("src/searcher/test_text_2.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        service.set_max_bytes_per_second(Some(4096));
+        let (report, throughput) = service.snapshot().search_measured(None, None);
+        let size: u64 = ["src/searcher/test_text_1.txt", "src/searcher/test_text_2.txt"]
+            .iter()
+            .map(|path| std::fs::metadata(path).unwrap().len())
+            .sum();
+        assert_eq!(1, report.files[0].entries.len());
+        assert_eq!(size, throughput.bytes_read);
+        assert!(throughput.elapsed.as_secs_f64() >= size as f64 / 4096.0);
+        assert!(throughput.bytes_per_second().unwrap() <= 4096);
+    }
+
+    #[test]
+    fn test_cancel_scans() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_file("src/searcher/test_text_2.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        service.set_max_bytes_per_second(Some(256));
+
+        // Both files fail: the one being read when the scan is cancelled, and the one it hadn't got to
+        let snapshot = service.snapshot();
+        let (report, mut errors) = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(100));
+                service.cancel_scans();
+            });
+            let (sender, receiver) = std::sync::mpsc::sync_channel(16);
+            let mut sink = ChannelSink::new(sender);
+            snapshot.search_into(None, None, &mut sink);
+            drop(sink);
+            let errors: Vec<PathBuf> = receiver
+                .iter()
+                .filter_map(|event| match event {
+                    SinkEvent::Error { path, .. } => Some(path),
+                    _ => None
+                })
+                .collect();
+            (snapshot.search(None, None), errors)
+        });
+        errors.sort();
+        assert_eq!(vec![PathBuf::from("src/searcher/test_text_1.txt"), PathBuf::from("src/searcher/test_text_2.txt")], errors);
+        assert!(report.cancelled);
+        assert!(report.files.is_empty());
+
+        // Scans started afterwards aren't cancelled
+        service.set_max_bytes_per_second(None);
+        let report = service.search_all(None, None);
+        assert!(!report.cancelled);
+        assert_eq!(1, report.files[0].entries.len());
+    }
+
+    #[test]
+    fn test_search_all_dedup_content() {
+        let dir = std::env::temp_dir().join(format!("text-searcher-dedup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (first, second) = (dir.join("a.txt"), dir.join("b.txt"));
+        std::fs::copy("src/searcher/test_text_1.txt", &first).unwrap();
+        std::fs::copy("src/searcher/test_text_1.txt", &second).unwrap();
+        let service = FinderService::new(dir.join("persist.json"));
+        service.add_file(&first).unwrap();
+        service.add_file(&second).unwrap();
+        service.add_file("src/searcher/test_text_2.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        let groups = service.duplicate_groups();
+        assert_eq!(1, groups.len());
+        assert_eq!(vec![first.clone(), second.clone()], groups[0].files);
+        assert_eq!(639, groups[0].len);
+
+        // Only one copy is searched, but both get its results. Both copies were hashed already, so they aren't read again.
+        // The file with a size of its own is never hashed.
+        let other_len = std::fs::metadata("src/searcher/test_text_2.txt").unwrap().len();
+        let bytes_read = || service.state().history().summaries().last().unwrap().bytes_read;
+        service.set_dedup_content(true);
+        let report = service.search_all(None, None);
+        assert_eq!(639 + other_len, bytes_read());
+        let entries = |report: &SearchReport, path: &Path| report.files.iter().find(|result| result.path == path).unwrap().entries.clone();
+        assert_eq!(1, entries(&report, &first).len());
+        assert_eq!(entries(&report, &first), entries(&report, &second));
+
+        // Without dedup both are read, with the same results
+        service.set_dedup_content(false);
+        assert_eq!(report.files, service.search_all(None, None).files);
+        assert_eq!(2 * 639 + other_len, bytes_read());
+
+        // Once a copy changes, it's searched on its own. Its size is its own now, so it's not hashed again.
+        std::fs::write(&second, b"famine where").unwrap();
+        service.set_dedup_content(true);
+        let report = service.search_all(None, None);
+        assert_eq!(639 + 12 + other_len, bytes_read());
+        assert_eq!(0, entries(&report, &second)[0].instance.file_pos);
+        assert!(service.duplicate_groups().is_empty());
+
+        // Hashing counts towards the scan that needed it, on top of searching the file that's kept
+        std::fs::copy("src/searcher/test_text_1.txt", &second).unwrap();
+        service.search_all(None, None);
+        assert_eq!(639 + 639 + other_len, bytes_read());
+        service.search_all(None, None);
+        assert_eq!(639 + other_len, bytes_read());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_search_all_within() {
+        let dir = std::env::temp_dir().join(format!("text-searcher-report-bytes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("repeated.txt");
+        std::fs::write(&file, "famine where ".repeat(100)).unwrap();
+        let service = FinderService::new(dir.join("persist.json"));
+        service.add_file(&file).unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine"]));
+        let full = service.search_all(None, None);
+        let bytes_per_entry = full.files[0].entries[0].approx_bytes();
+
+        // Requests can lower the configured cap, but not raise it
+        service.set_max_report_bytes(Some(20 * bytes_per_entry));
+        let capped = service.search_all_within(None, None, Some(5 * bytes_per_entry));
+        assert!(capped.truncated);
+        assert_eq!(Some(5 * bytes_per_entry), capped.max_bytes);
+        assert_eq!(full.files[0].entries[..5], capped.files[0].entries[..]);
+        assert_eq!(95, capped.dropped[0].count);
+        let capped = service.search_all_within(None, None, Some(1000 * bytes_per_entry));
+        assert_eq!(20, capped.files[0].entries.len());
+        assert_eq!(80, capped.dropped[0].count);
+
+        // The scan's history counts the entries dropped too
+        let summary = service.state().history().summaries().last().unwrap().clone();
+        assert_eq!(Some(&100), summary.files.values().next());
+        assert_eq!(100, summary.phrases.values().next().unwrap().count);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_search_all_auto_sized() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        let report = service.search_all(None, None);
+
+        // 11 characters plus a gap, 2 bytes each
+        assert_eq!(SearchOptions { context_size: 52, window_size: 26 }, report.options);
+        assert_eq!(1, report.files[0].entries.len());
+        assert_eq!(288, report.files[0].entries[0].instance.file_pos);
+    }
+
+    #[test]
+    fn test_search_config() {
+        let config = SearchOptions { context_size: 64, window_size: 32 };
+        let service = FinderService::new_with_config("persist-file.json", config).unwrap();
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        assert_eq!(Some(&config), service.state().search_config());
+        assert_eq!(config, service.search_all(None, None).options);
+
+        // Options given still win, and without a config the options are sized from the phrases
+        let given = SearchOptions { context_size: 128, window_size: 64 };
+        assert_eq!(given, service.search_all(Some(given), None).options);
+        service.set_search_config(None).unwrap();
+        assert_eq!(SearchOptions { context_size: 52, window_size: 26 }, service.search_all(None, None).options);
+
+        // Options a finder can't be built with are refused, leaving the config as it was
+        let invalid = SearchOptions { context_size: 30, window_size: 8 };
+        assert!(matches!(service.set_search_config(Some(invalid)), Err(ConfigError::Invalid { field: "search_config", .. })));
+        assert!(FinderService::new_with_config("persist-file.json", invalid).is_err());
+        assert_eq!(None, service.state().search_config());
+    }
+
+    #[test]
+    fn test_set_file_encoding() {
+        let path = "src/searcher/test_text_1_utf16le.txt";
+        let service = FinderService::new("persist-file.json");
+        service.add_file(path).unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        let encoding = |bytes_per_character| FileEncoding { bytes_per_character, endianness: Endianness::Little };
+        let search = || service.search_all(Some(SearchOptions::default()), None).files.remove(0).entries;
+
+        // Searched as 1 byte per character, nothing is found
+        service.set_file_encoding(path, encoding(1)).unwrap();
+        assert!(search().is_empty());
+
+        // Override wins over the encoding passed in
+        service.set_file_encoding(path, encoding(2)).unwrap();
+        let one_byte = Some(encoding(1).encoding());
+        let entries = service.search_all(Some(SearchOptions::default()), one_byte).files.remove(0).entries;
+        assert_eq!(1, entries.len());
+        assert_eq!(576, entries[0].instance.file_pos);
+        assert_eq!(2, entries[0].instance.bytes_per_character);
+        assert_eq!(Some(&encoding(2)), service.state().file_encoding(path));
+
+        // Untracked files and unsupported widths are rejected
+        assert!(service.set_file_encoding("test_files/file.txt", encoding(2)).is_err());
+        assert!(service.set_file_encoding(path, encoding(3)).is_err());
+        assert_eq!(Some(&encoding(2)), service.state().file_encoding(path));
+
+        // Forgotten along with the file
+        service.remove_files(path);
+        assert_eq!(None, service.state().file_encoding(path));
+    }
+
+    // Fails every read
+    struct ErrorReader;
+
+    impl Read for ErrorReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::from(ErrorKind::BrokenPipe))
+        }
+    }
+
+    // Counts what it's given, breaking after `limit` matches in a file if set
+    #[derive(Default)]
+    struct CountingSink {
+        limit: Option<usize>,
+        matches: Vec<(PathBuf, usize)>,
+        done: Vec<PathBuf>,
+        errors: Vec<PathBuf>
+    }
+
+    impl ResultSink for CountingSink {
+        fn on_match(&mut self, file: &FileRef, entry: &ReportEntry) -> std::ops::ControlFlow<()> {
+            self.matches.push((file.path.to_path_buf(), entry.instance.file_pos));
+            let in_file = self.matches.iter().filter(|(path, _)| path == file.path).count();
+            match self.limit.is_some_and(|limit| in_file >= limit) {
+                true => std::ops::ControlFlow::Break(()),
+                false => std::ops::ControlFlow::Continue(())
+            }
+        }
+
+        fn on_file_done(&mut self, file: &FileRef, _changed_during_scan: bool) {
+            self.done.push(file.path.to_path_buf());
+        }
+
+        fn on_error(&mut self, file: &FileRef, _error: &std::io::Error) {
+            self.errors.push(file.path.to_path_buf());
+        }
+    }
+
+    #[test]
+    fn test_search_all_into_sink() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_file("src/searcher/test_text_2.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        service.add_phrase(Phrase::from_strs(&["within", "sunken", "deep"]));
+        service.add_phrase(Phrase::from_strs(&["sum", "my", "count"]));
+        service.add_dynamic_source("memory://broken", || Err(std::io::Error::other("Unavailable")));
+        let text_1 = PathBuf::from("src/searcher/test_text_1.txt");
+        let text_2 = PathBuf::from("src/searcher/test_text_2.txt");
+
+        // Every match arrives, and every file is either done or failed
+        let mut counting = CountingSink::default();
+        service.search_all_into(Some(SearchOptions::default()), None, &mut counting);
+        assert_eq!(vec![(text_1.clone(), 288), (text_2.clone(), 285), (text_2.clone(), 479)], counting.matches);
+        assert_eq!(vec![text_1.clone(), text_2.clone()], counting.done);
+        assert_eq!(vec![PathBuf::from("memory://broken")], counting.errors);
+
+        // Breaking stops the file early, but carries on with the next
+        let mut breaking = CountingSink { limit: Some(1), ..Default::default() };
+        service.search_all_into(Some(SearchOptions::default()), None, &mut breaking);
+        assert_eq!(vec![(text_1.clone(), 288), (text_2.clone(), 285)], breaking.matches);
+        assert_eq!(vec![text_1.clone(), text_2.clone()], breaking.done);
+
+        // Each scan is added to the history, counting the entries handed to the sink
+        let counts: Vec<(usize, Option<usize>)> = service.state().history().summaries()
+            .iter()
+            .map(|summary| (summary.phrases.values().map(|tally| tally.count).sum(), summary.files.get(&encode_path(&text_2)).copied()))
+            .collect();
+        assert_eq!(vec![(3, Some(2)), (2, Some(1))], counts);
+
+        // The collecting report is the same as before
+        let report = service.search_all(Some(SearchOptions::default()), None);
+        assert_eq!(2, report.files.len());
+        assert_eq!(vec![285, 479], report.files[1].entries.iter().map(|entry| entry.instance.file_pos).collect::<Vec<_>>());
+
+        // Sources that fail partway are reported as errors, not as done
+        service.add_dynamic_source("memory://truncated", || {
+            let bytes: &[u8] = include_bytes!("searcher/test_text_1.txt");
+            let failing = bytes.chain(ErrorReader);
+            Ok(Box::new(failing) as Box<dyn Read + Send>)
+        });
+        let mut failing = CountingSink::default();
+        service.search_all_into(Some(SearchOptions::default()), None, &mut failing);
+        assert_eq!(vec![PathBuf::from("memory://broken"), PathBuf::from("memory://truncated")], failing.errors);
+        assert!(failing.matches.contains(&(PathBuf::from("memory://truncated"), 288)));
+        assert!(!failing.done.contains(&PathBuf::from("memory://truncated")));
+        service.remove_dynamic_source("memory://truncated");
+
+        // A channel gets every event, in order
+        let (sender, receiver) = std::sync::mpsc::sync_channel(16);
+        service.search_all_into(Some(SearchOptions::default()), None, &mut ChannelSink::new(sender));
+        let events: Vec<(&str, PathBuf, Option<usize>)> = receiver
+            .iter()
+            .map(|event| match event {
+                SinkEvent::Match { path, entry } => ("match", path, Some(entry.instance.file_pos)),
+                SinkEvent::FileDone { path, .. } => ("done", path, None),
+                SinkEvent::Error { path, .. } => ("error", path, None)
+            })
+            .collect();
+        let expected = vec![
+            ("match", text_1.clone(), Some(288)),
+            ("done", text_1.clone(), None),
+            ("match", text_2.clone(), Some(285)),
+            ("match", text_2.clone(), Some(479)),
+            ("done", text_2.clone(), None),
+            ("error", PathBuf::from("memory://broken"), None)
+        ];
+        assert_eq!(expected, events);
+
+        // A channel with nobody listening stops every file at its first entry
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        drop(receiver);
+        service.search_all_into(Some(SearchOptions::default()), None, &mut ChannelSink::new(sender));
+        let last = service.state().history().summaries().last().cloned().unwrap();
+        assert_eq!(2, last.phrases.values().map(|tally| tally.count).sum::<usize>());
+        assert_eq!(Some(&1), last.files.get(&encode_path(&text_2)));
+    }
+
+    #[test]
+    fn test_search_all_dynamic_source() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        service.add_dynamic_source("memory://sonnet", || {
+            let bytes: &[u8] = include_bytes!("searcher/test_text_1.txt");
+            Ok(Box::new(bytes) as Box<dyn Read + Send>)
+        });
+        service.add_dynamic_source("memory://broken", || Err(std::io::Error::other("Unavailable")));
+
+        // Scanned after files and attributed by name. Broken sources are left out.
+        let report = service.search_all(Some(SearchOptions::default()), None);
+        assert_eq!(2, report.files.len());
+        assert_eq!(PathBuf::from("src/searcher/test_text_1.txt"), report.files[0].path);
+        assert_eq!(PathBuf::from("memory://sonnet"), report.files[1].path);
+        assert_eq!(288, report.files[1].entries[0].instance.file_pos);
+
+        // Never persisted, and removable
+        assert!(!serde_json::to_string(&*service.state()).unwrap().contains("memory://"));
+        assert!(service.remove_dynamic_source("memory://sonnet"));
+        assert!(!service.remove_dynamic_source("memory://sonnet"));
+        assert_eq!(1, service.search_all(Some(SearchOptions::default()), None).files.len());
+    }
+
+    #[test]
+    fn test_phrase_cache() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_file("src/searcher/test_text_2.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+
+        // Prepared once for a scan of both files, then reused without sorting or sizing them again
+        let report = service.search_all(None, None);
+        assert_eq!(2, report.files.len());
+        assert_eq!(CacheStats { hits: 0, misses: 1 }, service.phrase_cache_stats());
+        let (first, second) = (service.snapshot(), service.snapshot());
+        assert!(Arc::ptr_eq(&first.phrases, &second.phrases));
+        assert_eq!(CacheStats { hits: 2, misses: 1 }, service.phrase_cache_stats());
+
+        // Prepared again after the phrases change
+        service.add_phrase(Phrase::from_strs(&["sum", "my", "count"]));
+        let report = service.search_all(None, None);
+        assert_eq!(2, report.phrases.len());
+        assert_eq!(CacheStats { hits: 2, misses: 2 }, service.phrase_cache_stats());
+        assert!(!Arc::ptr_eq(&first.phrases, &service.snapshot().phrases));
+    }
+
+    #[test]
+    fn test_trace_file() {
+        let service = FinderService::new("persist-file.json");
+        let path = "src/searcher/test_text_1.txt";
+        service.add_file(path).unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        service.add_phrase(Phrase::from_strs(&["famine", "nowhere"]));
+        let options = Some(SearchOptions { context_size: 64, window_size: 32 });
+
+        // Only the most recent events are kept, and the missing phrase is rejected in every one
+        let events = service.trace_file(path, options, 1, 8).unwrap();
+        assert_eq!(8, events.len());
+        assert!(events.windows(2).all(|pair| pair[0].file_pos < pair[1].file_pos));
+        assert!(events.iter().all(|event| event.rejected_reason_per_phrase[0] == Some(RejectReason::TokenNotFound)));
+
+        // Only for tracked files, with valid options
+        assert_eq!(ErrorKind::NotFound, service.trace_file("src/searcher/test_text_2.txt", options, 1, 8).unwrap_err().kind());
+        let invalid = Some(SearchOptions { context_size: 30, window_size: 8 });
+        assert_eq!(ErrorKind::InvalidInput, service.trace_file(path, invalid, 1, 8).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_validate_search_config() {
+        let service = FinderService::new("persist-file.json");
+        service.add_phrase(Phrase::from_strs(&["within", "sunken", "deep"]));
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+
+        // 16 and 11 characters
+        let warnings = service.validate_search_config(64, 12);
+        let found: Vec<(&str, u32, usize)> = warnings
+            .iter()
+            .map(|warning| (warning.phrase.text.as_str(), warning.bytes_per_character, warning.phrase_bytes))
+            .collect();
+        assert_eq!(vec![("famine where", 2, 22), ("within sunken deep", 1, 16)], found);
+        assert!(service.validate_search_config(64, 32).is_empty());
+
+        // The window can't be larger than the context
+        assert_eq!(2, service.validate_search_config(8, 32).len());
+        assert!(service.validate_search_config(8, 32).iter().all(|warning| warning.window_size == 8));
+    }
+
+    #[test]
+    fn test_snapshot_attribution() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        service.add_phrase(Phrase::from_strs(&["zebra"]));
+        let snapshot = service.snapshot();
+
+        // Changes after the snapshot shift phrase indices in the live state
+        service.remove_phrase(&Phrase::from_strs(&["zebra"]));
+        service.add_phrase(Phrase::from_strs(&["Making"]));
+        let report = snapshot.search(Some(SearchOptions::default()), None);
+
+        let phrase = Phrase::from_strs(&["famine", "where"]);
+        let entries = &report.files[0].entries;
+        assert_eq!(1, entries.len());
+        assert_eq!(PhraseRef::new(&phrase), entries[0].phrase);
+        assert_eq!(phrase.id(), entries[0].phrase.id);
+        assert_eq!(phrase, report.phrases[entries[0].instance.phrase_index]);
+        assert_eq!(2, report.phrases.len());
+    }
+
+    #[test]
+    fn test_snapshot_concurrent_mutations() {
+        let service = Arc::new(FinderService::new("persist-file.json"));
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+
+        // Adds and removes files and phrases while scans run
+        let mutator = {
+            let service = Arc::clone(&service);
+            std::thread::spawn(move || {
+                for i in 0..200 {
+                    let phrase = Phrase::from_strs(&["Making", &i.to_string()]);
+                    service.add_phrase(phrase.clone());
+                    service.add_file("src/searcher/test_text_2.txt").unwrap();
+                    service.remove_phrase(&phrase);
+                    service.remove_files("src/searcher/test_text_2.txt");
+                }
+            })
+        };
+        while !mutator.is_finished() {
+            let snapshot = service.snapshot();
+            let report = snapshot.search(Some(SearchOptions::default()), None);
+            assert_eq!(snapshot.generation, report.generation);
+            assert_eq!(*snapshot.phrases, report.phrases);
+            for entry in report.files.iter().flat_map(|file| &file.entries) {
+                let phrase = &report.phrases[entry.instance.phrase_index];
+                assert_eq!(PhraseRef::new(phrase), entry.phrase);
+            }
+        }
+        mutator.join().unwrap();
+        assert_eq!(1 + 1 + 4 * 200, service.state().generation());
+    }
+
+    #[test]
+    fn test_get_context() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("test_files/file.txt").unwrap();
+        let context = service.get_context("test_files/file.txt", 4, 2, 0, 1, None).unwrap();
+        let string = service.get_context("test_files/file.txt", 4, 64, 0, 1, Some(b" ")).unwrap();
+        let untracked = service.get_context("test_files/dir/sub_file_1.txt", 0, 64, 0, 1, None);
+
+        assert_eq!(Text::from_str("m a "), context);
+        assert_eq!(Text::from_str("a"), string);
+        assert!(untracked.is_err());
+    }
+
+    #[test]
+    fn test_follow() {
+        use std::io::Write;
+        let path = std::env::temp_dir().join(format!("text-searcher-follow-{}.txt", std::process::id()));
+        std::fs::write(&path, "the quick brown fox was here before following started. ").unwrap();
+        let service = FinderService::new("persist-file.json");
+        let mut follower = service.follow(&path, &[Phrase::from_strs(&["quick", "fox"])]).unwrap();
+        let append = |text: &str| std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(text.as_bytes()).unwrap();
+        let positions = |follower: &mut super::Follower| -> Vec<usize> {
+            follower.poll().unwrap().instances.iter().map(|instance| instance.file_pos).collect()
+        };
+
+        // Text already there isn't searched, and phrases spanning appends are found once, at their absolute position
+        assert!(positions(&mut follower).is_empty());
+        append("then a quick br");
+        assert!(positions(&mut follower).is_empty());
+        append("own fox came by. ");
+        assert_eq!(vec![62], positions(&mut follower));
+        append("Nothing else happened. ");
+        assert!(positions(&mut follower).is_empty());
+        append("A quick fox. ");
+        assert_eq!(vec![112], positions(&mut follower));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), follower.offset());
+
+        // Truncated files are followed from the start
+        std::fs::write(&path, "quick fox").unwrap();
+        assert_eq!(vec![0], positions(&mut follower));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_follow_rotated() {
+        use std::io::Write;
+        let path = std::env::temp_dir().join(format!("text-searcher-rotated-{}.log", std::process::id()));
+        let rotated_path = path.with_extension("log.1");
+        let append = |path: &PathBuf, text: &str| std::fs::OpenOptions::new().append(true).open(path).unwrap().write_all(text.as_bytes()).unwrap();
+        let positions = |instances: &[text_searcher_rust::PhraseInstance]| -> Vec<usize> {
+            instances.iter().map(|instance| instance.file_pos).collect()
+        };
+        std::fs::write(&path, "started. ").unwrap();
+        let service = FinderService::new("persist-file.json");
+        let mut follower = service.follow(&path, &[Phrase::from_strs(&["quick", "fox"])]).unwrap().scan_rotated(true);
+
+        // Truncated in place, next to a copy from before that isn't searched
+        append(&path, "a quick fox. ");
+        let update = follower.poll().unwrap();
+        assert!(!update.rotated);
+        assert_eq!(vec![11], positions(&update.instances));
+        std::fs::write(&rotated_path, "started. a quick fox. Then a quick fox, and another quick fox").unwrap();
+        std::fs::write(&path, "the quick fox").unwrap();
+        let update = follower.poll().unwrap();
+        assert!(update.rotated);
+        assert!(update.rotated_instances.is_empty());
+        assert_eq!(vec![4], positions(&update.instances));
+
+        // Renamed and recreated, with a match written to the old file just before
+        append(&path, ". Then, after a long while, a quick fox");
+        std::fs::rename(&path, &rotated_path).unwrap();
+        std::fs::write(&path, "quick fox").unwrap();
+        let update = follower.poll().unwrap();
+        assert!(update.rotated);
+        assert_eq!(vec![0], positions(&update.instances));
+        if cfg!(unix) {
+            assert_eq!(vec![43], positions(&update.rotated_instances));
+        }
+        let update = follower.poll().unwrap();
+        assert!(!update.rotated);
+        assert!(update.instances.is_empty());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated_path).unwrap();
+    }
+
+    #[test]
+    fn test_try_add_phrase_cost_budget() {
+        let service = FinderService::new("persist-file.json");
+        service.set_cost_budget(150);
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+
+        // Two tokens at every position of the auto-sized window of 26, at 2 widths, fit. Four in a window of 28 don't.
+        let Err(AddPhraseError::CostExceeded(err)) = service.try_add_phrase(Phrase::from_strs(&["within", "sunken"]), false) else {
+            panic!("Expected the cost to exceed the budget");
+        };
+        assert_eq!(4, err.estimate.token_count);
+        assert_eq!(224, err.estimate.per_byte);
+        assert!(err.to_string().contains("over the budget of 150"));
+        assert_eq!(1, service.state().phrases().count());
+
+        // Unless forced
+        service.try_add_phrase(Phrase::from_strs(&["within", "sunken"]), true).unwrap();
+        assert_eq!(2, service.state().phrases().count());
+    }
+
+    #[test]
+    fn test_export_search() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_file("src/searcher/test_text_2.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        let name = format!("text-searcher-export-{}.jsonl", std::process::id());
+        assert_eq!(ErrorKind::PermissionDenied, service.export_search(&name, None, None).unwrap_err().kind());
+        service.set_export_dir(Some(std::env::temp_dir()));
+
+        // One record per file, in the order they were searched
+        let written = service.export_search(&name, Some(SearchOptions::default()), None).unwrap();
+        let path = std::env::temp_dir().join(&name);
+        let exported = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<text_searcher_rust::FileSearchResult> = exported
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, written);
+        assert_eq!(service.search_all(Some(SearchOptions::default()), None).files, records);
+        assert!(service.export_search("missing-dir/results.jsonl", None, None).is_err());
+
+        // Only paths within the export directory are written to
+        for outside in [path.to_str().unwrap(), "../results.jsonl", "dir/../../results.jsonl", "", "."] {
+            assert_eq!(ErrorKind::InvalidInput, service.export_search(outside, None, None).unwrap_err().kind(), "{}", outside);
+        }
+    }
+
+    #[test]
+    fn test_search_phrase_count_per_file() {
+        let service = FinderService::new("persist-file.json");
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_file("src/searcher/test_text_2.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        service.add_phrase(Phrase::from_strs(&["sum", "my", "count"]));
+
+        // Same counts as a full search
+        let counts = service.search_phrase_count_per_file();
+        let expected: std::collections::HashMap<PathBuf, usize> = service
+            .search_all(None, None)
+            .files
+            .into_iter()
+            .map(|file| (file.path, file.entries.len()))
+            .collect();
+        assert_eq!(expected, counts);
+        assert_eq!(Some(&1), counts.get(&PathBuf::from("src/searcher/test_text_2.txt")));
+
+        // Options a finder can't be built with leave every source out instead of panicking
+        let invalid = SearchOptions { context_size: 30, window_size: 8 };
+        assert!(service.snapshot().count_per_source(Some(invalid), None).is_empty());
+    }
+
+    #[test]
+    fn test_add_phrase_duplicate() {
+        let service = FinderService::new("persist-file.json");
+        assert!(service.add_phrase(Phrase::from_strs(&["famine", "where"])));
+        let generation = service.state().generation();
+        assert!(!service.add_phrase(Phrase::from_strs(&["famine", "where"])));
+        assert!(!service.try_add_phrase(Phrase::from_strs(&["famine", "where"]), false).unwrap());
+        assert_eq!(generation, service.state().generation());
+        assert_eq!(1, service.state().phrases().count());
+    }
+
+    #[test]
+    fn test_name_phrase() {
+        let service = FinderService::new("persist-file.json");
+        let phrase = Phrase::from_strs(&["famine", "where"]);
+        let named = |service: &FinderService| -> Vec<(String, Phrase)> {
+            let mut named: Vec<(String, Phrase)> = service.state().named_phrases().map(|(name, phrase)| (name.clone(), phrase.clone())).collect();
+            named.sort();
+            named
+        };
+
+        // Only tracked phrases can be named
+        assert!(!service.name_phrase("famine", &phrase));
+        service.add_phrase(phrase.clone());
+        assert!(service.name_phrase("famine", &phrase));
+        assert_eq!(vec![("famine".to_owned(), phrase.clone())], named(&service));
+
+        // A name moves to the phrase it's given next
+        let other = Phrase::from_strs(&["within", "sunken", "deep"]);
+        service.add_phrase(other.clone());
+        assert!(service.name_phrase("famine", &other));
+        assert!(service.name_phrase("hunger", &phrase));
+        assert_eq!(vec![("famine".to_owned(), other.clone()), ("hunger".to_owned(), phrase.clone())], named(&service));
+
+        // Removing a phrase forgets its names, and undoing the removal doesn't give them back
+        service.remove_phrase(&phrase);
+        assert_eq!(vec![("famine".to_owned(), other.clone())], named(&service));
+        let id = service.state().operations().operations().last().unwrap().id;
+        service.undo_operation(id).unwrap();
+        assert!(service.state().phrases().any(|restored| *restored == phrase));
+        assert_eq!(vec![("famine".to_owned(), other)], named(&service));
+    }
+
+    #[test]
+    fn test_try_add_phrase_limits() {
+        let service = FinderService::new("persist-file.json");
+        let short = Phrase::from_strs(&["sum", "my", "count"]);
+        assert!(matches!(service.try_add_phrase(short.clone(), false), Err(AddPhraseError::Invalid(_))));
+        service.try_add_phrase(short.with_allow_short_tokens(true), false).unwrap();
+
+        service.set_phrase_limits(text_searcher_rust::PhraseLimits { min_token_len: 1, max_tokens: 2 });
+        assert!(service.try_add_phrase(Phrase::from_strs(&["sum", "my"]), false).is_ok());
+        assert!(!service.try_add_phrase(Phrase::from_strs(&["sum", "my"]), false).unwrap());
+        assert!(service.try_add_phrase(Phrase::from_strs(&["sum", "my", "count"]), false).is_err());
+        assert_eq!(2, service.state().phrases().count());
+    }
+}
@@ -1,11 +1,11 @@
 use std::path::{PathBuf, Path};
 use std::ffi::{OsString};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Read};
 use csv::Writer;
 
 use clap::{arg, Command};
-use text_searcher_rust::{Finder, Phrase, Text};
+use text_searcher_rust::{tally_interpretations, Finder, Phrase, PhraseInstance, Text, TraceEvent};
 use threadpool::ThreadPool;
 use walkdir::WalkDir;
 
@@ -20,6 +20,7 @@ fn main() {
         .arg(arg!(-w --window_size <VALUE> ...))
         .arg(arg!(-e --extension <VALUE> ...).required(false))
         .arg(arg!(-t --threads <VALUE>).required(false).default_value("8"))
+        .arg(arg!(--trace "Writes what was compared at every byte to stderr as JSON lines").required(false))
         .get_matches();
 
     // gets files listed as path buffers
@@ -38,7 +39,7 @@ fn main() {
             .map(|text_str| Text::from_str(text_str))
             .collect::<Vec<Text>>()
         )
-        .map(|text_vec| Phrase(text_vec))
+        .map(Phrase::new)
         .collect();
 
     // Gets optional extension
@@ -68,6 +69,9 @@ fn main() {
         .parse()
         .unwrap();
 
+    // Gets whether to trace
+    let trace = matches.is_present("trace");
+
     // Stores non-directory files that whos extensions are in `extensions`
     let extensions = extensions
         .as_ref()
@@ -75,12 +79,18 @@ fn main() {
     let mut files_recursive: Vec<PathBuf> = Vec::new();
     get_files_recursive(&mut files_recursive, &files, extensions);
 
+    // "-" reads from stdin, before any files are processed
+    if files.iter().any(|file| file.as_os_str() == "-") {
+        let stdin = std::io::stdin();
+        process_reader("-", stdin.lock(), &phrases, context_size, window_size, trace);
+    }
+
     // Processes expanded files
     let pool = ThreadPool::new(threads);
     for file in files_recursive {
         let phrases = phrases.clone();
         pool.execute(move || {
-            process(file, phrases.as_slice(), context_size, window_size).unwrap();
+            process(file, phrases.as_slice(), context_size, window_size, trace).unwrap();
         });
     }
     pool.join()
@@ -94,7 +104,7 @@ fn get_files_recursive(
     src: &[PathBuf],
     extensions: Option<&[OsString]>
 ) {
-    for path in src {
+    for path in src.iter().filter(|path| path.as_os_str() != "-") {
         for entry in WalkDir::new(path) {
             match entry {
                 Ok(entry) => {
@@ -103,7 +113,7 @@ fn get_files_recursive(
                         dest.push(entry.path().into())
                     }
                 }
-                _ => {}
+                Err(err) => eprintln!("Skipping unreadable entry: {}", err)
             }
         }
     }
@@ -128,22 +138,50 @@ fn process(
     path: impl AsRef<Path>,
     phrases: &[Phrase],
     context_size: usize,
-    window_size: usize
+    window_size: usize,
+    trace: bool
 ) -> Result<(), std::io::Error> {
     let path = path.as_ref();
     let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
+    process_reader(&path.display().to_string(), BufReader::new(file), phrases, context_size, window_size, trace);
+    Ok(())
+}
+
+// Searches any reader, writing results as CSV under `name`.
+// Then writes which interpretation most results were found with to stderr, as a hint at the encoding.
+// If tracing, also writes a trace event for every byte to stderr as JSON lines.
+fn process_reader(
+    name: &str,
+    mut reader: impl Read,
+    phrases: &[Phrase],
+    context_size: usize,
+    window_size: usize,
+    trace: bool
+) {
+    let trace_name = name.to_owned();
+    let write_trace = move |event: TraceEvent| {
+        let line = serde_json::json!({ "file": trace_name, "event": event });
+        eprintln!("{}", line);
+    };
     let mut finder = Finder::new(phrases, context_size, window_size, &mut reader);
+    if trace {
+        finder.trace(1, write_trace);
+    }
     let mut next = finder.next();
     let mut writer = Writer::from_writer(std::io::stdout());
     writer.write_record(&[
         "file",
         "phrase",
         "file_pos",
+        "end_pos",
+        "len_bytes",
+        "line",
+        "column",
         "codepoint_diff",
         "bytes_per_character",
         "context"
     ]).unwrap();
+    let mut found: Vec<PhraseInstance> = Vec::new();
     while let Some(group) = next {
         for instance in group.0 {
             let phrase = &phrases[instance.phrase_index];
@@ -151,15 +189,23 @@ fn process(
             let cpd = instance.codepoint_diff;
             let ctx = finder.get_context(cpd, bbc);
             writer.write_record(&[
-                &path.display().to_string(),
+                name,
                 &phrase.to_string(),
-                &finder.bytes_read().to_string(),
+                &instance.file_pos.to_string(),
+                &instance.end_pos.to_string(),
+                &instance.len_bytes().to_string(),
+                &instance.line.map(|line| line.to_string()).unwrap_or_default(),
+                &instance.column.map(|column| column.to_string()).unwrap_or_default(),
                 &cpd.to_string(),
                 &bbc.to_string(),
                 &ctx.to_string()
             ]).unwrap();
+            found.push(instance);
         }
         next = finder.next();
     }
-    Ok(())
+    writer.flush().unwrap();
+    if let Some(dominant) = tally_interpretations(&found, None).first() {
+        eprintln!("{}: {} of {} matches were {}", name, dominant.count, found.len(), dominant.interpretation);
+    }
 }
\ No newline at end of file
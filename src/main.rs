@@ -1,57 +1,190 @@
-use std::path::PathBuf;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use rocket::{launch, routes, get, post, State};
+use rocket::{launch, get, patch, post, put, Build, Rocket, State};
+use rocket::fairing::AdHoc;
 use rocket::http::Status;
 use rocket::serde::json::Json;
+use rocket_okapi::{openapi, openapi_get_routes};
 
-use text_searcher_rust::{Phrase, Text};
+use serde::Deserialize;
+use text_searcher_rust::{CandidateString, Encoding, Endianness, PatchError, PhraseRef, SearchOptions, SearchReport, Text, TraceEvent};
+use text_searcher_rust::dto::{
+    AddedFiles, AddedPhrase, DuplicateFiles, ExportRequest, ExportSummary, FileContent, FilePath, FileSummary, Health, MatchedInstance, OperationEntry,
+    PatchRequest, PatchedFile, PhraseBody, PhraseFingerprint, RemovedFiles, TrackedFile, TrackedFileEncoding
+};
 
-use crate::finder_service::FinderService;
+use crate::finder_service::{
+    decode_path, AddPhraseError, AddReport, FilePage, FinderService, PhraseValidationWarning, WalkLimits
+};
+use crate::scan_history::{Bucket, FileTotal, HistoryRetention, PhraseSeries};
+use crate::operation_log::{Mutation, Operation, UndoError};
+use crate::validation::ValidationStatus;
 
 pub mod finder_service;
+pub mod scan_history;
+pub mod operation_log;
+pub mod validation;
 
+#[openapi]
 #[get("/")]
 fn index() -> &'static str { "Hello, world!" }
 
-#[post("/add-file/<file_name>")]
-fn add_file(file_name: &str, finder_service: &State<FinderService>) -> Result<(), Status> {
-    match finder_service.add_file(file_name) {
-        Ok(_) => {},
-        Err(_) => return Err(Status::NotFound)
+/// Tracks a file, or every file in a directory, returning the files that weren't tracked already and what walking the directory came across.
+/// Directories are walked within the limits the app is configured with. With `dry_run`, reports the same without tracking anything.
+#[openapi]
+#[post("/add-file/<file_name>?<dry_run>")]
+fn add_file(file_name: &str, dry_run: Option<bool>, finder_service: &State<FinderService>) -> Result<Json<AddedFiles>, Status> {
+    let dry_run = dry_run.unwrap_or(false);
+    let report = match dry_run {
+        true => finder_service.files_to_add(file_name),
+        false => finder_service.add_file_reporting(file_name)
+    };
+    let report = report.map_err(|_| Status::NotFound)?;
+    if !dry_run {
+        for err in &report.errors {
+            log::warn!("WalkDir error: {}", err);
+        }
+        persist_finder(finder_service)?;
     }
-    persist_finder(finder_service)
+    Ok(Json(added_files(report)))
 }
 
-#[post("/remove-files/<file_name>")]
-fn remove_files(file_name: &str, finder_service: &State<FinderService>) -> Result<(), Status> {
-    finder_service.remove_files(file_name);
-    persist_finder(finder_service)
+/// Stops tracking a file, or every file in a directory.
+/// Responds with how many files were removed. With `dry_run`, counts the files that would be, without removing them.
+#[openapi]
+#[post("/remove-files/<file_name>?<dry_run>")]
+fn remove_files(file_name: &str, dry_run: Option<bool>, finder_service: &State<FinderService>) -> Result<Json<RemovedFiles>, Status> {
+    if dry_run.unwrap_or(false) {
+        return Ok(Json(RemovedFiles { removed: finder_service.files_to_remove(file_name).len() }));
+    }
+    let removed = finder_service.remove_files(file_name);
+    persist_finder(finder_service)?;
+    Ok(Json(RemovedFiles { removed }))
 }
 
+/// Lists tracked files sorted by path, along with their encodings if set
+#[openapi]
 #[get("/list-files")]
-fn list_files(finder_service: &State<FinderService>) -> Json<Vec<PathBuf>> {
+fn list_files(finder_service: &State<FinderService>) -> Json<Vec<TrackedFile>> {
+    let files = finder_service.list_files_sorted();
     let state = finder_service.state();
-    let files: Vec<PathBuf> = state.files().map(|path| path.to_owned()).collect();
-    Json(files)
+    Json(files.iter().map(|path| tracked_file(path, &state)).collect())
 }
 
-#[post("/add-phrase", data = "<phrase>", format = "json")]
-fn add_phrase(phrase: Json<String>, finder_service: &State<FinderService>) -> Result<(), Status> {
-    let texts: Vec<Text> = phrase.0
-        .split_whitespace()
-        .map(|text_str| Text::from_str(text_str))
-        .collect();
-    finder_service.add_phrase(Phrase(texts));
+/// Lists tracked files that no longer exist on disk
+#[openapi]
+#[get("/files-not-found")]
+fn files_not_found(finder_service: &State<FinderService>) -> Json<Vec<TrackedFile>> {
+    let missing = finder_service.files_not_found();
+    let state = finder_service.state();
+    Json(missing.iter().map(|path| tracked_file(path, &state)).collect())
+}
+
+/// Lists groups of tracked files with the same contents, hashing files that haven't been, or that changed since they were.
+/// With `dedup_content` configured, scans only read the first file of each group among files with the same encoding.
+#[openapi]
+#[get("/duplicates")]
+fn duplicates(finder_service: &State<FinderService>) -> Json<Vec<DuplicateFiles>> {
+    let groups = finder_service.duplicate_groups();
+    Json(groups
+        .into_iter()
+        .map(|group| DuplicateFiles {
+            hash: group.hash,
+            len: group.len,
+            files: group.files.iter().map(|file| FilePath::new(file)).collect()
+        })
+        .collect())
+}
+
+/// Stops tracking files that no longer exist on disk
+#[openapi]
+#[post("/prune-missing-files")]
+fn prune_missing_files(finder_service: &State<FinderService>) -> Result<Json<RemovedFiles>, Status> {
+    let removed = finder_service.prune_missing_files().len();
+    persist_finder(finder_service)?;
+    Ok(Json(RemovedFiles { removed }))
+}
+
+/// Lists logged operations, oldest first, along with the files or phrases they removed or restored.
+/// Removing files and phrases is logged, as are undos.
+#[openapi]
+#[get("/operations")]
+fn list_operations(finder_service: &State<FinderService>) -> Json<Vec<OperationEntry>> {
+    Json(finder_service.state().operations().operations().map(operation_entry).collect())
+}
+
+/// Undoes a logged operation, putting back what it removed or removing what it restored, and returns the undo, which is logged too.
+/// Refused with 404 if the operation isn't logged, or 409 with a message if it was already undone or later changes conflict with it.
+#[openapi]
+#[post("/operations/<id>/undo")]
+fn undo_operation(id: u64, finder_service: &State<FinderService>) -> Result<Json<OperationEntry>, (Status, String)> {
+    let undo_id = match finder_service.undo_operation(id) {
+        Ok(undo_id) => undo_id,
+        Err(err @ UndoError::NotFound(_)) => return Err((Status::NotFound, err.to_string())),
+        Err(err) => return Err((Status::Conflict, err.to_string()))
+    };
+    persist_finder(finder_service).map_err(|status| (status, "Failed to persist".to_owned()))?;
+    let state = finder_service.state();
+    let undo = state.operations().get(undo_id).ok_or((Status::InternalServerError, "Undo not logged".to_owned()))?;
+    Ok(Json(operation_entry(undo)))
+}
+
+/// Sets the encoding a tracked file is searched with
+#[openapi]
+#[patch("/files", data = "<file>", format = "json")]
+fn set_file_encoding(file: Json<TrackedFileEncoding>, finder_service: &State<FinderService>) -> Result<(), Status> {
+    let TrackedFileEncoding { path, encoding } = file.0;
+    match finder_service.set_file_encoding(path, encoding) {
+        Ok(_) => {},
+        Err(err) if err.kind() == ErrorKind::NotFound => return Err(Status::NotFound),
+        Err(_) => return Err(Status::BadRequest)
+    }
     persist_finder(finder_service)
 }
 
+/// Adds a phrase to search for. Tokens are separated by whitespace.
+/// Matches can be excluded when any of the `not` tokens are nearby, or required to follow an `anchor`.
+/// Tokens shorter than 3 characters are refused with 400 unless `allow_short_tokens` is set, as are unsupported `widths`.
+/// Refused with 422 if the phrase is empty or only whitespace, or if the phrases would be too costly to search for, unless `force` is true.
+/// Refusals come with a message saying why.
+/// With `name`, the phrase is given that name, even if it was already there. Names are forgotten when the phrase is removed.
+/// Responds with 201 and the phrase's id if it was added, or 200 if it was already there.
+#[openapi]
+#[post("/add-phrase?<force>&<name>", data = "<phrase>", format = "json")]
+fn add_phrase(
+    phrase: Json<PhraseBody>,
+    force: Option<bool>,
+    name: Option<&str>,
+    finder_service: &State<FinderService>
+) -> Result<(Status, Json<AddedPhrase>), (Status, String)> {
+    let phrase = phrase.0.into_phrase().map_err(|err| (Status::UnprocessableEntity, err.to_string()))?;
+    let id = phrase.id();
+    let added = match finder_service.try_add_phrase(phrase.clone(), force.unwrap_or(false)) {
+        Ok(added) => added,
+        Err(AddPhraseError::Invalid(err)) => return Err((Status::BadRequest, err.to_string())),
+        Err(err @ AddPhraseError::CostExceeded(_)) => {
+            log::warn!("Refused phrase. {}", err);
+            return Err((Status::UnprocessableEntity, err.to_string()));
+        }
+    };
+    let named = name.is_some_and(|name| finder_service.name_phrase(name, &phrase));
+    if added || named {
+        persist_finder(finder_service).map_err(|status| (status, "Failed to persist".to_owned()))?;
+    }
+    match added {
+        true => Ok((Status::Created, Json(AddedPhrase { id, duplicate: false }))),
+        false => Ok((Status::Ok, Json(AddedPhrase { id, duplicate: true })))
+    }
+}
+
+/// Removes a phrase. Returns true if it was present. Refused with 422 if the phrase is empty or only whitespace.
+#[openapi]
 #[post("/remove-phrase", data = "<phrase>", format = "json")]
-fn remove_phrase(phrase: Json<String>, finder_service: &State<FinderService>) -> Result<Json<bool>, Status> {
-    let texts: Vec<Text> = phrase.0
-        .split_whitespace()
-        .map(|text_str| Text::from_str(text_str))
-        .collect();
-    if finder_service.remove_phrase(&Phrase(texts)) {
+fn remove_phrase(phrase: Json<PhraseBody>, finder_service: &State<FinderService>) -> Result<Json<bool>, Status> {
+    let phrase = phrase.0.into_phrase().map_err(|_| Status::UnprocessableEntity)?;
+    if finder_service.remove_phrase(&phrase) {
         persist_finder(finder_service)?;
         Ok(Json(true))
     }
@@ -60,6 +193,8 @@ fn remove_phrase(phrase: Json<String>, finder_service: &State<FinderService>) ->
     }
 }
 
+/// Lists phrases
+#[openapi]
 #[get("/list-phrases")]
 fn list_phrases(finder_service: &State<FinderService>) -> Json<Vec<String>> {
     let state = finder_service.state();
@@ -70,6 +205,489 @@ fn list_phrases(finder_service: &State<FinderService>) -> Json<Vec<String>> {
     Json(phrases)
 }
 
+/// Fingerprint of the tracked phrases, which changes whenever they do.
+/// Clients caching the phrases can compare it instead of fetching them all again.
+#[openapi]
+#[get("/phrase-fingerprint")]
+fn phrase_fingerprint(finder_service: &State<FinderService>) -> Json<PhraseFingerprint> {
+    Json(PhraseFingerprint::new(finder_service.state().phrase_hashes()))
+}
+
+/// Searches a tracked file for a single phrase. Refused with 422 if the phrase is empty or only whitespace.
+#[openapi]
+#[post("/search-file/<file_name>", data = "<phrase>", format = "json")]
+fn search_file(file_name: &str, phrase: Json<PhraseBody>, finder_service: &State<FinderService>) -> Result<Json<Vec<MatchedInstance>>, Status> {
+    if !finder_service.state().contains_file(file_name) {
+        return Err(Status::NotFound);
+    }
+    let phrase = phrase.0.into_phrase().map_err(|_| Status::UnprocessableEntity)?;
+    match finder_service.search_phrase_in_file(&phrase, file_name) {
+        Ok(instances) => {
+            let instances = instances
+                .into_iter()
+                .map(|instance| MatchedInstance::new(instance, &phrase))
+                .collect();
+            Ok(Json(instances))
+        },
+        Err(_) => Err(Status::InternalServerError)
+    }
+}
+
+/// Searches all tracked files for all phrases. Sizes are picked from the phrases if neither is given.
+/// Files with an encoding set are searched with it. Others use `bpc` and `endianness`, or try every width if `bpc` isn't given.
+/// With `min_printability`, from 0 to 1, entries found in less printable context, such as binary noise, are left out.
+/// Entries past `max_report_bytes` of memory, or the configured cap if it's lower, are dropped, counted and the report marked `truncated`.
+/// 503 if the search is cancelled. See /cancel-scans.
+#[openapi]
+#[get("/search?<context_size>&<window_size>&<sort>&<bpc>&<endianness>&<min_printability>&<max_report_bytes>")]
+#[allow(clippy::too_many_arguments)]
+fn search(
+    context_size: Option<usize>,
+    window_size: Option<usize>,
+    sort: Option<&str>,
+    bpc: Option<u32>,
+    endianness: Option<&str>,
+    min_printability: Option<f32>,
+    max_report_bytes: Option<usize>,
+    finder_service: &State<FinderService>
+) -> Result<Json<SearchReport>, Status> {
+    let defaults = SearchOptions::default();
+    let options = match (context_size, window_size) {
+        (None, None) => None,
+        (context_size, window_size) => Some(SearchOptions {
+            context_size: context_size.unwrap_or(defaults.context_size),
+            window_size: window_size.unwrap_or(defaults.window_size)
+        })
+    };
+    if options.is_some_and(|options| !options.is_valid()) {
+        return Err(Status::BadRequest);
+    }
+    let endianness = match endianness {
+        None | Some("little") => Endianness::Little,
+        Some("big") => Endianness::Big,
+        Some(_) => return Err(Status::BadRequest)
+    };
+    let encoding = match bpc {
+        None => None,
+        Some(bytes_per_character @ (1 | 2)) => Some(Encoding { bytes_per_character, endianness }),
+        Some(_) => return Err(Status::BadRequest)
+    };
+    let min_printability = check_printability(min_printability)?;
+    let report = finder_service.search_all_within(options, encoding, max_report_bytes).min_printability(min_printability);
+    if report.cancelled {
+        return Err(Status::ServiceUnavailable);
+    }
+    match sort {
+        None => Ok(Json(report)),
+        Some("score") => Ok(Json(report.ranked())),
+        Some(_) => Err(Status::BadRequest)
+    }
+}
+
+/// Searches all tracked files for all phrases with sizes picked from the phrases,
+/// only returning how many matches each file had and which phrases they were of. `min_printability` is the same as for /search.
+/// 503 if the search is cancelled.
+#[openapi]
+#[get("/search-summary?<min_printability>")]
+fn search_summary(min_printability: Option<f32>, finder_service: &State<FinderService>) -> Result<Json<Vec<FileSummary>>, Status> {
+    let min_printability = check_printability(min_printability)?;
+    let report = finder_service.search_all(None, None).min_printability(min_printability);
+    if report.cancelled {
+        return Err(Status::ServiceUnavailable);
+    }
+    let summaries = report.files
+        .into_iter()
+        .map(|file| {
+            let mut phrases_found: Vec<String> = file.entries
+                .iter()
+                .map(|entry| entry.phrase.text.clone())
+                .collect();
+            phrases_found.sort();
+            phrases_found.dedup();
+            FileSummary {
+                path: file.path,
+                match_count: file.entries.len(),
+                phrases_found
+            }
+        })
+        .collect();
+    Ok(Json(summaries))
+}
+
+// Minimum printability searches keep entries at, refused with 400 if it's not from 0 to 1
+fn check_printability(min_printability: Option<f32>) -> Result<f32, Status> {
+    match min_printability {
+        None => Ok(0.0),
+        Some(min) if (0.0..=1.0).contains(&min) => Ok(min),
+        Some(_) => Err(Status::BadRequest)
+    }
+}
+
+/// Searches all tracked files for all phrases with sizes picked from the phrases,
+/// writing each file's results as a line of JSON to `output_path`, relative to the configured `export_dir` on the server.
+/// 403 if no export directory is configured, and 400 if `output_path` is absolute or has a `..` component.
+/// 503 if the search is cancelled, in which case nothing is written.
+#[openapi]
+#[post("/search-and-export", data = "<export>", format = "json")]
+fn search_and_export(export: Json<ExportRequest>, finder_service: &State<FinderService>) -> Result<Json<ExportSummary>, Status> {
+    let output_path = export.0.output_path;
+    let exported = finder_service.export_search(&output_path, None, None);
+    persist_history(finder_service);
+    match exported {
+        Ok(written_records) => Ok(Json(ExportSummary { written_records, output_path })),
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => Err(Status::Forbidden),
+        Err(err) if err.kind() == ErrorKind::InvalidInput => Err(Status::BadRequest),
+        Err(err) if err.kind() == ErrorKind::Interrupted => Err(Status::ServiceUnavailable),
+        Err(err) => {
+            log::error!("Failed to export to '{}': {:?}", output_path.display(), err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Lists tracked phrases too long to ever be found with the sizes given.
+/// If neither size is given, checks the sizes a search without them would use.
+#[openapi]
+#[get("/validate-config?<context_size>&<window_size>")]
+fn validate_config(
+    context_size: Option<usize>,
+    window_size: Option<usize>,
+    finder_service: &State<FinderService>
+) -> Json<Vec<PhraseValidationWarning>> {
+    let defaults = SearchOptions::default();
+    let options = match (context_size, window_size) {
+        (None, None) => finder_service.snapshot().resolve_options(None),
+        (context_size, window_size) => SearchOptions {
+            context_size: context_size.unwrap_or(defaults.context_size),
+            window_size: window_size.unwrap_or(defaults.window_size)
+        }
+    };
+    Json(finder_service.validate_search_config(options.context_size, options.window_size))
+}
+
+/// Lists phrases with codepoints that aren't valid Unicode, which can't be displayed or persisted as they are
+#[openapi]
+#[get("/validate-phrases")]
+fn validate_phrases(finder_service: &State<FinderService>) -> Json<Vec<PhraseRef>> {
+    let state = finder_service.state();
+    let mut invalid: Vec<PhraseRef> = state
+        .phrases()
+        .filter(|phrase| !phrase.is_valid_unicode())
+        .map(PhraseRef::new)
+        .collect();
+    invalid.sort_by(|a, b| a.text.cmp(&b.text));
+    Json(invalid)
+}
+
+/// Starts checking that tracked files exist and can be read, that phrases pass validation, and that the persist file can be written.
+/// The check runs in the background, as it does at startup. Missing files are pruned and invalid phrases dropped if the app
+/// is configured with `validation_auto_fix`. Responds with 202, or 409 if a check is in progress already.
+#[openapi]
+#[post("/validate")]
+fn start_validation(finder_service: &State<FinderService>) -> (Status, Json<ValidationStatus>) {
+    let status = match finder_service.start_validation() {
+        Some(_) => Status::Accepted,
+        None => Status::Conflict
+    };
+    (status, Json(finder_service.validation_status()))
+}
+
+/// Whether a check of the loaded state is in progress, or what the latest one found. See /validate.
+#[openapi]
+#[get("/validate/report")]
+fn validation_report(finder_service: &State<FinderService>) -> Json<ValidationStatus> {
+    Json(finder_service.validation_status())
+}
+
+/// Reads the text around a position in a tracked file, or the string it lives in if terminators are given
+#[openapi]
+#[get("/context/<file_name>?<pos>&<diff>&<bpc>&<max_len>&<terminator>")]
+fn context(
+    file_name: &str,
+    pos: u64,
+    diff: Option<i32>,
+    bpc: Option<u32>,
+    max_len: Option<usize>,
+    terminator: Vec<&str>,
+    finder_service: &State<FinderService>
+) -> Result<Json<String>, Status> {
+    let bpc = bpc.unwrap_or(1);
+    if bpc != 1 && bpc != 2 {
+        return Err(Status::BadRequest);
+    }
+    let terminators: Vec<u8> = terminator
+        .iter()
+        .map(|term| parse_byte(term))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or(Status::BadRequest)?;
+    let terminators = if terminators.is_empty() { None } else { Some(terminators.as_slice()) };
+    let text = finder_service.get_context(
+        file_name,
+        pos,
+        max_len.unwrap_or(64),
+        diff.unwrap_or(0),
+        bpc,
+        terminators
+    );
+    match text {
+        Ok(text) => Ok(Json(text.to_string())),
+        Err(err) if err.kind() == ErrorKind::NotFound => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError)
+    }
+}
+
+/// Reads a page of a tracked file decoded as text, for browsing it a page at a time.
+/// Reads `length` bytes from `offset`, 4096 by default and at most 64 KiB, leaving off a partial character at the end.
+/// Characters are `bpc` bytes wide, 1 by default, with `diff` subtracted from each.
+#[openapi]
+#[get("/files/content?<path>&<offset>&<length>&<diff>&<bpc>")]
+fn file_content(
+    path: &str,
+    offset: Option<u64>,
+    length: Option<usize>,
+    diff: Option<i32>,
+    bpc: Option<u32>,
+    finder_service: &State<FinderService>
+) -> Result<Json<FileContent>, Status> {
+    let bpc = bpc.unwrap_or(1);
+    if bpc != 1 && bpc != 2 {
+        return Err(Status::BadRequest);
+    }
+    let page = finder_service.read_page(path, offset.unwrap_or(0), length.unwrap_or(4096), diff.unwrap_or(0), bpc);
+    match page {
+        Ok(page) => Ok(Json(file_content_of(page))),
+        Err(err) if err.kind() == ErrorKind::NotFound => Err(Status::NotFound),
+        Err(err) if err.kind() == ErrorKind::InvalidInput => Err(Status::BadRequest),
+        Err(_) => Err(Status::InternalServerError)
+    }
+}
+
+/// Suggests phrases to search for in a tracked file whose encoding isn't known, from runs of at least `min_len` characters
+/// (4 by default) that read like text under some diff, 1 or 2 bytes wide. Returns the `limit` with the most letters, 20 by default
+/// and at most 1000, with where they are and how they were decoded. 404 if the file isn't tracked.
+#[openapi]
+#[get("/analyze/strings?<file>&<min_len>&<limit>")]
+fn analyze_strings(
+    file: &str,
+    min_len: Option<usize>,
+    limit: Option<usize>,
+    finder_service: &State<FinderService>
+) -> Result<Json<Vec<CandidateString>>, (Status, String)> {
+    let (min_len, limit) = (min_len.unwrap_or(4), limit.unwrap_or(20));
+    if min_len == 0 || limit > 1000 {
+        return Err((Status::BadRequest, "min_len must be at least 1, and limit at most 1000".to_owned()));
+    }
+    match finder_service.analyze_strings(file, min_len, limit) {
+        Ok(candidates) => Ok(Json(candidates)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Err((Status::NotFound, err.to_string())),
+        Err(err) => Err((Status::InternalServerError, err.to_string()))
+    }
+}
+
+/// Traces a search of the start of a tracked file for every phrase, describing the window every `every` bytes (64 by default)
+/// and why each phrase didn't match it. Returns the last `capacity` events, 256 by default and at most 4096, to debug missing matches.
+/// Searches with the sizes given, or those searches use by default. 404 if the file isn't tracked, and 400 for invalid sizes.
+#[openapi]
+#[get("/trace?<file>&<every>&<capacity>&<context_size>&<window_size>")]
+fn trace_file(
+    file: &str,
+    every: Option<usize>,
+    capacity: Option<usize>,
+    context_size: Option<usize>,
+    window_size: Option<usize>,
+    finder_service: &State<FinderService>
+) -> Result<Json<Vec<TraceEvent>>, (Status, String)> {
+    let (every, capacity) = (every.unwrap_or(64), capacity.unwrap_or(256));
+    if every == 0 || capacity > 4096 {
+        return Err((Status::BadRequest, "every must be at least 1, and capacity at most 4096".to_owned()));
+    }
+    let defaults = SearchOptions::default();
+    let options = match (context_size, window_size) {
+        (None, None) => None,
+        (context_size, window_size) => Some(SearchOptions {
+            context_size: context_size.unwrap_or(defaults.context_size),
+            window_size: window_size.unwrap_or(defaults.window_size)
+        })
+    };
+    match finder_service.trace_file(file, options, every, capacity) {
+        Ok(events) => Ok(Json(events)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Err((Status::NotFound, err.to_string())),
+        Err(err) if err.kind() == ErrorKind::InvalidInput => Err((Status::BadRequest, err.to_string())),
+        Err(err) => Err((Status::InternalServerError, err.to_string()))
+    }
+}
+
+/// Writes text over a tracked file at `pos`, encoded under `diff` at `bpc` bytes per character and followed by the `terminator` if given.
+/// With `pad_to`, the text must fit in that many bytes, and the rest are filled with the terminator, or zeros without one.
+/// Returns the bytes overwritten so the patch can be undone. Refused with 403 unless the app is configured with `allow_writes`,
+/// 404 if the file isn't tracked, and 422 with a message if the text can't be encoded or doesn't fit.
+#[openapi]
+#[post("/patch", data = "<patch>", format = "json")]
+fn patch_file(patch: Json<PatchRequest>, finder_service: &State<FinderService>) -> Result<Json<PatchedFile>, (Status, String)> {
+    let PatchRequest { path, pos, text, diff, bpc, pad_to, terminator } = patch.0;
+    let report = finder_service.patch_file(&path, pos, &Text::from_str(&text), diff, bpc, pad_to, terminator);
+    match report {
+        Ok(report) => Ok(Json(PatchedFile::from(&report))),
+        Err(PatchError::Io(err)) => match err.kind() {
+            ErrorKind::PermissionDenied => Err((Status::Forbidden, err.to_string())),
+            ErrorKind::NotFound => Err((Status::NotFound, err.to_string())),
+            _ => Err((Status::InternalServerError, err.to_string()))
+        },
+        Err(err) => Err((Status::UnprocessableEntity, err.to_string()))
+    }
+}
+
+// Files tracked by /add-file, and what walking a directory for them came across
+fn added_files(report: AddReport) -> AddedFiles {
+    AddedFiles {
+        added: report.added.iter().map(|path| FilePath::new(path)).collect(),
+        dirs_visited: report.dirs_visited,
+        skipped: report.skipped,
+        truncated: report.truncated,
+        errors: report.errors.iter().map(|err| err.to_string()).collect()
+    }
+}
+
+// Page read by /files/content
+fn file_content_of(page: FilePage) -> FileContent {
+    FileContent {
+        text: page.text.to_string(),
+        offset: page.offset,
+        length: page.len_bytes,
+        next_offset: page.next_offset(),
+        total_size: page.total_size
+    }
+}
+
+// A tracked file, as listed, along with its encoding if set
+fn tracked_file(path: &Path, state: &finder_service::State) -> TrackedFile {
+    TrackedFile::new(path, state.file_encoding(path).cloned())
+}
+
+// A logged operation, as listed, along with the files or phrases it removed or restored
+fn operation_entry(operation: &Operation) -> OperationEntry {
+    let (files, phrases) = match &operation.mutation {
+        Mutation::RemoveFiles { files, .. } | Mutation::RestoreFiles { files, .. } => {
+            let files = files
+                .iter()
+                .map(|encoded| FilePath::new(&decode_path(encoded).unwrap_or_else(|_| PathBuf::from(encoded))))
+                .collect();
+            (files, Vec::new())
+        },
+        Mutation::RemovePhrase { phrase } | Mutation::RestorePhrase { phrase } => (Vec::new(), vec![PhraseRef::new(phrase)])
+    };
+    OperationEntry {
+        id: operation.id,
+        timestamp: operation.timestamp,
+        kind: operation.mutation.kind().to_owned(),
+        files,
+        phrases,
+        undoes: operation.undoes,
+        undone_by: operation.undone_by
+    }
+}
+
+// Helper function that parses a byte written as hex ("0x0a") or decimal ("10")
+fn parse_byte(str: &str) -> Option<u8> {
+    match str.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => str.parse().ok()
+    }
+}
+
+/// Instances found of each phrase by past searches, summed per `bucket` ("hour", "day" or "week", by default "day").
+/// `since` and `until` are seconds since the Unix epoch, and limit the searches to those that ran at or after `since`, and before `until`.
+#[openapi]
+#[get("/stats/phrases?<since>&<until>&<bucket>")]
+fn stats_phrases(
+    since: Option<u64>,
+    until: Option<u64>,
+    bucket: Option<&str>,
+    finder_service: &State<FinderService>
+) -> Result<Json<Vec<PhraseSeries>>, Status> {
+    let bucket = match bucket {
+        None => Bucket::Day,
+        Some(bucket) => Bucket::parse(bucket).ok_or(Status::BadRequest)?
+    };
+    Ok(Json(finder_service.state().history().phrase_series(since, until, bucket)))
+}
+
+/// Instances found in each file by past searches. `since` and `until` are as for `/stats/phrases`.
+#[openapi]
+#[get("/stats/files?<since>&<until>")]
+fn stats_files(since: Option<u64>, until: Option<u64>, finder_service: &State<FinderService>) -> Json<Vec<FileTotal>> {
+    Json(finder_service.state().history().file_totals(since, until))
+}
+
+/// How long summaries of past searches are kept, and how many before they're compacted
+#[openapi]
+#[get("/stats/retention")]
+fn stats_retention(finder_service: &State<FinderService>) -> Json<HistoryRetention> {
+    Json(finder_service.state().history().retention())
+}
+
+/// Sets how long summaries of past searches are kept, compacting them to fit, then persists
+#[openapi]
+#[post("/stats/retention", data = "<retention>", format = "json")]
+fn set_stats_retention(retention: Json<HistoryRetention>, finder_service: &State<FinderService>) -> Result<(), Status> {
+    finder_service.set_history_retention(retention.0);
+    persist_finder(finder_service)
+}
+
+/// Options searches use when they don't specify any. Null if they're sized from the phrases.
+#[openapi]
+#[get("/config")]
+fn search_config(finder_service: &State<FinderService>) -> Json<Option<SearchOptions>> {
+    Json(finder_service.state().search_config().copied())
+}
+
+/// Sets the options searches use when they don't specify any, then persists. Null sizes them from the phrases instead.
+/// Refused with 400 if the options are invalid.
+#[openapi]
+#[put("/config", data = "<config>", format = "json")]
+fn set_search_config(config: Json<Option<SearchOptions>>, finder_service: &State<FinderService>) -> Result<(), Status> {
+    finder_service.set_search_config(config.0).map_err(|_| Status::BadRequest)?;
+    persist_finder(finder_service)
+}
+
+/// Cancels every search running now. They respond with 503 rather than partial results. Searches started afterwards run as usual.
+#[openapi]
+#[post("/cancel-scans")]
+fn cancel_scans(finder_service: &State<FinderService>) {
+    finder_service.cancel_scans();
+}
+
+/// Reloads files and phrases from the persist file, picking up changes made to it by hand
+#[openapi]
+#[post("/reload-persist")]
+fn reload_persist(finder_service: &State<FinderService>) -> Result<(), Status> {
+    match finder_service.reload() {
+        Ok(_) => Ok(()),
+        Err(err) => {
+            log::error!("Failed to reload persist file: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Reports that the service is up, how long for, what it tracks and how often scans reused prepared phrases.
+/// Responds with 503 if the service's state is unusable, after a panic while it was locked.
+#[openapi]
+#[get("/health")]
+fn health(finder_service: &State<FinderService>) -> Result<Json<Health>, Status> {
+    let (file_count, phrase_count) = {
+        let state = finder_service.try_state().ok_or(Status::ServiceUnavailable)?;
+        (state.files().count(), state.phrases().count())
+    };
+    Ok(Json(Health {
+        status: "ok".to_owned(),
+        uptime_secs: finder_service.uptime().as_secs_f64(),
+        persist_file_bytes: finder_service.persist_file_size(),
+        file_count,
+        phrase_count,
+        phrase_cache: finder_service.phrase_cache_stats()
+    }))
+}
 
 //  Helper function that persists the finder service
 fn persist_finder(finder_service: &State<FinderService>) -> Result<(), Status> {
@@ -79,18 +697,901 @@ fn persist_finder(finder_service: &State<FinderService>) -> Result<(), Status> {
     }
 }
 
+//  Helper function that persists the summary a search added to the history. Failing to is logged, since the search itself succeeded.
+fn persist_history(finder_service: &State<FinderService>) {
+    if let Err(err) = finder_service.persist_history() {
+        log::warn!("Failed to persist search history: {:?}", err);
+    }
+}
+
+/// What the app is built with. Read from Rocket's config, so `persist_file` can be set in Rocket.toml or with `ROCKET_PERSIST_FILE`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    pub persist_file: PathBuf,                  // Where files and phrases are persisted to, and loaded from at startup
+    pub walk_limits: WalkLimits,                // Limits on walking directories added with /add-file
+    pub search_config: Option<SearchOptions>,   // Options searches use when they don't specify any, replacing those persisted
+    pub allow_writes: bool,                     // Whether tracked files can be written to with /patch
+    pub validation_auto_fix: bool,              // Whether validating the state prunes missing files and drops invalid phrases
+    pub intra_file_parallelism: Option<usize>,  // Chunks each file is searched in parallel in, or picked from its size if 0. One pass if not set.
+    pub max_bytes_per_second: Option<u64>,      // Most bytes per second scans read, so they don't saturate the disk. No limit if not set.
+    pub dedup_content: bool,                    // Whether scans read only one of each group of identical files. See /duplicates.
+    pub max_report_bytes: Option<usize>,        // Most bytes of memory a search's results take up, roughly. No limit if not set.
+    pub export_dir: Option<PathBuf>,            // Directory /search-and-export writes under. Exports are refused if not set.
+    pub history_flush_secs: u64                 // How often summaries of searches are persisted, since GET /search doesn't write them itself
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            persist_file: PathBuf::from("persist.json"),
+            walk_limits: WalkLimits::default(),
+            search_config: None,
+            allow_writes: false,
+            validation_auto_fix: false,
+            intra_file_parallelism: None,
+            max_bytes_per_second: None,
+            dedup_content: false,
+            max_report_bytes: None,
+            export_dir: None,
+            history_flush_secs: 60
+        }
+    }
+}
+
 #[launch]
 fn rocket() -> _ {
     env_logger::init();
+    let config: AppConfig = match rocket::Config::figment().extract() {
+        Ok(config) => config,
+        Err(err) => panic!("Invalid app config: {}", err)
+    };
+    build_app(config)
+}
+
+/// Builds the app with its routes mounted and its service loaded from the configured persist file.
+/// The loaded state is validated in the background. See /validate. Summaries of searches are persisted in the background too,
+/// every `history_flush_secs`. Panics if the configured search options are invalid.
+pub fn build_app(config: AppConfig) -> Rocket<Build> {
+    let finder_service = match config.search_config {
+        Some(search_config) => match FinderService::new_with_config(config.persist_file, search_config) {
+            Ok(finder_service) => finder_service,
+            Err(err) => panic!("Invalid app config: {}", err)
+        },
+        None => FinderService::new(config.persist_file)
+    };
+    finder_service.set_walk_limits(config.walk_limits);
+    finder_service.set_allow_writes(config.allow_writes);
+    finder_service.set_validation_auto_fix(config.validation_auto_fix);
+    finder_service.set_intra_file_parallelism(config.intra_file_parallelism);
+    finder_service.set_max_bytes_per_second(config.max_bytes_per_second);
+    finder_service.set_dedup_content(config.dedup_content);
+    finder_service.set_max_report_bytes(config.max_report_bytes);
+    finder_service.set_export_dir(config.export_dir);
+    finder_service.start_validation();
+    finder_service.start_history_flush(Duration::from_secs(config.history_flush_secs.max(1)));
+    build_app_with(finder_service)
+}
+
+/// Builds the app around a service that's already set up
+pub fn build_app_with(finder_service: FinderService) -> Rocket<Build> {
     rocket::build()
-        .mount("/", routes![
+        .mount("/", openapi_get_routes![
             index,
             add_file,
             remove_files,
             list_files,
+            files_not_found,
+            duplicates,
+            prune_missing_files,
+            set_file_encoding,
+            list_operations,
+            undo_operation,
             add_phrase,
             remove_phrase,
-            list_phrases
+            list_phrases,
+            phrase_fingerprint,
+            search_file,
+            search,
+            search_summary,
+            search_and_export,
+            validate_config,
+            validate_phrases,
+            start_validation,
+            validation_report,
+            stats_phrases,
+            stats_files,
+            stats_retention,
+            set_stats_retention,
+            search_config,
+            set_search_config,
+            context,
+            file_content,
+            analyze_strings,
+            trace_file,
+            patch_file,
+            reload_persist,
+            cancel_scans,
+            health
         ])
-        .manage(FinderService::new("persist.json"))
-}
\ No newline at end of file
+        .manage(finder_service)
+        .attach(AdHoc::on_shutdown("Cancel scans", |rocket| Box::pin(async move {
+            if let Some(finder_service) = rocket.state::<FinderService>() {
+                finder_service.cancel_scans();
+            }
+        })))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::{Client, LocalRequest};
+    use serde_json::{json, Value};
+    use text_searcher_rust::{Phrase, SearchOptions, Text};
+    use crate::{build_app, build_app_with, AppConfig};
+    use crate::finder_service::{migrate_v1_to_v2, FinderService, State, StateV1};
+
+    // Temp dir holding a file to search and the persist file, unique to the test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("text-searcher-app-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_add_named_phrase() {
+        let dir = temp_dir("add-named-phrase");
+        let client = app_client(&dir);
+        let add = |uri: &str| client.post(uri).header(ContentType::JSON).body(r#""famine where""#).dispatch().status();
+        let named = || -> Value {
+            let persisted: Value = serde_json::from_slice(&fs::read(dir.join("persist.json")).unwrap()).unwrap();
+            persisted["named_phrases"].clone()
+        };
+        assert_eq!(Status::Created, add("/add-phrase?name=famine"));
+        assert_eq!(json!({ "famine": ["famine", "where"] }), named());
+
+        // Naming a phrase that's already there is persisted too
+        assert_eq!(Status::Ok, add("/add-phrase?name=hunger"));
+        assert_eq!(json!({ "famine": ["famine", "where"], "hunger": ["famine", "where"] }), named());
+
+        // Names go with the phrase
+        client.post("/remove-phrase").header(ContentType::JSON).body(r#""famine where""#).dispatch();
+        assert_eq!(json!({}), named());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn app_client(dir: &std::path::Path) -> Client {
+        let config = AppConfig { persist_file: dir.join("persist.json"), ..AppConfig::default() };
+        Client::tracked(build_app(config)).unwrap()
+    }
+
+    // Path segments can't contain slashes unless they're encoded
+    fn encode_path(path: &std::path::Path) -> String {
+        path.display().to_string().replace('/', "%2F")
+    }
+
+    #[test]
+    fn test_app_lifecycle() {
+        let dir = temp_dir("lifecycle");
+        let file = dir.join("haystack.txt");
+        // Padded past the context, so the match isn't found while the context fills up
+        fs::write(&file, format!("{}Making a famine where abundance lies", ".".repeat(100))).unwrap();
+
+        let client = app_client(&dir);
+        let status = |request: LocalRequest| request.dispatch().status();
+        assert_eq!(Status::Ok, status(client.post(format!("/add-file/{}", encode_path(&file)))));
+        assert_eq!(Status::NotFound, status(client.post(format!("/add-file/{}", encode_path(&dir.join("missing.txt"))))));
+
+        // The fingerprint of a single phrase is its id
+        let fingerprint = || -> Value { client.get("/phrase-fingerprint").dispatch().into_json().unwrap() };
+        assert_eq!(json!({ "fingerprint": "0000000000000000" }), fingerprint());
+
+        // Phrases must be sent as JSON
+        assert_eq!(Status::NotFound, status(client.post("/add-phrase").body(r#""famine where""#)));
+        let added = client.post("/add-phrase").header(ContentType::JSON).body(r#""famine where""#).dispatch();
+        assert_eq!(Status::Created, added.status());
+        let id = Phrase::from_strs(&["famine", "where"]).id();
+        assert_eq!(json!({ "id": id, "duplicate": false }), added.into_json::<Value>().unwrap());
+        assert_eq!(json!({ "fingerprint": id }), fingerprint());
+
+        // Adding it again is reported, without persisting
+        let persisted = fs::read(dir.join("persist.json")).unwrap();
+        fs::remove_file(dir.join("persist.json")).unwrap();
+        let duplicate = client.post("/add-phrase").header(ContentType::JSON).body(r#""famine where""#).dispatch();
+        assert_eq!(Status::Ok, duplicate.status());
+        assert_eq!(json!({ "id": id, "duplicate": true }), duplicate.into_json::<Value>().unwrap());
+        assert!(!dir.join("persist.json").exists());
+        fs::write(dir.join("persist.json"), persisted).unwrap();
+        assert_eq!(Status::BadRequest, status(client.post("/add-phrase").header(ContentType::JSON).body(r#""a famine""#)));
+        let empty = client.post("/add-phrase").header(ContentType::JSON).body(r#""   ""#).dispatch();
+        assert_eq!(Status::UnprocessableEntity, empty.status());
+        assert_eq!("Phrase has no tokens", empty.into_string().unwrap());
+
+        let files: Value = client.get("/list-files").dispatch().into_json().unwrap();
+        assert_eq!(json!([{ "path": file, "encoded_path": file, "encoding": null }]), files);
+        let phrases: Value = client.get("/list-phrases").dispatch().into_json().unwrap();
+        assert_eq!(json!(["famine where"]), phrases);
+
+        let report: Value = client.get("/search?context_size=64&window_size=32").dispatch().into_json().unwrap();
+        let entries = report["files"][0]["entries"].as_array().unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!(109, entries[0]["instance"]["file_pos"]);
+        assert_eq!("famine where", entries[0]["phrase"]["text"]);
+        assert_eq!(Status::BadRequest, status(client.get("/search?bpc=3")));
+        assert_eq!(Status::BadRequest, status(client.get("/search?context_size=0&window_size=0")));
+        let summary: Value = client.get("/search-summary").dispatch().into_json().unwrap();
+        assert_eq!(json!([{ "path": file, "match_count": 1, "phrases_found": ["famine where"] }]), summary);
+
+        // Rebuilding against the same persist file picks up where it left off
+        drop(client);
+        let client = app_client(&dir);
+        let phrases: Value = client.get("/list-phrases").dispatch().into_json().unwrap();
+        assert_eq!(json!(["famine where"]), phrases);
+
+        let removed: Value = client.post("/remove-phrase").header(ContentType::JSON).body(r#""famine where""#).dispatch().into_json().unwrap();
+        assert_eq!(json!(true), removed);
+        let removed: Value = client.post(format!("/remove-files/{}", encode_path(&file))).dispatch().into_json().unwrap();
+        assert_eq!(json!({ "removed": 1 }), removed);
+
+        // Files deleted from disk can be found and pruned
+        assert_eq!(Status::Ok, status(client.post(format!("/add-file/{}", encode_path(&file)))));
+        let missing: Value = client.get("/files-not-found").dispatch().into_json().unwrap();
+        assert_eq!(json!([]), missing);
+        fs::remove_file(&file).unwrap();
+        let missing: Value = client.get("/files-not-found").dispatch().into_json().unwrap();
+        assert_eq!(json!([{ "path": file, "encoded_path": file, "encoding": null }]), missing);
+        let removed: Value = client.post("/prune-missing-files").dispatch().into_json().unwrap();
+        assert_eq!(json!({ "removed": 1 }), removed);
+
+        drop(client);
+        let client = app_client(&dir);
+        let files: Value = client.get("/list-files").dispatch().into_json().unwrap();
+        assert_eq!(json!([]), files);
+        let phrases: Value = client.get("/list-phrases").dispatch().into_json().unwrap();
+        assert_eq!(json!([]), phrases);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_distinct_phrases_listed() {
+        let dir = temp_dir("distinct-phrases");
+        let file = dir.join("haystack.txt");
+        fs::write(&file, format!("famine where{}", ".".repeat(100))).unwrap();
+        let client = app_client(&dir);
+        client.post(format!("/add-file/{}", encode_path(&file))).dispatch();
+        for body in [json!("famine where"), json!({ "phrase": "famine where", "not": ["lies"] }), json!({ "phrase": "famine where", "anchor": "line_start" })] {
+            let added = client.post("/add-phrase").header(ContentType::JSON).body(body.to_string()).dispatch();
+            assert_eq!(Status::Created, added.status());
+        }
+
+        // Phrases that only differ in their exclusions or anchor are listed and summarized apart
+        let expected = json!(["famine where", "famine where (at line start)", "famine where (not lies)"]);
+        let mut phrases: Vec<String> = client.get("/list-phrases").dispatch().into_json().unwrap();
+        phrases.sort();
+        assert_eq!(expected, json!(phrases));
+        let summary: Value = client.get("/search-summary").dispatch().into_json().unwrap();
+        assert_eq!(expected, summary[0]["phrases_found"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_apps_dont_interfere() {
+        let dir_a = temp_dir("instance-a");
+        let dir_b = temp_dir("instance-b");
+        let client_a = app_client(&dir_a);
+        let client_b = app_client(&dir_b);
+
+        let status = |request: LocalRequest| request.dispatch().status();
+        assert_eq!(Status::Created, status(client_a.post("/add-phrase").header(ContentType::JSON).body(r#""famine where""#)));
+        assert_eq!(Status::Created, status(client_b.post("/add-phrase").header(ContentType::JSON).body(r#""within sunken deep""#)));
+
+        let phrases_a: Value = client_a.get("/list-phrases").dispatch().into_json().unwrap();
+        let phrases_b: Value = client_b.get("/list-phrases").dispatch().into_json().unwrap();
+        assert_eq!(json!(["famine where"]), phrases_a);
+        assert_eq!(json!(["within sunken deep"]), phrases_b);
+        assert!(fs::read_to_string(dir_a.join("persist.json")).unwrap().contains("famine"));
+        assert!(!fs::read_to_string(dir_b.join("persist.json")).unwrap().contains("famine"));
+        fs::remove_dir_all(&dir_a).unwrap();
+        fs::remove_dir_all(&dir_b).unwrap();
+    }
+
+    #[test]
+    fn test_app_with_loaded_state() {
+        let dir = temp_dir("loaded-state");
+        let state = migrate_v1_to_v2(StateV1 {
+            phrases: [Phrase::from_strs(&["famine", "where"])].into(),
+            ..StateV1::default()
+        });
+        let client = Client::tracked(build_app_with(FinderService::with_state(dir.join("persist.json"), state))).unwrap();
+        let phrases: Value = client.get("/list-phrases").dispatch().into_json().unwrap();
+        assert_eq!(json!(["famine where"]), phrases);
+        let invalid: Value = client.get("/validate-phrases").dispatch().into_json().unwrap();
+        assert_eq!(json!([]), invalid);
+        assert!(!dir.join("persist.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dry_run_add_and_remove() {
+        let dir = temp_dir("dry-run");
+        let files = [dir.join("data/a.txt"), dir.join("data/b.txt")];
+        fs::create_dir_all(dir.join("data")).unwrap();
+        for file in &files {
+            fs::write(file, "Making a famine where abundance lies").unwrap();
+        }
+        let client = app_client(&dir);
+        let post = |uri: String| -> Value { client.post(uri).dispatch().into_json().unwrap() };
+        let listed = || -> Value { client.get("/list-files").dispatch().into_json().unwrap() };
+        let data = encode_path(&dir.join("data"));
+
+        // Dry runs change nothing and don't persist, but list what the real call then affects
+        let preview = post(format!("/add-file/{}?dry_run=true", data));
+        assert_eq!(2, preview["added"].as_array().unwrap().len());
+        assert_eq!(json!([]), listed());
+        assert!(!dir.join("persist.json").exists());
+        assert_eq!(preview, post(format!("/add-file/{}", data)));
+        let paths: Vec<Value> = listed().as_array().unwrap().iter().map(|file| file["path"].clone()).collect();
+        assert_eq!(vec![json!(files[0]), json!(files[1])], paths);
+        assert_eq!(json!([]), post(format!("/add-file/{}?dry_run=true", data))["added"]);
+
+        let preview = post(format!("/remove-files/{}?dry_run=true", encode_path(&files[0])));
+        assert_eq!(json!({ "removed": 1 }), preview);
+        assert_eq!(2, listed().as_array().unwrap().len());
+        assert_eq!(preview, post(format!("/remove-files/{}", encode_path(&files[0]))));
+        assert_eq!(1, listed().as_array().unwrap().len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_set_file_encoding() {
+        let dir = temp_dir("set-file-encoding");
+        let path = "src/searcher/test_text_1.txt";
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.add_file(path).unwrap();
+        let client = Client::tracked(build_app_with(service)).unwrap();
+        let patch = |body: Value| client.patch("/files").header(ContentType::JSON).body(body.to_string()).dispatch().status();
+        assert_eq!(Status::Ok, patch(json!({ "path": path, "bytes_per_character": 2 })));
+        assert_eq!(Status::BadRequest, patch(json!({ "path": path, "bytes_per_character": 0 })));
+        let files: Value = client.get("/list-files").dispatch().into_json().unwrap();
+        assert_eq!(json!({ "bytes_per_character": 2, "endianness": "little" }), files[0]["encoding"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_undo() {
+        let dir = temp_dir("undo");
+        let files = [dir.join("data/a.txt"), dir.join("data/b.txt"), dir.join("data/sub/c.txt"), dir.join("other.txt")];
+        fs::create_dir_all(dir.join("data/sub")).unwrap();
+        for file in &files {
+            fs::write(file, "Making a famine where abundance lies").unwrap();
+        }
+        let client = app_client(&dir);
+        let post = |uri: String| client.post(uri).dispatch();
+        let listed = || -> Value { client.get("/list-files").dispatch().into_json().unwrap() };
+        post(format!("/add-file/{}", encode_path(&dir))).into_json::<Value>().unwrap();
+        let encoding = json!({ "path": files[2], "encoding": { "bytes_per_character": 2, "endianness": "big" } });
+        client.patch("/files").header(ContentType::JSON).body(encoding.to_string()).dispatch();
+        let before = listed();
+
+        // Removing by prefix is logged, and undoing it restores exactly what was tracked, encodings included
+        post(format!("/remove-files/{}", encode_path(&dir.join("data"))));
+        assert_eq!(1, listed().as_array().unwrap().len());
+        let operations: Value = client.get("/operations").dispatch().into_json().unwrap();
+        assert_eq!(1, operations.as_array().unwrap().len());
+        assert_eq!("remove_files", operations[0]["kind"]);
+        assert_eq!(3, operations[0]["files"].as_array().unwrap().len());
+        let id = operations[0]["id"].as_u64().unwrap();
+        let undo: Value = post(format!("/operations/{}/undo", id)).into_json().unwrap();
+        assert_eq!("restore_files", undo["kind"]);
+        assert_eq!(json!(id), undo["undoes"]);
+        let sorted = |files: Value| -> Vec<String> {
+            let mut files: Vec<String> = files.as_array().unwrap().iter().map(|file| file.to_string()).collect();
+            files.sort();
+            files
+        };
+        assert_eq!(sorted(before.clone()), sorted(listed()));
+
+        // Undos are persisted, and can't be repeated
+        let persisted = FinderService::new(dir.join("persist.json"));
+        assert_eq!(4, persisted.state().files().count());
+        assert_eq!(2, persisted.state().operations().operations().count());
+        assert_eq!(Status::Conflict, post(format!("/operations/{}/undo", id)).status());
+        assert_eq!(Status::NotFound, post("/operations/1000/undo".to_owned()).status());
+
+        // Undoing the undo removes them again, unless later changes conflict with it
+        let undo_id = undo["id"].as_u64().unwrap();
+        post(format!("/remove-files/{}", encode_path(&files[0])));
+        let conflict = post(format!("/operations/{}/undo", undo_id));
+        assert_eq!(Status::Conflict, conflict.status());
+        assert!(conflict.into_string().unwrap().contains("no longer tracked"));
+        let operations: Value = client.get("/operations").dispatch().into_json().unwrap();
+        let last = operations.as_array().unwrap().last().unwrap()["id"].as_u64().unwrap();
+        assert_eq!(Status::Ok, post(format!("/operations/{}/undo", last)).status());
+        assert_eq!(Status::Ok, post(format!("/operations/{}/undo", undo_id)).status());
+        assert_eq!(1, listed().as_array().unwrap().len());
+
+        // Removed phrases come back too
+        client.post("/add-phrase").header(ContentType::JSON).body(r#""famine where""#).dispatch();
+        client.post("/remove-phrase").header(ContentType::JSON).body(r#""famine where""#).dispatch();
+        let operations: Value = client.get("/operations").dispatch().into_json().unwrap();
+        let removed = operations.as_array().unwrap().last().unwrap();
+        assert_eq!("remove_phrase", removed["kind"]);
+        assert_eq!("famine where", removed["phrases"][0]["text"]);
+        post(format!("/operations/{}/undo", removed["id"]));
+        let phrases: Value = client.get("/list-phrases").dispatch().into_json().unwrap();
+        assert_eq!(json!(["famine where"]), phrases);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_startup_validation() {
+        let dir = temp_dir("validation");
+        let missing = dir.join("missing.txt");
+        fs::write(&missing, "Making a famine where abundance lies").unwrap();
+        let service = FinderService::new(dir.join("persist.json"));
+        service.add_file(&missing).unwrap();
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.persist().unwrap();
+        fs::remove_file(&missing).unwrap();
+
+        // The state loaded at startup is checked in the background
+        let client = app_client(&dir);
+        fn report(client: &Client) -> Value {
+            for _ in 0..500 {
+                let report: Value = client.get("/validate/report").dispatch().into_json().unwrap();
+                if report["status"] == "done" {
+                    return report;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            panic!("Validation didn't finish");
+        }
+        let done = report(&client);
+        assert_eq!(2, done["files_checked"]);
+        assert_eq!(json!([missing]), json!(done["missing_files"].as_array().unwrap().iter().map(|file| &file["path"]).collect::<Vec<_>>()));
+        assert_eq!(json!([]), done["unreadable_files"]);
+        assert_eq!(json!([]), done["invalid_phrases"]);
+        assert_eq!(Value::Null, done["persist_error"]);
+        assert_eq!(Value::Null, done["fixed"]);
+        assert_eq!(2, client.get("/list-files").dispatch().into_json::<Value>().unwrap().as_array().unwrap().len());
+
+        // And again on request, fixing what it can when configured to
+        drop(client);
+        let config = AppConfig { persist_file: dir.join("persist.json"), validation_auto_fix: true, ..AppConfig::default() };
+        let client = Client::tracked(build_app(config)).unwrap();
+        let done = report(&client);
+        assert_eq!(json!({ "pruned_files": 1, "dropped_phrases": 0 }), done["fixed"]);
+        assert_eq!(1, client.get("/list-files").dispatch().into_json::<Value>().unwrap().as_array().unwrap().len());
+        assert_eq!(Status::Accepted, client.post("/validate").dispatch().status());
+        assert_eq!(json!([]), report(&client)["missing_files"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cancel_scans() {
+        let dir = temp_dir("cancel-scans");
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        service.set_max_bytes_per_second(Some(256));
+        let client = Client::tracked(build_app_with(service)).unwrap();
+
+        // A search cancelled partway responds with 503
+        let service = client.rocket().state::<FinderService>().unwrap();
+        let status = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                service.cancel_scans();
+            });
+            client.get("/search").dispatch().status()
+        });
+        assert_eq!(Status::ServiceUnavailable, status);
+
+        // Cancelling with nothing running doesn't affect later searches
+        assert_eq!(Status::Ok, client.post("/cancel-scans").dispatch().status());
+        service.set_max_bytes_per_second(None);
+        assert_eq!(Status::Ok, client.get("/search").dispatch().status());
+    }
+
+    #[test]
+    fn test_openapi_spec() {
+        let dir = temp_dir("openapi");
+        let client = Client::tracked(build_app_with(FinderService::with_state(dir.join("persist.json"), State::new()))).unwrap();
+        let response = client.get("/openapi.json").dispatch();
+        assert_eq!(Status::Ok, response.status());
+        let spec: Value = response.into_json().unwrap();
+
+        // Routes are listed under their methods, with their query parameters and docs
+        let paths = &spec["paths"];
+        for (path, method) in [("/search", "get"), ("/add-phrase", "post"), ("/files", "patch"), ("/config", "put"), ("/cancel-scans", "post"), ("/health", "get")] {
+            assert!(paths[path][method].is_object(), "{} {}", method, path);
+        }
+        assert!(paths["/add-file/{file_name}"]["post"].is_object());
+        let parameters: Vec<&str> = paths["/search"]["get"]["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|parameter| parameter["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(vec!["context_size", "window_size", "sort", "bpc", "endianness", "min_printability", "max_report_bytes"], parameters);
+        assert!(paths["/health"]["get"]["description"].as_str().unwrap().starts_with("Reports that the service is up"));
+        assert_eq!(json!("#/components/schemas/Health"), paths["/health"]["get"]["responses"]["200"]["content"]["application/json"]["schema"]["$ref"]);
+
+        // Schemas match what's sent over the wire, including fields computed when serializing
+        let schemas = &spec["components"]["schemas"];
+        let properties = |name: &str| -> Vec<String> {
+            let mut properties: Vec<String> = schemas[name]["properties"].as_object().unwrap().keys().cloned().collect();
+            properties.sort();
+            properties
+        };
+        assert_eq!(vec!["context_printability", "instance", "len_bytes", "phrase", "score"], properties("ReportEntry"));
+        assert!(properties("SearchReport").contains(&"cancelled".to_owned()));
+        assert!(properties("SearchReport").contains(&"truncated".to_owned()));
+        assert!(schemas["Phrase"].is_object());
+        assert!(schemas["PhraseBody"].is_object());
+    }
+
+    #[test]
+    fn test_health() {
+        let dir = temp_dir("health");
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        let client = Client::tracked(build_app_with(service)).unwrap();
+        let health = || -> Value { client.get("/health").dispatch().into_json().unwrap() };
+        let before = health();
+        assert_eq!("ok", before["status"]);
+        assert_eq!(json!(0), before["persist_file_bytes"]);
+        assert_eq!(json!(1), before["file_count"]);
+        assert_eq!(json!(1), before["phrase_count"]);
+
+        client.rocket().state::<FinderService>().unwrap().persist().unwrap();
+        let after = health();
+        assert_eq!(json!(fs::metadata(dir.join("persist.json")).unwrap().len()), after["persist_file_bytes"]);
+        assert!(after["uptime_secs"].as_f64().unwrap() >= before["uptime_secs"].as_f64().unwrap());
+
+        // A panic while the state is locked leaves it poisoned
+        let service = client.rocket().state::<FinderService>().unwrap();
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _state = service.state();
+            panic!("Poisoning the state");
+        }));
+        assert_eq!(Status::ServiceUnavailable, client.get("/health").dispatch().status());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_content() {
+        let dir = temp_dir("file-content");
+        let path = "src/searcher/test_text_1.txt";
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.add_file(path).unwrap();
+        let client = Client::tracked(build_app_with(service)).unwrap();
+        let page = |query: String| -> Value { client.get(format!("/files/content?path={}&{}", path, query)).dispatch().into_json().unwrap() };
+
+        // Paging through reassembles the whole file as it renders
+        let input = fs::read(path).unwrap();
+        let mut text = String::new();
+        let mut offset = Some(0);
+        while let Some(pos) = offset {
+            let content = page(format!("offset={}&length=64", pos));
+            assert_eq!(json!(input.len()), content["total_size"]);
+            text.push_str(content["text"].as_str().unwrap());
+            offset = content["next_offset"].as_u64();
+        }
+        assert_eq!(Text::from_slice(&input, 0, 1).to_string(), text);
+
+        // Decoded with the diff, only within the limits, and only from tracked files
+        assert_eq!(json!("famine where"), page("offset=288&length=12".to_owned())["text"]);
+        assert_eq!(json!("e`lhmd"), page("offset=288&length=6&diff=1".to_owned())["text"]);
+        let status = |uri: String| client.get(uri).dispatch().status();
+        assert_eq!(Status::BadRequest, status(format!("/files/content?path={}&length=100000", path)));
+        assert_eq!(Status::BadRequest, status(format!("/files/content?path={}&bpc=3", path)));
+        assert_eq!(Status::NotFound, status("/files/content?path=src/searcher/test_text_2.txt".to_owned()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_context_past_end() {
+        let dir = temp_dir("context-past-end");
+        let path = std::path::Path::new("src/searcher/test_text_1.txt");
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.add_file(path).unwrap();
+        let client = Client::tracked(build_app_with(service)).unwrap();
+        let context = |query: &str| client.get(format!("/context/{}?{}", encode_path(path), query)).dispatch();
+        let response = context("pos=288&max_len=6&terminator=32");
+        assert_eq!(json!("famine"), response.into_json::<Value>().unwrap());
+
+        // Nothing lives past the end of the file
+        let response = context("pos=100000&terminator=0");
+        assert_eq!(Status::Ok, response.status());
+        assert_eq!(json!(""), response.into_json::<Value>().unwrap());
+        assert_eq!(Status::Ok, context("pos=100000&bpc=2&terminator=0").status());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_search_and_export() {
+        let dir = temp_dir("search-and-export");
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.add_file("src/searcher/test_text_1.txt").unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        service.set_export_dir(Some(dir.join("exports")));
+        fs::create_dir(dir.join("exports")).unwrap();
+        let client = Client::tracked(build_app_with(service)).unwrap();
+        let export = |output_path: &str| client
+            .post("/search-and-export")
+            .header(ContentType::JSON)
+            .body(json!({ "output_path": output_path }).to_string())
+            .dispatch();
+
+        // Written within the export directory, and nowhere else
+        assert_eq!(Status::Ok, export("results.jsonl").status());
+        assert_eq!(1, fs::read_to_string(dir.join("exports/results.jsonl")).unwrap().lines().count());
+        let outside_file = dir.join("outside.txt");
+        fs::write(&outside_file, "untouched").unwrap();
+        for outside in [outside_file.to_str().unwrap(), "../outside.txt", "nested/../../outside.txt"] {
+            assert_eq!(Status::BadRequest, export(outside).status());
+        }
+        assert_eq!("untouched", fs::read_to_string(&outside_file).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Refused when there's nowhere to export to
+        let dir = temp_dir("search-and-export-refused");
+        let client = app_client(&dir);
+        let response = client.post("/search-and-export").header(ContentType::JSON).body(r#"{"output_path": "results.jsonl"}"#).dispatch();
+        assert_eq!(Status::Forbidden, response.status());
+        assert!(!dir.join("results.jsonl").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let dir = temp_dir("duplicates");
+        let (first, second) = (dir.join("a.txt"), dir.join("b.txt"));
+        fs::copy("src/searcher/test_text_1.txt", &first).unwrap();
+        fs::copy("src/searcher/test_text_1.txt", &second).unwrap();
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.add_file(&first).unwrap();
+        service.add_file(&second).unwrap();
+        service.add_file("src/searcher/test_text_2.txt").unwrap();
+        let client = Client::tracked(build_app_with(service)).unwrap();
+        let groups: Value = client.get("/duplicates").dispatch().into_json().unwrap();
+        let groups = groups.as_array().unwrap();
+        assert_eq!(1, groups.len());
+        assert_eq!(json!(639), groups[0]["len"]);
+        assert_eq!(json!([first.display().to_string(), second.display().to_string()]), json!([groups[0]["files"][0]["path"], groups[0]["files"][1]["path"]]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_analyze_strings() {
+        let dir = temp_dir("analyze-strings");
+        let path = dir.join("rotated.txt");
+        let rotated: Vec<u8> = fs::read("src/searcher/test_text_2.txt").unwrap().iter().map(|b| b + 13).collect();
+        fs::write(&path, &rotated).unwrap();
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.add_file(&path).unwrap();
+        let client = Client::tracked(build_app_with(service)).unwrap();
+        let analyze = |query: &str| client.get(format!("/analyze/strings?file={}&{}", path.display(), query)).dispatch();
+
+        // Words in the rotated fixture are suggested at diff 13
+        let candidates: Value = analyze("limit=5").into_json().unwrap();
+        let candidates = candidates.as_array().unwrap();
+        assert_eq!(5, candidates.len());
+        for candidate in candidates {
+            assert_eq!(json!(13), candidate["codepoint_diff"]);
+            assert_eq!(json!(1), candidate["bytes_per_character"]);
+        }
+        let all: Value = analyze("min_len=8&limit=1000").into_json().unwrap();
+        let count = all.as_array().unwrap().iter().find(|candidate| candidate["text"].as_str().unwrap().contains("sum my count")).unwrap();
+        assert_eq!(json!(13), count["codepoint_diff"]);
+
+        // Only within the limits, and only for tracked files
+        assert_eq!(Status::BadRequest, analyze("min_len=0").status());
+        assert_eq!(Status::BadRequest, analyze("limit=1001").status());
+        let untracked = client.get("/analyze/strings?file=src/searcher/test_text_2.txt").dispatch();
+        assert_eq!(Status::NotFound, untracked.status());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trace() {
+        let dir = temp_dir("trace");
+        let path = "src/searcher/test_text_1.txt";
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.add_file(path).unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "nowhere"]));
+        let client = Client::tracked(build_app_with(service)).unwrap();
+        let trace = |query: &str| client.get(format!("/trace?file={}&context_size=64&window_size=32&{}", path, query)).dispatch();
+
+        // The last events, each with why the phrase didn't match
+        let events: Value = trace("every=4&capacity=16").into_json().unwrap();
+        let events = events.as_array().unwrap();
+        assert_eq!(16, events.len());
+        assert!(events.iter().all(|event| event["rejected_reason_per_phrase"] == json!(["token_not_found"])));
+
+        // Only within the limits, and only for tracked files
+        assert_eq!(Status::BadRequest, trace("every=0").status());
+        assert_eq!(Status::BadRequest, trace("capacity=4097").status());
+        let untracked = client.get("/trace?file=src/searcher/test_text_2.txt").dispatch();
+        assert_eq!(Status::NotFound, untracked.status());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_patch() {
+        let dir = temp_dir("patch");
+        let path = dir.join("test_text_1.txt");
+        fs::copy("src/searcher/test_text_1.txt", &path).unwrap();
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.add_file(&path).unwrap();
+        service.add_phrase(Phrase::from_strs(&["hunger", "where"]));
+        let client = Client::tracked(build_app_with(service)).unwrap();
+        let patch = |body: Value| client.post("/patch").header(ContentType::JSON).body(body.to_string()).dispatch();
+
+        // Refused until writes are allowed
+        let body = json!({ "path": path, "pos": 288, "text": "hunger", "pad_to": 6 });
+        assert_eq!(Status::Forbidden, patch(body.clone()).status());
+        client.rocket().state::<FinderService>().unwrap().set_allow_writes(true);
+
+        // The new phrase is found where the old one was
+        let patched: Value = patch(body).into_json().unwrap();
+        assert_eq!(json!({ "pos": 288, "old_bytes": "66616d696e65", "new_bytes": "68756e676572" }), patched);
+        let report: Value = client.get("/search").dispatch().into_json().unwrap();
+        let entries = report["files"][0]["entries"].as_array().unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!(288, entries[0]["instance"]["file_pos"]);
+        assert_eq!("hunger where", entries[0]["phrase"]["text"]);
+
+        // Text that doesn't fit or isn't tracked isn't written
+        let status = |body: Value| patch(body).status();
+        assert_eq!(Status::UnprocessableEntity, status(json!({ "path": path, "pos": 288, "text": "starvation", "pad_to": 6 })));
+        assert_eq!(Status::UnprocessableEntity, status(json!({ "path": path, "pos": 288, "text": "a", "diff": -98 })));
+        assert_eq!(Status::NotFound, status(json!({ "path": "src/searcher/test_text_1.txt", "pos": 288, "text": "hunger" })));
+        assert_eq!(b"hunger where".to_vec(), fs::read(&path).unwrap()[288..300].to_vec());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_search_config() {
+        let dir = temp_dir("search-config");
+        let config = AppConfig {
+            persist_file: dir.join("persist.json"),
+            search_config: Some(SearchOptions { context_size: 64, window_size: 32 }),
+            ..AppConfig::default()
+        };
+        let client = Client::tracked(build_app(config)).unwrap();
+        let get = |uri: &str| -> Value { client.get(uri).dispatch().into_json().unwrap() };
+        let put = |body: Value| client.put("/config").header(ContentType::JSON).body(body.to_string()).dispatch().status();
+        assert_eq!(json!({ "context_size": 64, "window_size": 32 }), get("/config"));
+        assert_eq!(json!({ "context_size": 64, "window_size": 32 }), get("/search")["options"]);
+
+        // Updates take effect for the next search, and are persisted
+        assert_eq!(Status::Ok, put(json!({ "context_size": 128, "window_size": 40 })));
+        assert_eq!(json!({ "context_size": 128, "window_size": 40 }), get("/search")["options"]);
+        assert_eq!(json!({ "context_size": 16, "window_size": 8 }), get("/search?context_size=16&window_size=8")["options"]);
+        let persisted = FinderService::new(dir.join("persist.json"));
+        assert_eq!(Some(&SearchOptions { context_size: 128, window_size: 40 }), persisted.state().search_config());
+        assert_eq!(Status::BadRequest, put(json!({ "context_size": 30, "window_size": 8 })));
+        assert_eq!(Status::Ok, put(json!(null)));
+        assert_eq!(json!(null), get("/config"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid app config")]
+    fn test_invalid_search_config() {
+        let config = AppConfig {
+            persist_file: temp_dir("invalid-search-config").join("persist.json"),
+            search_config: Some(SearchOptions { context_size: 30, window_size: 8 }),
+            ..AppConfig::default()
+        };
+        let _ = build_app(config);
+    }
+
+    #[test]
+    fn test_min_printability() {
+        let dir = temp_dir("min-printability");
+        let text = dir.join("text.txt");
+        let noise = dir.join("noise.bin");
+        fs::write(&text, format!("{}Making a famine where abundance lies{}", ".".repeat(100), ".".repeat(100))).unwrap();
+        let mut bytes: Vec<u8> = (0..=255u8).map(|byte| byte.wrapping_mul(167)).collect();
+        bytes.splice(128..128, b"famine where".iter().copied());
+        fs::write(&noise, bytes).unwrap();
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.add_file(&text).unwrap();
+        service.add_file(&noise).unwrap();
+        service.add_phrase(Phrase::from_strs(&["famine", "where"]));
+        let client = Client::tracked(build_app_with(service)).unwrap();
+        let get = |uri: &str| -> Value { client.get(uri).dispatch().into_json().unwrap() };
+
+        // Both are found without a minimum, but only the one in text is kept with one
+        let found = |report: &Value, path: &PathBuf| -> Vec<Value> {
+            let file = report["files"].as_array().unwrap().iter().find(|file| file["path"] == json!(path)).unwrap();
+            file["entries"].as_array().unwrap().iter().map(|entry| entry["context_printability"].clone()).collect()
+        };
+        let report = get("/search?context_size=64&window_size=32");
+        assert_eq!(vec![json!(1.0)], found(&report, &text));
+        assert!(found(&report, &noise)[0].as_f64().unwrap() < 0.8);
+        let report = get("/search?context_size=64&window_size=32&min_printability=0.8");
+        assert_eq!(1, found(&report, &text).len());
+        assert!(found(&report, &noise).is_empty());
+        let summary = get("/search-summary?min_printability=0.8");
+        let match_count = |path: &PathBuf| summary.as_array().unwrap().iter().find(|file| file["path"] == json!(path)).unwrap()["match_count"].clone();
+        assert_eq!((json!(1), json!(0)), (match_count(&text), match_count(&noise)));
+        assert_eq!(Status::BadRequest, client.get("/search?min_printability=1.5").dispatch().status());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_phrases() {
+        let dir = temp_dir("validate-phrases");
+        let surrogate = Phrase::new(vec![Text::from_str("famine"), Text::from_codepoints(vec![0x77, 0xd800])]);
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.add_phrase(surrogate.clone());
+        service.add_phrase(Phrase::from_strs(&["within", "sunken", "deep"]));
+        let client = Client::tracked(build_app_with(service)).unwrap();
+        let invalid: Value = client.get("/validate-phrases").dispatch().into_json().unwrap();
+        assert_eq!(json!([{ "id": surrogate.id(), "text": "famine w?" }]), invalid);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stats() {
+        use crate::scan_history::{HistoryRetention, PhraseTally, ScanSummary, SECONDS_PER_DAY as DAY};
+        let dir = temp_dir("stats");
+        let service = FinderService::with_state(dir.join("persist.json"), State::new());
+        service.set_history_retention(HistoryRetention { max_age_secs: None, max_summaries: 10 });
+        for (timestamp, count) in [(DAY + 60, 1), (DAY + 120, 2), (3*DAY, 4)] {
+            service.record_scan(ScanSummary {
+                timestamp,
+                scans: 1,
+                phrases: [("id".to_owned(), PhraseTally { text: "famine where".to_owned(), count })].into(),
+                files: [("a.txt".to_owned(), count)].into(),
+                ..ScanSummary::default()
+            });
+        }
+        let client = Client::tracked(build_app_with(service)).unwrap();
+        let get = |uri: String| -> Value { client.get(uri).dispatch().into_json().unwrap() };
+
+        let series = get(format!("/stats/phrases?since={}&bucket=day", DAY + 120));
+        assert_eq!(json!([{ "id": "id", "text": "famine where", "points": [
+            { "start": DAY, "scans": 1, "count": 2 },
+            { "start": 3*DAY, "scans": 1, "count": 4 }
+        ]}]), series);
+        let series = get(format!("/stats/phrases?until={}", 3*DAY));
+        assert_eq!(json!([{ "start": DAY, "scans": 2, "count": 3 }]), series[0]["points"]);
+        assert_eq!(Status::BadRequest, client.get("/stats/phrases?bucket=month").dispatch().status());
+        assert_eq!(json!([{ "path": "a.txt", "encoded_path": "a.txt", "count": 7, "scans": 3 }]), get("/stats/files".to_owned()));
+
+        // Searching adds to the history, which is persisted later rather than by the search
+        let file = dir.join("haystack.txt");
+        fs::write(&file, "Making a famine where abundance lies").unwrap();
+        assert_eq!(Status::Ok, client.post(format!("/add-file/{}", encode_path(&file))).dispatch().status());
+        assert_eq!(Status::Created, client.post("/add-phrase").header(ContentType::JSON).body(r#""famine where""#).dispatch().status());
+        assert_eq!(Status::Ok, client.get("/search").dispatch().status());
+        let totals = get(format!("/stats/files?since={}", 4*DAY));
+        assert_eq!(json!([{ "path": file, "encoded_path": crate::finder_service::encode_path(&file), "count": 1, "scans": 1 }]), totals);
+        assert_eq!(3, FinderService::new(dir.join("persist.json")).state().history().summaries().len());
+        let service = client.rocket().state::<FinderService>().unwrap();
+        assert!(service.persist_history().unwrap());
+        assert!(!service.persist_history().unwrap());
+        let persisted = FinderService::new(dir.join("persist.json"));
+        assert_eq!(4, persisted.state().history().summaries().len());
+        assert_eq!(36, persisted.state().history().summaries().last().unwrap().bytes_read);
+
+        // Shrinking the retention compacts the history
+        let retention = json!({ "max_age_secs": null, "max_summaries": 3 });
+        assert_eq!(Status::Ok, client.post("/stats/retention").header(ContentType::JSON).body(retention.to_string()).dispatch().status());
+        assert_eq!(retention, get("/stats/retention".to_owned()));
+        let series = get("/stats/phrases".to_owned());
+        let seeded = series.as_array().unwrap().iter().find(|series| series["id"] == "id").unwrap();
+        assert_eq!(json!({ "start": DAY, "scans": 2, "count": 3 }), seeded["points"][0]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+use text_searcher_rust::Phrase;
+
+use crate::finder_service::FileEncoding;
+
+/// How many operations are kept by default before the oldest are forgotten
+pub const DEFAULT_MAX_OPERATIONS: usize = 100;
+
+/// A change to the tracked files or phrases, holding enough to invert it
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Mutation {
+    RemoveFiles {
+        files: Vec<String>,                         // Encoded paths. See finder_service::encode_path.
+        #[serde(default)]
+        encodings: BTreeMap<String, FileEncoding>   // Encodings the files had, by encoded path
+    },
+    RestoreFiles {
+        files: Vec<String>,
+        #[serde(default)]
+        encodings: BTreeMap<String, FileEncoding>
+    },
+    RemovePhrase { phrase: Phrase },
+    RestorePhrase { phrase: Phrase }
+}
+
+impl Mutation {
+
+    /// Mutation that puts back what this one changed
+    pub fn inverse(&self) -> Self {
+        match self {
+            Self::RemoveFiles { files, encodings } => Self::RestoreFiles { files: files.clone(), encodings: encodings.clone() },
+            Self::RestoreFiles { files, encodings } => Self::RemoveFiles { files: files.clone(), encodings: encodings.clone() },
+            Self::RemovePhrase { phrase } => Self::RestorePhrase { phrase: phrase.clone() },
+            Self::RestorePhrase { phrase } => Self::RemovePhrase { phrase: phrase.clone() }
+        }
+    }
+
+    /// Name of the kind of mutation, as it's serialized
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::RemoveFiles { .. } => "remove_files",
+            Self::RestoreFiles { .. } => "restore_files",
+            Self::RemovePhrase { .. } => "remove_phrase",
+            Self::RestorePhrase { .. } => "restore_phrase"
+        }
+    }
+}
+
+/// A mutation that was applied, and how it relates to undos
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Operation {
+    pub id: u64,
+    pub timestamp: u64,             // Seconds since the Unix epoch it was applied at
+    pub mutation: Mutation,
+    pub undoes: Option<u64>,        // Operation this one undid, if it was an undo
+    pub undone_by: Option<u64>      // Operation that undid this one, if any
+}
+
+/// Most recent operations, oldest first, so destructive changes can be undone
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OperationLog {
+    max_operations: usize,
+    next_id: u64,
+    operations: VecDeque<Operation>
+}
+
+impl Default for OperationLog {
+    fn default() -> Self {
+        Self {
+            max_operations: DEFAULT_MAX_OPERATIONS,
+            next_id: 1,
+            operations: VecDeque::new()
+        }
+    }
+}
+
+impl OperationLog {
+
+    /// Operations kept, oldest first
+    pub fn operations(&self) -> impl Iterator<Item=&Operation> {
+        self.operations.iter()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Operation> {
+        self.operations.iter().find(|operation| operation.id == id)
+    }
+
+    pub fn max_operations(&self) -> usize {
+        self.max_operations
+    }
+
+    /// Sets how many operations are kept, forgetting the oldest past it
+    pub fn set_max_operations(&mut self, max_operations: usize) {
+        self.max_operations = max_operations;
+        self.truncate();
+    }
+
+    /// Adds a mutation that was applied at `timestamp`, marking the operation it undoes if any.
+    /// Returns the id of the new operation.
+    pub fn record(&mut self, mutation: Mutation, timestamp: u64, undoes: Option<u64>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        if let Some(undone) = undoes.and_then(|undone| self.operations.iter_mut().find(|operation| operation.id == undone)) {
+            undone.undone_by = Some(id);
+        }
+        self.operations.push_back(Operation { id, timestamp, mutation, undoes, undone_by: None });
+        self.truncate();
+        id
+    }
+
+    fn truncate(&mut self) {
+        while self.operations.len() > self.max_operations {
+            self.operations.pop_front();
+        }
+    }
+}
+
+/// Reasons an operation can't be undone
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum UndoError {
+    NotFound(u64),                          // Never logged, or forgotten since
+    AlreadyUndone { id: u64, by: u64 },
+    Conflict { id: u64, reason: String }    // Later changes mean undoing it wouldn't restore what it changed
+}
+
+impl fmt::Display for UndoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "Operation {} not found", id),
+            Self::AlreadyUndone { id, by } => write!(f, "Operation {} was already undone by operation {}", id, by),
+            Self::Conflict { id, reason } => write!(f, "Operation {} conflicts with later changes. {}", id, reason)
+        }
+    }
+}
+
+impl std::error::Error for UndoError {}
+
+
+#[cfg(test)]
+mod tests {
+    use text_searcher_rust::Phrase;
+    use super::{Mutation, OperationLog};
+
+    #[test]
+    fn test_record() {
+        let mut log = OperationLog::default();
+        log.set_max_operations(2);
+        let remove = Mutation::RemovePhrase { phrase: Phrase::from_strs(&["famine", "where"]) };
+        let first = log.record(remove.clone(), 10, None);
+        let second = log.record(remove.inverse(), 20, Some(first));
+        assert_eq!(Some(second), log.get(first).unwrap().undone_by);
+        assert_eq!(Some(first), log.get(second).unwrap().undoes);
+        assert_eq!(remove, log.get(second).unwrap().mutation.inverse());
+
+        // The oldest are forgotten past the limit
+        let third = log.record(remove, 30, None);
+        let ids: Vec<u64> = log.operations().map(|operation| operation.id).collect();
+        assert_eq!(vec![second, third], ids);
+        assert!(log.get(first).is_none());
+    }
+}
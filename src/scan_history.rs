@@ -0,0 +1,368 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use text_searcher_rust::{PhraseRef, ReportEntry, SearchReport, Throughput};
+
+use crate::finder_service::{decode_path, encode_path};
+
+/// Seconds in a day, the span compaction merges summaries over
+pub const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Seconds since the Unix epoch
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// What a scan found, or what several scans compacted together found
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ScanSummary {
+    pub timestamp: u64,                         // Seconds since the Unix epoch the scan ran at. The start of the day for compacted scans.
+    pub scans: usize,                           // How many scans the counts are summed over
+    pub phrases: BTreeMap<String, PhraseTally>, // Instances found of each phrase, by phrase id
+    pub files: BTreeMap<String, usize>,         // Instances found in each file, by encoded path. See finder_service::encode_path.
+    #[serde(default)]
+    pub bytes_read: u64,                        // Bytes the scans read from their sources
+    #[serde(default)]
+    pub elapsed_ms: u64,                        // How long the scans took to read them
+    #[serde(default)]
+    pub bytes_per_second: Option<u64>           // Effective throughput over all the scans. None if no time was measured.
+}
+
+/// Instances found of a phrase
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PhraseTally {
+    pub text: String,   // Tokens separated by spaces
+    pub count: usize
+}
+
+impl ScanSummary {
+
+    /// Summarizes a scan that ran at `timestamp`. Phrases and files without any instances are left out.
+    /// Instances in files `count_file` rejects, like dynamic sources, only count towards their phrases.
+    /// Entries the report dropped to stay within its size are counted too, since they were found all the same.
+    pub fn from_report(timestamp: u64, report: &SearchReport, count_file: impl Fn(&Path) -> bool) -> Self {
+        let mut summary = Self { timestamp, scans: 1, ..Self::default() };
+        for file in &report.files {
+            let count_file = count_file(&file.path);
+            for entry in &file.entries {
+                summary.count_entry(&file.path, entry, count_file);
+            }
+        }
+        for dropped in &report.dropped {
+            summary.count(&dropped.path, &dropped.phrase, dropped.count, count_file(&dropped.path));
+        }
+        summary
+    }
+
+    /// Counts an instance found in a file towards its phrase, and towards the file if `count_file`
+    pub fn count_entry(&mut self, path: &Path, entry: &ReportEntry, count_file: bool) {
+        self.count(path, &entry.phrase, 1, count_file);
+    }
+
+    // Counts instances of a phrase found in a file
+    fn count(&mut self, path: &Path, phrase: &PhraseRef, count: usize, count_file: bool) {
+        if count_file {
+            *self.files.entry(encode_path(path)).or_default() += count;
+        }
+        let tally = self.phrases.entry(phrase.id.clone()).or_insert_with(|| PhraseTally {
+            text: phrase.text.clone(),
+            count: 0
+        });
+        tally.count += count;
+    }
+
+    /// Records how many bytes the scan read and how fast, such as when its reads were capped
+    pub fn with_throughput(mut self, throughput: Throughput) -> Self {
+        self.bytes_read = throughput.bytes_read;
+        self.elapsed_ms = throughput.elapsed.as_millis() as u64;
+        self.bytes_per_second = throughput.bytes_per_second();
+        self
+    }
+
+    // Adds another summary's counts to this one's
+    fn merge(&mut self, other: ScanSummary) {
+        self.scans += other.scans;
+        self.bytes_read += other.bytes_read;
+        self.elapsed_ms += other.elapsed_ms;
+        self.bytes_per_second = match self.elapsed_ms {
+            0 => None,
+            elapsed_ms => Some(self.bytes_read * 1000 / elapsed_ms)
+        };
+        for (id, tally) in other.phrases {
+            self.phrases.entry(id).or_insert_with(|| PhraseTally { text: tally.text, count: 0 }).count += tally.count;
+        }
+        for (path, count) in other.files {
+            *self.files.entry(path).or_default() += count;
+        }
+    }
+}
+
+/// How long scan summaries are kept, and how many before they're compacted
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct HistoryRetention {
+    pub max_age_secs: Option<u64>,  // Summaries older than this are dropped. None keeps them until there are too many.
+    pub max_summaries: usize        // Past this many, summaries from the same day are merged, oldest first, then the oldest are dropped
+}
+
+impl Default for HistoryRetention {
+    fn default() -> Self {
+        Self {
+            max_age_secs: Some(90 * SECONDS_PER_DAY),
+            max_summaries: 1000
+        }
+    }
+}
+
+/// Span of time stats are bucketed by. Buckets start at multiples of it since the Unix epoch, in UTC.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Bucket {
+    Hour,
+    Day,
+    Week
+}
+
+impl Bucket {
+
+    /// Parses "hour", "day" or "week"
+    pub fn parse(str: &str) -> Option<Self> {
+        match str {
+            "hour" => Some(Self::Hour),
+            "day" => Some(Self::Day),
+            "week" => Some(Self::Week),
+            _ => None
+        }
+    }
+
+    pub fn seconds(self) -> u64 {
+        match self {
+            Self::Hour => 60 * 60,
+            Self::Day => SECONDS_PER_DAY,
+            Self::Week => 7 * SECONDS_PER_DAY
+        }
+    }
+
+    /// Start of the bucket the timestamp falls in
+    pub fn start_of(self, timestamp: u64) -> u64 {
+        timestamp - timestamp % self.seconds()
+    }
+}
+
+/// Instances found of a phrase in each bucket that had scans
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct PhraseSeries {
+    pub id: String,
+    pub text: String,
+    pub points: Vec<StatsPoint>
+}
+
+/// Counts summed over a bucket
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct StatsPoint {
+    pub start: u64,     // Seconds since the Unix epoch the bucket starts at
+    pub scans: usize,   // Scans that ran in the bucket
+    pub count: usize    // Instances those scans found
+}
+
+/// Instances found in a file over a span of time.
+/// `path` is for display, with anything that isn't valid UTF-8 replaced. `encoded_path` names the file exactly.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct FileTotal {
+    pub path: String,
+    pub encoded_path: String,   // See finder_service::encode_path
+    pub count: usize,
+    pub scans: usize            // Scans that found anything in the file
+}
+
+/// Summaries of past scans, kept within a [`HistoryRetention`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScanHistory {
+    #[serde(default)]
+    retention: HistoryRetention,
+    #[serde(default)]
+    summaries: Vec<ScanSummary>     // Oldest first
+}
+
+impl ScanHistory {
+    pub fn retention(&self) -> HistoryRetention { self.retention }
+
+    pub fn summaries(&self) -> &[ScanSummary] { &self.summaries }
+
+    /// Adds a summary, then compacts as of `now`
+    pub fn record(&mut self, summary: ScanSummary, now: u64) {
+        let idx = self.summaries.partition_point(|existing| existing.timestamp <= summary.timestamp);
+        self.summaries.insert(idx, summary);
+        self.compact(now);
+    }
+
+    /// Replaces the retention, then compacts as of `now`
+    pub fn set_retention(&mut self, retention: HistoryRetention, now: u64) {
+        self.retention = retention;
+        self.compact(now);
+    }
+
+    /// Drops summaries older than the retention allows. If there are still too many,
+    /// merges each day's summaries into one, oldest day first, until there aren't. Then drops the oldest.
+    pub fn compact(&mut self, now: u64) {
+        if let Some(max_age) = self.retention.max_age_secs {
+            let cutoff = now.saturating_sub(max_age);
+            self.summaries.retain(|summary| summary.timestamp >= cutoff);
+        }
+        let mut idx = 0;
+        while self.summaries.len() > self.retention.max_summaries && idx < self.summaries.len() {
+            let day = Bucket::Day.start_of(self.summaries[idx].timestamp);
+            let day_end = idx + self.summaries[idx..].partition_point(|summary| Bucket::Day.start_of(summary.timestamp) == day);
+            if day_end - idx > 1 {
+                let mut merged = ScanSummary { timestamp: day, ..ScanSummary::default() };
+                for summary in self.summaries.drain(idx..day_end) {
+                    merged.merge(summary);
+                }
+                self.summaries.insert(idx, merged);
+            }
+            idx += 1;
+        }
+        let excess = self.summaries.len().saturating_sub(self.retention.max_summaries);
+        self.summaries.drain(..excess);
+    }
+
+    // Summaries of scans that ran at or after `since`, and before `until`
+    fn between(&self, since: Option<u64>, until: Option<u64>) -> impl Iterator<Item=&ScanSummary> {
+        self.summaries
+            .iter()
+            .filter(move |summary| since.is_none_or(|since| summary.timestamp >= since))
+            .filter(move |summary| until.is_none_or(|until| summary.timestamp < until))
+    }
+
+    /// Instances found of each phrase per bucket, sorted by phrase id.
+    /// Every phrase found in the span gets a point for each bucket with scans, even where it wasn't found.
+    pub fn phrase_series(&self, since: Option<u64>, until: Option<u64>, bucket: Bucket) -> Vec<PhraseSeries> {
+        let mut scans: BTreeMap<u64, usize> = BTreeMap::new();
+        let mut counts: BTreeMap<&String, (&String, BTreeMap<u64, usize>)> = BTreeMap::new();
+        for summary in self.between(since, until) {
+            let start = bucket.start_of(summary.timestamp);
+            *scans.entry(start).or_default() += summary.scans;
+            for (id, tally) in &summary.phrases {
+                let (_, per_bucket) = counts.entry(id).or_insert_with(|| (&tally.text, BTreeMap::new()));
+                *per_bucket.entry(start).or_default() += tally.count;
+            }
+        }
+        counts
+            .into_iter()
+            .map(|(id, (text, per_bucket))| PhraseSeries {
+                id: id.clone(),
+                text: text.clone(),
+                points: scans
+                    .iter()
+                    .map(|(&start, &scans)| StatsPoint { start, scans, count: per_bucket.get(&start).copied().unwrap_or(0) })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Instances found in each file over the span, sorted by path
+    pub fn file_totals(&self, since: Option<u64>, until: Option<u64>) -> Vec<FileTotal> {
+        let mut totals: BTreeMap<&String, (usize, usize)> = BTreeMap::new();
+        for summary in self.between(since, until) {
+            for (path, count) in &summary.files {
+                let (total, scans) = totals.entry(path).or_default();
+                *total += count;
+                *scans += summary.scans;
+            }
+        }
+        totals
+            .into_iter()
+            .map(|(encoded_path, (count, scans))| FileTotal {
+                path: decode_path(encoded_path)
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .unwrap_or_else(|_| encoded_path.clone()),
+                encoded_path: encoded_path.clone(),
+                count,
+                scans
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = SECONDS_PER_DAY;
+
+    // Summary of one scan finding the counts given of phrases "a" and "b", all in the file "f"
+    fn summary(timestamp: u64, a: usize, b: usize) -> ScanSummary {
+        let mut summary = ScanSummary { timestamp, scans: 1, ..ScanSummary::default() };
+        for (id, count) in [("a", a), ("b", b)] {
+            if count > 0 {
+                summary.phrases.insert(id.to_owned(), PhraseTally { text: id.to_uppercase(), count });
+            }
+        }
+        if a + b > 0 {
+            summary.files.insert("f".to_owned(), a + b);
+        }
+        summary
+    }
+
+    fn seeded() -> ScanHistory {
+        let mut history = ScanHistory::default();
+        for seed in [summary(DAY + 10, 1, 0), summary(DAY + 20, 2, 1), summary(3*DAY + 5, 0, 4), summary(4*DAY, 3, 0)] {
+            history.record(seed, 4*DAY);
+        }
+        history
+    }
+
+    #[test]
+    fn test_phrase_series() {
+        let history = seeded();
+        let series = history.phrase_series(None, None, Bucket::Day);
+        let points = |series: &PhraseSeries| -> Vec<(u64, usize, usize)> {
+            series.points.iter().map(|point| (point.start, point.scans, point.count)).collect()
+        };
+        assert_eq!(vec!["a", "b"], series.iter().map(|series| series.id.as_str()).collect::<Vec<_>>());
+        assert_eq!("A", series[0].text);
+        assert_eq!(vec![(DAY, 2, 3), (3*DAY, 1, 0), (4*DAY, 1, 3)], points(&series[0]));
+        assert_eq!(vec![(DAY, 2, 1), (3*DAY, 1, 4), (4*DAY, 1, 0)], points(&series[1]));
+
+        // Since is inclusive and until exclusive
+        let series = history.phrase_series(Some(DAY + 20), Some(4*DAY), Bucket::Day);
+        assert_eq!(vec![(DAY, 1, 2), (3*DAY, 1, 0)], points(&series[0]));
+        assert_eq!(vec![(DAY, 1, 1), (3*DAY, 1, 4)], points(&series[1]));
+
+        // Weeks start on the epoch's weekday
+        let series = history.phrase_series(None, None, Bucket::Week);
+        assert_eq!(vec![(0, 4, 6)], points(&series[0]));
+        assert!(history.phrase_series(Some(5*DAY), None, Bucket::Day).is_empty());
+    }
+
+    #[test]
+    fn test_file_totals() {
+        let history = seeded();
+        let total = |count: usize, scans: usize| FileTotal { path: "f".to_owned(), encoded_path: "f".to_owned(), count, scans };
+        assert_eq!(vec![total(11, 4)], history.file_totals(None, None));
+        assert_eq!(vec![total(4, 1)], history.file_totals(Some(2*DAY), Some(4*DAY)));
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut history = seeded();
+
+        // Old summaries are dropped
+        history.set_retention(HistoryRetention { max_age_secs: Some(2*DAY), max_summaries: 10 }, 4*DAY);
+        assert_eq!(vec![3*DAY + 5, 4*DAY], history.summaries().iter().map(|summary| summary.timestamp).collect::<Vec<_>>());
+
+        // Too many are merged per day, oldest first, and then dropped
+        let mut history = seeded();
+        history.set_retention(HistoryRetention { max_age_secs: None, max_summaries: 3 }, 4*DAY);
+        let mut merged = summary(DAY, 3, 1);
+        merged.scans = 2;
+        assert_eq!(vec![merged, summary(3*DAY + 5, 0, 4), summary(4*DAY, 3, 0)], history.summaries());
+        history.set_retention(HistoryRetention { max_age_secs: None, max_summaries: 1 }, 4*DAY);
+        assert_eq!(vec![summary(4*DAY, 3, 0)], history.summaries());
+    }
+}
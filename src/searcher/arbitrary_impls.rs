@@ -0,0 +1,25 @@
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{Phrase, Text};
+
+impl<'a> Arbitrary<'a> for Text {
+    /// 1-16 ASCII codepoints, so that fuzzed phrases stay readable
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let len = u.int_in_range(1..=16)?;
+        let codepoints = (0..len)
+            .map(|_| u.int_in_range(0..=127))
+            .collect::<Result<Vec<u32>>>()?;
+        Ok(Self::from_codepoints(codepoints))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Phrase {
+    /// 1-4 tokens
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let len = u.int_in_range(1..=4)?;
+        let texts = (0..len)
+            .map(|_| Text::arbitrary(u))
+            .collect::<Result<Vec<Text>>>()?;
+        Ok(Self::new(texts))
+    }
+}
@@ -0,0 +1,460 @@
+use std::fmt;
+use std::io::Read;
+use std::ops::RangeInclusive;
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+
+#[cfg(doc)]
+use crate::refine_coarse_hits;
+use crate::{Encoder, Finder, LinearEncoder1, MatchOptions, Matcher, Phrase, SearchOptions};
+
+/// Widest character, in bytes, that the finder searches for
+pub const MAX_BYTES_PER_CHARACTER: usize = 2;
+
+
+/// How the tokens of a phrase must be laid out within the window to count as a match
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum MatchPolicy {
+    /// Tokens can appear anywhere in the window, in any order
+    #[default]
+    AnyOrder,
+    /// Tokens must appear in the same order as in the phrase, without overlapping
+    Ordered
+}
+
+/// Byte order of multi-byte characters
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big
+}
+
+/// Known layout of the characters in an input. Without one, a [`Finder`] tries every width as little-endian.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct Encoding {
+    pub bytes_per_character: u32,   // 1 or 2
+    pub endianness: Endianness      // Only used when bytes_per_character is 2
+}
+
+/// Reasons a [`FinderBuilder`] can fail to build a [`Finder`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FinderConfigError {
+    ContextSizeNotDivisibleBy4(usize),
+    WindowSizeZero,
+    WindowLargerThanContext { window_size: usize, context_size: usize },
+    EmptyDiffRange(RangeInclusive<i32>),
+    ExactOnlyExcludedByDiffRange(RangeInclusive<i32>),
+    UnsupportedBytesPerCharacter(u32),
+    StrideZero,
+    StrideLargerThanWindow { stride: usize, window_size: usize }
+}
+
+impl fmt::Display for FinderConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ContextSizeNotDivisibleBy4(size) => write!(f, "Context size must be divisible by 4, got {}", size),
+            Self::WindowSizeZero => write!(f, "Window size must be > 0"),
+            Self::WindowLargerThanContext { window_size, context_size } => {
+                write!(f, "Window size must be <= context_size, got {} > {}", window_size, context_size)
+            },
+            Self::EmptyDiffRange(range) => write!(f, "Diff range {:?} is empty", range),
+            Self::ExactOnlyExcludedByDiffRange(range) => write!(f, "Exact only search requires diff range {:?} to contain 0", range),
+            Self::UnsupportedBytesPerCharacter(bpc) => {
+                write!(f, "Bytes per character must be between 1 and {}, got {}", MAX_BYTES_PER_CHARACTER, bpc)
+            },
+            Self::StrideZero => write!(f, "Stride must be > 0"),
+            Self::StrideLargerThanWindow { stride, window_size } => {
+                write!(f, "Stride must be <= window_size, got {} > {}", stride, window_size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FinderConfigError {}
+
+
+/// Configures and builds a [`Finder`].
+///
+/// When sizes are left unspecified:
+/// * The window defaults to half the context size if the context size is specified.
+/// * The context defaults to twice the window size, rounded up to a multiple of 4.
+/// * If neither are specified, both are sized by [`SearchOptions::auto_size`] at [`MAX_BYTES_PER_CHARACTER`].
+#[derive(Debug, Clone)]
+pub struct FinderBuilder {
+    context_size: Option<usize>,
+    window_size: Option<usize>,
+    exact_only: bool,
+    diff_range: RangeInclusive<i32>,
+    match_policy: MatchPolicy,
+    encoding: Option<Encoding>,
+    widths: Vec<u32>,
+    stride: usize
+}
+
+impl Default for FinderBuilder {
+    fn default() -> Self {
+        Self {
+            context_size: None,
+            window_size: None,
+            exact_only: false,
+            diff_range: i32::MIN..=i32::MAX,
+            match_policy: MatchPolicy::default(),
+            encoding: None,
+            widths: Vec::new(),
+            stride: 1
+        }
+    }
+}
+
+impl FinderBuilder {
+    pub fn new() -> Self { Self::default() }
+
+    /// Size of the buffer bytes are streamed through. Must be divisible by 4.
+    pub fn context_size(mut self, context_size: usize) -> Self {
+        self.context_size = Some(context_size);
+        self
+    }
+
+    /// Size of the region phrases are searched for in. Must be <= the context size.
+    pub fn window_size(mut self, window_size: usize) -> Self {
+        self.window_size = Some(window_size);
+        self
+    }
+
+    /// Only matches text with a codepoint diff of 0
+    pub fn exact_only(mut self, exact_only: bool) -> Self {
+        self.exact_only = exact_only;
+        self
+    }
+
+    /// Only matches text with a codepoint diff within the range
+    pub fn diff_range(mut self, diff_range: RangeInclusive<i32>) -> Self {
+        self.diff_range = diff_range;
+        self
+    }
+
+    pub fn match_policy(mut self, match_policy: MatchPolicy) -> Self {
+        self.match_policy = match_policy;
+        self
+    }
+
+    /// Only matches characters laid out as specified. `None` tries every width as little-endian.
+    pub fn encoding(mut self, encoding: Option<Encoding>) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Only matches characters with these bytes per character, on top of any widths phrases are restricted to.
+    /// Empty tries every supported width.
+    pub fn widths(mut self, widths: Vec<u32>) -> Self {
+        self.widths = widths;
+        self
+    }
+
+    /// How many bytes the window slides by between searches. Defaults to 1. Must be <= the window size.
+    /// Larger strides search faster, but can miss phrases that are shorter than the stride, or that only fit
+    /// in the window at positions the window skips over. See [`refine_coarse_hits`] to recover exact positions.
+    pub fn stride(mut self, stride: usize) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    /// Resolves unspecified sizes and validates the configuration
+    pub fn build<'a, R: Read>(self, phrases: &[Phrase], reader: &'a mut R) -> Result<Finder<'a, R>, FinderConfigError> {
+        self.build_finder::<R, LinearEncoder1>(phrases, None, reader)
+    }
+
+    /// Same as [`Self::build`], but characters are decoded with `encoder` instead of trying every width and diff.
+    /// The diff range and encoding are ignored.
+    pub fn build_with_encoder<'a, R: Read, E: Encoder>(
+        self,
+        phrases: &[Phrase],
+        encoder: E,
+        reader: &'a mut R
+    ) -> Result<Finder<'a, R, E>, FinderConfigError> {
+        self.build_finder(phrases, Some(encoder), reader)
+    }
+
+    fn build_finder<'a, R: Read, E: Encoder>(
+        self,
+        phrases: &[Phrase],
+        encoder: Option<E>,
+        reader: &'a mut R
+    ) -> Result<Finder<'a, R, E>, FinderConfigError> {
+        let (context_size, window_size) = self.sizes(phrases);
+        SearchOptions { context_size, window_size }.validate()?;
+        if self.stride == 0 {
+            return Err(FinderConfigError::StrideZero);
+        }
+        if self.stride > window_size {
+            return Err(FinderConfigError::StrideLargerThanWindow { stride: self.stride, window_size });
+        }
+        if self.diff_range.is_empty() {
+            return Err(FinderConfigError::EmptyDiffRange(self.diff_range));
+        }
+        let widths = self.encoding.map(|encoding| encoding.bytes_per_character).into_iter().chain(self.widths.iter().copied());
+        for bpc in widths {
+            if bpc == 0 || bpc as usize > MAX_BYTES_PER_CHARACTER {
+                return Err(FinderConfigError::UnsupportedBytesPerCharacter(bpc));
+            }
+        }
+        let diff_range = if self.exact_only {
+            if !self.diff_range.contains(&0) {
+                return Err(FinderConfigError::ExactOnlyExcludedByDiffRange(self.diff_range));
+            }
+            0..=0
+        }
+        else {
+            self.diff_range
+        };
+        let options = MatchOptions {
+            diff_range,
+            match_policy: self.match_policy,
+            encoding: self.encoding,
+            widths: self.widths
+        };
+        let matcher = Matcher::from_parts(phrases, options, encoder);
+        Ok(Finder::with_config(matcher, context_size, window_size, self.stride, reader))
+    }
+
+    // Context and window sizes, with defaults filled in
+    fn sizes(&self, phrases: &[Phrase]) -> (usize, usize) {
+        match (self.context_size, self.window_size) {
+            (Some(context_size), Some(window_size)) => (context_size, window_size),
+            (Some(context_size), None) => (context_size, context_size / 2),
+            (None, Some(window_size)) => (round_up_to_4(window_size * 2), window_size),
+            (None, None) => {
+                let options = SearchOptions::auto_size(phrases, MAX_BYTES_PER_CHARACTER, usize::MAX);
+                (options.context_size, options.window_size)
+            }
+        }
+    }
+}
+
+fn round_up_to_4(size: usize) -> usize {
+    size.div_ceil(4) * 4
+}
+
+
+#[test]
+fn test_builder_defaults() {
+    let phrases = [
+        Phrase::from_strs(&["famine", "where"]),
+        Phrase::from_strs(&["within", "sunken", "deep"])
+    ];
+    let mut input: &[u8] = &[];
+
+    // Longest phrase is "within sunken deep", 16 characters plus 2 gaps
+    let finder = FinderBuilder::new().build(&phrases, &mut input).unwrap();
+    assert_eq!(40, finder.window_size);
+    assert_eq!(80, finder.context.capacity());
+
+    let finder = FinderBuilder::new().context_size(64).build(&phrases, &mut input).unwrap();
+    assert_eq!(32, finder.window_size);
+    assert_eq!(64, finder.context.capacity());
+
+    let finder = FinderBuilder::new().window_size(7).build(&phrases, &mut input).unwrap();
+    assert_eq!(7, finder.window_size);
+    assert_eq!(16, finder.context.capacity());
+}
+
+#[test]
+fn test_builder_validation() {
+    let phrases = [Phrase::from_strs(&["word"])];
+    let mut input: &[u8] = &[];
+    let mut build = |builder: FinderBuilder| builder.build(&phrases, &mut input).err();
+
+    assert_eq!(
+        Some(FinderConfigError::ContextSizeNotDivisibleBy4(30)),
+        build(FinderBuilder::new().context_size(30).window_size(8))
+    );
+    assert_eq!(
+        Some(FinderConfigError::WindowSizeZero),
+        build(FinderBuilder::new().context_size(32).window_size(0))
+    );
+    assert_eq!(
+        Some(FinderConfigError::WindowLargerThanContext {
+            window_size: 64,
+            context_size: 32
+        }),
+        build(FinderBuilder::new().context_size(32).window_size(64))
+    );
+    assert_eq!(
+        Some(FinderConfigError::EmptyDiffRange(RangeInclusive::new(
+            5, -5
+        ))),
+        build(FinderBuilder::new().diff_range(RangeInclusive::new(5, -5)))
+    );
+    assert_eq!(
+        Some(FinderConfigError::ExactOnlyExcludedByDiffRange(1..=10)),
+        build(FinderBuilder::new().diff_range(1..=10).exact_only(true))
+    );
+    assert_eq!(
+        Some(FinderConfigError::UnsupportedBytesPerCharacter(3)),
+        build(FinderBuilder::new().encoding(Some(Encoding {
+            bytes_per_character: 3,
+            endianness: Endianness::Little
+        })))
+    );
+    assert_eq!(
+        Some(FinderConfigError::StrideZero),
+        build(FinderBuilder::new().stride(0))
+    );
+    assert_eq!(
+        Some(FinderConfigError::StrideLargerThanWindow {
+            stride: 16,
+            window_size: 8
+        }),
+        build(
+            FinderBuilder::new()
+                .context_size(32)
+                .window_size(8)
+                .stride(16)
+        )
+    );
+}
+
+#[test]
+fn test_builder_diff_range() {
+    let padding = ".".repeat(64);
+    let input = format!("{}the quick brown fox{}", padding, padding);
+    let input: Vec<u8> = input.bytes().map(|b| b + 13).collect();
+    let phrases = [Phrase::from_strs(&["quick", "fox"])];
+    let count = |builder: FinderBuilder| {
+        let mut reader = input.as_slice();
+        builder.context_size(64).build(&phrases, &mut reader).unwrap().count()
+    };
+    assert_eq!(1, count(FinderBuilder::new()));
+    assert_eq!(1, count(FinderBuilder::new().diff_range(10..=20)));
+    assert_eq!(0, count(FinderBuilder::new().diff_range(-32..=12)));
+    assert_eq!(0, count(FinderBuilder::new().exact_only(true)));
+}
+
+#[test]
+fn test_builder_match_policy() {
+    let phrases = [Phrase::from_strs(&["quick", "fox"])];
+    let count = |input: &str, match_policy| {
+        let padding = ".".repeat(64);
+        let input = format!("{}{}{}", padding, input, padding);
+        let mut reader = input.as_bytes();
+        FinderBuilder::new()
+            .context_size(64)
+            .match_policy(match_policy)
+            .build(&phrases, &mut reader)
+            .unwrap()
+            .count()
+    };
+    assert_eq!(1, count("the quick brown fox", MatchPolicy::AnyOrder));
+    assert_eq!(1, count("the quick brown fox", MatchPolicy::Ordered));
+    assert_eq!(1, count("the fox was quick", MatchPolicy::AnyOrder));
+    assert_eq!(0, count("the fox was quick", MatchPolicy::Ordered));
+}
+
+#[test]
+fn test_builder_match_policy_width() {
+    // "ab" is only found 2 bytes wide, so the 1-byte "xz" before the 2-byte one can't stand in for it
+    let phrases = [Phrase::from_strs(&["ab", "xz"])];
+    let padding = [b'.'; 64];
+    let input = [&padding[..], b"a\0b\0xzx\0z\0", &padding[..]].concat();
+    let find = |match_policy| {
+        let mut reader = input.as_slice();
+        FinderBuilder::new()
+            .context_size(64)
+            .match_policy(match_policy)
+            .build(&phrases, &mut reader)
+            .unwrap()
+            .flat_map(|group| group.0)
+            .map(|instance| (instance.file_pos, instance.end_pos, instance.bytes_per_character))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec![(64, 74, 2)], find(MatchPolicy::AnyOrder));
+    assert_eq!(vec![(64, 74, 2)], find(MatchPolicy::Ordered));
+}
+
+#[test]
+fn test_builder_encoding() {
+    let phrases = [Phrase::from_strs(&["quick", "fox"])];
+    let padding = ".".repeat(64);
+    let input = format!("{}the quick brown fox{}", padding, padding);
+    let big_endian: Vec<u8> = input.bytes().flat_map(|b| [0, b]).collect();
+    let find = |input: &[u8], encoding| {
+        let mut reader = input;
+        FinderBuilder::new()
+            .context_size(64)
+            .encoding(encoding)
+            .build(&phrases, &mut reader)
+            .unwrap()
+            .flat_map(|group| group.0)
+            .map(|instance| (instance.file_pos, instance.bytes_per_character))
+            .collect::<Vec<_>>()
+    };
+    let encoding = |bytes_per_character, endianness| Some(Encoding { bytes_per_character, endianness });
+
+    assert_eq!(vec![(68, 1)], find(input.as_bytes(), None));
+    assert_eq!(vec![(68, 1)], find(input.as_bytes(), encoding(1, Endianness::Little)));
+    assert!(find(input.as_bytes(), encoding(2, Endianness::Little)).is_empty());
+
+    // Big-endian input is found one byte late, unless the encoding is known
+    assert_eq!(vec![(137, 2)], find(&big_endian, None));
+    assert_eq!(vec![(136, 2)], find(&big_endian, encoding(2, Endianness::Big)));
+}
+
+#[test]
+fn test_builder_widths() {
+    let padding = ".".repeat(64);
+    let input = format!("{}the quick brown fox{}", padding, padding);
+    let u16_le: Vec<u8> = input.bytes().flat_map(|b| [b, 0]).collect();
+    let find = |input: &[u8], phrase: Phrase, widths: Vec<u32>| {
+        let mut reader = input;
+        FinderBuilder::new()
+            .context_size(64)
+            .widths(widths)
+            .build(&[phrase], &mut reader)
+            .unwrap()
+            .flat_map(|group| group.0)
+            .map(|instance| (instance.file_pos, instance.bytes_per_character))
+            .collect::<Vec<_>>()
+    };
+    let phrase = Phrase::from_strs(&["quick", "fox"]);
+
+    // A phrase restricted to a width isn't found in data of another
+    assert_eq!(vec![(68, 1)], find(input.as_bytes(), phrase.clone().with_widths(vec![1]), vec![]));
+    assert!(find(&u16_le, phrase.clone().with_widths(vec![1]), vec![]).is_empty());
+    assert_eq!(vec![(136, 2)], find(&u16_le, phrase.clone().with_widths(vec![2]), vec![]));
+
+    // Same when the finder is restricted, and widths of both must allow a match
+    assert!(find(input.as_bytes(), phrase.clone(), vec![2]).is_empty());
+    assert_eq!(vec![(136, 2)], find(&u16_le, phrase.clone(), vec![2]));
+    assert!(find(&u16_le, phrase.clone().with_widths(vec![1]), vec![2]).is_empty());
+
+    let mut reader = input.as_bytes();
+    let result = FinderBuilder::new().widths(vec![1, 3]).build(&[phrase], &mut reader);
+    assert_eq!(Some(FinderConfigError::UnsupportedBytesPerCharacter(3)), result.err());
+}
+
+#[test]
+fn test_builder_encoder() {
+    use crate::{LinearEncoder2Be, LinearEncoder2Le};
+    let phrases = [Phrase::from_strs(&["quick", "fox"])];
+    let padding = ".".repeat(64);
+    let input = format!("{}the quick brown fox{}", padding, padding);
+    let little_endian: Vec<u8> = input.bytes().flat_map(|b| [b + 3, 0]).collect();
+    let big_endian: Vec<u8> = input.bytes().flat_map(|b| [0, b + 3]).collect();
+    fn find<E: Encoder>(phrases: &[Phrase], mut input: &[u8], encoder: E) -> Vec<(usize, i32, u32)> {
+        FinderBuilder::new()
+            .context_size(64)
+            .build_with_encoder(phrases, encoder, &mut input)
+            .unwrap()
+            .flat_map(|group| group.0)
+            .map(|instance| (instance.file_pos, instance.codepoint_diff, instance.bytes_per_character))
+            .collect()
+    }
+    assert_eq!(vec![(136, 3, 2)], find(&phrases, &little_endian, LinearEncoder2Le { diff: 3 }));
+    assert_eq!(vec![(136, 3, 2)], find(&phrases, &big_endian, LinearEncoder2Be { diff: 3 }));
+    assert!(find(&phrases, &little_endian, LinearEncoder2Le { diff: 0 }).is_empty());
+
+    // Every byte is tried, so big-endian input decodes as little-endian one byte late
+    assert_eq!(vec![(137, 3, 2)], find(&phrases, &big_endian, LinearEncoder2Le { diff: 3 }));
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::{FinderBuilder, FinderConfigError, Phrase, PhraseInstance};
+
+/// Reasons a two-pass search can fail
+#[derive(Debug)]
+pub enum CoarseSearchError {
+    Config(FinderConfigError),
+    Io(io::Error)
+}
+
+impl fmt::Display for CoarseSearchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Config(err) => write!(f, "Invalid finder configuration: {}", err),
+            Self::Io(err) => write!(f, "Failed to read input: {}", err)
+        }
+    }
+}
+
+impl std::error::Error for CoarseSearchError {}
+
+impl From<FinderConfigError> for CoarseSearchError {
+    fn from(err: FinderConfigError) -> Self { Self::Config(err) }
+}
+
+impl From<io::Error> for CoarseSearchError {
+    fn from(err: io::Error) -> Self { Self::Io(err) }
+}
+
+/// Searches `reader` from the start with the window sliding `stride` bytes at a time,
+/// then searches again one byte at a time around whatever that found. See [`refine_coarse_hits`].
+/// Phrases the coarse pass misses altogether stay missed.
+pub fn search_two_pass<R: Read + Seek>(
+    builder: FinderBuilder,
+    phrases: &[Phrase],
+    stride: usize,
+    reader: &mut R
+) -> Result<Vec<PhraseInstance>, CoarseSearchError> {
+    reader.seek(SeekFrom::Start(0))?;
+    let coarse: Vec<PhraseInstance> = builder
+        .clone()
+        .stride(stride)
+        .build(phrases, reader)?
+        .flat_map(|group| group.0)
+        .collect();
+    refine_coarse_hits(builder, phrases, &coarse, reader)
+}
+
+/// Searches the bytes within a window's length of each coarse hit again, one byte at a time,
+/// to recover the positions a search with the same `builder` and a stride of 1 would have found.
+/// Overlapping regions are searched once. Results are sorted by file position.
+/// Lines and columns are kept from coarse hits found at the same position, and are None otherwise.
+pub fn refine_coarse_hits<R: Read + Seek>(
+    builder: FinderBuilder,
+    phrases: &[Phrase],
+    coarse: &[PhraseInstance],
+    reader: &mut R
+) -> Result<Vec<PhraseInstance>, CoarseSearchError> {
+    let builder = builder.stride(1);
+    let mut empty: &[u8] = &[];
+    let window_size = builder.clone().build(phrases, &mut empty)?.window_size;
+
+    // Regions around each hit, merged where they overlap
+    let mut regions: Vec<(usize, usize)> = coarse
+        .iter()
+        .map(|hit| (hit.file_pos.saturating_sub(window_size), hit.end_pos + window_size))
+        .collect();
+    regions.sort();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(regions.len());
+    for (start, end) in regions {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end))
+        }
+    }
+
+    // Searches each region, moving the instances found to where the region starts
+    let positions: HashMap<(usize, usize), (Option<usize>, Option<usize>)> = coarse
+        .iter()
+        .map(|hit| ((hit.phrase_index, hit.file_pos), (hit.line, hit.column)))
+        .collect();
+    let mut refined = Vec::new();
+    for (start, end) in merged {
+        reader.seek(SeekFrom::Start(start as u64))?;
+        let mut bytes = Vec::with_capacity(end - start);
+        reader.by_ref().take((end - start) as u64).read_to_end(&mut bytes)?;
+        let mut region = bytes.as_slice();
+        let finder = builder.clone().build(phrases, &mut region)?;
+        for mut instance in finder.flat_map(|group| group.0) {
+            instance.file_pos += start;
+            instance.end_pos += start;
+            (instance.line, instance.column) = positions
+                .get(&(instance.phrase_index, instance.file_pos))
+                .copied()
+                .unwrap_or((None, None));
+            refined.push(instance);
+        }
+    }
+    refined.sort();
+    refined.dedup();
+    refined.sort_by_key(|instance| instance.file_pos);
+    Ok(refined)
+}
+
+
+#[test]
+fn test_stride_finds_long_phrase() {
+    let input: &[u8] = include_bytes!("test_text_2.txt");
+    let phrases = [Phrase::from_strs(&["within", "sunken", "deep"])];
+    let find = |stride: usize| {
+        let mut reader = input;
+        FinderBuilder::new()
+            .context_size(64)
+            .stride(stride)
+            .build(&phrases, &mut reader)
+            .unwrap()
+            .flat_map(|group| group.0)
+            .map(|instance| instance.file_pos)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec![285], find(1));
+    assert_eq!(vec![285], find(4));
+
+    // The phrase only just fits in the window, so coarser strides can step over it
+    assert!(find(16).is_empty());
+}
+
+#[test]
+fn test_refine_coarse_hits() {
+    use std::io::Cursor;
+    let input: &[u8] = include_bytes!("test_text_2.txt");
+    let phrases = [
+        Phrase::from_strs(&["within", "sunken", "deep"]),
+        Phrase::from_strs(&["sum", "my", "count"])
+    ];
+    let builder = FinderBuilder::new().context_size(64);
+    let mut reader = input;
+    let exact: Vec<PhraseInstance> = builder
+        .clone()
+        .build(&phrases, &mut reader)
+        .unwrap()
+        .flat_map(|group| group.0)
+        .collect();
+    assert_eq!(vec![285, 479], exact.iter().map(|instance| instance.file_pos).collect::<Vec<_>>());
+
+    // Refining recovers the same instances a search one byte at a time finds
+    let mut reader = Cursor::new(input);
+    let refined = search_two_pass(builder.clone(), &phrases, 4, &mut reader).unwrap();
+    let positions = |instances: &[PhraseInstance]| -> Vec<(usize, usize, usize)> {
+        instances.iter().map(|instance| (instance.phrase_index, instance.file_pos, instance.end_pos)).collect()
+    };
+    assert_eq!(positions(&exact), positions(&refined));
+
+    // Even from a hit that's off by a few bytes
+    let mut nudged = exact[0].clone();
+    nudged.file_pos += 3;
+    let refined = refine_coarse_hits(builder, &phrases, &[nudged], &mut reader).unwrap();
+    assert_eq!(285, refined[0].file_pos);
+    assert_eq!(None, refined[0].line);
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+
+use crate::{Phrase, SearchOptions, MAX_BYTES_PER_CHARACTER};
+
+/// Most work per byte of input a set of phrases is expected to need before it's refused.
+/// A hundred three-token phrases in a window of 64 come to about a third of it.
+pub const DEFAULT_COST_BUDGET: u64 = 100_000;
+
+/// Rough amount of work searching for a set of phrases takes for every byte of input.
+/// Every token is searched for at every position of the window, at every width, each time the window moves.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CostEstimate {
+    pub phrase_count: usize,
+    pub token_count: usize,     // Tokens across all phrases
+    pub window_size: usize,
+    pub per_byte: u64           // Positions compared per byte of input
+}
+
+impl CostEstimate {
+    pub fn exceeds(&self, budget: u64) -> bool {
+        self.per_byte > budget
+    }
+}
+
+impl fmt::Display for CostEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} phrases with {} tokens in a window of {} compare about {} positions per byte",
+            self.phrase_count, self.token_count, self.window_size, self.per_byte
+        )
+    }
+}
+
+/// Estimates the work searching for `phrases` with `options` takes per byte of input.
+/// Phrases restricted to fewer widths cost less.
+pub fn estimate_cost(phrases: &[Phrase], options: &SearchOptions) -> CostEstimate {
+    let token_count: usize = phrases.iter().map(|phrase| phrase.tokens.len() + phrase.not.len()).sum();
+    let token_widths: usize = phrases
+        .iter()
+        .map(|phrase| {
+            let widths = match phrase.widths.is_empty() {
+                true => MAX_BYTES_PER_CHARACTER,
+                false => phrase.widths.len().min(MAX_BYTES_PER_CHARACTER)
+            };
+            (phrase.tokens.len() + phrase.not.len()) * widths
+        })
+        .sum();
+    let window_size = options.window_size.min(options.context_size);
+    CostEstimate {
+        phrase_count: phrases.len(),
+        token_count,
+        window_size,
+        per_byte: token_widths as u64 * window_size as u64
+    }
+}
+
+
+#[test]
+fn test_estimate_cost() {
+    let options = SearchOptions { context_size: 128, window_size: 64 };
+
+    // Realistic phrase sets fit in the budget
+    let phrases: Vec<Phrase> = (0..100)
+        .map(|i| Phrase::from_strs(&["famine", "where", &format!("abundance{}", i)]))
+        .collect();
+    let estimate = estimate_cost(&phrases, &options);
+    assert_eq!(38_400, estimate.per_byte);
+    assert!(!estimate.exceeds(DEFAULT_COST_BUDGET));
+
+    // Thousands of tiny phrases don't, even in a small window
+    let tiny: Vec<Phrase> = (0..10_000)
+        .map(|i| Phrase::from_strs(&[&char::from(b'a' + (i % 26) as u8).to_string()]))
+        .collect();
+    let estimate = estimate_cost(&tiny, &SearchOptions { context_size: 16, window_size: 8 });
+    assert_eq!(160_000, estimate.per_byte);
+    assert!(estimate.exceeds(DEFAULT_COST_BUDGET));
+    assert!(estimate.to_string().contains("10000 phrases"));
+
+    // Restricting phrases to one width halves their cost
+    let ascii_only: Vec<Phrase> = phrases.into_iter().map(|phrase| phrase.with_widths(vec![1])).collect();
+    assert_eq!(19_200, estimate_cost(&ascii_only, &options).per_byte);
+}
@@ -0,0 +1,449 @@
+//! Request and response bodies of the server's JSON API, shared with anything that talks to it.
+//! Changing how these serialize changes the API, so the tests below pin their wire format.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+
+use crate::{Anchor, Encoding, Endianness, Phrase, PhraseError, PhraseInstance, PhraseRef, Text};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::PatchReport;
+
+/// Encodes a path as a string that decodes back to it exactly.
+/// Paths that are valid UTF-8 are kept as is. Others are written as their raw bytes (or UTF-16 units on Windows)
+/// in hex, after a NUL, which no real path contains.
+pub fn encode_path(path: &Path) -> String {
+    if let Some(str) = path.to_str() {
+        return str.to_owned();
+    }
+    #[cfg(unix)] {
+        use std::os::unix::ffi::OsStrExt;
+        let hex: String = path.as_os_str().as_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("\0u8:{}", hex)
+    }
+    #[cfg(windows)] {
+        use std::os::windows::ffi::OsStrExt;
+        let hex: String = path.as_os_str().encode_wide().map(|unit| format!("{:04x}", unit)).collect();
+        format!("\0u16:{}", hex)
+    }
+    #[cfg(not(any(unix, windows)))] {
+        path.to_string_lossy().into_owned()
+    }
+}
+
+/// Decodes a path written by [`encode_path`].
+/// Raw paths from another platform are decoded as text, replacing what isn't valid.
+pub fn decode_path(str: &str) -> Result<PathBuf, String> {
+    let Some(escaped) = str.strip_prefix('\0') else {
+        return Ok(PathBuf::from(str));
+    };
+    let invalid = || format!("Invalid escaped path '{}'", escaped);
+    let (width, hex) = match escaped.split_once(':') {
+        Some(("u8", hex)) => (2, hex),
+        Some(("u16", hex)) => (4, hex),
+        _ => return Err(invalid())
+    };
+    if !hex.is_ascii() || hex.len() % width != 0 {
+        return Err(invalid());
+    }
+    let units: Vec<u16> = (0..hex.len())
+        .step_by(width)
+        .map(|idx| u16::from_str_radix(&hex[idx..idx + width], 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    match width {
+        2 => Ok(path_from_bytes(units.iter().map(|unit| *unit as u8).collect())),
+        _ => Ok(path_from_wide(&units))
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(windows)]
+fn path_from_wide(units: &[u16]) -> PathBuf {
+    use std::os::windows::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_wide(units))
+}
+
+#[cfg(not(windows))]
+fn path_from_wide(units: &[u16]) -> PathBuf {
+    PathBuf::from(String::from_utf16_lossy(units))
+}
+
+/// A path, as listed.
+/// `path` is for display, with anything that isn't valid UTF-8 replaced. `encoded_path` names the file exactly.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FilePath {
+    pub path: String,
+    pub encoded_path: String    // See encode_path
+}
+
+impl FilePath {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_string_lossy().into_owned(),
+            encoded_path: encode_path(path)
+        }
+    }
+
+    /// The path named exactly, or the display path if it can't be decoded
+    pub fn to_path(&self) -> PathBuf {
+        decode_path(&self.encoded_path).unwrap_or_else(|_| PathBuf::from(&self.path))
+    }
+}
+
+/// Encoding a tracked file is known to use
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FileEncoding {
+    pub bytes_per_character: u32,
+    #[serde(default)]
+    pub endianness: Endianness
+}
+
+impl FileEncoding {
+    pub fn encoding(&self) -> Encoding {
+        Encoding {
+            bytes_per_character: self.bytes_per_character,
+            endianness: self.endianness
+        }
+    }
+}
+
+/// A tracked file, as listed.
+/// `path` is for display, with anything that isn't valid UTF-8 replaced. `encoded_path` names the file exactly.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TrackedFile {
+    pub path: String,
+    pub encoded_path: String,   // See encode_path
+    pub encoding: Option<FileEncoding>
+}
+
+impl TrackedFile {
+    pub fn new(path: &Path, encoding: Option<FileEncoding>) -> Self {
+        Self {
+            path: path.to_string_lossy().into_owned(),
+            encoded_path: encode_path(path),
+            encoding
+        }
+    }
+}
+
+/// Encoding to set on a tracked file
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TrackedFileEncoding {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub encoding: FileEncoding
+}
+
+/// Files that started being tracked, and what walking a directory for them came across
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AddedFiles {
+    pub added: Vec<FilePath>,
+    pub dirs_visited: usize,
+    pub skipped: usize,         // Entries left out by the walk limits, and anything that's neither a file nor a directory
+    pub truncated: bool,        // The walk stopped at the most entries allowed
+    pub errors: Vec<String>     // Entries that couldn't be read
+}
+
+/// How many files stopped being tracked
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RemovedFiles {
+    pub removed: usize
+}
+
+/// A phrase sent by a client, as a string or with exclusions, an anchor and the widths it can be found with.
+/// Tokens are separated by whitespace.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum PhraseBody {
+    Text(String),
+    Detailed {
+        phrase: String,
+        #[serde(default)]
+        not: Vec<String>,
+        #[serde(default)]
+        anchor: Anchor,
+        #[serde(default)]
+        allow_short_tokens: bool,
+        #[serde(default)]
+        widths: Vec<u32>
+    }
+}
+
+impl PhraseBody {
+
+    /// The phrase sent. Fails if it has no tokens.
+    pub fn into_phrase(self) -> Result<Phrase, PhraseError> {
+        let tokens = |str: &str| -> Vec<Text> { str.split_whitespace().map(Text::from_str).collect() };
+        match self {
+            Self::Text(phrase) => Phrase::try_new(tokens(&phrase)),
+            Self::Detailed { phrase, not, anchor, allow_short_tokens, widths } => {
+                let not = not.iter().flat_map(|str| tokens(str)).collect();
+                let phrase = Phrase::try_new(tokens(&phrase))?
+                    .with_not(not)
+                    .with_anchor(anchor)
+                    .with_allow_short_tokens(allow_short_tokens)
+                    .with_widths(widths);
+                Ok(phrase)
+            }
+        }
+    }
+}
+
+/// Body that sends the phrase, as a string when it has nothing but tokens
+impl From<&Phrase> for PhraseBody {
+    fn from(phrase: &Phrase) -> Self {
+        let is_plain = phrase.not.is_empty() && phrase.anchor == Anchor::None && !phrase.allow_short_tokens && phrase.widths.is_empty();
+        let join = |texts: &[Text]| texts.iter().map(|text| text.to_string()).collect::<Vec<_>>().join(" ");
+        match is_plain {
+            true => Self::Text(join(&phrase.tokens)),
+            false => Self::Detailed {
+                phrase: join(&phrase.tokens),
+                not: phrase.not.iter().map(|text| text.to_string()).collect(),
+                anchor: phrase.anchor,
+                allow_short_tokens: phrase.allow_short_tokens,
+                widths: phrase.widths.clone()
+            }
+        }
+    }
+}
+
+impl TryFrom<PhraseBody> for Phrase {
+    type Error = PhraseError;
+    fn try_from(body: PhraseBody) -> Result<Self, Self::Error> {
+        body.into_phrase()
+    }
+}
+
+/// A phrase that was added, or was already there
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AddedPhrase {
+    pub id: String,         // See Phrase::id
+    pub duplicate: bool     // The phrase was already there, so nothing changed
+}
+
+/// XOR of the hashes of every tracked phrase
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PhraseFingerprint {
+    pub fingerprint: String     // Hex, like Phrase::id, since JSON numbers can't hold every u64
+}
+
+impl PhraseFingerprint {
+    /// Fingerprint of phrases with these [`Phrase::stable_hash`]es
+    pub fn new(hashes: impl IntoIterator<Item=u64>) -> Self {
+        let fingerprint = hashes.into_iter().fold(0, |fingerprint, hash| fingerprint ^ hash);
+        Self { fingerprint: format!("{:016x}", fingerprint) }
+    }
+}
+
+/// A phrase instance along with the phrase it matched
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MatchedInstance {
+    #[serde(flatten)]
+    pub instance: PhraseInstance,
+    pub len_bytes: usize,
+    pub phrase: PhraseRef
+}
+
+impl MatchedInstance {
+    pub fn new(instance: PhraseInstance, phrase: &Phrase) -> Self {
+        Self { len_bytes: instance.len_bytes(), instance, phrase: PhraseRef::new(phrase) }
+    }
+}
+
+/// How many matches a file had, and which phrases they were of
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FileSummary {
+    pub path: PathBuf,
+    pub match_count: usize,
+    pub phrases_found: Vec<String>
+}
+
+/// Where to export search results to
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ExportRequest {
+    pub output_path: PathBuf
+}
+
+/// How many results were exported, and where to
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ExportSummary {
+    pub written_records: usize,
+    pub output_path: PathBuf
+}
+
+/// Tracked files with the same contents
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DuplicateFiles {
+    pub hash: String,           // Prefixed with the algorithm used, like "blake3:"
+    pub len: u64,               // Size of each file in bytes
+    pub files: Vec<FilePath>    // Sorted by path
+}
+
+/// A page of a file, decoded
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FileContent {
+    pub text: String,
+    pub offset: u64,                // Where in the file the page starts
+    pub length: usize,              // Bytes the text was decoded from
+    pub next_offset: Option<u64>,   // Where the next page starts. None at the end of the file.
+    pub total_size: u64             // Size of the whole file in bytes
+}
+
+/// Text to write over part of a file. See [`crate::patch_at`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PatchRequest {
+    pub path: PathBuf,
+    pub pos: u64,
+    pub text: String,
+    #[serde(default)]
+    pub diff: i32,
+    #[serde(default = "default_bpc")]
+    pub bpc: u32,
+    #[serde(default)]
+    pub pad_to: Option<usize>,
+    #[serde(default)]
+    pub terminator: Option<u8>
+}
+
+fn default_bpc() -> u32 { 1 }
+
+/// What a patch wrote, and what it overwrote, as hex
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PatchedFile {
+    pub pos: u64,
+    pub old_bytes: String,  // Writing these back at pos undoes the patch
+    pub new_bytes: String
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<&PatchReport> for PatchedFile {
+    fn from(report: &PatchReport) -> Self {
+        let hex = |bytes: &[u8]| -> String { bytes.iter().map(|byte| format!("{:02x}", byte)).collect() };
+        Self {
+            pos: report.pos,
+            old_bytes: hex(&report.old_bytes),
+            new_bytes: hex(&report.new_bytes)
+        }
+    }
+}
+
+
+/// A logged operation
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct OperationEntry {
+    pub id: u64,
+    pub timestamp: u64,             // Seconds since the Unix epoch
+    pub kind: String,               // remove_files, restore_files, remove_phrase or restore_phrase
+    pub files: Vec<FilePath>,       // Files removed or restored
+    pub phrases: Vec<PhraseRef>,    // Phrases removed or restored
+    pub undoes: Option<u64>,        // Operation this one undid
+    pub undone_by: Option<u64>      // Operation that undid this one
+}
+
+/// How often scans reused the prepared phrases
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64
+}
+
+/// Status of the service
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Health {
+    pub status: String,
+    pub uptime_secs: f64,           // Seconds since the service started
+    pub persist_file_bytes: u64,    // 0 if it hasn't been written yet
+    pub file_count: usize,
+    pub phrase_count: usize,
+    pub phrase_cache: CacheStats
+}
+
+#[cfg(test)]
+use serde_json::json;
+
+#[test]
+fn test_response_wire_format() {
+    let file = FilePath::new(Path::new("dir/file.txt"));
+    assert_eq!(json!({ "path": "dir/file.txt", "encoded_path": "dir/file.txt" }), json!(file));
+    let added = AddedFiles { added: vec![file.clone()], dirs_visited: 1, skipped: 2, truncated: false, errors: vec!["denied".to_owned()] };
+    assert_eq!(
+        json!({ "added": [{ "path": "dir/file.txt", "encoded_path": "dir/file.txt" }], "dirs_visited": 1, "skipped": 2, "truncated": false, "errors": ["denied"] }),
+        json!(added)
+    );
+    assert_eq!(json!({ "removed": 1 }), json!(RemovedFiles { removed: 1 }));
+    let encoding = FileEncoding { bytes_per_character: 2, endianness: Endianness::Big };
+    assert_eq!(
+        json!({ "path": "dir/file.txt", "encoded_path": "dir/file.txt", "encoding": { "bytes_per_character": 2, "endianness": "big" } }),
+        json!(TrackedFile::new(Path::new("dir/file.txt"), Some(encoding)))
+    );
+    let operation = OperationEntry { id: 2, timestamp: 60, kind: "restore_files".to_owned(), files: vec![file.clone()], phrases: Vec::new(), undoes: Some(1), undone_by: None };
+    assert_eq!(
+        json!({
+            "id": 2, "timestamp": 60, "kind": "restore_files", "files": [{ "path": "dir/file.txt", "encoded_path": "dir/file.txt" }], "phrases": [],
+            "undoes": 1, "undone_by": null
+        }),
+        json!(operation)
+    );
+    let health = Health { status: "ok".to_owned(), uptime_secs: 1.5, persist_file_bytes: 0, file_count: 1, phrase_count: 2, phrase_cache: CacheStats { hits: 3, misses: 1 } };
+    assert_eq!(
+        json!({ "status": "ok", "uptime_secs": 1.5, "persist_file_bytes": 0, "file_count": 1, "phrase_count": 2, "phrase_cache": { "hits": 3, "misses": 1 } }),
+        json!(health)
+    );
+
+    let phrase = Phrase::from_strs(&["famine", "where"]);
+    assert_eq!(json!({ "id": phrase.id(), "duplicate": false }), json!(AddedPhrase { id: phrase.id(), duplicate: false }));
+    assert_eq!(json!({ "fingerprint": "0000000000000003" }), json!(PhraseFingerprint::new([1, 2])));
+    let instance = PhraseInstance { phrase_index: 0, file_pos: 288, end_pos: 300, codepoint_diff: 0, bytes_per_character: 1, line: Some(9), column: Some(10) };
+    assert_eq!(
+        json!({
+            "phrase_index": 0, "file_pos": 288, "end_pos": 300, "codepoint_diff": 0, "bytes_per_character": 1, "line": 9, "column": 10,
+            "len_bytes": 12,
+            "phrase": { "id": phrase.id(), "text": "famine where" }
+        }),
+        json!(MatchedInstance::new(instance, &phrase))
+    );
+
+    let summary = FileSummary { path: PathBuf::from("a.txt"), match_count: 1, phrases_found: vec!["famine where".to_owned()] };
+    assert_eq!(json!({ "path": "a.txt", "match_count": 1, "phrases_found": ["famine where"] }), json!(summary));
+    assert_eq!(json!({ "written_records": 2, "output_path": "out.csv" }), json!(ExportSummary { written_records: 2, output_path: PathBuf::from("out.csv") }));
+    let content = FileContent { text: "famine".to_owned(), offset: 288, length: 6, next_offset: None, total_size: 294 };
+    assert_eq!(json!({ "text": "famine", "offset": 288, "length": 6, "next_offset": null, "total_size": 294 }), json!(content));
+    let report = PatchReport { pos: 4, old_bytes: vec![0x61, 0x0a], new_bytes: vec![0x62, 0] };
+    assert_eq!(json!({ "pos": 4, "old_bytes": "610a", "new_bytes": "6200" }), json!(PatchedFile::from(&report)));
+}
+
+#[test]
+fn test_request_wire_format() {
+    let parse = |value: serde_json::Value| serde_json::from_value::<PhraseBody>(value).unwrap().into_phrase();
+    let phrase = Phrase::from_strs(&["famine", "where"]);
+    assert_eq!(Ok(phrase.clone()), parse(json!("famine  where")));
+    assert_eq!(json!("famine where"), json!(PhraseBody::from(&phrase)));
+    let detailed = phrase.clone().with_not(vec![Text::from_str("lies")]).with_anchor(Anchor::LineStart).with_widths(vec![1]);
+    let body = json!({ "phrase": "famine where", "not": ["lies"], "anchor": "line_start", "allow_short_tokens": false, "widths": [1] });
+    assert_eq!(body, json!(PhraseBody::from(&detailed)));
+    assert_eq!(Ok(detailed), parse(body));
+    assert_eq!(Ok(phrase), parse(json!({ "phrase": "famine where" })));
+    assert_eq!(Err(PhraseError::NoTokens), parse(json!("  ")));
+
+    let patch: PatchRequest = serde_json::from_value(json!({ "path": "a.bin", "pos": 4, "text": "bye" })).unwrap();
+    assert_eq!(PatchRequest { path: PathBuf::from("a.bin"), pos: 4, text: "bye".to_owned(), diff: 0, bpc: 1, pad_to: None, terminator: None }, patch);
+    let encoding: TrackedFileEncoding = serde_json::from_value(json!({ "path": "a.bin", "bytes_per_character": 2 })).unwrap();
+    assert_eq!(PathBuf::from("a.bin"), encoding.path);
+    assert_eq!(FileEncoding { bytes_per_character: 2, endianness: Endianness::Little }, encoding.encoding);
+    let export: ExportRequest = serde_json::from_value(json!({ "output_path": "out.csv" })).unwrap();
+    assert_eq!(PathBuf::from("out.csv"), export.output_path);
+    let file: FilePath = serde_json::from_value(json!({ "path": "caf?", "encoded_path": "\u{0}u8:636166c3a9" })).unwrap();
+    assert_eq!(PathBuf::from("caf\u{e9}"), file.to_path());
+}
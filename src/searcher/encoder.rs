@@ -0,0 +1,71 @@
+/// Maps the bytes of an input to codepoints, for inputs whose encoding is known.
+pub trait Encoder {
+
+    /// Decodes the character starting at `byte_idx`. Returns the codepoint and the number of bytes it spans,
+    /// or `None` if the bytes there don't decode to a character.
+    fn decode_codepoint(&self, slice: &[u8], byte_idx: usize) -> Option<(u32, usize)>;
+
+    /// Width reported for instances found with this encoder
+    fn bytes_per_char(&self) -> u32;
+
+    /// Codepoint diff reported for instances found with this encoder
+    fn codepoint_diff(&self) -> i32 { 0 }
+}
+
+/// 1 byte per character, offset from the codepoint by `diff`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct LinearEncoder1 { pub diff: i32 }
+
+/// 2 bytes per character in little-endian order, offset from the codepoint by `diff`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct LinearEncoder2Le { pub diff: i32 }
+
+/// 2 bytes per character in big-endian order, offset from the codepoint by `diff`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct LinearEncoder2Be { pub diff: i32 }
+
+impl Encoder for LinearEncoder1 {
+    fn decode_codepoint(&self, slice: &[u8], byte_idx: usize) -> Option<(u32, usize)> {
+        let raw = *slice.get(byte_idx)? as i32;
+        undo_diff(raw, self.diff).map(|codepoint| (codepoint, 1))
+    }
+    fn bytes_per_char(&self) -> u32 { 1 }
+    fn codepoint_diff(&self) -> i32 { self.diff }
+}
+
+impl Encoder for LinearEncoder2Le {
+    fn decode_codepoint(&self, slice: &[u8], byte_idx: usize) -> Option<(u32, usize)> {
+        let bytes = slice.get(byte_idx..byte_idx + 2)?;
+        let raw = u16::from_le_bytes([bytes[0], bytes[1]]) as i32;
+        undo_diff(raw, self.diff).map(|codepoint| (codepoint, 2))
+    }
+    fn bytes_per_char(&self) -> u32 { 2 }
+    fn codepoint_diff(&self) -> i32 { self.diff }
+}
+
+impl Encoder for LinearEncoder2Be {
+    fn decode_codepoint(&self, slice: &[u8], byte_idx: usize) -> Option<(u32, usize)> {
+        let bytes = slice.get(byte_idx..byte_idx + 2)?;
+        let raw = u16::from_be_bytes([bytes[0], bytes[1]]) as i32;
+        undo_diff(raw, self.diff).map(|codepoint| (codepoint, 2))
+    }
+    fn bytes_per_char(&self) -> u32 { 2 }
+    fn codepoint_diff(&self) -> i32 { self.diff }
+}
+
+// Codepoint that encodes to raw under the diff, if it isn't negative
+fn undo_diff(raw: i32, diff: i32) -> Option<u32> {
+    u32::try_from(raw.checked_sub(diff)?).ok()
+}
+
+
+#[test]
+fn test_linear_encoders() {
+    let input = [b'a' + 1, 0, b'b' + 1];
+    assert_eq!(Some((b'a' as u32, 1)), LinearEncoder1 { diff: 1 }.decode_codepoint(&input, 0));
+    assert_eq!(None, LinearEncoder1 { diff: 1 }.decode_codepoint(&input, 1));
+    assert_eq!(None, LinearEncoder1 { diff: 1 }.decode_codepoint(&input, 3));
+    assert_eq!(Some((b'a' as u32, 2)), LinearEncoder2Le { diff: 1 }.decode_codepoint(&input, 0));
+    assert_eq!(Some((b'b' as u32, 2)), LinearEncoder2Be { diff: 1 }.decode_codepoint(&input, 1));
+    assert_eq!(None, LinearEncoder2Be { diff: 1 }.decode_codepoint(&input, 2));
+}
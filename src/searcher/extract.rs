@@ -0,0 +1,194 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::Text;
+
+/// Extracts the string that the character at `pos` lives in.
+/// Walks forward from `pos` until a terminator is hit, then backward until a terminator is hit,
+/// stepping `bytes_per_character` bytes at a time. Terminators are compared against the raw
+/// character values (before `codepoint_diff` is applied), so a 0x00 terminator is 0x0000 in a 2-byte file.
+/// The resulting string is at most `max_len` characters long. Characters from `pos` onward are kept first.
+/// Empty if `pos` is past the end of `slice`.
+pub fn extract_string_in(
+    slice: &[u8],
+    pos: usize,
+    terminators: &[u8],
+    max_len: usize,
+    codepoint_diff: i32,
+    bytes_per_character: u32
+) -> Text {
+    let bpc = bytes_per_character as usize;
+    if pos > slice.len() {
+        return Text::from_codepoints(Vec::new());
+    }
+    let is_terminator = |idx: usize| -> bool {
+        let raw = raw_char_at(slice, idx, bpc);
+        terminators.iter().any(|term| *term as u32 == raw)
+    };
+
+    // Walks forward, starting with the character at pos
+    let mut end = pos;
+    let mut len = 0;
+    while len < max_len && end + bpc <= slice.len() && !is_terminator(end) {
+        end += bpc;
+        len += 1;
+    }
+
+    // Walks backward, using whatever length remains
+    let mut start = pos;
+    while len < max_len && start >= bpc && !is_terminator(start - bpc) {
+        start -= bpc;
+        len += 1;
+    }
+
+    Text::from_slice(&slice[start..end], codepoint_diff, bytes_per_character)
+}
+
+/// Same as [`extract_string_in`], but reads the bytes around `pos` from a reader.
+/// Only reads as many bytes as `max_len` characters could span on either side of `pos`.
+/// Fails with [`io::ErrorKind::InvalidInput`] unless `bytes_per_character` is 1 or 2.
+pub fn extract_string_at<R: Read + Seek>(
+    reader: &mut R,
+    pos: u64,
+    terminators: &[u8],
+    max_len: usize,
+    codepoint_diff: i32,
+    bytes_per_character: u32
+) -> io::Result<Text> {
+    let (bytes, pos_in_bytes) = read_around(reader, pos, max_len, bytes_per_character)?;
+    Ok(extract_string_in(&bytes, pos_in_bytes, terminators, max_len, codepoint_diff, bytes_per_character))
+}
+
+/// Reads up to `chars` characters on either side of `pos` and decodes them as a [`Text`].
+/// Fails with [`io::ErrorKind::InvalidInput`] unless `bytes_per_character` is 1 or 2.
+pub fn read_context_at<R: Read + Seek>(
+    reader: &mut R,
+    pos: u64,
+    chars: usize,
+    codepoint_diff: i32,
+    bytes_per_character: u32
+) -> io::Result<Text> {
+    let (bytes, _) = read_around(reader, pos, chars, bytes_per_character)?;
+    let whole_chars = bytes.len() - bytes.len() % bytes_per_character as usize;
+    Ok(Text::from_slice(&bytes[..whole_chars], codepoint_diff, bytes_per_character))
+}
+
+/// Reads up to `len_bytes` bytes from `pos` and decodes them as a [`Text`], leaving off a partial character at the end.
+/// Returns the text and how many bytes it was decoded from, which is 0 at the end of the input.
+/// Fails with [`io::ErrorKind::InvalidInput`] unless `bytes_per_character` is 1 or 2.
+pub fn read_text_at<R: Read + Seek>(
+    reader: &mut R,
+    pos: u64,
+    len_bytes: usize,
+    codepoint_diff: i32,
+    bytes_per_character: u32
+) -> io::Result<(Text, usize)> {
+    check_bytes_per_character(bytes_per_character)?;
+    reader.seek(SeekFrom::Start(pos))?;
+    let mut bytes = Vec::with_capacity(len_bytes);
+    reader.take(len_bytes as u64).read_to_end(&mut bytes)?;
+    let whole_chars = bytes.len() - bytes.len() % bytes_per_character as usize;
+    Ok((Text::from_slice(&bytes[..whole_chars], codepoint_diff, bytes_per_character), whole_chars))
+}
+
+// Reads up to `chars` characters on either side of `pos`, keeping the start aligned to `pos`.
+// Returns the bytes read and the index of `pos` within them.
+fn read_around<R: Read + Seek>(
+    reader: &mut R,
+    pos: u64,
+    chars: usize,
+    bytes_per_character: u32
+) -> io::Result<(Vec<u8>, usize)> {
+    check_bytes_per_character(bytes_per_character)?;
+    let bpc = bytes_per_character as u64;
+    let span = chars as u64 * bpc;
+    let start = if pos >= span { pos - span } else { pos % bpc };
+    reader.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    reader.take(pos - start + span).read_to_end(&mut bytes)?;
+    Ok((bytes, (pos - start) as usize))
+}
+
+// Fails unless the width is one text can be decoded at
+fn check_bytes_per_character(bytes_per_character: u32) -> io::Result<()> {
+    match bytes_per_character {
+        1 | 2 => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid bytes per character {}. Must be 1 or 2", bytes_per_character)
+        ))
+    }
+}
+
+// Raw value of the character at byte index idx. Multi-byte characters are little-endian.
+fn raw_char_at(slice: &[u8], idx: usize, bytes_per_character: usize) -> u32 {
+    slice[idx..idx + bytes_per_character]
+        .iter()
+        .rev()
+        .fold(0, |acc, byte| (acc << 8) | *byte as u32)
+}
+
+
+#[test]
+fn test_extract_string_flush_against_terminator() {
+    let input: &[u8] = b"\0\0first\0second\0third";
+    let expected = Text::from_str("second");
+
+    // Match at the first character, right after a terminator
+    assert_eq!(expected, extract_string_in(input, 8, &[0], 64, 0, 1));
+
+    // Match at the last character, right before a terminator
+    assert_eq!(expected, extract_string_in(input, 13, &[0], 64, 0, 1));
+
+    // Last string runs until EOF
+    assert_eq!(Text::from_str("third"), extract_string_in(input, 17, &[0], 64, 0, 1));
+}
+
+#[test]
+fn test_extract_string_exceeding_max_len() {
+    let input: &[u8] = b"\0a long string without an end";
+    assert_eq!(Text::from_str("string"), extract_string_in(input, 8, &[0], 6, 0, 1));
+
+    // Remaining length is spent walking backward once the end is found
+    let input: &[u8] = b"\0a long string\0";
+    assert_eq!(Text::from_str("long string"), extract_string_in(input, 8, &[0], 11, 0, 1));
+}
+
+#[test]
+fn test_extract_string_past_end() {
+    use std::io::Cursor;
+    let input: &[u8] = b"\0first\0second";
+    assert_eq!(Text::from_str(""), extract_string_in(input, 20, &[0], 64, 0, 1));
+    assert_eq!(Text::from_str("second"), extract_string_in(input, input.len(), &[0], 64, 0, 1));
+    let mut reader = Cursor::new(input.to_vec());
+    assert_eq!(Text::from_str(""), extract_string_at(&mut reader, 1000, &[0], 64, 0, 1).unwrap());
+    assert_eq!(Text::from_str(""), extract_string_at(&mut reader, 1000, &[0], 64, 0, 2).unwrap());
+}
+
+#[test]
+fn test_read_text_at() {
+    use std::io::Cursor;
+    let mut reader = Cursor::new(b"a\0b\0c\0d".to_vec());
+    assert_eq!((Text::from_str("bc"), 4), read_text_at(&mut reader, 2, 5, 0, 2).unwrap());
+    assert_eq!((Text::from_str("c"), 2), read_text_at(&mut reader, 4, 3, 0, 2).unwrap());
+    assert_eq!((Text::from_str("d"), 1), read_text_at(&mut reader, 6, 64, 0, 1).unwrap());
+    assert_eq!((Text::from_str(""), 0), read_text_at(&mut reader, 7, 64, 0, 1).unwrap());
+
+    // Widths text can't be decoded at are refused rather than panicking
+    for bpc in [0, 3, 4] {
+        assert_eq!(io::ErrorKind::InvalidInput, read_text_at(&mut reader, 2, 4, 0, bpc).unwrap_err().kind());
+        assert_eq!(io::ErrorKind::InvalidInput, read_context_at(&mut reader, 2, 4, 0, bpc).unwrap_err().kind());
+        assert_eq!(io::ErrorKind::InvalidInput, extract_string_at(&mut reader, 2, &[0], 4, 0, bpc).unwrap_err().kind());
+    }
+}
+
+#[test]
+fn test_extract_string_2bytes_rotated() {
+    use std::io::Cursor;
+    let input: Vec<u8> = b"\0\0first\0second\0third"
+        .iter()
+        .flat_map(|b| if *b == 0 { [0, 0] } else { [*b + 13, 0] })
+        .collect();
+    let mut reader = Cursor::new(input);
+    let text = extract_string_at(&mut reader, 20, &[0], 64, 13, 2).unwrap();
+    assert_eq!(Text::from_str("second"), text);
+}
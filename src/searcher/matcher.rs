@@ -0,0 +1,210 @@
+use std::ops::RangeInclusive;
+
+use crate::{Encoder, Encoding, LinearEncoder1, MatchPolicy, Phrase, PhraseInstance, RejectReason, TokenInstance, MAX_BYTES_PER_CHARACTER};
+use super::trace::diagnose_phrase;
+use super::{line_breaks, match_phrase, WidthMask};
+
+/// How a [`Matcher`] matches phrases. Defaults to every codepoint diff and width, with tokens in any order.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MatchOptions {
+    pub diff_range: RangeInclusive<i32>,    // Codepoint diffs that are allowed to match
+    pub match_policy: MatchPolicy,          // How tokens must be laid out in the window
+    pub encoding: Option<Encoding>,         // Layout of the characters, if known
+    pub widths: Vec<u32>                    // Bytes per character phrases can be found with. Empty for every supported width.
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        Self {
+            diff_range: i32::MIN..=i32::MAX,
+            match_policy: MatchPolicy::default(),
+            encoding: None,
+            widths: Vec::new()
+        }
+    }
+}
+
+/// Matches phrases within a buffer. A [`crate::Finder`] runs one over every window of its input.
+/// `E` defaults to [`LinearEncoder1`] only so matchers made with [`Matcher::new`] have a type to name. They never decode with it.
+#[derive(Debug, Clone)]
+pub struct Matcher<E: Encoder = LinearEncoder1> {
+    phrases: Vec<Phrase>,
+    options: MatchOptions,
+    encoder: Option<E>      // Decodes characters, if the encoding is fully known
+}
+
+impl Matcher {
+    pub fn new(phrases: &[Phrase], options: MatchOptions) -> Self {
+        Self::from_parts(phrases, options, None)
+    }
+}
+
+impl<E: Encoder> Matcher<E> {
+
+    /// Decodes characters with `encoder` instead of trying every width and diff.
+    /// The diff range and encoding of the options are ignored.
+    pub fn with_encoder(phrases: &[Phrase], options: MatchOptions, encoder: E) -> Self {
+        Self::from_parts(phrases, options, Some(encoder))
+    }
+
+    pub(crate) fn from_parts(phrases: &[Phrase], options: MatchOptions, encoder: Option<E>) -> Self {
+        Self {
+            phrases: phrases.to_vec(),
+            options,
+            encoder
+        }
+    }
+
+    pub fn phrases(&self) -> &[Phrase] { &self.phrases }
+
+    /// Replaces the phrases to match
+    pub fn set_phrases(&mut self, phrases: &[Phrase]) {
+        self.phrases = phrases.to_vec();
+    }
+
+    /// Searches `window` for every phrase, reporting each at most once, at its earliest token.
+    /// Positions are offset by `base_offset`. The start of the window counts as the start of the input,
+    /// both for anchors and for lines and columns.
+    pub fn find_in(&self, window: &[u8], base_offset: usize) -> Vec<PhraseInstance> {
+        (0..self.phrases.len())
+            .filter_map(|phrase_index| self.find_phrase_in(phrase_index, window, base_offset))
+            .collect()
+    }
+
+    /// Same as [`Self::find_in`], but phrases are matched in parallel, each on its own thread from rayon's pool.
+    /// Only worth it for large sets of phrases, as each phrase is quick to match on its own.
+    #[cfg(feature = "rayon")]
+    pub fn find_in_parallel(&self, window: &[u8], base_offset: usize) -> Vec<PhraseInstance>
+    where E: Sync {
+        use rayon::prelude::*;
+        (0..self.phrases.len())
+            .into_par_iter()
+            .filter_map(|phrase_index| self.find_phrase_in(phrase_index, window, base_offset))
+            .collect()
+    }
+
+    // Searches `window` for a single phrase. See find_in.
+    fn find_phrase_in(&self, phrase_index: usize, window: &[u8], base_offset: usize) -> Option<PhraseInstance> {
+        let preceding = |idx: usize| window[idx.saturating_sub(MAX_BYTES_PER_CHARACTER)..idx].to_vec();
+        let (found, end) = self.match_phrase(phrase_index, window, preceding)?;
+        let (line, column) = match found.bytes_per_character {
+            1 => {
+                let (newlines, last_newline) = line_breaks(&window[..found.index], found.codepoint_diff);
+                let line_start = last_newline.map_or(0, |idx| idx + 1);
+                (Some(newlines + 1), Some(found.index - line_start + 1))
+            },
+            _ => (None, None)
+        };
+        Some(PhraseInstance {
+            phrase_index,
+            file_pos: base_offset + found.index,
+            end_pos: base_offset + end,
+            codepoint_diff: found.codepoint_diff,
+            bytes_per_character: found.bytes_per_character,
+            line,
+            column
+        })
+    }
+
+    // Matches a single phrase in the window. Returns its earliest token and the end of its furthest one.
+    // `preceding` gives the bytes before an index of the window, which the phrase's anchor checks.
+    pub(crate) fn match_phrase(
+        &self,
+        phrase_index: usize,
+        window: &[u8],
+        preceding: impl FnOnce(usize) -> Vec<u8>
+    ) -> Option<(TokenInstance, usize)> {
+        let phrase = &self.phrases[phrase_index];
+        let options = &self.options;
+        let (found, end) = match_phrase(
+            phrase,
+            window,
+            &options.diff_range,
+            options.match_policy,
+            options.encoding,
+            WidthMask::from_widths(&options.widths),
+            self.encoder.as_ref()
+        )?;
+        let preceding = preceding(found.index);
+        phrase.anchor
+            .accepts(&preceding, found.codepoint_diff, found.bytes_per_character)
+            .then_some((found, end))
+    }
+
+    // Works out why a single phrase doesn't match the window, if it doesn't.
+    // Also returns the diffs its tokens were found with.
+    pub(crate) fn diagnose(
+        &self,
+        phrase_index: usize,
+        window: &[u8],
+        preceding: impl FnOnce(usize) -> Vec<u8>
+    ) -> (Option<RejectReason>, Vec<i32>) {
+        let phrase = &self.phrases[phrase_index];
+        let (reason, diffs) = diagnose_phrase(phrase, window, &self.options, self.encoder.as_ref());
+        if reason.is_some() {
+            return (reason, diffs);
+        }
+        let accepted = self.match_phrase(phrase_index, window, preceding).is_some();
+        (if accepted { None } else { Some(RejectReason::AnchorMismatch) }, diffs)
+    }
+}
+
+/// Searches `window` for every phrase in parallel, with every diff and width allowed. See [`Matcher::find_in_parallel`].
+/// Positions are within the window.
+#[cfg(feature = "rayon")]
+pub fn search_parallel_phrases(phrases: &[Phrase], window: &[u8]) -> Vec<PhraseInstance> {
+    Matcher::new(phrases, MatchOptions::default()).find_in_parallel(window, 0)
+}
+
+
+#[test]
+fn test_matcher_earliest_token() {
+    let phrases = [Phrase::from_strs(&["quick", "fox"]), Phrase::from_strs(&["brown"])];
+    let matcher = Matcher::new(&phrases, MatchOptions::default());
+
+    // Reported at the earliest token, even when it's the last one in the phrase
+    let found = matcher.find_in(b"the fox was quick and brown", 100);
+    assert_eq!(2, found.len());
+    assert_eq!((0, 104, 117), (found[0].phrase_index, found[0].file_pos, found[0].end_pos));
+    assert_eq!((1, 122, 127), (found[1].phrase_index, found[1].file_pos, found[1].end_pos));
+
+    // Unless tokens must be in order
+    let ordered = MatchOptions { match_policy: MatchPolicy::Ordered, ..MatchOptions::default() };
+    let matcher = Matcher::new(&phrases[..1], ordered);
+    assert!(matcher.find_in(b"the fox was quick", 0).is_empty());
+    assert_eq!(1, matcher.find_in(b"the quick fox", 0).len());
+}
+
+#[test]
+fn test_matcher_diff_consistency() {
+    let phrases = [Phrase::from_strs(&["quick", "fox"])];
+    let matcher = Matcher::new(&phrases, MatchOptions::default());
+
+    // Every token must share a diff, which must be within the range
+    let rotated: Vec<u8> = b"the quick brown fox".iter().map(|b| b + 3).collect();
+    let mut mixed = rotated.clone();
+    mixed[16..].iter_mut().for_each(|b| *b += 1);
+    assert_eq!(3, matcher.find_in(&rotated, 0)[0].codepoint_diff);
+    assert!(matcher.find_in(&mixed, 0).is_empty());
+    let narrow = MatchOptions { diff_range: -2..=2, ..MatchOptions::default() };
+    assert!(Matcher::new(&phrases, narrow).find_in(&rotated, 0).is_empty());
+
+    // An encoder only matches its own diff
+    let matcher = Matcher::with_encoder(&phrases, MatchOptions::default(), LinearEncoder1 { diff: 3 });
+    assert_eq!(1, matcher.find_in(&rotated, 0).len());
+    assert!(matcher.find_in(b"the quick brown fox", 0).is_empty());
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_search_parallel_phrases() {
+    let window: &[u8] = include_bytes!("test_text_2.txt");
+    let mut phrases: Vec<Phrase> = (0..64).map(|i| Phrase::from_strs(&["famine", &format!("abundance{}", i)])).collect();
+    phrases.insert(10, Phrase::from_strs(&["sum", "my", "count"]));
+    phrases.push(Phrase::from_strs(&["within", "sunken", "deep"]));
+
+    // Same as matching them one after another, in the same order
+    let found = search_parallel_phrases(&phrases, window);
+    assert_eq!(Matcher::new(&phrases, MatchOptions::default()).find_in(window, 0), found);
+    assert_eq!(vec![(10, 479), (65, 55)], found.iter().map(|instance| (instance.phrase_index, instance.file_pos)).collect::<Vec<_>>());
+}
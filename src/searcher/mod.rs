@@ -1,99 +1,241 @@
-use std::io::Read;
-use std::fmt::{self, Write, Display};
-use std::ops::Range;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::fmt::{self, Display};
+use std::ops::{Range, RangeInclusive};
 use circle_buffer::CircleBuffer;
 use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
 
 mod text;
+mod extract;
+mod report;
+mod builder;
+mod encoder;
+mod watchdog;
+mod matcher;
+mod trace;
+mod cost;
+mod coarse;
+mod sink;
+#[cfg(not(target_arch = "wasm32"))]
+mod patch;
+mod regex;
+#[cfg(not(target_arch = "wasm32"))]
+mod throttle;
+mod strings;
+mod wasm;
+pub mod dto;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "fuzzing")]
+mod arbitrary_impls;
+#[cfg(test)]
+mod testsupport;
 pub use text::*;
+pub use extract::*;
+pub use report::*;
+pub use builder::*;
+pub use encoder::*;
+pub use watchdog::*;
+pub use matcher::*;
+pub use trace::*;
+pub use cost::*;
+pub use coarse::*;
+pub use sink::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use patch::*;
+pub use regex::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use throttle::*;
+pub use strings::*;
+pub use wasm::*;
+#[cfg(feature = "python")]
+pub use python::*;
+
+
+/// Sizes used when constructing a [`Finder`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SearchOptions {
+    pub context_size: usize,    // Must be divisible by 4
+    pub window_size: usize      // Must be > 0 and <= context_size
+}
+
+impl SearchOptions {
+    /// True if a [`Finder`] can be constructed with these options. See [`Self::validate`].
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    /// Checks the sizes against the rules [`FinderBuilder::build`] enforces
+    pub fn validate(&self) -> Result<(), FinderConfigError> {
+        let Self { context_size, window_size } = *self;
+        if !context_size.is_multiple_of(4) {
+            return Err(FinderConfigError::ContextSizeNotDivisibleBy4(context_size));
+        }
+        if window_size == 0 {
+            return Err(FinderConfigError::WindowSizeZero);
+        }
+        if window_size > context_size {
+            return Err(FinderConfigError::WindowLargerThanContext { window_size, context_size });
+        }
+        Ok(())
+    }
 
+    /// Sizes the window to fit the longest phrase, allowing [`TOKEN_GAP_CHARS`] characters between tokens,
+    /// with every character `bytes_per_character` wide. The context is twice the window, rounded up to a multiple of 4.
+    /// The context is clamped to `max_context_size` (rounded down to a multiple of 4), and the window to the context.
+    pub fn auto_size(phrases: &[Phrase], bytes_per_character: usize, max_context_size: usize) -> Self {
+        let window_size = phrases
+            .iter()
+            .map(|phrase| phrase_span_bytes(phrase, bytes_per_character))
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let max_context_size = (max_context_size - max_context_size % 4).max(4);
+        let context_size = (window_size * 2).div_ceil(4) * 4;
+        let context_size = context_size.min(max_context_size);
+        Self {
+            context_size,
+            window_size: window_size.min(context_size)
+        }
+    }
+}
+
+/// Characters allowed between consecutive tokens of a phrase when sizing a window
+pub const TOKEN_GAP_CHARS: usize = 2;
+
+/// Bytes a phrase spans when [`TOKEN_GAP_CHARS`] characters separate its tokens
+pub fn phrase_span_bytes(phrase: &Phrase, bytes_per_character: usize) -> usize {
+    let chars: usize = phrase.tokens.iter().map(|token| token.0.len()).sum();
+    let gaps = phrase.tokens.len().saturating_sub(1) * TOKEN_GAP_CHARS;
+    (chars + gaps) * bytes_per_character
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            context_size: 64,
+            window_size: 32
+        }
+    }
+}
 
 /// Searches for a set of phrases.
-pub struct Finder<'a, R: Read> {
-    phrases: Vec<Phrase>,              // Phrases to search for
-    phrase_skip_counters: Vec<usize>,   // Skip counter parallel to phrases
+/// Without an encoder, every width and codepoint diff is tried. With one, characters are decoded by it.
+/// `E` defaults to [`LinearEncoder1`] only so finders built without an encoder have a type to name.
+/// Those never decode with it: use [`FinderBuilder::build_with_encoder`] to search with one.
+pub struct Finder<'a, R: Read, E: Encoder = LinearEncoder1> {
+    matcher: Matcher<E>,                // Matches phrases in the window
+    phrase_found_at: Vec<Option<usize>>, // File position of the last instance found of each phrase. Not searched for again until the window moves past it.
+    matched_phrase_indices: HashSet<usize>, // Phrases found at least once so far
     reader: &'a mut R,                  // Input to search
     bytes_read: usize,                  // Current position of the stream we're in. Similar to file position.
     context: CircleBuffer<u8>,          // Buffer that bytes from input will be sent to / searched in
     window_size: usize,                 // Size of the window into the context
     window_right: usize,                // Last index + 1 of the window
-    flush_counter: usize                // How many extra times we need to slice the window to the right at the end of the file
+    stride: usize,                      // Bytes pushed between searches of the window
+    unsearched: usize,                  // Bytes pushed since the window was last searched
+    flush_counter: usize,               // How many extra times we need to slide the window to the right at the end of the file, until it's past the end
+    padding: usize,                     // Zeros pushed by the flush, which the window never covers
+    evicted: [u8; MAX_BYTES_PER_CHARACTER], // Last bytes rotated out of the context, most recent last
+    evicted_counts: Vec<usize>,         // How many times each byte value was rotated out of the context
+    evicted_last: Vec<Option<usize>>,   // File position each byte value was last rotated out of the context at
+    peeked: Option<PhraseInstanceGroup>, // Group found by peek, yielded by the next call to next
+    read_error: Option<io::Error>,      // Error that ended reading early, if any
+    tracer: Option<Tracer>              // Receives trace events, if tracing
 }
 
-impl<'a, R: Read> Iterator for Finder<'a, R> {
-    type Item = PhraseInstanceGroup;
-    
-    fn next(&mut self) -> Option<Self::Item> {
-
-        let mut phrase_instances = Vec::new();
-
-        // Reads in next char.
-        // If it's not None...
-        let mut next = self.next_char();
-        while let Some(char) = next {
-
-            // Put the char into the circle buffer and search for phrases in it
-            phrase_instances.clear();
-            self.context.push(char);
-            self.find_phrases(&mut phrase_instances);
-            self.bytes_read += 1;
-
-            // If at least once instance was found, return it as a group
-            if !phrase_instances.is_empty() {
-                return Some(PhraseInstanceGroup(phrase_instances));
-            }
+// Sends a trace event to a callback every so many bytes.
+// The callback is owned, so it doesn't have to outlive the reader, and can be shared with whatever collects the events.
+struct Tracer {
+    every: usize,
+    callback: Box<dyn FnMut(TraceEvent) + Send>
+}
 
-            // Otherwise, keep searching
-            next = self.next_char();
-        }
+impl<'a, R: Read, E: Encoder> Iterator for Finder<'a, R, E> {
+    type Item = PhraseInstanceGroup;
 
-        // EOF. Flush the remainder of the window
-        while self.flush_counter > 0 {
-            phrase_instances.clear();
-            self.context.push(0);
-            self.find_phrases(&mut phrase_instances);
-            self.bytes_read += 1;
-            self.flush_counter -= 1;
-            if !phrase_instances.is_empty() {
-                return Some(PhraseInstanceGroup(phrase_instances));
-            }
-        }
+    fn next(&mut self) -> Option<Self::Item> {
+        self.peeked.take().or_else(|| self.advance())
+    }
+}
 
-        // Done
-        None
+/// Shows the phrases as text, and the reader and encoder by type, so neither needs to implement `Debug`
+impl<R: Read, E: Encoder> fmt::Debug for Finder<'_, R, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let phrases: Vec<String> = self.matcher.phrases().iter().map(|phrase| phrase.to_string()).collect();
+        f.debug_struct("Finder")
+            .field("phrases", &phrases)
+            .field("reader", &std::any::type_name::<R>())
+            .field("encoder", &std::any::type_name::<E>())
+            .field("current_byte", &self.bytes_read)
+            .field("window_size", &self.window_size)
+            .field("context_capacity", &self.context.capacity())
+            .field("stride", &self.stride)
+            .field("phrase_found_at", &self.phrase_found_at)
+            .field("tracing", &self.tracer.is_some())
+            .finish_non_exhaustive()
     }
 }
 
 impl<'a, R: Read> Finder<'a, R> {
+
+    /// Creates a finder with the sizes specified. See [`FinderBuilder`] for more options.
+    /// Panics if the context size isn't divisible by 4, or if the window size is larger than the context size.
+    #[must_use = "search results must be handled"]
     pub fn new(
         phrases: &[Phrase],
         context_size: usize,
         window_size: usize,
         reader: &'a mut R
     ) -> Self {
-        if context_size % 4 != 0 {
-            panic!("Context size must be divisible by 4");
-        }
-        if window_size > context_size {
-            panic!("Window size must be <= context_size");
+        let builder = FinderBuilder::new()
+            .context_size(context_size)
+            .window_size(window_size);
+        match builder.build(phrases, reader) {
+            Ok(finder) => finder,
+            Err(err) => panic!("{}", err)
         }
+    }
+}
+
+impl<'a, R: Read, E: Encoder> Finder<'a, R, E> {
 
+    // Creates a finder from a configuration that has already been validated by a FinderBuilder
+    fn with_config(
+        matcher: Matcher<E>,
+        context_size: usize,
+        window_size: usize,
+        stride: usize,
+        reader: &'a mut R
+    ) -> Self {
         let ws = window_size;
         let hws = ws / 2;
         let c_mid = context_size/2;
-        let w_left = if c_mid > hws  { c_mid - hws } else { 0 };
+        let w_left = c_mid.saturating_sub(hws);
         let w_right = w_left + window_size;
         let w_right = if w_right > context_size { context_size } else { w_right };
 
         Self {
-            phrases: phrases.to_vec(),
-            phrase_skip_counters: vec![0; phrases.len()],
+            phrase_found_at: vec![None; matcher.phrases().len()],
+            matcher,
+            matched_phrase_indices: HashSet::new(),
             context: CircleBuffer::with_capacity(context_size),
             window_size,
             window_right: w_right,
+            stride,
+            unsearched: 0,
             reader,
             bytes_read: 0,
-            flush_counter: context_size - w_right
+            flush_counter: context_size - w_right + window_size,
+            padding: 0,
+            evicted: [0; MAX_BYTES_PER_CHARACTER],
+            evicted_counts: vec![0; 256],
+            evicted_last: vec![None; 256],
+            peeked: None,
+            read_error: None,
+            tracer: None
         }
     }
 
@@ -106,9 +248,10 @@ impl<'a, R: Read> Finder<'a, R> {
 
     pub fn get_window_range(&self) -> Range<usize> {
         let (w_left, w_right) = self.get_window_bounds();
+        let context_start = self.bytes_read - self.context.len();
         Range {
-            start: w_left - self.bytes_read,
-            end: w_right - self.bytes_read
+            start: context_start + w_left,
+            end: context_start + w_right
         }
     }
 
@@ -116,26 +259,240 @@ impl<'a, R: Read> Finder<'a, R> {
 
     pub fn bytes_read(&self) -> usize { self.bytes_read }
 
+    /// Returns the group the next call to `next` yields, without consuming it.
+    /// The finder reads ahead to find it, so ranges and context reflect the peeked group.
+    pub fn peek(&mut self) -> Option<&PhraseInstanceGroup> {
+        if self.peeked.is_none() {
+            self.peeked = self.advance();
+        }
+        self.peeked.as_ref()
+    }
+
+    /// Indices of the phrases found at least once so far.
+    /// Once it holds every phrase, callers that only need one instance of each can stop iterating.
+    pub fn phrases_matched_so_far(&self) -> &HashSet<usize> { &self.matched_phrase_indices }
+
+    /// Calls `callback` with a [`TraceEvent`] describing the window every `every` bytes, for debugging missing matches.
+    /// Tracing works out why every phrase doesn't match, so it's much slower than searching.
+    /// See [`TraceRing::recorder`] to keep the most recent events.
+    pub fn trace(&mut self, every: usize, callback: impl FnMut(TraceEvent) + Send + 'static) {
+        self.tracer = Some(Tracer { every: every.max(1), callback: Box::new(callback) });
+    }
+
+    /// Describes the current window, and why each phrase doesn't match it
+    pub fn trace_now(&self) -> TraceEvent {
+        let (w_left, w_right) = self.get_window_bounds();
+        let window = &self.context.as_slice()[w_left..w_right];
+        let mut candidate_diffs = Vec::new();
+        let reasons = (0..self.matcher.phrases().len())
+            .map(|phrase_index| {
+                let (reason, diffs) = self.matcher.diagnose(phrase_index, window, |idx| self.preceding_bytes(w_left + idx));
+                candidate_diffs.extend(diffs);
+                reason
+            })
+            .collect();
+        candidate_diffs.sort();
+        candidate_diffs.dedup();
+        TraceEvent {
+            file_pos: self.bytes_read - self.context.len() + w_left,
+            window_bytes: window.to_vec(),
+            candidate_diffs_considered: candidate_diffs,
+            rejected_reason_per_phrase: reasons
+        }
+    }
+
+    /// Replaces the phrases searched for, carrying on from the current position with the context kept.
+    /// Which phrases were matched so far is forgotten, as is any peeked group, since phrase indices now refer to the new phrases.
+    /// Matches still in the window are found again.
+    pub fn set_phrases(&mut self, phrases: &[Phrase]) {
+        self.matcher.set_phrases(phrases);
+        self.phrase_found_at = vec![None; phrases.len()];
+        self.matched_phrase_indices.clear();
+        self.peeked = None;
+    }
+
+    /// Starts over from the beginning of a new reader, such as a log file that was truncated and rewritten.
+    /// Keeps the phrases and tracer, but forgets everything read so far, including which phrases were matched.
+    pub fn restart(&mut self, reader: &'a mut R) {
+        let context_size = self.context.capacity();
+        self.reader = reader;
+        self.bytes_read = 0;
+        self.context = CircleBuffer::with_capacity(context_size);
+        self.phrase_found_at.fill(None);
+        self.matched_phrase_indices.clear();
+        self.unsearched = 0;
+        self.flush_counter = context_size - self.window_right + self.window_size;
+        self.padding = 0;
+        self.evicted = [0; MAX_BYTES_PER_CHARACTER];
+        self.evicted_counts.fill(0);
+        self.evicted_last.fill(None);
+        self.peeked = None;
+        self.read_error = None;
+    }
+
+    /// Error that stopped the finder reading before the end of its input, if one did.
+    /// Reads that were interrupted are retried, so they never end up here.
+    pub fn read_error(&self) -> Option<&io::Error> { self.read_error.as_ref() }
+
+    /// Gives back the reader, wherever it was left
+    pub fn into_reader(self) -> &'a mut R { self.reader }
+
+    /// Gets the raw bytes of the context for this finder
+    pub fn get_context_bytes(&self) -> &[u8] {
+        self.context.as_slice()
+    }
+
+    /// Raw bytes of the context that were read from the input, leaving out the zeros pushed to flush its end
+    pub fn get_context_input_bytes(&self) -> &[u8] {
+        &self.context.as_slice()[..self.context.len() - self.padding]
+    }
+
     /// Gets context for this finder
     pub fn get_context(&self, codepoint_diff: i32, bytes_per_character: u32) -> Text {
         Text::from_slice(self.context.as_slice(), codepoint_diff, bytes_per_character)
     }
 
+    /// Gets the context as UTF-8, replacing invalid sequences.
+    /// Cheaper than [`Self::get_context`] for input that's UTF-8 already.
+    pub fn context_as_utf8_lossy(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(self.context.as_slice())
+    }
+
+    /// Runs the finder to completion, grouping every instance found by phrase index.
+    /// Phrases that were never found have no entry.
+    pub fn collect_by_phrase(self) -> HashMap<usize, Vec<PhraseInstance>> {
+        let mut by_phrase: HashMap<usize, Vec<PhraseInstance>> = HashMap::with_capacity(self.matcher.phrases().len());
+        for instance in self.flat_map(|group| group.0) {
+            by_phrase.entry(instance.phrase_index).or_default().push(instance);
+        }
+        by_phrase
+    }
+
+    /// Same as [`Self::collect_by_phrase`]
+    pub fn into_phrase_map(self) -> HashMap<usize, Vec<PhraseInstance>> {
+        self.collect_by_phrase()
+    }
+
+    /// Runs the finder to completion, pairing every instance found with the bytes of context on either side of it,
+    /// so they can be shown without reading the input again.
+    /// The context is what the finder held when the instance was found, so less is captured near the start and end of the input.
+    pub fn collect_rich(mut self) -> Vec<RichPhraseInstance> {
+        let mut rich = Vec::new();
+        while let Some(group) = self.next() {
+            let context_pos = self.bytes_read - self.context.len();
+            let input = self.get_context_input_bytes();
+            for instance in group.0 {
+                let left = (instance.file_pos - context_pos).min(input.len());
+                let right = (instance.end_pos - context_pos).clamp(left, input.len());
+                rich.push(RichPhraseInstance {
+                    context_left: input[..left].to_vec(),
+                    context_right: input[right..].to_vec(),
+                    instance
+                });
+            }
+        }
+        rich
+    }
+
+    /// Only yields the groups `predicate` accepts, as [`Iterator::filter`] does.
+    /// To filter on the text around each group, such as whether it also holds a date, see [`Self::map_context`].
+    pub fn filter<F>(self, predicate: F) -> impl Iterator<Item=PhraseInstanceGroup> + 'a
+    where E: 'a, F: Fn(&PhraseInstanceGroup) -> bool + 'a {
+        Iterator::filter(self, predicate)
+    }
+
+    /// Only yields the groups with an instance starting from `start` to `end` inclusive, such as to ignore matches before a checkpoint.
+    /// The whole input is still read, so the context of the groups yielded is the same as without filtering.
+    pub fn filter_range(self, start: usize, end: usize) -> impl Iterator<Item=PhraseInstanceGroup> + 'a
+    where E: 'a {
+        Iterator::filter(self, move |group| group.0.iter().any(|instance| (start..=end).contains(&instance.file_pos)))
+    }
+
+    /// Pairs each group with the context it was found in, decoded with the diff and width of its first instance
+    pub fn map_context(mut self) -> impl Iterator<Item=(PhraseInstanceGroup, Text)> + 'a
+    where E: 'a {
+        std::iter::from_fn(move || {
+            let group = self.next()?;
+            let first = &group.0[0];
+            let context = self.get_context(first.codepoint_diff, first.bytes_per_character);
+            Some((group, context))
+        })
+    }
+
+    // Reads until the next group is found
+    fn advance(&mut self) -> Option<PhraseInstanceGroup> {
+
+        let mut phrase_instances = Vec::new();
+
+        // Reads in next char.
+        // If it's not None...
+        let mut next = self.next_char();
+        while let Some(char) = next {
+
+            // Put the char into the circle buffer and search for phrases in it, once every stride
+            phrase_instances.clear();
+            self.push(char);
+            self.bytes_read += 1;
+            if self.unsearched >= self.stride {
+                self.find_phrases(&mut phrase_instances);
+            }
+
+            // If at least once instance was found, return it as a group
+            if !phrase_instances.is_empty() {
+                return Some(PhraseInstanceGroup(phrase_instances));
+            }
+
+            // Otherwise, keep searching
+            next = self.next_char();
+        }
+
+        // EOF. Flush the window past the end of the input, always searching the window that ends where the input does
+        while self.flush_counter > 0 {
+            phrase_instances.clear();
+            if self.unsearched > 0 && self.padding == self.context.capacity() - self.window_right {
+                self.find_phrases(&mut phrase_instances);
+                if !phrase_instances.is_empty() {
+                    return Some(PhraseInstanceGroup(phrase_instances));
+                }
+            }
+            self.push(0);
+            self.bytes_read += 1;
+            self.padding += 1;
+            if self.unsearched >= self.stride {
+                self.find_phrases(&mut phrase_instances);
+            }
+            self.flush_counter -= 1;
+            if !phrase_instances.is_empty() {
+                return Some(PhraseInstanceGroup(phrase_instances));
+            }
+        }
+
+        // Done
+        None
+    }
+
     // Finds phrases in current window
     fn find_phrases(&mut self, phrase_instances: &mut Vec<PhraseInstance>) {
+        self.unsearched = 0;
+        let (w_left, w_right) = self.get_window_bounds();
+        if w_left == w_right {
+            return;
+        }
 
-        // For all phrases..
-        for i in 0..self.phrases.len() {
+        // Traces the window before any phrase is found in it
+        if self.tracer.as_ref().is_some_and(|tracer| self.bytes_read.is_multiple_of(tracer.every)) {
+            let event = self.trace_now();
+            if let Some(tracer) = self.tracer.as_mut() {
+                (tracer.callback)(event);
+            }
+        }
 
-            // If phrase is to be skipped, skip it
-            let skip = &mut self.phrase_skip_counters[i];
-            if *skip > 0 {
-                *skip -= 1;
+        // Searches for every phrase, except those found where the window still covers
+        let w_left_pos = self.bytes_read - self.context.len() + w_left;
+        for i in 0..self.matcher.phrases().len() {
+            if self.phrase_found_at[i].is_some_and(|pos| pos >= w_left_pos) {
                 continue;
             }
-
-            // Search for phrase
-            let (w_left, w_right) = self.get_window_bounds();
             self.find_phrase(i, w_left, w_right, phrase_instances);
         }
     }
@@ -149,116 +506,330 @@ impl<'a, R: Read> Finder<'a, R> {
         instances: &mut Vec<PhraseInstance>
     ) {
 
-        // Computes the window of the current context
-        let phrase = &self.phrases[phrase_index];
-        let context = self.context.as_slice();
-        let window = &context[w_left..w_right];
-
-        // Searches for the phrase in the window calculated
-        let mut earliest_token_idx = usize::MAX;    // Index of earliest token index found
-        let mut last_diff: Option<i32> = None;      // Last character diff
-        let mut last_bpc = 0;                       // Last bytes-per-character
-        for token in &phrase.0 {
-
-            // If token was found in the buffer...
-            if let Some(token_instance) = search_multibyte(&token.0, window, last_diff) {
-
-                // If another token in the phrase was found previously, but it had a different
-                // codepoint diff or bytes-per-character value, it's a failed match
-                if let Some(last_diff) = last_diff {
-                    let diff = token_instance.codepoint_diff;
-                    let bpc = token_instance.bytes_per_character;
-                    if diff != last_diff || bpc != last_bpc {
-                        return;
-                    }
-                }
-
-                // Keep track of the earliest token index in the phrase so we know how much to skip when we're done
-                let token_idx = token_instance.index;
-                if token_idx < earliest_token_idx {
-                    earliest_token_idx = token_idx;
-                    last_diff = Some(token_instance.codepoint_diff);
-                    last_bpc = token_instance.bytes_per_character;
-                }
-            }
-
-            // Otherwise, it's a failed match
-            else {
-                return;
-            }
-        }
+        // Searches for the phrase in the window of the current context.
+        // A window ending where the input does also covers the first byte of padding, standing in for the missing high
+        // byte of a 2-byte character read one byte late, like the last character of big-endian text.
+        // A 1-byte match can't end in padding, so then the window is searched again without it.
+        let input_len = self.context.len() - self.padding;
+        let padded = w_right == input_len && self.padding > 0;
+        let search = |w_right: usize| {
+            let window = &self.context.as_slice()[w_left..w_right];
+            self.matcher.match_phrase(phrase_index, window, |idx| self.preceding_bytes(w_left + idx))
+        };
+        let found = match search(if padded { w_right + 1 } else { w_right }) {
+            Some((found, end)) if found.bytes_per_character == 1 && w_left + end > input_len => search(w_right),
+            found => found
+        };
+        let Some((found, end)) = found else { return };
 
         // Add the buffer's contents to results and skip past the phrase
-        let bytes_read = self.bytes_read + 1;
-        let w_left_pos = bytes_read - self.context.len() + w_left;
+        let context_pos = self.bytes_read - self.context.len();
+        let w_left_pos = context_pos + w_left;
+        let (line, column) = match found.bytes_per_character {
+            1 => {
+                let (newlines, last_newline) = line_breaks(&self.context.as_slice()[..w_left + found.index], found.codepoint_diff);
+                let newline = newline_byte(found.codepoint_diff);
+                let evicted_newlines = newline.map_or(0, |byte| self.evicted_counts[byte as usize]);
+                let evicted_last = newline.and_then(|byte| self.evicted_last[byte as usize]);
+                let line_start_pos = match last_newline {
+                    Some(idx) => context_pos + idx + 1,
+                    None => evicted_last.map_or(0, |pos| pos + 1)
+                };
+                (Some(evicted_newlines + newlines + 1), Some(w_left_pos + found.index - line_start_pos + 1))
+            },
+            _ => (None, None)
+        };
         instances.push(PhraseInstance {
             phrase_index,
-            codepoint_diff: last_diff.unwrap(),
-            file_pos: w_left_pos + earliest_token_idx,
-            bytes_per_character: last_bpc
+            codepoint_diff: found.codepoint_diff,
+            file_pos: w_left_pos + found.index,
+            end_pos: w_left_pos + end,
+            bytes_per_character: found.bytes_per_character,
+            line,
+            column
         });
-        self.phrase_skip_counters[phrase_index] = earliest_token_idx;
+        self.matched_phrase_indices.insert(phrase_index);
+        self.phrase_found_at[phrase_index] = Some(w_left_pos + found.index);
+    }
+
+    // Up to MAX_BYTES_PER_CHARACTER bytes before the context index, including bytes already evicted.
+    // Empty at the start of the input.
+    fn preceding_bytes(&self, idx: usize) -> Vec<u8> {
+        let context = self.context.as_slice();
+        if idx >= MAX_BYTES_PER_CHARACTER {
+            return context[idx - MAX_BYTES_PER_CHARACTER..idx].to_vec();
+        }
+        let evicted_count = (self.bytes_read - context.len()).min(MAX_BYTES_PER_CHARACTER);
+        let evicted = &self.evicted[MAX_BYTES_PER_CHARACTER - evicted_count..];
+        let bytes: Vec<u8> = evicted.iter().chain(&context[..idx]).copied().collect();
+        bytes[bytes.len().saturating_sub(MAX_BYTES_PER_CHARACTER)..].to_vec()
     }
 
+    // Pushes a byte into the context, remembering the byte it evicts.
+    // Only bytes of the input are ever evicted, as the flush never pushes more than the context holds.
+    fn push(&mut self, byte: u8) {
+        self.unsearched += 1;
+        if self.context.len() == self.context.capacity() {
+            let evicted = self.context.as_slice()[0];
+            self.evicted.rotate_left(1);
+            self.evicted[MAX_BYTES_PER_CHARACTER - 1] = evicted;
+            self.evicted_counts[evicted as usize] += 1;
+            self.evicted_last[evicted as usize] = Some(self.bytes_read - self.context.len());
+        }
+        self.context.push(byte);
+    }
+
+    // Bounds of the window within the context. Until the context is full, the bytes it's missing come before the input,
+    // so the window is cut off on the left. Padding pushed by the flush isn't input either, so it's cut off on the right.
     fn get_window_bounds(&self) -> (usize, usize) {
-        let w_right = if self.window_right > self.context.len() { self.context.len() } else { self.window_right };
-        let w_left = if w_right > self.window_size { w_right - self.window_size } else { 0 };
+        let missing = self.context.capacity() - self.context.len();
+        let input_len = self.context.len() - self.padding;
+        let w_right = self.window_right.saturating_sub(missing).min(input_len);
+        let w_left = (self.window_right - self.window_size).saturating_sub(missing).min(w_right);
         (w_left, w_right)
     }
 
+    // Reads the next byte, retrying interrupted reads. Other errors are kept and end the input, like EOF.
     fn next_char(&mut self) -> Option<u8> {
+        if self.read_error.is_some() {
+            return None;
+        }
         let mut b: [u8; 1] = [0];
-        match self.reader.read(&mut b) {
-            Ok(bytes_read) => {
-                match bytes_read {
-                    0 => None,
-                    _ => {
-                        Some(b[0])
-                    }
+        loop {
+            match self.reader.read(&mut b) {
+                Ok(0) => return None,
+                Ok(_) => return Some(b[0]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.read_error = Some(err);
+                    return None;
                 }
-            },
-            Err(_) => None
+            }
         }
     }
 }
 
-/// Searches for a within b
-fn search_multibyte(a: &[u32], b: &[u8], codepoint_diff: Option<i32>) -> Option<TokenInstance> {
-    if let Some(codepoint_diff) = codepoint_diff {
-        let result = search_with_diff(a, b, codepoint_diff);
-        if result.is_some() {
-            return result;
+/// Searches `window` for `phrase`, the same way a [`Finder`] searches its window: tokens can appear in any order,
+/// with any codepoint diff, as long as every token shares the same diff and bytes-per-character.
+/// `file_pos` and `end_pos` of the result are indices in `window`, and `phrase_index` is 0.
+/// The start of the window counts as the start of the input when checking the phrase's anchor.
+pub fn find_phrase_in_window(phrase: &Phrase, window: &[u8]) -> Option<PhraseInstance> {
+    Matcher::new(std::slice::from_ref(phrase), MatchOptions::default()).find_in(window, 0).pop()
+}
+
+// Byte a '\n' is encoded as in 1-byte text with the codepoint diff, if it can be.
+// A "\r\n" ends a line at its '\n', so it counts as a single break.
+fn newline_byte(codepoint_diff: i32) -> Option<u8> {
+    u8::try_from(b'\n' as i32 + codepoint_diff).ok()
+}
+
+// Number of line breaks in 1-byte text, and the index of the last one
+fn line_breaks(bytes: &[u8], codepoint_diff: i32) -> (usize, Option<usize>) {
+    let Some(newline) = newline_byte(codepoint_diff) else { return (0, None) };
+    let count = bytes.iter().filter(|&&byte| byte == newline).count();
+    (count, bytes.iter().rposition(|&byte| byte == newline))
+}
+
+// Searches window for every token in phrase. Returns the earliest token found and the end of the furthest one,
+// if all were found with the same codepoint diff and bytes-per-character.
+// Tokens are decoded with the encoder if there is one.
+fn match_phrase<E: Encoder>(
+    phrase: &Phrase,
+    window: &[u8],
+    diff_range: &RangeInclusive<i32>,
+    match_policy: MatchPolicy,
+    encoding: Option<Encoding>,
+    widths: WidthMask,
+    encoder: Option<&E>
+) -> Option<(TokenInstance, usize)> {
+    if phrase.tokens.is_empty() {
+        return None;
+    }
+    let widths = widths.and(WidthMask::from_widths(&phrase.widths));
+
+    // The longest token sets the diff the others must share, since short ones match almost anywhere under some diff.
+    // Its earliest instance under any diff is also its earliest under that diff, so it's reused when the token's turn comes
+    // if the search would start before it, at the same alignment.
+    let longest = phrase.tokens.iter().enumerate().max_by_key(|(idx, token)| (token.0.len(), Reverse(*idx)));
+    let anchor = match (longest, encoder) {
+        (Some((idx, token)), None) if idx > 0 => Some((idx, search_multibyte(&token.0, window, None, diff_range, encoding, widths)?)),
+        _ => None
+    };
+    let anchor_diff = anchor.map(|(_, anchor)| anchor.codepoint_diff);
+
+    let mut earliest: Option<TokenInstance> = None;    // Earliest token found
+    let mut end = 0;                                    // Index after the last byte of the furthest token found
+    let mut search_start = 0;                           // Where in the window to search for the next token
+    let mut found: Vec<(TokenInstance, usize)> = Vec::with_capacity(phrase.tokens.len());  // Tokens found, with their lengths in bytes
+    let ordered = match_policy == MatchPolicy::Ordered;
+    for (token_idx, token) in phrase.tokens.iter().enumerate() {
+
+        // If token isn't in the window, it's a failed match.
+        // Instances overlapping a token already found don't count, so it's searched for again past them.
+        let last_diff = anchor_diff.or(earliest.map(|earliest| earliest.codepoint_diff));
+
+        // Later tokens must share the width of the first one found, so a narrower instance can't hide one that would match
+        let token_widths = earliest.map_or(widths, |earliest| widths.and(WidthMask::from_widths(&[earliest.bytes_per_character])));
+        let mut token_start = search_start;
+        let (token_instance, token_len) = loop {
+            let haystack = &window[token_start..];
+            let (mut token_instance, token_len) = match encoder {
+                Some(encoder) => search_encoded(&token.0, haystack, encoder)?,
+                None => {
+                    let token_instance = match anchor {
+                        Some((anchor_idx, anchor)) if anchor_idx == token_idx && anchor.index >= token_start
+                            && (anchor.index - token_start) % anchor.bytes_per_character as usize == 0 => {
+                            TokenInstance { index: anchor.index - token_start, ..anchor }
+                        },
+                        _ => search_multibyte(&token.0, haystack, last_diff, diff_range, encoding, token_widths)?
+                    };
+                    (token_instance, token.0.len() * token_instance.bytes_per_character as usize)
+                }
+            };
+            token_instance.index += token_start;
+            let overlaps = found
+                .iter()
+                .any(|(other, other_len)| token_instance.overlaps_with(other, token_len, *other_len));
+            if !overlaps {
+                break (token_instance, token_len);
+            }
+            // An encoder's characters can vary in width, so its instances are stepped past a byte at a time
+            token_start = token_instance.index + match encoder {
+                Some(_) => 1,
+                None => token_instance.bytes_per_character as usize
+            };
+        };
+        found.push((token_instance, token_len));
+        end = end.max(token_instance.index + token_len);
+
+        // When tokens must be in order, the next token is searched for after this one
+        if ordered {
+            search_start = token_instance.index + token_len;
         }
-        let result = search_2bytes_with_diff(a, b, codepoint_diff);
-        if result.is_some() {
-            return result;
+
+        // If another token in the phrase was found previously, but it had a different
+        // codepoint diff or bytes-per-character value, it's a failed match
+        if let Some(earliest) = earliest {
+            let diff = token_instance.codepoint_diff;
+            let bpc = token_instance.bytes_per_character;
+            if diff != earliest.codepoint_diff || bpc != earliest.bytes_per_character {
+                return None;
+            }
+        }
+
+        // Keep track of the earliest token in the phrase so we know how much to skip when we're done
+        if earliest.is_none_or(|earliest| token_instance.index < earliest.index) {
+            earliest = Some(token_instance);
         }
     }
-    else {
-        let result = search(a, b);
-        if result.is_some() {
-            return result;
+
+    // If any exclusion appears in the window with the same diff and width, it's a failed match
+    let earliest = earliest?;
+    let same_layout = Encoding {
+        bytes_per_character: earliest.bytes_per_character,
+        endianness: encoding.map(|encoding| encoding.endianness).unwrap_or_default()
+    };
+    let excluded = phrase.not.iter().any(|token| match encoder {
+        Some(encoder) => search_encoded(&token.0, window, encoder).is_some(),
+        None => search_multibyte(&token.0, window, Some(earliest.codepoint_diff), diff_range, Some(same_layout), WidthMask::ALL).is_some()
+    });
+    if excluded {
+        return None;
+    }
+    Some((earliest, end))
+}
+
+// Widths a search may try, as a bit per bytes-per-character
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct WidthMask(u32);
+
+impl WidthMask {
+    pub(crate) const ALL: Self = Self(u32::MAX);
+
+    // Mask of the widths listed, or every width if none are
+    pub(crate) fn from_widths(widths: &[u32]) -> Self {
+        match widths.is_empty() {
+            true => Self::ALL,
+            false => Self(widths.iter().filter(|width| **width < 32).fold(0, |mask, width| mask | 1 << width))
         }
-        let result = search_2bytes(a, b);
+    }
+
+    pub(crate) fn and(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub(crate) fn allows(self, bytes_per_character: u32) -> bool {
+        bytes_per_character < 32 && self.0 & 1 << bytes_per_character != 0
+    }
+}
+
+/// Searches for a within b.
+/// If codepoint_diff is None, any diff within diff_range can match.
+fn search_multibyte(
+    a: &[u32],
+    b: &[u8],
+    codepoint_diff: Option<i32>,
+    diff_range: &RangeInclusive<i32>,
+    encoding: Option<Encoding>,
+    widths: WidthMask
+) -> Option<TokenInstance> {
+    let (try_1byte, endianness) = match encoding {
+        None => (true, Some(Endianness::Little)),
+        Some(Encoding { bytes_per_character: 1, .. }) => (true, None),
+        Some(Encoding { endianness, .. }) => (false, Some(endianness))
+    };
+    let try_1byte = try_1byte && widths.allows(1);
+    let endianness = endianness.filter(|_| widths.allows(2));
+    if try_1byte {
+        let result = match codepoint_diff {
+            Some(codepoint_diff) => search_with_diff(a, b, codepoint_diff),
+            None => search(a, b, diff_range)
+        };
         if result.is_some() {
             return result;
         }
     }
+    let endianness = endianness?;
+    match codepoint_diff {
+        Some(codepoint_diff) => search_2bytes_with_diff(a, b, codepoint_diff, endianness),
+        None => search_2bytes(a, b, diff_range, endianness)
+    }
+}
+
+/// Searches for a within b, decoding b with the encoder starting at every byte.
+/// Also returns the number of bytes the match spans.
+fn search_encoded<E: Encoder>(a: &[u32], b: &[u8], encoder: &E) -> Option<(TokenInstance, usize)> {
+    if a.is_empty() { return None; }
+    'outer: for b_idx in 0..b.len() {
+        let mut idx = b_idx;
+        for char_a in a {
+            match encoder.decode_codepoint(b, idx) {
+                Some((char_b, len)) if char_b == *char_a && len > 0 => idx += len,
+                _ => continue 'outer
+            }
+        }
+        let token_instance = TokenInstance {
+            index: b_idx,
+            codepoint_diff: encoder.codepoint_diff(),
+            bytes_per_character: encoder.bytes_per_char()
+        };
+        return Some((token_instance, idx - b_idx));
+    }
     None
 }
 
-/// Searches for a within b
-fn search(a: &[u32], b: &[u8]) -> Option<TokenInstance> {
-    let a = a.as_ref();
-    let b = b.as_ref();
+/// Searches for a within b, with any diff within diff_range
+fn search(a: &[u32], b: &[u8], diff_range: &RangeInclusive<i32>) -> Option<TokenInstance> {
     let b_len = b.len();
     if a.is_empty() { return None; }
     if a.len() > b_len { return None; }
-    'outer: for b_idx in 0..(b_len - a.len() + 1) {
+    let first_bytes = first_bytes(a[0], diff_range, 1, Endianness::Little);
+    'outer: for b_idx in start_indices(b, 1, b_len - a.len() + 1, first_bytes) {
         let b_at_idx = b[b_idx];
         let codepoint_diff = b_at_idx as i32 - a[0] as i32;
-        for a_idx in 0..a.len() {
-            let char_a = a[a_idx] as i32;
+        if !diff_range.contains(&codepoint_diff) { continue 'outer; }
+        for (a_idx, char_a) in a.iter().enumerate() {
+            let char_a = *char_a as i32;
             let char_b = b[b_idx + a_idx] as u32;
             let char_b = char_b as i32 - codepoint_diff;
             if char_a != char_b { continue 'outer; }
@@ -269,22 +840,22 @@ fn search(a: &[u32], b: &[u8]) -> Option<TokenInstance> {
             bytes_per_character: 1
         });
     }
-    return None;
+    None
 }
 
-/// Searches for a within b. Assumes b is 2 bytes per character.
-fn search_2bytes(a: &[u32], b: &[u8]) -> Option<TokenInstance> {
-    let a = a.as_ref();
-    let b = b.as_ref();
+/// Searches for a within b, with any diff within diff_range. Assumes b is 2 bytes per character.
+fn search_2bytes(a: &[u32], b: &[u8], diff_range: &RangeInclusive<i32>, endianness: Endianness) -> Option<TokenInstance> {
     let b_len = b.len() / 2;
     if a.is_empty() { return None; }
     if a.len() > b_len { return None; }
-    'outer: for b_idx in 0..(b_len - a.len() + 1) {
-        let b_at_idx = get_2bytes(b, b_idx);
+    let first_bytes = first_bytes(a[0], diff_range, 2, endianness);
+    'outer: for b_idx in start_indices(b, 2, b_len - a.len() + 1, first_bytes) {
+        let b_at_idx = get_2bytes_with(b, b_idx*2, endianness);
         let codepoint_diff = b_at_idx as i32 - a[0] as i32;
-        for a_idx in 0..a.len() {
-            let char_a = a[a_idx] as i32;
-            let char_b = get_2bytes(b, b_idx + a_idx);
+        if !diff_range.contains(&codepoint_diff) { continue 'outer; }
+        for (a_idx, char_a) in a.iter().enumerate() {
+            let char_a = *char_a as i32;
+            let char_b = get_2bytes_with(b, (b_idx + a_idx)*2, endianness);
             let char_b = char_b as i32 - codepoint_diff;
             if char_a != char_b { continue 'outer; }
         }
@@ -294,109 +865,603 @@ fn search_2bytes(a: &[u32], b: &[u8]) -> Option<TokenInstance> {
             bytes_per_character: 2
         });
     }
-    return None;
+    None
 }
 
 /// Searches for a within b, assuming the specified codepoind diff
 fn search_with_diff(a: &[u32], b: &[u8], codepoint_diff: i32) -> Option<TokenInstance> {
-    let a = a.as_ref();
-    let b = b.as_ref();
     let b_len = b.len();
     if a.is_empty() { return None; }
     if a.len() > b_len { return None; }
-    'outer: for b_idx in 0..(b_len - a.len() + 1) {
-        for a_idx in 0..a.len() {
-            let char_a = a[a_idx] as i32;
+    let first_bytes = first_bytes(a[0], &(codepoint_diff..=codepoint_diff), 1, Endianness::Little);
+    'outer: for b_idx in start_indices(b, 1, b_len - a.len() + 1, first_bytes) {
+        for (a_idx, char_a) in a.iter().enumerate() {
+            let char_a = *char_a as i32;
             let char_b = b[b_idx + a_idx] as u32;
             let char_b = char_b as i32 - codepoint_diff;
             if char_a != char_b { continue 'outer; }
         }
-        return Some(TokenInstance {
-            index: b_idx,
-            codepoint_diff,
-            bytes_per_character: 1
-        });
+        return Some(TokenInstance {
+            index: b_idx,
+            codepoint_diff,
+            bytes_per_character: 1
+        });
+    }
+    None
+}
+
+/// Searches for a within b. Assumes b is 2 bytes per character.
+fn search_2bytes_with_diff(a: &[u32], b: &[u8], codepoint_diff: i32, endianness: Endianness) -> Option<TokenInstance> {
+    let b_len = b.len() / 2;
+    if a.is_empty() { return None; }
+    if a.len() > b_len { return None; }
+    let first_char = first_char_bytes(a[0], codepoint_diff, endianness);
+    'outer: for b_idx in char_start_indices(b, b_len - a.len() + 1, first_char) {
+        for (a_idx, char_a) in a.iter().enumerate() {
+            let char_a = *char_a as i32;
+            let char_b = get_2bytes_with(b, (b_idx + a_idx)*2, endianness);
+            let char_b = char_b as i32 - codepoint_diff;
+            if char_a != char_b { continue 'outer; }
+        }
+        return Some(TokenInstance {
+            index: b_idx*2,
+            codepoint_diff,
+            bytes_per_character: 2
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+const ALL_DIFFS: RangeInclusive<i32> = i32::MIN..=i32::MAX;
+
+/// Little-endian 2-byte character at character index `idx`, which starts at byte `idx*2`
+pub fn get_2bytes(slice: &[u8], idx: usize) -> u32 {
+    get_2bytes_at(slice, idx*2)
+}
+
+/// Little-endian 2-byte character starting at `byte_offset`, which doesn't need to be even.
+/// Panics if the slice ends before `byte_offset + 2`.
+pub fn get_2bytes_at(slice: &[u8], byte_offset: usize) -> u32 {
+    let a = slice[byte_offset] as u32;
+    let b = slice[byte_offset+1] as u32;
+    a + (b << 8)
+}
+
+// Bytes a character matching `first` can start with in memory, under any diff in the range.
+// None if there are too many to be worth skipping to, as with the default range of every diff.
+// Searches with a known diff, which every token after the first is, always have one to skip to.
+fn first_bytes(first: u32, diff_range: &RangeInclusive<i32>, bytes_per_character: u32, endianness: Endianness) -> Option<Vec<u8>> {
+    let max_codepoint = (1i64 << (8 * bytes_per_character)) - 1;
+    let lowest = (first as i64 + *diff_range.start() as i64).max(0);
+    let highest = (first as i64 + *diff_range.end() as i64).min(max_codepoint);
+    if highest - lowest >= 3 { return None; }
+    let mut bytes: Vec<u8> = (lowest..=highest)
+        .map(|codepoint| match (bytes_per_character, endianness) {
+            (2, Endianness::Big) => (codepoint >> 8) as u8,
+            _ => codepoint as u8
+        })
+        .collect();
+    bytes.dedup();
+    Some(bytes)
+}
+
+// Bytes of the 2-byte character matching `first` under the diff, in the order they're stored.
+// None if the diff takes it out of range, so nothing can match.
+fn first_char_bytes(first: u32, codepoint_diff: i32, endianness: Endianness) -> Option<[u8; 2]> {
+    let codepoint = u16::try_from(first as i64 + codepoint_diff as i64).ok()?;
+    Some(match endianness {
+        Endianness::Little => codepoint.to_le_bytes(),
+        Endianness::Big => codepoint.to_be_bytes()
+    })
+}
+
+// Character indices of b below `end` that a match may start at.
+// With the simd feature, only those starting with one of `first_bytes` are visited, if known.
+#[cfg(feature = "simd")]
+fn start_indices(b: &[u8], bytes_per_character: usize, end: usize, first_bytes: Option<Vec<u8>>) -> StartIndices<'_> {
+    let searched = &b[..(end * bytes_per_character).min(b.len())];
+    let positions = match first_bytes.as_deref() {
+        None => BytePositions::Every(0..end),
+        Some([]) => BytePositions::Every(0..0),
+        Some(&[b1]) => BytePositions::One(memchr::memchr_iter(b1, searched)),
+        Some(&[b1, b2]) => BytePositions::Two(memchr::memchr2_iter(b1, b2, searched)),
+        Some(&[b1, b2, b3]) => BytePositions::Three(memchr::memchr3_iter(b1, b2, b3, searched)),
+        Some(_) => BytePositions::Every(0..end)
+    };
+    StartIndices { positions, bytes_per_character, first_char: None, last: None }
+}
+
+#[cfg(not(feature = "simd"))]
+fn start_indices(_b: &[u8], _bytes_per_character: usize, end: usize, _first_bytes: Option<Vec<u8>>) -> Range<usize> {
+    0..end
+}
+
+// Character indices of b below `end` that a 2-byte match with a known diff may start at.
+// With the simd feature, memchr2 skips to either byte of the first character, which is then checked whole.
+#[cfg(feature = "simd")]
+fn char_start_indices(b: &[u8], end: usize, first_char: Option<[u8; 2]>) -> StartIndices<'_> {
+    let searched = &b[..(end * 2).min(b.len())];
+    let (positions, first_char) = match first_char {
+        Some(bytes) => (BytePositions::Two(memchr::memchr2_iter(bytes[0], bytes[1], searched)), Some((searched, bytes))),
+        None => (BytePositions::Every(0..0), None)
+    };
+    StartIndices { positions, bytes_per_character: 2, first_char, last: None }
+}
+
+#[cfg(not(feature = "simd"))]
+fn char_start_indices(_b: &[u8], end: usize, first_char: Option<[u8; 2]>) -> Range<usize> {
+    match first_char {
+        Some(_) => 0..end,
+        None => 0..0
+    }
+}
+
+// Where memchr found one of the bytes skipped to, or every character index when there's nothing to skip to
+#[cfg(feature = "simd")]
+enum BytePositions<'a> {
+    Every(Range<usize>),
+    One(memchr::Memchr<'a>),
+    Two(memchr::Memchr2<'a>),
+    Three(memchr::Memchr3<'a>)
+}
+
+// Character indices returned by start_indices and char_start_indices
+#[cfg(feature = "simd")]
+struct StartIndices<'a> {
+    positions: BytePositions<'a>,
+    bytes_per_character: usize,
+    first_char: Option<(&'a [u8], [u8; 2])>,    // Searched bytes and the 2-byte character every index must start with
+    last: Option<usize>                         // Index last returned, as both bytes of a character can be found
+}
+
+#[cfg(feature = "simd")]
+impl Iterator for StartIndices<'_> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        loop {
+            let pos = match &mut self.positions {
+                BytePositions::Every(indices) => return indices.next(),
+                BytePositions::One(positions) => positions.next()?,
+                BytePositions::Two(positions) => positions.next()?,
+                BytePositions::Three(positions) => positions.next()?
+            };
+            let idx = pos / self.bytes_per_character;
+            match self.first_char {
+                None if pos.is_multiple_of(self.bytes_per_character) => return Some(idx),
+                None => continue,
+                Some((searched, bytes)) => {
+                    if self.last != Some(idx) && searched[idx*2..idx*2 + 2] == bytes {
+                        self.last = Some(idx);
+                        return Some(idx);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// 2-byte character starting at `byte_offset`, read with the endianness
+fn get_2bytes_with(slice: &[u8], byte_offset: usize, endianness: Endianness) -> u32 {
+    match endianness {
+        Endianness::Little => get_2bytes_at(slice, byte_offset),
+        Endianness::Big => ((slice[byte_offset] as u32) << 8) + slice[byte_offset+1] as u32
+    }
+}
+
+
+/// Result of a text search
+#[derive(Debug, Copy, Clone)]
+pub struct TokenInstance {
+    pub index: usize,
+    pub codepoint_diff: i32,
+    pub bytes_per_character: u32
+}
+
+impl TokenInstance {
+    /// Extracts the bytes that produced this match from `source` as a [`Text`].
+    /// `token_len` is the length of the token in characters, not bytes.
+    pub fn to_text(&self, source: &[u8], token_len: usize) -> Text {
+        let end = self.index + token_len * self.bytes_per_character as usize;
+        Text::from_slice(&source[self.index..end], self.codepoint_diff, self.bytes_per_character)
+    }
+
+    /// True if the bytes this instance spans overlap the bytes `other` spans.
+    /// Lengths are in bytes. Empty instances overlap nothing.
+    pub fn overlaps_with(&self, other: &TokenInstance, self_len_bytes: usize, other_len_bytes: usize) -> bool {
+        self_len_bytes > 0 &&
+        other_len_bytes > 0 &&
+        self.index < other.index + other_len_bytes &&
+        other.index < self.index + self_len_bytes
+    }
+}
+
+/// A sequence of texts, along with texts that must not appear near them and what must come before them.
+/// Serialized as just the tokens when there are no exclusions, anchor, short token allowance or widths.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(from = "PhraseRepr", into = "PhraseRepr")]
+pub struct Phrase {
+    pub tokens: Vec<Text>,  // Texts to search for
+    pub not: Vec<Text>,     // Matches are rejected if any of these appear in the window under the same diff
+    pub anchor: Anchor,     // What must come right before a match
+    allow_short_tokens: bool,       // Whether validation lets tokens shorter than the minimum through. See with_allow_short_tokens.
+    widths: Vec<u32>                // Bytes per character the phrase can be found with. Empty for every supported width. See with_widths.
+}
+
+/// Bounds [`Phrase::validate`] checks phrases against.
+/// Short tokens match almost anywhere under some diff, so they make for phrases that match noise.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PhraseLimits {
+    pub min_token_len: usize,   // Fewest characters a token can have, unless the phrase allows short tokens
+    pub max_tokens: usize       // Most tokens a phrase can have
+}
+
+impl Default for PhraseLimits {
+    fn default() -> Self {
+        Self {
+            min_token_len: 3,
+            max_tokens: 16
+        }
+    }
+}
+
+/// Reasons a phrase can fail [`Phrase::validate`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PhraseError {
+    NoTokens,
+    TokenTooShort { token: String, min_token_len: usize },
+    TooManyTokens { count: usize, max_tokens: usize },
+    UnsupportedWidth(u32)
+}
+
+impl Display for PhraseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoTokens => write!(f, "Phrase has no tokens"),
+            Self::TokenTooShort { token, min_token_len } => {
+                write!(f, "Token '{}' is shorter than {} characters. Allow short tokens if it's meant to be.", token, min_token_len)
+            },
+            Self::TooManyTokens { count, max_tokens } => write!(f, "Phrase has {} tokens, more than {}", count, max_tokens),
+            Self::UnsupportedWidth(width) => {
+                write!(f, "Widths must be between 1 and {} bytes per character, got {}", MAX_BYTES_PER_CHARACTER, width)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PhraseError {}
+
+/// What must come right before the earliest token of a match. The start of the input always counts.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Anchor {
+    /// Anything
+    #[default]
+    None,
+    /// A newline character, decoded with the match's diff and width
+    LineStart,
+    /// A raw byte, before any diff is applied
+    AfterByte(u8)
+}
+
+impl Anchor {
+
+    /// True if a match found with the diff and width can follow `preceding`, the bytes right before it.
+    /// `preceding` is empty at the start of the input, and can be shorter than a character otherwise.
+    pub fn accepts(&self, preceding: &[u8], codepoint_diff: i32, bytes_per_character: u32) -> bool {
+        if preceding.is_empty() {
+            return true;
+        }
+        let bpc = bytes_per_character as usize;
+        match *self {
+            Anchor::None => true,
+            Anchor::AfterByte(byte) => preceding[preceding.len() - 1] == byte,
+            Anchor::LineStart if preceding.len() < bpc => false,
+            Anchor::LineStart => {
+                let char = &preceding[preceding.len() - bpc..];
+                Text::from_slice(char, codepoint_diff, bytes_per_character).0 == ['\n' as u32]
+            }
+        }
+    }
+}
+
+impl Phrase {
+    /// Phrase of the tokens given. A phrase without tokens never matches. See [`Self::try_new`] to refuse one.
+    pub fn new(tokens: Vec<Text>) -> Self {
+        Self { tokens, not: Vec::new(), anchor: Anchor::None, allow_short_tokens: false, widths: Vec::new() }
+    }
+
+    /// Same as [`Self::new`], but refuses an empty list of tokens, such as from splitting a string that's only whitespace
+    pub fn try_new(tokens: Vec<Text>) -> Result<Self, PhraseError> {
+        match tokens.is_empty() {
+            true => Err(PhraseError::NoTokens),
+            false => Ok(Self::new(tokens))
+        }
+    }
+
+    /// Phrase of the tokens given, with repeats removed. See [`Self::deduplicate_tokens`].
+    pub fn from_strs(strs: &[&str]) -> Self {
+        let texts = strs
+            .iter()
+            .map(|str| Text::from_str(str))
+            .collect();
+        Self::new(texts).deduplicate_tokens()
+    }
+
+    /// Same phrase, keeping only the first of each repeated token.
+    /// Finds everything the phrase with repeats does, and more, since repeats each need their own instance to match.
+    /// Use [`Self::new`] to keep them.
+    pub fn deduplicate_tokens(mut self) -> Self {
+        let mut seen: HashSet<&Text> = HashSet::new();
+        let keep: Vec<bool> = self.tokens.iter().map(|token| seen.insert(token)).collect();
+        let mut keep = keep.into_iter();
+        self.tokens.retain(|_| keep.next().unwrap());
+        self
+    }
+
+    /// Same phrase, excluding matches near any of `not`
+    pub fn with_not(mut self, not: Vec<Text>) -> Self {
+        self.not = not;
+        self
+    }
+
+    /// Same phrase, only matching after the anchor
+    pub fn with_anchor(mut self, anchor: Anchor) -> Self {
+        self.anchor = anchor;
+        self
+    }
+
+    /// Same phrase, letting tokens shorter than the minimum through validation.
+    /// Short tokens still never set the diff a match is made with while the phrase has a longer token.
+    pub fn with_allow_short_tokens(mut self, allow_short_tokens: bool) -> Self {
+        self.allow_short_tokens = allow_short_tokens;
+        self
+    }
+
+    /// Same phrase, only found with these bytes per character. Empty for every supported width.
+    /// Saves searching for the phrase at widths it can't be in, like 2 bytes for text known to be ASCII.
+    pub fn with_widths(mut self, widths: Vec<u32>) -> Self {
+        self.widths = widths;
+        self
+    }
+
+    /// Whether validation lets tokens shorter than the minimum through. See [`Self::with_allow_short_tokens`].
+    pub fn allow_short_tokens(&self) -> bool {
+        self.allow_short_tokens
+    }
+
+    /// Bytes per character the phrase can be found with. Empty for every supported width. See [`Self::with_widths`].
+    pub fn widths(&self) -> &[u32] {
+        &self.widths
+    }
+
+    /// Whether every token and exclusion is valid Unicode. See [`Text::is_valid_unicode`].
+    pub fn is_valid_unicode(&self) -> bool {
+        self.tokens.iter().chain(&self.not).all(Text::is_valid_unicode)
+    }
+
+    /// Checks the phrase has tokens, that they're within the limits, and that its widths are supported
+    pub fn validate(&self, limits: &PhraseLimits) -> Result<(), PhraseError> {
+        if self.tokens.is_empty() {
+            return Err(PhraseError::NoTokens);
+        }
+        if self.tokens.len() > limits.max_tokens {
+            return Err(PhraseError::TooManyTokens { count: self.tokens.len(), max_tokens: limits.max_tokens });
+        }
+        if let Some(width) = self.widths.iter().find(|width| **width == 0 || **width as usize > MAX_BYTES_PER_CHARACTER) {
+            return Err(PhraseError::UnsupportedWidth(*width));
+        }
+        let short = self.tokens.iter().find(|token| token.0.len() < limits.min_token_len);
+        match short {
+            Some(token) if !self.allow_short_tokens => Err(PhraseError::TokenTooShort {
+                token: token.to_string(),
+                min_token_len: limits.min_token_len
+            }),
+            _ => Ok(())
+        }
+    }
+
+    /// Identifier derived from the phrase's tokens, exclusions, anchor and options, so it's the same across runs.
+    /// [`Self::stable_hash`] as hex, since JSON numbers can't hold every u64.
+    pub fn id(&self) -> String {
+        format!("{:016x}", self.stable_hash())
+    }
+
+    /// 64-bit FNV-1a hash of the phrase's tokens, exclusions, anchor and options.
+    /// Unlike [`std::hash::Hash`] with the standard hashers, it's the same across runs and Rust versions.
+    pub fn stable_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut write = |codepoint: u32| {
+            for byte in codepoint.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        let write_tokens = |tokens: &[Text], write: &mut dyn FnMut(u32)| {
+            for token in tokens {
+                token.0.iter().for_each(|codepoint| write(*codepoint));
+                write(u32::MAX);
+            }
+        };
+        write_tokens(&self.tokens, &mut write);
+        if !self.not.is_empty() {
+            write(u32::MAX - 1);
+            write_tokens(&self.not, &mut write);
+        }
+        match self.anchor {
+            Anchor::None => {},
+            Anchor::LineStart => write(u32::MAX - 2),
+            Anchor::AfterByte(byte) => {
+                write(u32::MAX - 3);
+                write(byte as u32);
+            }
+        }
+        if self.allow_short_tokens {
+            write(u32::MAX - 4);
+        }
+        if !self.widths.is_empty() {
+            write(u32::MAX - 5);
+            self.widths.iter().for_each(|width| write(*width));
+        }
+        hash
     }
-    return None;
-}
 
-/// Searches for a within b. Assumes b is 2 bytes per character.
-fn search_2bytes_with_diff(a: &[u32], b: &[u8], codepoint_diff: i32) -> Option<TokenInstance> {
-    let a = a.as_ref();
-    let b = b.as_ref();
-    let b_len = b.len() / 2;
-    if a.is_empty() { return None; }
-    if a.len() > b_len { return None; }
-    'outer: for b_idx in 0..(b_len - a.len() + 1) {
-        for a_idx in 0..a.len() {
-            let char_a = a[a_idx] as i32;
-            let char_b = get_2bytes(b, b_idx + a_idx);
-            let char_b = char_b as i32 - codepoint_diff;
-            if char_a != char_b { continue 'outer; }
-        }
-        return Some(TokenInstance {
-            index: b_idx*2,
-            codepoint_diff,
-            bytes_per_character: 2
-        });
+    /// Appends a token to the end of the phrase
+    pub fn push(&mut self, token: Text) {
+        self.tokens.push(token);
+    }
+
+    /// True if any token of the phrase is also a token of `other`, so a window can match both with the same text
+    pub fn overlaps_with(&self, other: &Phrase) -> bool {
+        self.tokens.iter().any(|token| other.tokens.contains(token))
     }
-    return None;
 }
 
-pub fn get_2bytes(slice: &[u8], idx: usize) -> u32 {
-    let a = slice[idx*2] as u32;
-    let b = slice[idx*2+1] as u32;
-    a + (b << 8)
+impl Extend<Text> for Phrase {
+    fn extend<I: IntoIterator<Item=Text>>(&mut self, iter: I) {
+        self.tokens.extend(iter);
+    }
 }
 
+/// The tokens separated by spaces, followed by the phrase's exclusions, anchor and other settings in parentheses if it has any,
+/// so distinct phrases read differently. Like "famine where (not lies; at line start)".
+impl Display for Phrase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Text::concat(&self.tokens, Some(' ' as u32)).fmt(f)?;
+        let mut settings = Vec::new();
+        if !self.not.is_empty() {
+            settings.push(format!("not {}", Text::concat(&self.not, Some(' ' as u32))));
+        }
+        match self.anchor {
+            Anchor::None => {},
+            Anchor::LineStart => settings.push("at line start".to_owned()),
+            Anchor::AfterByte(byte) => settings.push(format!("after byte 0x{:02x}", byte))
+        }
+        if !self.widths.is_empty() {
+            let widths: Vec<String> = self.widths.iter().map(|width| width.to_string()).collect();
+            settings.push(format!("widths {}", widths.join(", ")));
+        }
+        if self.allow_short_tokens {
+            settings.push("short tokens allowed".to_owned());
+        }
+        match settings.is_empty() {
+            true => Ok(()),
+            false => write!(f, " ({})", settings.join("; "))
+        }
+    }
+}
 
-/// Result of a text search
-#[derive(Debug, Copy, Clone)]
-pub struct TokenInstance {
-    pub index: usize,
-    pub codepoint_diff: i32,
-    pub bytes_per_character: u32
+// Serialized form of a Phrase
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum PhraseRepr {
+    Tokens(Vec<Text>),
+    Detailed {
+        tokens: Vec<Text>,
+        #[serde(default)]
+        not: Vec<Text>,
+        #[serde(default)]
+        anchor: Anchor,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        allow_short_tokens: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        widths: Vec<u32>
+    }
 }
 
-/// A sequence of texts
-#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
-pub struct Phrase(pub Vec<Text>);
-impl Phrase {
-    pub fn from_strs(strs: &[&str]) -> Self {
-        let texts = strs
-            .iter()
-            .map(|str| Text::from_str(str))
-            .collect();
-        Self(texts)
+impl From<PhraseRepr> for Phrase {
+    fn from(repr: PhraseRepr) -> Self {
+        match repr {
+            PhraseRepr::Tokens(tokens) => Self::new(tokens),
+            PhraseRepr::Detailed { tokens, not, anchor, allow_short_tokens, widths } => Self { tokens, not, anchor, allow_short_tokens, widths }
+        }
     }
 }
 
-impl Display for Phrase {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (index, text) in self.0.iter().enumerate() {
-            text.fmt(f)?;
-            if index != self.0.len() - 1 {
-                f.write_char(' ')?;
+impl From<Phrase> for PhraseRepr {
+    fn from(phrase: Phrase) -> Self {
+        match phrase.not.is_empty() && phrase.anchor == Anchor::None && !phrase.allow_short_tokens && phrase.widths.is_empty() {
+            true => Self::Tokens(phrase.tokens),
+            false => Self::Detailed {
+                tokens: phrase.tokens,
+                not: phrase.not,
+                anchor: phrase.anchor,
+                allow_short_tokens: phrase.allow_short_tokens,
+                widths: phrase.widths
             }
         }
-        Ok(())
+    }
+}
+
+impl JsonSchema for Phrase {
+    fn schema_name() -> String { "Phrase".to_owned() }
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        PhraseRepr::json_schema(gen)
     }
 }
 
 /// Instance of a phrase found
-#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, JsonSchema)]
 pub struct PhraseInstance {
     pub phrase_index: usize,
-    pub file_pos: usize,
+    pub file_pos: usize,            // Position of the earliest token
+    pub end_pos: usize,             // Position after the last byte of the furthest token
     pub codepoint_diff: i32,
-    pub bytes_per_character: u32
+    pub bytes_per_character: u32,
+    #[serde(default)]
+    pub line: Option<usize>,        // 1-based line of file_pos. Only known for 1 byte per character.
+    #[serde(default)]
+    pub column: Option<usize>       // 1-based column of file_pos, in bytes. Only known for 1 byte per character.
+}
+
+impl PhraseInstance {
+
+    /// Bytes the instance spans, from the start of its earliest token to the end of its furthest one
+    pub fn len_bytes(&self) -> usize {
+        self.end_pos - self.file_pos
+    }
+
+    /// Re-reads the bytes the instance spans and decodes them
+    pub fn extract_text(&self, reader: &mut (impl Read + Seek)) -> io::Result<Text> {
+        reader.seek(SeekFrom::Start(self.file_pos as u64))?;
+        let mut bytes = vec![0; self.end_pos - self.file_pos];
+        reader.read_exact(&mut bytes)?;
+        Ok(Text::from_slice(&bytes, self.codepoint_diff, self.bytes_per_character))
+    }
+}
+
+/// Instance of a phrase found, along with the raw bytes around it. See [`Finder::collect_rich`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RichPhraseInstance {
+    pub instance: PhraseInstance,
+    pub context_left: Vec<u8>,      // Bytes of context before file_pos
+    pub context_right: Vec<u8>      // Bytes of context from end_pos
 }
 
 /// A group of phrase instances
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub struct PhraseInstanceGroup(pub Vec<PhraseInstance>);
+impl PhraseInstanceGroup {
+
+    /// Empty group yielded by a [`WatchdogFinder`] when it restarts from the beginning.
+    /// A [`Finder`] never yields an empty group.
+    pub fn wrapped_around() -> Self { Self(Vec::new()) }
+
+    pub fn is_wrapped_around(&self) -> bool { self.0.is_empty() }
+
+    /// Instances in the group by phrase index. A [`Finder`] yields at most one instance of each phrase per group.
+    pub fn by_phrase(&self) -> HashMap<usize, &PhraseInstance> {
+        self.0.iter().map(|instance| (instance.phrase_index, instance)).collect()
+    }
+
+    /// Instance of the phrase at `phrase_index`, if the group has one. Like [`Self::by_phrase`], without building a map.
+    pub fn get_phrase(&self, phrase_index: usize) -> Option<&PhraseInstance> {
+        self.0.iter().find(|instance| instance.phrase_index == phrase_index)
+    }
+}
 
 #[test]
+#[allow(clippy::map_clone)]
 fn test_finder_1() {
     use std::io::BufReader;
     // "Reads" input
@@ -413,7 +1478,10 @@ fn test_finder_1() {
         phrase_index: 0,
         codepoint_diff: 0,
         file_pos: 288,
-        bytes_per_character: 1
+        end_pos: 300,
+        bytes_per_character: 1,
+        line: Some(7),
+        column: Some(12)
     }];
     let groups: Vec<PhraseInstanceGroup> = finder.collect();
     let actual: Vec<PhraseInstance> = groups
@@ -425,6 +1493,7 @@ fn test_finder_1() {
 }
 
 #[test]
+#[allow(clippy::map_clone)]
 fn test_finder_2() {
     use std::io::BufReader;
     // "Reads" input
@@ -441,7 +1510,10 @@ fn test_finder_2() {
         phrase_index: 0,
         codepoint_diff: 0,
         file_pos: 285,
-        bytes_per_character: 1
+        end_pos: 313,
+        bytes_per_character: 1,
+        line: Some(7),
+        column: Some(10)
     }];
     let groups: Vec<PhraseInstanceGroup> = finder.collect();
     let actual: Vec<PhraseInstance> = groups
@@ -453,6 +1525,7 @@ fn test_finder_2() {
 }
 
 #[test]
+#[allow(clippy::map_clone)]
 fn test_finder_edgecase() {
     use std::io::BufReader;
     // "Reads" input
@@ -469,7 +1542,10 @@ fn test_finder_edgecase() {
         phrase_index: 0,
         codepoint_diff: 0,
         file_pos: 12,
-        bytes_per_character: 1
+        end_pos: 16,
+        bytes_per_character: 1,
+        line: Some(1),
+        column: Some(13)
     }];
     let groups: Vec<PhraseInstanceGroup> = finder.collect();
     let actual: Vec<PhraseInstance> = groups
@@ -481,6 +1557,7 @@ fn test_finder_edgecase() {
 }
 
 #[test]
+#[allow(clippy::map_clone)]
 fn test_finder_multiphrase() {
     use std::io::BufReader;
     // "Reads" input
@@ -499,13 +1576,19 @@ fn test_finder_multiphrase() {
             phrase_index: 0,
             codepoint_diff: 0,
             file_pos: 285,
-            bytes_per_character: 1
+            end_pos: 313,
+            bytes_per_character: 1,
+            line: Some(7),
+            column: Some(10)
         },
         PhraseInstance {
             phrase_index: 1,
             file_pos: 479,
+            end_pos: 491,
             codepoint_diff: 0,
-            bytes_per_character: 1
+            bytes_per_character: 1,
+            line: Some(11),
+            column: Some(9)
         }
     ];
     let groups: Vec<PhraseInstanceGroup> = finder.collect();
@@ -515,6 +1598,7 @@ fn test_finder_multiphrase() {
         .map(|instance| instance.clone())
         .collect();
     assert_eq!(expected, actual);
+    assert_eq!(vec![28, 12], actual.iter().map(PhraseInstance::len_bytes).collect::<Vec<_>>());
 }
 
 
@@ -534,9 +1618,260 @@ fn test_finder_context() {
     finder.next().unwrap();
     let context = finder.get_context(0, 1);
     assert_eq!(Text::from_str(" fuel,\n  Making a famine where abundance"), context);
+    assert_eq!(" fuel,\n  Making a famine where abundance", finder.context_as_utf8_lossy());
+}
+
+#[test]
+fn test_finder_set_phrases() {
+    use std::io::BufReader;
+    let input: &[u8] = include_bytes!("test_text_2.txt");
+    let mut reader = BufReader::new(input);
+    let phrases = [Phrase::from_strs(&["within", "sunken", "deep"])];
+    let mut finder = Finder::new(&phrases, 64, 32, &mut reader);
+    assert_eq!(285, finder.next().unwrap().0[0].file_pos);
+
+    // Carries on from where it was with the new phrases, finding the match still in the window again
+    finder.set_phrases(&[Phrase::from_strs(&["sum", "count"]), Phrase::from_strs(&["within", "sunken", "deep"])]);
+    assert_eq!(0, finder.phrases_matched_so_far().len());
+    let found: Vec<PhraseInstance> = finder.flat_map(|group| group.0).collect();
+    assert_eq!(vec![(1, 285), (0, 479)], found.iter().map(|instance| (instance.phrase_index, instance.file_pos)).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_finder_positions_at_edges() {
+    let phrase = "quick fox";
+    let phrases = [Phrase::from_strs(&["quick", "fox"])];
+    let sizes = [(20, 12), (32, 16), (40, 20), (64, 32), (64, 64), (128, 40)];
+    for (context_size, window_size) in sizes {
+        for input_len in [phrase.len(), 30, 100, 300] {
+            let last = input_len - phrase.len();
+            let mut offsets = vec![0, 1, window_size - 1, last / 2, last];
+            offsets.retain(|offset| *offset <= last);
+            for offset in offsets {
+                // Filler of counting digits can't match under any diff
+                let mut input: Vec<u8> = (0..input_len).map(|idx| b'0' + (idx % 10) as u8).collect();
+                input[offset..offset + phrase.len()].copy_from_slice(phrase.as_bytes());
+                let mut reader = input.as_slice();
+                let found: Vec<(usize, usize, Option<usize>)> = Finder::new(&phrases, context_size, window_size, &mut reader)
+                    .flat_map(|group| group.0)
+                    .map(|instance| (instance.file_pos, instance.end_pos, instance.column))
+                    .collect();
+                assert_eq!(
+                    vec![(offset, offset + phrase.len(), Some(offset + 1))],
+                    found,
+                    "context {}, window {}, input of {} with the phrase at {}", context_size, window_size, input_len, offset
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_finder_ignores_flush_padding() {
+    // Zeros pushed to flush the window match a token of repeated characters under some diff
+    let input = b"0123456789";
+    let mut reader = &input[..];
+    assert_eq!(0, Finder::new(&[Phrase::from_strs(&["aaaa"])], 32, 16, &mut reader).count());
+}
+
+#[test]
+fn test_finder_restart() {
+    let phrases = [Phrase::from_strs(&["famine", "where"]), Phrase::from_strs(&["sum", "my", "count"])];
+    let input_1: &[u8] = include_bytes!("test_text_1.txt");
+    let input_2: &[u8] = include_bytes!("test_text_2.txt");
+    let search = |finder: &mut Finder<&[u8]>| -> Vec<PhraseInstance> {
+        finder.flat_map(|group| group.0).collect()
+    };
+
+    // Restarting part way through searches the new reader as if from scratch
+    let mut reader_1 = input_1;
+    let mut finder = Finder::new(&phrases, 64, 32, &mut reader_1);
+    assert!(finder.next().is_some());
+    let mut reader_2 = input_2;
+    finder.restart(&mut reader_2);
+    assert_eq!(0, finder.bytes_read());
+    assert!(finder.phrases_matched_so_far().is_empty());
+    let restarted = search(&mut finder);
+    let mut reader_2 = input_2;
+    assert_eq!(search(&mut Finder::new(&phrases, 64, 32, &mut reader_2)), restarted);
+    assert_eq!(vec![479], restarted.iter().map(|instance| instance.file_pos).collect::<Vec<_>>());
+
+    // Also after running to the end
+    let mut reader_1 = input_1;
+    finder.restart(&mut reader_1);
+    assert_eq!(vec![288], search(&mut finder).iter().map(|instance| instance.file_pos).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_finder_collect_rich() {
+    let phrases = [Phrase::from_strs(&["famine", "where"]), Phrase::from_strs(&["lies"])];
+    let input: &[u8] = include_bytes!("test_text_1.txt");
+    let mut reader = input;
+    let rich = Finder::new(&phrases, 40, 20, &mut reader).collect_rich();
+    let mut reader = input;
+    let instances: Vec<PhraseInstance> = Finder::new(&phrases, 40, 20, &mut reader).flat_map(|group| group.0).collect();
+    assert_eq!(instances, rich.iter().map(|rich| rich.instance.clone()).collect::<Vec<_>>());
+
+    // The context on either side joins up with the instance to match the input
+    let famine = &rich[0];
+    assert_eq!(288, famine.instance.file_pos);
+    assert!(famine.context_left.ends_with(b"Making a "));
+    assert!(famine.context_right.starts_with(b" abundance"));
+    for rich in &rich {
+        let start = rich.instance.file_pos - rich.context_left.len();
+        let end = rich.instance.end_pos + rich.context_right.len();
+        assert_eq!(&input[start..end], [&rich.context_left[..], &input[rich.instance.file_pos..rich.instance.end_pos], &rich.context_right[..]].concat());
+        assert!(end <= input.len());
+    }
+
+    // Padding flushed past the end of the input isn't context
+    let mut reader: &[u8] = b"a famine where";
+    let rich = Finder::new(&phrases, 40, 20, &mut reader).collect_rich();
+    assert_eq!(b"a ".to_vec(), rich[0].context_left);
+    assert!(rich[0].context_right.is_empty());
+}
+
+#[test]
+fn test_finder_empty_phrase() {
+    let input: &[u8] = include_bytes!("test_text_1.txt");
+    let mut reader = input;
+    let phrases = [Phrase::new(vec![]), Phrase::from_strs(&["famine", "where"])];
+    let found: Vec<(usize, usize)> = Finder::new(&phrases, 40, 20, &mut reader)
+        .flat_map(|group| group.0)
+        .map(|instance| (instance.phrase_index, instance.file_pos))
+        .collect();
+    assert_eq!(vec![(1, 288)], found);
+    assert_eq!(Err(PhraseError::NoTokens), Phrase::try_new(vec![]));
+    assert!(Phrase::try_new(vec![Text::from_str("famine")]).is_ok());
+}
+
+#[test]
+fn test_phrase_instance_group_by_phrase() {
+    let input: &[u8] = include_bytes!("test_text_2.txt");
+    let phrases = [Phrase::from_strs(&["sum", "my", "count"]), Phrase::from_strs(&["sum", "count"])];
+    let mut reader = input;
+    let group = Finder::new(&phrases, 64, 32, &mut reader).next().unwrap();
+    let by_phrase = group.by_phrase();
+    assert_eq!(2, by_phrase.len());
+    assert_eq!(Some(&by_phrase[&0]), group.get_phrase(0).as_ref());
+    assert_eq!(479, group.get_phrase(1).unwrap().file_pos);
+    assert_eq!(None, group.get_phrase(2));
+    assert!(PhraseInstanceGroup::wrapped_around().by_phrase().is_empty());
+}
+
+#[test]
+fn test_finder_filter_by_phrase() {
+    let input: &[u8] = include_bytes!("test_text_2.txt");
+    let phrases = [Phrase::from_strs(&["within", "sunken", "deep"]), Phrase::from_strs(&["sum", "count"])];
+    let mut reader = input;
+    let found: Vec<usize> = Finder::new(&phrases, 64, 32, &mut reader)
+        .filter_map(|group| group.get_phrase(1).map(|instance| instance.file_pos))
+        .collect();
+    assert_eq!(vec![479], found);
+}
+
+#[test]
+fn test_finder_filter_and_map_context() {
+    let input: &[u8] = include_bytes!("test_text_2.txt");
+    let phrases = [Phrase::from_strs(&["within", "sunken", "deep"]), Phrase::from_strs(&["sum", "count"])];
+    let mut reader = input;
+    let found: Vec<usize> = Finder::new(&phrases, 64, 32, &mut reader)
+        .filter(|group| group.0.iter().any(|instance| instance.phrase_index == 1))
+        .flat_map(|group| group.0)
+        .map(|instance| instance.file_pos)
+        .collect();
+    assert_eq!(vec![479], found);
+
+    let mut reader = input;
+    let contexts: Vec<(usize, String)> = Finder::new(&phrases, 64, 32, &mut reader)
+        .map_context()
+        .map(|(group, context)| (group.0[0].phrase_index, context.to_string()))
+        .collect();
+    assert_eq!(2, contexts.len());
+    assert!(contexts[0].1.contains("within thine own deep sunken"));
+    assert!(contexts[1].1.contains("sum my count"));
+}
+
+#[test]
+fn test_finder_filter_range() {
+    let input: &[u8] = include_bytes!("test_text_2.txt");
+    let phrases = [Phrase::from_strs(&["within", "sunken", "deep"]), Phrase::from_strs(&["sum", "count"])];
+    let positions = |start: usize, end: usize| -> Vec<usize> {
+        let mut reader = input;
+        Finder::new(&phrases, 64, 32, &mut reader)
+            .filter_range(start, end)
+            .flat_map(|group| group.0)
+            .map(|instance| instance.file_pos)
+            .collect()
+    };
+    let all = positions(0, usize::MAX);
+    assert_eq!(2, all.len());
+    assert_eq!(479, all[1]);
+    assert_eq!(vec![479], positions(all[0] + 1, input.len()));
+    assert_eq!(vec![479], positions(479, 479));
+    assert_eq!(vec![all[0]], positions(0, 478));
+    assert!(positions(480, input.len()).is_empty());
+}
+
+#[test]
+fn test_finder_debug() {
+    let mut input: &[u8] = include_bytes!("test_text_1.txt");
+    let phrases = [Phrase::from_strs(&["famine", "where"])];
+    let mut finder = Finder::new(&phrases, 40, 20, &mut input);
+    assert!(finder.next().is_some());
+    let debug = format!("{:?}", finder);
+    assert!(debug.starts_with(r#"Finder { phrases: ["famine where"], reader: "&[u8]", encoder: "#), "{}", debug);
+    assert!(debug.contains(&format!("current_byte: {}, window_size: 20, context_capacity: 40", finder.bytes_read())), "{}", debug);
+    assert!(debug.contains("phrase_found_at: [Some(288)]"), "{}", debug);
+}
+
+#[test]
+fn test_finder_read_errors() {
+
+    // Interrupts every other read, reads at most one byte at a time, and fails for good after `fail_after` bytes
+    struct FlakyReader<'a> { input: &'a [u8], interrupted: bool, fail_after: Option<usize> }
+    impl Read for FlakyReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupted = !self.interrupted;
+            if self.interrupted {
+                return Err(io::Error::from(io::ErrorKind::Interrupted));
+            }
+            if self.fail_after == Some(0) {
+                return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+            }
+            self.fail_after = self.fail_after.map(|remaining| remaining - 1);
+            let len = buf.len().min(self.input.len()).min(1);
+            buf[..len].copy_from_slice(&self.input[..len]);
+            self.input = &self.input[len..];
+            Ok(len)
+        }
+    }
+
+    let input: &[u8] = include_bytes!("test_text_2.txt");
+    let phrases = [Phrase::from_strs(&["within", "sunken", "deep"]), Phrase::from_strs(&["sum", "count"])];
+    let mut reader = input;
+    let clean: Vec<PhraseInstanceGroup> = Finder::new(&phrases, 64, 32, &mut reader).collect();
+    assert_eq!(2, clean.len());
+
+    // Interrupted reads are retried, so the results are the same as a clean reader's
+    let mut reader = FlakyReader { input, interrupted: false, fail_after: None };
+    let mut finder = Finder::new(&phrases, 64, 32, &mut reader);
+    let flaky: Vec<PhraseInstanceGroup> = finder.by_ref().collect();
+    assert_eq!(clean, flaky);
+    assert!(finder.read_error().is_none());
+
+    // Other errors end the input early, and are kept
+    let mut reader = FlakyReader { input, interrupted: false, fail_after: Some(400) };
+    let mut finder = Finder::new(&phrases, 64, 32, &mut reader);
+    let failed: Vec<PhraseInstanceGroup> = finder.by_ref().collect();
+    assert_eq!(clean[..1], failed[..]);
+    assert_eq!(Some(io::ErrorKind::BrokenPipe), finder.read_error().map(|err| err.kind()));
+    assert!(finder.next().is_none());
+    assert_eq!(400, finder.bytes_read() - finder.padding);
 }
 
 #[test]
+#[allow(clippy::map_clone)]
 fn test_finder_u16_le() {
     use std::io::BufReader;
     // "Reads" input as little-endian
@@ -557,7 +1892,10 @@ fn test_finder_u16_le() {
         phrase_index: 0,
         codepoint_diff: 0,
         file_pos: 570,
-        bytes_per_character: 2
+        end_pos: 626,
+        bytes_per_character: 2,
+        line: None,
+        column: None
     }];
     let groups: Vec<PhraseInstanceGroup> = finder.collect();
     let actual: Vec<PhraseInstance> = groups
@@ -566,9 +1904,11 @@ fn test_finder_u16_le() {
         .map(|instance| instance.clone())
         .collect();
     assert_eq!(expected, actual);
+    assert_eq!(56, actual[0].len_bytes());
 }
 
 #[test]
+#[allow(clippy::map_clone)]
 fn test_finder_u16_be() {
     use std::io::BufReader;
     // "Reads" input as big-endian
@@ -589,7 +1929,10 @@ fn test_finder_u16_be() {
         phrase_index: 0,
         codepoint_diff: 0,
         file_pos: 571,
-        bytes_per_character: 2
+        end_pos: 627,
+        bytes_per_character: 2,
+        line: None,
+        column: None
     }];
     let groups: Vec<PhraseInstanceGroup> = finder.collect();
     let actual: Vec<PhraseInstance> = groups
@@ -602,6 +1945,7 @@ fn test_finder_u16_be() {
 
 
 #[test]
+#[allow(clippy::map_clone)]
 fn test_finder_offset13() {
     use std::io::BufReader;
     // "Reads" input
@@ -619,7 +1963,10 @@ fn test_finder_offset13() {
         phrase_index: 0,
         codepoint_diff: 13,
         file_pos: 285,
-        bytes_per_character: 1
+        end_pos: 313,
+        bytes_per_character: 1,
+        line: Some(7),
+        column: Some(10)
     }];
     let groups: Vec<PhraseInstanceGroup> = finder.collect();
     let actual: Vec<PhraseInstance> = groups
@@ -630,21 +1977,463 @@ fn test_finder_offset13() {
     assert_eq!(expected, actual);
 }
 
+#[test]
+fn test_finder_collect_by_phrase() {
+    use std::io::BufReader;
+    // "Reads" input
+    let input: &[u8] = include_bytes!("test_text_2.txt");
+    let mut reader = BufReader::new(input);
+
+    // Sets up finder
+    let phrase1 = Phrase::from_strs(&["within", "sunken", "deep"]);
+    let phrase2 = Phrase::from_strs(&["sum", "my", "count"]);
+    let phrase3 = Phrase::from_strs(&["not", "present"]);
+    let phrases = &[phrase1, phrase2, phrase3];
+    let finder = Finder::new(phrases, 64, 32, &mut reader);
+
+    // Runs finder and checks
+    let by_phrase = finder.collect_by_phrase();
+    assert_eq!(2, by_phrase.len());
+    assert_eq!(285, by_phrase[&0][0].file_pos);
+    assert_eq!(479, by_phrase[&1][0].file_pos);
+    assert!(!by_phrase.contains_key(&2));
+
+    let mut reader = BufReader::new(input);
+    assert_eq!(by_phrase, Finder::new(phrases, 64, 32, &mut reader).into_phrase_map());
+}
+
+#[test]
+fn test_finder_peek() {
+    let input: &[u8] = include_bytes!("test_text_2.txt");
+    let mut reader = input;
+    let phrases = &[
+        Phrase::from_strs(&["within", "sunken", "deep"]),
+        Phrase::from_strs(&["sum", "my", "count"])
+    ];
+    let mut finder = Finder::new(phrases, 64, 32, &mut reader);
+
+    let peeked = finder.peek().cloned();
+    assert_eq!(285, peeked.as_ref().unwrap().0[0].file_pos);
+    assert_eq!(peeked.as_ref(), finder.peek());
+    assert_eq!(peeked, finder.next());
+    assert_eq!(479, finder.peek().unwrap().0[0].file_pos);
+    assert_eq!(479, finder.next().unwrap().0[0].file_pos);
+    assert_eq!(None, finder.peek());
+    assert_eq!(None, finder.next());
+}
+
+#[test]
+fn test_finder_phrases_matched_so_far() {
+    let input: &[u8] = include_bytes!("test_text_2.txt");
+    let mut reader = input;
+    let phrases = &[
+        Phrase::from_strs(&["within", "sunken", "deep"]),
+        Phrase::from_strs(&["sum", "my", "count"]),
+        Phrase::from_strs(&["not", "present"])
+    ];
+    let mut finder = Finder::new(phrases, 64, 32, &mut reader);
+    assert!(finder.phrases_matched_so_far().is_empty());
+
+    finder.next();
+    assert_eq!(&HashSet::from([0]), finder.phrases_matched_so_far());
+    finder.next();
+    assert_eq!(&HashSet::from([0, 1]), finder.phrases_matched_so_far());
+    assert!(finder.next().is_none());
+    assert_eq!(&HashSet::from([0, 1]), finder.phrases_matched_so_far());
+}
+
+
+#[test]
+fn test_text_concat() {
+    let texts = [Text::from_str("famine"), Text::from_str("where")];
+    assert_eq!(Text::from_str("famine where"), Text::concat(&texts, Some(' ' as u32)));
+    assert_eq!(Text::from_str("faminewhere"), Text::concat(&texts, None));
+    assert_eq!(Text::from_str(""), Text::concat(&[], Some(' ' as u32)));
+    assert_eq!("famine where", Phrase::new(texts.to_vec()).to_string());
+}
+
+#[test]
+fn test_phrase_display() {
+    let phrase = Phrase::from_strs(&["famine", "where"]);
+    assert_eq!("famine where", phrase.to_string());
+    assert_eq!("famine where (not lies)", phrase.clone().with_not(vec![Text::from_str("lies")]).to_string());
+    assert_eq!("famine where (at line start)", phrase.clone().with_anchor(Anchor::LineStart).to_string());
+    let detailed = phrase
+        .with_not(vec![Text::from_str("abundance"), Text::from_str("lies")])
+        .with_anchor(Anchor::AfterByte(b'\n'))
+        .with_widths(vec![1, 2])
+        .with_allow_short_tokens(true);
+    assert_eq!("famine where (not abundance lies; after byte 0x0a; widths 1, 2; short tokens allowed)", detailed.to_string());
+}
+
+#[test]
+fn test_auto_size() {
+    let short = Phrase::from_strs(&["word"]);
+    let long = Phrase::from_strs(&["within", "sunken", "deep"]);
+
+    // 4 characters
+    let options = SearchOptions::auto_size(std::slice::from_ref(&short), 1, 4096);
+    assert_eq!(SearchOptions { context_size: 8, window_size: 4 }, options);
+
+    // 16 characters plus 2 gaps of 2 characters
+    let options = SearchOptions::auto_size(&[short.clone(), long.clone()], 2, 4096);
+    assert_eq!(SearchOptions { context_size: 80, window_size: 40 }, options);
+
+    // Clamped
+    let options = SearchOptions::auto_size(&[short, long], 2, 50);
+    assert_eq!(SearchOptions { context_size: 48, window_size: 40 }, options);
+    let options = SearchOptions::auto_size(&[], 2, 4096);
+    assert_eq!(SearchOptions { context_size: 4, window_size: 1 }, options);
+}
+
+#[test]
+fn test_auto_size_threshold() {
+    let phrases = [Phrase::from_strs(&["quick", "fox"])];
+    let options = SearchOptions::auto_size(&phrases, 1, 4096);
+    let padding = ".".repeat(2 * options.context_size);
+    let count = |text: &str| {
+        let input = format!("{}{}{}", padding, text, padding);
+        let mut reader = input.as_bytes();
+        Finder::new(&phrases, options.context_size, options.window_size, &mut reader).count()
+    };
+
+    // Gaps up to TOKEN_GAP_CHARS fit in the window. Any more don't.
+    assert_eq!(1, count("quick fox"));
+    assert_eq!(1, count("quick, fox"));
+    assert_eq!(0, count("quick,  fox"));
+}
+
+#[test]
+fn test_finder_line_column() {
+    // "\r\n" is a single break, and the flush that finds "word" at the end doesn't add lines
+    let mut input: &[u8] = b"one\r\ntwo\r\n\r\nthree word";
+    let finder = Finder::new(&[Phrase::from_strs(&["word"])], 8, 4, &mut input);
+    let found: Vec<(Option<usize>, Option<usize>)> = finder
+        .flat_map(|group| group.0)
+        .map(|instance| (instance.line, instance.column))
+        .collect();
+    assert_eq!(vec![(Some(4), Some(7))], found);
+
+    // Only known for 1 byte per character
+    let mut input: &[u8] = &b"a\nword".iter().flat_map(|b| [*b, 0]).collect::<Vec<u8>>();
+    let finder = Finder::new(&[Phrase::from_strs(&["word"])], 16, 8, &mut input);
+    let found: Vec<PhraseInstance> = finder.flat_map(|group| group.0).collect();
+    assert!(!found.is_empty());
+    assert!(found.iter().all(|instance| instance.line.is_none() && instance.column.is_none()));
+}
+
+#[test]
+fn test_find_phrase_in_window() {
+    let phrase = Phrase::from_strs(&["quick", "fox"]);
+    let instance = |file_pos: usize, end_pos, codepoint_diff, bytes_per_character| Some(PhraseInstance {
+        phrase_index: 0,
+        file_pos,
+        end_pos,
+        codepoint_diff,
+        bytes_per_character,
+        line: (bytes_per_character == 1).then_some(1),
+        column: (bytes_per_character == 1).then_some(file_pos + 1)
+    });
+
+    // Reports the earliest token, in any order
+    assert_eq!(instance(4, 19, 0, 1), find_phrase_in_window(&phrase, b"the quick brown fox"));
+    assert_eq!(instance(4, 17, 0, 1), find_phrase_in_window(&phrase, b"the fox was quick"));
+
+    // Rotated and 2 bytes per character
+    let rotated: Vec<u8> = b"the quick brown fox".iter().map(|b| b + 1).collect();
+    let two_bytes: Vec<u8> = b"the quick brown fox".iter().flat_map(|b| [*b, 0]).collect();
+    assert_eq!(instance(4, 19, 1, 1), find_phrase_in_window(&phrase, &rotated));
+    assert_eq!(instance(8, 38, 0, 2), find_phrase_in_window(&phrase, &two_bytes));
+
+    // The longest token's diff is taken from its earliest instance, which is reused rather than searched for again
+    let reversed = Phrase::from_strs(&["fox", "quick"]);
+    assert_eq!(instance(4, 19, 0, 1), find_phrase_in_window(&reversed, b"the quick brown fox"));
+    assert_eq!(instance(4, 19, 1, 1), find_phrase_in_window(&reversed, &rotated));
+    assert_eq!(instance(8, 38, 0, 2), find_phrase_in_window(&reversed, &two_bytes));
+    assert_eq!(instance(0, 9, 0, 1), find_phrase_in_window(&reversed, b"fox quick brown quick"));
+
+    // Every token must share a diff
+    let mut mixed = b"the quick brown fox".to_vec();
+    mixed[16..].iter_mut().for_each(|b| *b += 1);
+    assert_eq!(None, find_phrase_in_window(&phrase, &mixed));
+    assert_eq!(None, find_phrase_in_window(&phrase, b"the quick brown dog"));
+}
+
+#[test]
+fn test_phrase_id() {
+    let phrase = Phrase::from_strs(&["famine", "where"]);
+    assert_eq!(phrase.id(), Phrase::from_strs(&["famine", "where"]).id());
+    assert_eq!(16, phrase.id().len());
+    assert_ne!(phrase.id(), Phrase::from_strs(&["famin", "ewhere"]).id());
+    assert_ne!(phrase.id(), Phrase::from_strs(&["where", "famine"]).id());
+}
+
+#[test]
+fn test_find_phrase_not() {
+    let phrase = Phrase::from_strs(&["error"]).with_not(vec![Text::from_str("expected")]);
+    assert!(find_phrase_in_window(&phrase, b"expected error: none").is_none());
+    assert!(find_phrase_in_window(&phrase, b"error: expected none").is_none());
+    assert!(find_phrase_in_window(&phrase, b"unexpected: error").is_none());
+    assert!(find_phrase_in_window(&phrase, b"fatal error: none").is_some());
+
+    // Exclusions under a different diff don't count
+    let mut shifted: Vec<u8> = b"expected ".iter().map(|b| b + 1).collect();
+    shifted.extend_from_slice(b"error");
+    assert!(find_phrase_in_window(&phrase, &shifted).is_some());
+    let shifted: Vec<u8> = b"expected error".iter().map(|b| b + 1).collect();
+    assert!(find_phrase_in_window(&phrase, &shifted).is_none());
+}
+
+#[test]
+fn test_phrase_serde() {
+    let plain = Phrase::from_strs(&["famine", "where"]);
+    let with_not = plain.clone().with_not(vec![Text::from_str("abundance")]);
+    assert_eq!(r#"["famine","where"]"#, serde_json::to_string(&plain).unwrap());
+    let json = serde_json::to_string(&with_not).unwrap();
+    assert_eq!(r#"{"tokens":["famine","where"],"not":["abundance"],"anchor":"none"}"#, json);
+    assert_eq!(with_not, serde_json::from_str(&json).unwrap());
+    assert_ne!(plain.id(), with_not.id());
+
+    let anchored = plain.clone().with_anchor(Anchor::AfterByte(0));
+    let json = serde_json::to_string(&anchored).unwrap();
+    assert_eq!(r#"{"tokens":["famine","where"],"not":[],"anchor":{"after_byte":0}}"#, json);
+    assert_eq!(anchored, serde_json::from_str(&json).unwrap());
+    assert_ne!(plain.id(), anchored.id());
+
+    let ascii_only = plain.clone().with_widths(vec![1]);
+    let json = serde_json::to_string(&ascii_only).unwrap();
+    assert_eq!(r#"{"tokens":["famine","where"],"not":[],"anchor":"none","widths":[1]}"#, json);
+    assert_eq!(ascii_only, serde_json::from_str(&json).unwrap());
+    assert_ne!(plain.id(), ascii_only.id());
+}
+
+#[test]
+fn test_phrase_instance_extract_text() {
+    use std::io::Cursor;
+    let input: Vec<u8> = include_bytes!("test_text_2.txt").iter().map(|b| b + 13).collect();
+    let phrases = [Phrase::from_strs(&["within", "sunken", "deep"])];
+    let instance = Finder::new(&phrases, 64, 32, &mut input.as_slice())
+        .flat_map(|group| group.0)
+        .next()
+        .unwrap();
+    let text = instance.extract_text(&mut Cursor::new(&input)).unwrap();
+    assert_eq!(Text::from_str("within thine own deep sunken"), text);
+}
+
+#[test]
+fn test_finder_anchor() {
+    fn find(input: &[u8], anchor: Anchor, window_size: usize) -> Vec<usize> {
+        let phrases = [Phrase::from_strs(&["error"]).with_anchor(anchor)];
+        let mut reader = input;
+        let mut positions: Vec<usize> = Finder::new(&phrases, 16, window_size, &mut reader)
+            .flat_map(|group| group.0)
+            .map(|instance| instance.file_pos)
+            .collect();
+
+        // Matches in the first context's worth of input can be reported more than once
+        positions.dedup();
+        positions
+    }
+
+    // Starts the file, starts a line, and is mid-line
+    let input = format!("error{pad}\nerror{pad} error{pad}", pad = ".".repeat(32));
+    let rotated: Vec<u8> = input.bytes().map(|b| b + 1).collect();
+    let input = input.as_bytes();
+    assert_eq!(vec![0, 38, 76], find(input, Anchor::None, 8));
+    assert_eq!(vec![0, 38], find(input, Anchor::LineStart, 8));
+    assert_eq!(vec![0, 76], find(input, Anchor::AfterByte(b' '), 8));
+
+    // Newlines are decoded with the diff, bytes are raw
+    assert_eq!(vec![0, 38], find(&rotated, Anchor::LineStart, 8));
+    assert_eq!(vec![0, 76], find(&rotated, Anchor::AfterByte(b' ' + 1), 8));
+
+    // The second token arrives once the first is at the start of the context,
+    // so the byte before the match has already been evicted
+    let phrases = |anchor| [Phrase::from_strs(&["error", "code"]).with_anchor(anchor)];
+    let count = |input: &str, anchor| {
+        let phrases = phrases(anchor);
+        Finder::new(&phrases, 16, 16, &mut input.as_bytes()).count()
+    };
+    let pad = ".".repeat(32);
+    assert_eq!(1, count(&format!("{pad}\nerror.......code{pad}"), Anchor::LineStart));
+    assert_eq!(0, count(&format!("{pad} error.......code{pad}"), Anchor::LineStart));
+}
+
+#[test]
+fn test_phrase_overlaps_with() {
+    let famine = Phrase::from_strs(&["famine", "where"]);
+    assert!(famine.overlaps_with(&Phrase::from_strs(&["where", "abundance"])));
+    assert!(Phrase::from_strs(&["where", "abundance"]).overlaps_with(&famine));
+    assert!(!famine.overlaps_with(&Phrase::from_strs(&["wherever", "abundance"])));
+}
+
+#[test]
+fn test_token_instance_overlaps_with() {
+    let at = |index| TokenInstance { index, codepoint_diff: 0, bytes_per_character: 1 };
+    assert!(at(0).overlaps_with(&at(4), 5, 3));
+    assert!(at(4).overlaps_with(&at(0), 3, 5));
+    assert!(!at(0).overlaps_with(&at(5), 5, 3));
+    assert!(!at(2).overlaps_with(&at(2), 0, 3));
+
+    // Repeated tokens need an instance each
+    let phrases = [Phrase::new(vec![Text::from_str("quick"), Text::from_str("quick")])];
+    let matcher = Matcher::new(&phrases, MatchOptions::default());
+    assert!(matcher.find_in(b"the quick fox", 0).is_empty());
+    assert_eq!(19, matcher.find_in(b"the quick fox quick", 0)[0].end_pos);
+}
+
+#[test]
+fn test_phrase_deduplicate_tokens() {
+    let phrase = Phrase::from_strs(&["word", "other", "word"]);
+    assert_eq!(Phrase::new(vec![Text::from_str("word"), Text::from_str("other")]), phrase);
+
+    // Finds the same instances as the phrase with the repeat, and also those with the token only once
+    let repeated = Phrase::new(["word", "word", "other"].iter().map(|str| Text::from_str(str)).collect());
+    let input = "a word, then a word and an other";
+    let search = |phrase: Phrase| -> Vec<PhraseInstance> {
+        let phrases = [phrase];
+        let mut reader = input.as_bytes();
+        Finder::new(&phrases, 64, 32, &mut reader).flat_map(|group| group.0).collect()
+    };
+    let expected = search(repeated.clone());
+    let actual = search(repeated.deduplicate_tokens());
+    assert_eq!(vec![2], expected.iter().map(|instance| instance.file_pos).collect::<Vec<_>>());
+    assert_eq!(vec![2, 15], actual.iter().map(|instance| instance.file_pos).collect::<Vec<_>>());
+    assert_eq!(expected[0], actual[0]);
+}
+
+#[test]
+fn test_phrase_push() {
+    let mut phrase = Phrase::default();
+    phrase.push(Text::from_str("famine"));
+    phrase.extend([Text::from_str("where")]);
+    assert_eq!(Phrase::from_strs(&["famine", "where"]), phrase);
+}
+
+#[test]
+fn test_phrase_settings() {
+    let phrase = Phrase::from_strs(&["sum", "my", "count"]);
+    assert!(!phrase.allow_short_tokens());
+    assert!(phrase.widths().is_empty());
+    let phrase = phrase.with_allow_short_tokens(true).with_widths(vec![1]);
+    assert!(phrase.allow_short_tokens());
+    assert_eq!(&[1], phrase.widths());
+}
 
 #[test]
 fn test_search() {
     let b: Vec<u8> = "This is the text we're testing".bytes().collect();
 
     let a = Text::from_str("text");
-    assert!(search(&a.0, &b).is_some());
+    assert!(search(&a.0, &b, &ALL_DIFFS).is_some());
     let a = Text::from_str("text!");
-    assert!(search(&a.0, &b).is_none());
+    assert!(search(&a.0, &b, &ALL_DIFFS).is_none());
     let a = Text::from_str("e're");
-    assert!(search(&a.0, &b).is_some());
+    assert!(search(&a.0, &b, &ALL_DIFFS).is_some());
     let a = Text::from_str("this");
-    assert!(search(&a.0, &b).is_none());
+    assert!(search(&a.0, &b, &ALL_DIFFS).is_none());
+}
+
+#[test]
+fn test_search_known_diff() {
+    let text = "the fox was quick, the other fox was not";
+    let rotated: Vec<u8> = text.bytes().map(|b| b + 2).collect();
+    let a = Text::from_str("fox");
+    let index = |found: Option<TokenInstance>| found.map(|found| found.index);
+
+    // Skipping to the first byte finds the same instance as trying every diff
+    assert_eq!(Some(4), index(search(&a.0, &rotated, &(1..=3))));
+    assert_eq!(Some(4), index(search(&a.0, &rotated, &ALL_DIFFS)));
+    assert_eq!(Some(4), index(search_with_diff(&a.0, &rotated, 2)));
+    assert_eq!(None, index(search_with_diff(&a.0, &rotated, 1)));
+    assert_eq!(None, index(search_with_diff(&a.0, &rotated, 300)));
+
+    // 2 byte text only matches at even offsets, even when the first byte occurs at odd ones
+    let wide: Vec<u8> = text.bytes().flat_map(|b| [b, 0]).collect();
+    let shifted: Vec<u8> = std::iter::once(b'f').chain(wide.iter().copied()).collect();
+    assert_eq!(Some(8), index(search_2bytes_with_diff(&a.0, &wide, 0, Endianness::Little)));
+    assert_eq!(Some(8), index(search_2bytes(&a.0, &wide, &(-1..=1), Endianness::Little)));
+    assert_eq!(None, index(search_2bytes_with_diff(&a.0, &shifted, 0, Endianness::Little)));
+    let big: Vec<u8> = text.bytes().flat_map(|b| [0, b]).collect();
+    assert_eq!(Some(8), index(search_2bytes_with_diff(&a.0, &big, 0, Endianness::Big)));
+    assert_eq!(Some(8), index(search_2bytes(&a.0, &big, &(-1..=1), Endianness::Big)));
+}
+
+#[test]
+fn test_start_indices() {
+    let text = b"the quick brown fox jumps over the lazy dog, quick fox";
+
+    // With the diff known, only positions holding the token's first byte are visited when skipping with memchr
+    let first = first_bytes('q' as u32, &(0..=0), 1, Endianness::Little);
+    assert_eq!(Some(vec![b'q']), first);
+    let visited: Vec<usize> = start_indices(text, 1, text.len(), first).collect();
+    match cfg!(feature = "simd") {
+        true => assert_eq!(vec![4, 45], visited),
+        false => assert_eq!((0..text.len()).collect::<Vec<usize>>(), visited)
+    }
+    let wide: Vec<u8> = text.iter().flat_map(|b| [0, *b]).collect();
+    let first = first_bytes('q' as u32, &(0..=0), 2, Endianness::Big);
+    assert_eq!(Some(vec![0]), first);
+    let first = first_bytes('q' as u32, &(256..=256), 2, Endianness::Big);
+    assert_eq!(Some(vec![1]), first);
+    let visited = start_indices(&wide, 2, text.len(), first).count();
+    assert_eq!(if cfg!(feature = "simd") { 0 } else { text.len() }, visited);
+
+    // With 2 bytes per character and the diff known, both bytes of the first character are skipped to, then checked together
+    assert_eq!(Some([0, b'q']), first_char_bytes('q' as u32, 0, Endianness::Big));
+    assert_eq!(None, first_char_bytes('q' as u32, 0x10000, Endianness::Big));
+    let visited: Vec<usize> = char_start_indices(&wide, text.len(), first_char_bytes('q' as u32, 0, Endianness::Big)).collect();
+    match cfg!(feature = "simd") {
+        true => assert_eq!(vec![4, 45], visited),
+        false => assert_eq!((0..text.len()).collect::<Vec<usize>>(), visited)
+    }
+    assert_eq!(0, char_start_indices(&wide, text.len(), None).count());
+
+    // Any diff leaves too many first bytes to skip to, so every position is visited
+    assert_eq!(None, first_bytes('q' as u32, &ALL_DIFFS, 1, Endianness::Little));
+
+    // Skipping finds the same instances as visiting every position, for every width and diff
+    let scalar = |a: &[u32], b: &[u8], diff: i32, bytes_per_character: usize, endianness| -> Option<usize> {
+        let chars = b.len() / bytes_per_character;
+        (0..(chars + 1).checked_sub(a.len())?)
+            .find(|idx| a.iter().enumerate().all(|(a_idx, char_a)| {
+                let pos = (idx + a_idx) * bytes_per_character;
+                let char_b = match bytes_per_character {
+                    1 => b[pos] as u32,
+                    _ => get_2bytes_with(b, pos, endianness)
+                };
+                char_b as i64 - diff as i64 == *char_a as i64
+            }))
+            .map(|idx| idx * bytes_per_character)
+    };
+    for diff in [-32, 0, 1, 13, 100] {
+        let rotated: Vec<u8> = text.iter().map(|b| (*b as i32 + diff) as u8).collect();
+        let little: Vec<u8> = text.iter().flat_map(|b| ((*b as i32 + diff) as u16).to_le_bytes()).collect();
+        let big: Vec<u8> = text.iter().flat_map(|b| ((*b as i32 + diff) as u16).to_be_bytes()).collect();
+        for token in ["quick", "fox", "dog,", "zebra", "the"] {
+            let a = Text::from_str(token);
+            for searched in [diff, diff + 1] {
+                let index = |found: Option<TokenInstance>| found.map(|found| found.index);
+                assert_eq!(scalar(&a.0, &rotated, searched, 1, Endianness::Little), index(search_with_diff(&a.0, &rotated, searched)));
+                assert_eq!(scalar(&a.0, &little, searched, 2, Endianness::Little), index(search_2bytes_with_diff(&a.0, &little, searched, Endianness::Little)));
+                assert_eq!(scalar(&a.0, &big, searched, 2, Endianness::Big), index(search_2bytes_with_diff(&a.0, &big, searched, Endianness::Big)));
+            }
+        }
+    }
 }
 
+#[test]
+fn test_get_2bytes_at() {
+    let bytes = [0x61, 0x00, 0x62, 0x01, 0x63];
+    assert_eq!(0x61, get_2bytes(&bytes, 0));
+    assert_eq!(0x0162, get_2bytes(&bytes, 1));
+    assert_eq!(get_2bytes(&bytes, 1), get_2bytes_at(&bytes, 2));
+
+    // Odd offsets read across the characters
+    assert_eq!(0x6200, get_2bytes_at(&bytes, 1));
+    assert_eq!(0x6301, get_2bytes_at(&bytes, 3));
+    assert_eq!(0x0062, get_2bytes_with(&bytes, 1, Endianness::Big));
+}
 
 #[test]
 fn test_edgecase() {
@@ -654,12 +2443,16 @@ fn test_edgecase() {
     let phrases = &[phrase];
     let mut finder = Finder::new(phrases, 64, 32, &mut input);
 
+    // "a" within "Making" overlaps it, so the match ends at the "a" after it
     let found = finder.next();
     let expected = Some(PhraseInstanceGroup(vec![PhraseInstance {
         phrase_index: 0,
         file_pos: 8,
+        end_pos: 16,
         codepoint_diff: 0,
-        bytes_per_character: 1
+        bytes_per_character: 1,
+        line: Some(1),
+        column: Some(9)
     }]));
     assert_eq!(expected, found);
 }
@@ -682,8 +2475,139 @@ fn test_edgecase_2() {
     let expected = Some(PhraseInstanceGroup(vec![PhraseInstance {
         phrase_index: 0,
         file_pos: 8,
+        end_pos: 23,
         codepoint_diff: 0,
-        bytes_per_character: 1
+        bytes_per_character: 1,
+        line: Some(1),
+        column: Some(9)
     }]));
     assert_eq!(expected, found);
-}
\ No newline at end of file
+}
+#[test]
+fn test_token_instance_to_text() {
+    let b: Vec<u8> = "This is the text we're testing".bytes().collect();
+    let a = Text::from_str("text");
+    let instance = search(&a.0, &b, &ALL_DIFFS).unwrap();
+    assert_eq!(a, instance.to_text(&b, a.0.len()));
+
+    let rotated: Vec<u8> = b.iter().map(|b| b + 13).collect();
+    let instance = search(&a.0, &rotated, &ALL_DIFFS).unwrap();
+    assert_eq!(a, instance.to_text(&rotated, a.0.len()));
+
+    let b_le: Vec<u8> = b.iter().flat_map(|b| [*b, 0]).collect();
+    let instance = search_2bytes(&a.0, &b_le, &ALL_DIFFS, Endianness::Little).unwrap();
+    assert_eq!(a, instance.to_text(&b_le, a.0.len()));
+}
+
+#[test]
+fn test_phrase_validate() {
+    let limits = PhraseLimits::default();
+    assert_eq!(Ok(()), Phrase::from_strs(&["famine", "where"]).validate(&limits));
+    assert_eq!(Err(PhraseError::NoTokens), Phrase::new(vec![]).validate(&limits));
+    assert_eq!(
+        Err(PhraseError::TokenTooShort { token: "my".to_owned(), min_token_len: 3 }),
+        Phrase::from_strs(&["sum", "my", "count"]).validate(&limits)
+    );
+    assert_eq!(Ok(()), Phrase::from_strs(&["sum", "my", "count"]).with_allow_short_tokens(true).validate(&limits));
+    let limits = PhraseLimits { min_token_len: 3, max_tokens: 2 };
+    assert_eq!(
+        Err(PhraseError::TooManyTokens { count: 3, max_tokens: 2 }),
+        Phrase::from_strs(&["within", "sunken", "deep"]).validate(&limits)
+    );
+    assert_eq!(
+        Err(PhraseError::UnsupportedWidth(4)),
+        Phrase::from_strs(&["famine", "where"]).with_widths(vec![1, 4]).validate(&limits)
+    );
+}
+
+#[test]
+fn test_match_phrase_anchors_on_longest() {
+    // "ab" first appears shifted by 1, which "cdefg" doesn't share. Anchored on "cdefg", the unshifted "ab" is found.
+    let mut input = "bc ab cdefg".as_bytes();
+    let phrases = &[Phrase::from_strs(&["ab", "cdefg"]).with_allow_short_tokens(true)];
+    let mut finder = Finder::new(phrases, 64, 32, &mut input);
+    let found = finder.next().unwrap().0;
+    assert_eq!(0, found[0].codepoint_diff);
+    assert_eq!(3, found[0].file_pos);
+    assert_eq!(11, found[0].end_pos);
+}
+
+#[test]
+fn test_short_tokens_binary_noise() {
+    // Pseudo-random bytes, so any match is a false positive
+    let mut seed: u32 = 12345;
+    let noise: Vec<u8> = (0..16384)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect();
+    let phrases = [Phrase::from_strs(&["ab"]), Phrase::from_strs(&["famine", "where"])];
+    let count = |phrases: &[Phrase]| {
+        let mut reader = noise.as_slice();
+        Finder::new(phrases, 128, 64, &mut reader).flat_map(|group| group.0).count()
+    };
+
+    // Without the guard, the short phrase matches all over the noise
+    let unguarded = count(&phrases);
+    assert!(unguarded > 20, "{}", unguarded);
+
+    // With it, the short phrase is refused and nothing matches
+    let limits = PhraseLimits::default();
+    let guarded: Vec<Phrase> = phrases.iter().filter(|phrase| phrase.validate(&limits).is_ok()).cloned().collect();
+    assert_eq!(1, guarded.len());
+    assert_eq!(0, count(&guarded));
+}
+
+#[test]
+fn test_finder_big_endian_ends_input() {
+    // Read one byte late, the last character's high byte is past the end of the input
+    use testsupport::*;
+    let phrases = [Phrase::from_strs(&["famine", "where"])];
+    let planted = plant_phrase(&phrases[0], 128, 0);
+    let spec = EncodingSpec { bytes_per_character: 2, big_endian: true, codepoint_diff: 0, junk_prefix: Vec::new(), control_chars: Vec::new() };
+    let input = encode(&planted, 0, &spec);
+    let mut reader = input.bytes.as_slice();
+    let actual: Vec<PhraseInstance> = Finder::new(&phrases, 128, 64, &mut reader).flat_map(|group| group.0).collect();
+    assert_eq!(input.expected, actual);
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_finder_matches_oracle(
+        phrase_index in 0..3usize,
+        bytes_per_character in 1..=2u32,
+        big_endian in proptest::bool::ANY,
+        codepoint_diff in -32..=64i32,
+        junk_prefix in proptest::collection::vec(proptest::num::u8::ANY, 0..16),
+        // Phrases within the first context_size bytes can be reported more than once,
+        // since the window doesn't slide while the context is still filling up
+        filler_before in 128..192usize,
+        filler_after in 0..80usize,
+        control_chars in proptest::collection::vec((0..200usize, proptest::sample::select(testsupport::CONTROL_CHARS.to_vec())), 0..8)
+    ) {
+        use std::io::BufReader;
+        use testsupport::*;
+
+        let phrases = [
+            Phrase::from_strs(&["famine", "where"]),
+            Phrase::from_strs(&["within", "sunken", "deep"]),
+            Phrase::from_strs(&["word"])
+        ];
+        let spec = EncodingSpec {
+            bytes_per_character,
+            big_endian,
+            codepoint_diff,
+            junk_prefix,
+            control_chars
+        };
+        let planted = plant_phrase(&phrases[phrase_index], filler_before, filler_after);
+        let input = encode(&planted, phrase_index, &spec);
+
+        let mut reader = BufReader::new(input.bytes.as_slice());
+        let finder = Finder::new(&phrases, 128, 64, &mut reader);
+        let actual: Vec<PhraseInstance> = finder.flat_map(|group| group.0).collect();
+        proptest::prop_assert_eq!(input.expected, actual);
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::Text;
+
+/// What [`patch_at`] wrote, and what was there before, so it can be undone by writing `old_bytes` back at `pos`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PatchReport {
+    pub pos: u64,
+    pub old_bytes: Vec<u8>,     // Bytes that were overwritten
+    pub new_bytes: Vec<u8>      // Bytes written, including the terminator and padding
+}
+
+/// Reasons a patch can fail. Nothing is written if it does.
+#[derive(Debug)]
+pub enum PatchError {
+    UnsupportedBytesPerCharacter(u32),
+    Unencodable { index: usize, codepoint: u32 },   // A character can't be stored under the diff at the width given
+    TooLong { len: usize, pad_to: usize },          // The encoded text and terminator take more bytes than the slot
+    PastEnd { end: u64, file_len: u64 },            // The patch would run past the end of the file
+    Overflow { pos: u64, len: usize },              // The patch would end past the largest position a file can have
+    Io(io::Error)
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnsupportedBytesPerCharacter(bpc) => write!(f, "Unsupported bytes per character {}. Must be 1 or 2", bpc),
+            Self::Unencodable { index, codepoint } => write!(f, "Character {} (U+{:04X}) can't be encoded", index, codepoint),
+            Self::TooLong { len, pad_to } => write!(f, "Patch takes {} bytes, which doesn't fit in {}", len, pad_to),
+            Self::PastEnd { end, file_len } => write!(f, "Patch would end at {}, past the end of the file at {}", end, file_len),
+            Self::Overflow { pos, len } => write!(f, "Patch of {} bytes at {} would end past the largest file position", len, pos),
+            Self::Io(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl From<io::Error> for PatchError {
+    fn from(err: io::Error) -> Self { Self::Io(err) }
+}
+
+/// Encodes text the way [`Text::from_slice`] decodes it: adding the diff to each codepoint, little-endian at `bytes_per_character` wide.
+/// Followed by the terminator if there is one, which is a raw character value like those [`extract_string_in`](crate::extract_string_in) stops at.
+pub fn encode_text(text: &Text, codepoint_diff: i32, bytes_per_character: u32, terminator: Option<u8>) -> Result<Vec<u8>, PatchError> {
+    let bpc = bytes_per_character as usize;
+    if bpc != 1 && bpc != 2 {
+        return Err(PatchError::UnsupportedBytesPerCharacter(bytes_per_character));
+    }
+    let max = (1u64 << (8 * bpc)) - 1;
+    let mut bytes = Vec::with_capacity((text.0.len() + 1) * bpc);
+    for (index, &codepoint) in text.0.iter().enumerate() {
+        let raw = codepoint as i64 + codepoint_diff as i64;
+        if raw < 0 || raw as u64 > max {
+            return Err(PatchError::Unencodable { index, codepoint });
+        }
+        bytes.extend_from_slice(&(raw as u32).to_le_bytes()[..bpc]);
+    }
+    if let Some(terminator) = terminator {
+        bytes.extend_from_slice(&(terminator as u32).to_le_bytes()[..bpc]);
+    }
+    Ok(bytes)
+}
+
+/// Writes `new_text` over the bytes at `pos`, encoded with [`encode_text`].
+/// With `pad_to`, the text must fit in that many bytes along with the terminator, and the rest of them are filled with
+/// the terminator, or zeros without one. The file isn't resized, so patches running past its end fail.
+pub fn patch_at<P: AsRef<Path>>(
+    path: P,
+    pos: u64,
+    new_text: &Text,
+    codepoint_diff: i32,
+    bytes_per_character: u32,
+    pad_to: Option<usize>,
+    terminator: Option<u8>
+) -> Result<PatchReport, PatchError> {
+    let mut file = File::options().read(true).write(true).open(path)?;
+    patch_in(&mut file, pos, new_text, codepoint_diff, bytes_per_character, pad_to, terminator)
+}
+
+/// Same as [`patch_at`], but patches any seekable input
+pub fn patch_in<F: Read + Write + Seek>(
+    file: &mut F,
+    pos: u64,
+    new_text: &Text,
+    codepoint_diff: i32,
+    bytes_per_character: u32,
+    pad_to: Option<usize>,
+    terminator: Option<u8>
+) -> Result<PatchReport, PatchError> {
+    let mut new_bytes = encode_text(new_text, codepoint_diff, bytes_per_character, terminator)?;
+    if let Some(pad_to) = pad_to {
+        if new_bytes.len() > pad_to {
+            return Err(PatchError::TooLong { len: new_bytes.len(), pad_to });
+        }
+        let padding = match terminator {
+            Some(terminator) => (terminator as u32).to_le_bytes()[..bytes_per_character as usize].to_vec(),
+            None => vec![0; bytes_per_character as usize]
+        };
+        let mut padding = padding.into_iter().cycle();
+        new_bytes.resize_with(pad_to, || padding.next().unwrap_or(0));
+    }
+
+    // Reads what's there first, so it can be put back
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let end = pos
+        .checked_add(new_bytes.len() as u64)
+        .ok_or(PatchError::Overflow { pos, len: new_bytes.len() })?;
+    if end > file_len {
+        return Err(PatchError::PastEnd { end, file_len });
+    }
+    let mut old_bytes = vec![0; new_bytes.len()];
+    file.seek(SeekFrom::Start(pos))?;
+    file.read_exact(&mut old_bytes)?;
+    file.seek(SeekFrom::Start(pos))?;
+    file.write_all(&new_bytes)?;
+    file.flush()?;
+    Ok(PatchReport { pos, old_bytes, new_bytes })
+}
+
+
+#[test]
+fn test_patch_in() {
+    use std::io::Cursor;
+    let mut rom = Cursor::new(b"\0hello\0\0\0\0world\0".to_vec());
+
+    // Padded with the terminator, and undone by writing the old bytes back
+    let report = patch_in(&mut rom, 1, &Text::from_str("bye"), 0, 1, Some(9), Some(0)).unwrap();
+    assert_eq!(b"hello\0\0\0\0".to_vec(), report.old_bytes);
+    assert_eq!(b"\0bye\0\0\0\0\0\0world\0".to_vec(), *rom.get_ref());
+    patch_in(&mut rom, 1, &Text::from_codepoints(report.old_bytes.iter().map(|byte| *byte as u32).collect()), 0, 1, None, None).unwrap();
+    assert_eq!(b"\0hello\0\0\0\0world\0".to_vec(), *rom.get_ref());
+
+    // Written under the diff, and only if it fits
+    let mut rom = Cursor::new(vec![0; 8]);
+    patch_in(&mut rom, 2, &Text::from_str("ab"), 1, 2, None, None).unwrap();
+    assert_eq!(vec![0, 0, b'b', 0, b'c', 0, 0, 0], *rom.get_ref());
+    assert_eq!(Text::from_str("ab"), Text::from_slice(&rom.get_ref()[2..6], 1, 2));
+    assert!(matches!(patch_in(&mut rom, 0, &Text::from_str("abc"), 0, 1, Some(3), Some(0)), Err(PatchError::TooLong { len: 4, pad_to: 3 })));
+    assert!(matches!(patch_in(&mut rom, 6, &Text::from_str("abc"), 0, 1, None, None), Err(PatchError::PastEnd { end: 9, file_len: 8 })));
+    assert!(matches!(patch_in(&mut rom, u64::MAX, &Text::from_str("abc"), 0, 1, None, None), Err(PatchError::Overflow { pos: u64::MAX, len: 3 })));
+    assert!(matches!(encode_text(&Text::from_str("a"), -98, 1, None), Err(PatchError::Unencodable { index: 0, codepoint: 97 })));
+    assert!(matches!(encode_text(&Text::from_codepoints(vec![0x1_0000]), 0, 2, None), Err(PatchError::Unencodable { .. })));
+    assert_eq!(vec![0, 0, 0, 0, 0, 0, 0, 0], {
+        let mut untouched = Cursor::new(vec![0; 8]);
+        let _ = patch_in(&mut untouched, 0, &Text::from_str("abcdefghi"), 0, 1, None, None);
+        untouched.into_inner()
+    });
+}
@@ -0,0 +1,152 @@
+// pyo3's generated wrappers convert PyErr into itself
+#![allow(clippy::useless_conversion)]
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+
+use crate::{search_scored, search_slice, Encoding, Endianness, Phrase, ReportEntry, SearchOptions, SliceSearchOptions, Text};
+
+/// Phrase to search for, made of whitespace-separated tokens
+#[pyclass(name = "Phrase")]
+#[derive(Clone)]
+pub struct PyPhrase {
+    phrase: Phrase
+}
+
+#[pymethods]
+impl PyPhrase {
+    #[new]
+    fn new(text: &str) -> PyResult<Self> {
+        let tokens: Vec<Text> = text.split_whitespace().map(Text::from_str).collect();
+        let phrase = Phrase::try_new(tokens).map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(Self { phrase })
+    }
+
+    /// See Phrase::id
+    fn id(&self) -> String { self.phrase.id() }
+
+    fn __str__(&self) -> String { self.phrase.to_string() }
+
+    fn __repr__(&self) -> String { format!("Phrase({:?})", self.phrase.to_string()) }
+}
+
+/// Searches bytes or files for a set of phrases. Results are lists of dicts shaped like the JSON report entries.
+/// The GIL is released while searching.
+#[pyclass(name = "Finder")]
+pub struct PyFinder {
+    phrases: Vec<Phrase>,
+    sizes: SearchOptions,           // Validated, and picked from the phrases if not specified
+    encoding: Option<Encoding>
+}
+
+#[pymethods]
+impl PyFinder {
+
+    /// Sizes are picked from the phrases if both are left out. Every width is tried if `bytes_per_character` is.
+    #[new]
+    #[pyo3(signature = (phrases, context_size=None, window_size=None, bytes_per_character=None, endianness="little"))]
+    fn new(
+        phrases: Vec<PyPhrase>,
+        context_size: Option<usize>,
+        window_size: Option<usize>,
+        bytes_per_character: Option<u32>,
+        endianness: &str
+    ) -> PyResult<Self> {
+        let defaults = SearchOptions::default();
+        let sizes = match (context_size, window_size) {
+            (None, None) => None,
+            (context_size, window_size) => Some(SearchOptions {
+                context_size: context_size.unwrap_or(defaults.context_size),
+                window_size: window_size.unwrap_or(defaults.window_size)
+            })
+        };
+        let endianness = match endianness {
+            "little" => Endianness::Little,
+            "big" => Endianness::Big,
+            _ => return Err(PyValueError::new_err(format!("Unknown endianness '{}'", endianness)))
+        };
+        let encoding = bytes_per_character.map(|bytes_per_character| Encoding { bytes_per_character, endianness });
+        let phrases: Vec<Phrase> = phrases.into_iter().map(|phrase| phrase.phrase).collect();
+        let options = SliceSearchOptions { sizes, encoding };
+
+        // Searching nothing validates the options and resolves the sizes
+        let sizes = search_slice(&[], &phrases, &options)
+            .map_err(|err| PyValueError::new_err(err.to_string()))?
+            .options;
+        Ok(Self { phrases, sizes, encoding })
+    }
+
+    fn search_bytes(&self, py: Python<'_>, bytes: &[u8]) -> PyResult<PyObject> {
+        let mut reader = bytes;
+        let entries = py.allow_threads(|| search_scored(&self.phrases, &self.sizes, self.encoding, &mut reader));
+        let entries = entries.map_err(|err| PyValueError::new_err(err.to_string()))?;
+        to_dicts(py, &entries)
+    }
+
+    fn search_file(&self, py: Python<'_>, path: PathBuf) -> PyResult<PyObject> {
+        let entries = py.allow_threads(|| {
+            let mut reader = BufReader::new(File::open(&path)?);
+            search_scored(&self.phrases, &self.sizes, self.encoding, &mut reader)
+        });
+        let entries = entries.map_err(|err| PyIOError::new_err(err.to_string()))?;
+        to_dicts(py, &entries)
+    }
+}
+
+// Entries as a list of dicts, the same shape they have in JSON
+fn to_dicts(py: Python<'_>, entries: &[ReportEntry]) -> PyResult<PyObject> {
+    let dicts = pythonize::pythonize(py, entries).map_err(|err| PyValueError::new_err(err.to_string()))?;
+    Ok(dicts.unbind())
+}
+
+#[pymodule]
+fn text_searcher_rust(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyPhrase>()?;
+    module.add_class::<PyFinder>()?;
+    Ok(())
+}
+
+
+#[test]
+fn test_python_finder() {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let module = PyModule::new_bound(py, "text_searcher_rust").unwrap();
+        text_searcher_rust(&module).unwrap();
+        let locals = pyo3::types::PyDict::new_bound(py);
+        locals.set_item("ts", module).unwrap();
+        locals.set_item("fixture", pyo3::types::PyBytes::new_bound(py, include_bytes!("test_text_1.txt"))).unwrap();
+        locals.set_item("path", concat!(env!("CARGO_MANIFEST_DIR"), "/src/searcher/test_text_1.txt")).unwrap();
+
+        // Same results from bytes and files, as plain dicts
+        py.run_bound(r#"
+finder = ts.Finder([ts.Phrase("famine where")], context_size=64, window_size=32)
+entries = finder.search_bytes(fixture)
+assert len(entries) == 1, entries
+assert entries[0]["instance"]["file_pos"] == 288
+assert entries[0]["instance"]["line"] == 7
+assert entries[0]["phrase"]["text"] == "famine where"
+assert finder.search_file(path) == entries
+assert len(ts.Finder([ts.Phrase("famine where")]).search_bytes(fixture)) == 1
+"#, None, Some(&locals)).unwrap();
+
+        // Invalid options and missing files raise
+        py.run_bound(r#"
+for kwargs in [dict(context_size=30), dict(window_size=0), dict(bytes_per_character=3), dict(endianness="middle")]:
+    try:
+        ts.Finder([ts.Phrase("famine where")], **kwargs)
+        raise AssertionError(kwargs)
+    except ValueError:
+        pass
+try:
+    ts.Finder([ts.Phrase("famine where")]).search_file("test_files/missing.txt")
+    raise AssertionError("missing file")
+except IOError:
+    pass
+"#, None, Some(&locals)).unwrap();
+    });
+}
@@ -0,0 +1,111 @@
+use std::fmt::{self, Display};
+use regex_syntax::ast::{self, Ast, RepetitionKind, RepetitionRange};
+
+use crate::{Phrase, Text};
+
+/// Reasons a phrase can't be made from a regex with [`Phrase::from_regex`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RegexError {
+    Syntax(Box<ast::Error>),
+    NoLiterals
+}
+
+impl Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Syntax(err) => write!(f, "Invalid regex. {}", err),
+            Self::NoLiterals => write!(f, "Regex has no literal text to search for")
+        }
+    }
+}
+
+impl std::error::Error for RegexError {}
+
+impl Phrase {
+    /// Best-effort phrase of the literal words in a regex, such as `famine` and `where` from `famine.+where`.
+    /// Anything that isn't a literal separates tokens, as does whitespace within one.
+    /// Literals that may not appear in a match, like those in alternations or optional repetitions, are left out.
+    /// The phrase finds the words near each other, not only where the regex matches.
+    pub fn from_regex(pattern: &str) -> Result<Self, RegexError> {
+        let ast = ast::parse::Parser::new().parse(pattern).map_err(|err| RegexError::Syntax(Box::new(err)))?;
+        let mut literals = Vec::new();
+        let mut run = String::new();
+        collect_literals(&ast, &mut run, &mut literals);
+        literals.push(run);
+        let tokens: Vec<Text> = literals
+            .iter()
+            .flat_map(|literal| literal.split_whitespace())
+            .map(Text::from_str)
+            .collect();
+        match tokens.is_empty() {
+            true => Err(RegexError::NoLiterals),
+            false => Ok(Self::new(tokens).deduplicate_tokens())
+        }
+    }
+}
+
+// Appends runs of literal characters that every match contains to `literals`, continuing `run` while they're adjacent
+fn collect_literals(ast: &Ast, run: &mut String, literals: &mut Vec<String>) {
+    match ast {
+        Ast::Literal(literal) => run.push(literal.c),
+        Ast::Concat(concat) => {
+            for ast in &concat.asts {
+                collect_literals(ast, run, literals);
+            }
+        },
+        Ast::Group(group) => {
+            end_run(run, literals);
+            collect_literals(&group.ast, run, literals);
+            end_run(run, literals);
+        },
+        Ast::Repetition(repetition) => {
+            end_run(run, literals);
+            let required = match repetition.op.kind {
+                RepetitionKind::ZeroOrOne | RepetitionKind::ZeroOrMore => false,
+                RepetitionKind::OneOrMore => true,
+                RepetitionKind::Range(RepetitionRange::Exactly(min))
+                | RepetitionKind::Range(RepetitionRange::AtLeast(min))
+                | RepetitionKind::Range(RepetitionRange::Bounded(min, _)) => min > 0
+            };
+            if required {
+                collect_literals(&repetition.ast, run, literals);
+                end_run(run, literals);
+            }
+        },
+        Ast::Empty(_) | Ast::Flags(_) => {},
+        Ast::Dot(_) | Ast::Assertion(_) | Ast::Class(_) | Ast::Alternation(_) => end_run(run, literals)
+    }
+}
+
+fn end_run(run: &mut String, literals: &mut Vec<String>) {
+    if !run.is_empty() {
+        literals.push(std::mem::take(run));
+    }
+}
+
+
+#[test]
+fn test_phrase_from_regex() {
+    let tokens = |pattern: &str| -> Vec<String> {
+        Phrase::from_regex(pattern).unwrap().tokens.iter().map(|token| token.to_string()).collect()
+    };
+    assert_eq!(vec!["famine", "where"], tokens("famine.+where"));
+    assert_eq!(vec!["famine", "where"], tokens(r"famine\s+where"));
+    assert_eq!(vec!["famine", "where"], tokens("famine where"));
+    assert_eq!(vec!["within", "deep"], tokens(r"\bwithin\b.*(?:sunken)?\W(deep)+"));
+    assert_eq!(vec!["sum", "count"], tokens("(?i)sum (my|your) (count){1}"));
+    assert_eq!(vec!["a.b"], tokens(r"a\.b"));
+
+    // Phrases made from regexes search like any other
+    let mut input: &[u8] = include_bytes!("test_text_1.txt");
+    let phrases = [Phrase::from_regex(r"famine\s+where").unwrap()];
+    let positions: Vec<usize> = crate::Finder::new(&phrases, 40, 20, &mut input)
+        .flat_map(|group| group.0)
+        .map(|instance| instance.file_pos)
+        .collect();
+    assert_eq!(vec![288], positions);
+
+    assert_eq!(Err(RegexError::NoLiterals), Phrase::from_regex(r"\w+\s*.?"));
+    assert_eq!(Err(RegexError::NoLiterals), Phrase::from_regex("(famine|where)"));
+    assert!(matches!(Phrase::from_regex("famine(where"), Err(RegexError::Syntax(_))));
+}
@@ -0,0 +1,1066 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::Read;
+use std::ops::ControlFlow;
+#[cfg(not(target_arch = "wasm32"))]
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+
+use crate::{Encoding, Endianness, Finder, FinderBuilder, FinderConfigError, Phrase, PhraseInstance, SearchOptions, Text};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{Throttle, ThrottledReader, MAX_BYTES_PER_CHARACTER};
+
+/// Number of characters inspected on either side of a match when scoring it
+pub const ADJACENT_CHARS: usize = 8;
+
+/// Smallest chunk [`auto_intra_file_parallelism`] splits a file into
+#[cfg(not(target_arch = "wasm32"))]
+pub const MIN_CHUNK_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Most entries each chunk of [`search_file_chunked`] holds back while the chunks before it are handed over
+#[cfg(not(target_arch = "wasm32"))]
+pub const CHUNK_BACKLOG: usize = 256;
+
+
+/// Results of searching a set of files for a set of phrases.
+/// `phrase_index` in every instance refers to `phrases`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SearchReport {
+    pub phrases: Vec<Phrase>,
+    pub options: SearchOptions,     // Sizes the files were searched with
+    #[serde(default)]
+    pub generation: u64,            // Generation of the service state that was searched, if any
+    pub files: Vec<FileSearchResult>,
+    #[serde(default)]
+    pub max_bytes: Option<usize>,   // Most bytes of memory the entries can take up, roughly. See ReportEntry::approx_bytes.
+    #[serde(default)]
+    pub approx_bytes: usize,        // Bytes of memory the entries take up, roughly
+    #[serde(default)]
+    pub truncated: bool,            // Entries were dropped to stay within max_bytes
+    #[serde(default)]
+    pub dropped: Vec<DroppedEntries>, // How many entries were dropped of each file and phrase, in the order they started being dropped
+    #[serde(default)]
+    pub cancelled: bool,            // The scan was cancelled, so the files it hadn't finished searching failed and were left out
+    #[serde(skip)]
+    #[schemars(skip)]
+    dropped_index: HashMap<(PathBuf, String), usize> // Index in dropped of each file and phrase id, so counting a drop doesn't scan them all
+}
+
+/// Entries of a phrase left out of a file's results, because the report reached its [`SearchReport::max_bytes`]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DroppedEntries {
+    pub path: PathBuf,
+    pub phrase: PhraseRef,
+    pub count: usize
+}
+
+impl SearchReport {
+
+    /// Empty report of a search for `phrases`, whose entries can take up at most `max_bytes` if given
+    pub fn new(phrases: Vec<Phrase>, options: SearchOptions, generation: u64, max_bytes: Option<usize>) -> Self {
+        Self {
+            phrases,
+            options,
+            generation,
+            files: Vec::new(),
+            max_bytes,
+            approx_bytes: 0,
+            truncated: false,
+            dropped: Vec::new(),
+            cancelled: false,
+            dropped_index: HashMap::new()
+        }
+    }
+
+    // Counts another entry of the phrase dropped from the file, if its entries are being dropped already.
+    // Returns whether they were. The index is rebuilt for reports that were deserialized without it.
+    pub(crate) fn count_dropped(&mut self, path: &Path, phrase: &PhraseRef) -> bool {
+        if self.dropped_index.len() != self.dropped.len() {
+            self.dropped_index = self.dropped
+                .iter()
+                .enumerate()
+                .map(|(idx, dropped)| ((dropped.path.clone(), dropped.phrase.id.clone()), idx))
+                .collect();
+        }
+        match self.dropped_index.get(&(path.to_path_buf(), phrase.id.clone())) {
+            Some(idx) => {
+                self.dropped[*idx].count += 1;
+                true
+            },
+            None => false
+        }
+    }
+
+    // Starts dropping entries of the phrase from the file, counting the first
+    pub(crate) fn start_dropping(&mut self, path: &Path, phrase: &PhraseRef) {
+        self.truncated = true;
+        self.dropped_index.insert((path.to_path_buf(), phrase.id.clone()), self.dropped.len());
+        self.dropped.push(DroppedEntries { path: path.to_path_buf(), phrase: phrase.clone(), count: 1 });
+    }
+
+    /// Drops entries whose context is less printable than `min`. See [`ReportEntry::context_printability`].
+    /// Files are kept even if none of their entries are, and their interpretation counts still cover every entry found.
+    pub fn min_printability(mut self, min: f32) -> Self {
+        for file in &mut self.files {
+            file.entries.retain(|entry| entry.context_printability >= min);
+        }
+        self
+    }
+
+    /// Sorts entries in each file from best to worst score, then sorts files by their best entry.
+    /// Ties are broken by path, then by position in the file, so the ordering is deterministic.
+    pub fn ranked(mut self) -> Self {
+        for file in &mut self.files {
+            file.entries.sort_by_key(|entry| (Reverse(entry.score), entry.instance.clone()));
+        }
+        self.files.sort_by(|a, b| {
+            let a_key = Reverse(a.best_score());
+            let b_key = Reverse(b.best_score());
+            a_key.cmp(&b_key).then_with(|| a.path.cmp(&b.path))
+        });
+        self
+    }
+}
+
+/// Results of searching a single file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FileSearchResult {
+    pub path: PathBuf,
+    pub entries: Vec<ReportEntry>,
+    #[serde(default)]
+    pub changed_during_scan: bool,  // The file grew or shrank while it was read, so it may be worth searching again
+    #[serde(default)]
+    pub interpretations: Vec<InterpretationCount>,      // How many entries were found with each interpretation, most first
+    #[serde(default)]
+    pub dominant_interpretation: Option<Interpretation> // Interpretation most entries were found with. A strong hint at the file's encoding.
+}
+
+impl FileSearchResult {
+
+    /// Searches the file at `path` for `phrases`, only matching characters laid out as `encoding` if specified.
+    /// Only the bytes within the size the file had when opened are searched, so appends made during the scan are left out.
+    /// If the file's size changes before the scan finishes, it's marked as `changed_during_scan`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn search<P: AsRef<Path>>(
+        path: P,
+        phrases: &[Phrase],
+        options: &SearchOptions,
+        encoding: Option<Encoding>
+    ) -> Result<Self, std::io::Error> {
+        Self::search_throttled(path, phrases, options, encoding, None)
+    }
+
+    /// Same as [`Self::search`], reading through `throttle` if there is one
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn search_throttled<P: AsRef<Path>>(
+        path: P,
+        phrases: &[Phrase],
+        options: &SearchOptions,
+        encoding: Option<Encoding>,
+        throttle: Option<&Throttle>
+    ) -> Result<Self, std::io::Error> {
+        let path = path.as_ref();
+        let mut entries = Vec::new();
+        let changed_during_scan = search_file_with(path, phrases, options, encoding, throttle, |entry| {
+            entries.push(entry);
+            ControlFlow::Continue(())
+        })?;
+        let interpretations = tally_interpretations(entries.iter().map(|entry| &entry.instance), encoding);
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+            changed_during_scan,
+            dominant_interpretation: interpretations.first().map(|count| count.interpretation),
+            interpretations
+        })
+    }
+
+    /// Searches any reader for `phrases`, reporting its results under `path`, which doesn't need to exist.
+    /// Fails with InvalidInput if the options or encoding are invalid.
+    pub fn search_reader<P: Into<PathBuf>, R: Read>(
+        path: P,
+        phrases: &[Phrase],
+        options: &SearchOptions,
+        encoding: Option<Encoding>,
+        reader: &mut R
+    ) -> Result<Self, std::io::Error> {
+        let entries = search_scored(phrases, options, encoding, reader)?;
+        let interpretations = tally_interpretations(entries.iter().map(|entry| &entry.instance), encoding);
+        Ok(Self {
+            path: path.into(),
+            entries,
+            changed_during_scan: false,
+            dominant_interpretation: interpretations.first().map(|count| count.interpretation),
+            interpretations
+        })
+    }
+
+    fn best_score(&self) -> Option<i64> {
+        self.entries.iter().map(|entry| entry.score).max()
+    }
+}
+
+/// Searches the file at `path` like [`FileSearchResult::search`], handing each entry to `on_entry` as it's found.
+/// Stops early if `on_entry` breaks. Returns whether the file changed during the scan.
+/// A file that's stopped early is only marked as changed if its size differs from when it was opened.
+/// The file is read through `throttle` if there is one. Fails with InvalidInput if the options or encoding are invalid,
+/// and with the read's error if reading fails partway, such as when the throttle is cancelled.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn search_file_with<P: AsRef<Path>>(
+    path: P,
+    phrases: &[Phrase],
+    options: &SearchOptions,
+    encoding: Option<Encoding>,
+    throttle: Option<&Throttle>,
+    on_entry: impl FnMut(ReportEntry) -> ControlFlow<()>
+) -> Result<bool, std::io::Error> {
+    let path = path.as_ref();
+    let (file, size) = open_bounded(path)?;
+    let reader = std::io::BufReader::new(ThrottledReader::new(file, throttle));
+    let mut reader = CountingReader { inner: reader, count: 0 };
+    let flow = search_reader_with(phrases, options, encoding, &mut reader, on_entry)?;
+    let truncated = flow.is_continue() && reader.count < size;
+    let resized = std::fs::metadata(path).map_or(true, |meta| meta.len() != size);
+    Ok(truncated || resized)
+}
+
+/// Opens the file at `path` to read only the bytes it has when opened, so appends made while it's read are left out.
+/// Returns the reader along with that size. Files that report a size of 0, like those in /proc, are read to the end.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn open_bounded<P: AsRef<Path>>(path: P) -> Result<(std::io::Take<std::fs::File>, u64), std::io::Error> {
+    let file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let limit = match size {
+        0 => u64::MAX,
+        size => size
+    };
+    Ok((file.take(limit), size))
+}
+
+/// Searches the file at `path` like [`search_file_with`], but split into `chunks` parts searched in parallel, each on its own thread.
+/// Each chunk is read along with `context_size` bytes on either side, so instances near its edges are found as they are in one pass,
+/// and is only credited with the instances that start within it. Positions are absolute. Instances past the first chunk have no line or column.
+/// Entries are handed to `on_entry` in file order as they're found, with each chunk holding back at most [`CHUNK_BACKLOG`] entries
+/// while the ones before it are handed over. If `on_entry` breaks, every chunk stops. Returns whether the file's size changed during the scan.
+/// Every chunk is read through `throttle` if there is one, so together they stay within its cap.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn search_file_chunked<P: AsRef<Path>>(
+    path: P,
+    phrases: &[Phrase],
+    options: &SearchOptions,
+    encoding: Option<Encoding>,
+    chunks: usize,
+    throttle: Option<&Throttle>,
+    mut on_entry: impl FnMut(ReportEntry) -> ControlFlow<()>
+) -> Result<bool, std::io::Error> {
+    let path = path.as_ref();
+    let size = std::fs::metadata(path)?.len();
+    let width = MAX_BYTES_PER_CHARACTER as u64;
+    let chunk_len = size.div_ceil(chunks.max(1) as u64).next_multiple_of(width).max(width);
+    let ranges: Vec<Range<u64>> = (0..size)
+        .step_by(chunk_len as usize)
+        .map(|start| start..(start + chunk_len).min(size))
+        .collect();
+    let searched = std::thread::scope(|scope| {
+        let chunks: Vec<_> = ranges
+            .into_iter()
+            .map(|range| {
+                let (sender, receiver) = std::sync::mpsc::sync_channel(CHUNK_BACKLOG);
+                let handle = scope.spawn(move || search_chunk(path, phrases, options, encoding, range, size, throttle, |entry| {
+                    match sender.send(entry) {
+                        Ok(_) => ControlFlow::Continue(()),
+                        Err(_) => ControlFlow::Break(())
+                    }
+                }));
+                (receiver, handle)
+            })
+            .collect();
+
+        // Chunks are drained in order. Once one stops the scan, dropping the receivers of the rest has them stop too.
+        let mut searched = Ok(());
+        for (receiver, handle) in chunks {
+            if searched.is_ok() {
+                for entry in receiver.iter() {
+                    if on_entry(entry).is_break() {
+                        searched = Err(None);
+                        break;
+                    }
+                }
+            }
+            drop(receiver);
+            let result = handle.join().expect("Chunk search panicked");
+            if let (Ok(()), Err(err)) = (&searched, result) {
+                searched = Err(Some(err));
+            }
+        }
+        searched
+    });
+    if let Err(Some(err)) = searched {
+        return Err(err);
+    }
+    Ok(std::fs::metadata(path).map_or(true, |meta| meta.len() != size))
+}
+
+/// Chunks to split a file of `file_size` bytes into with [`search_file_chunked`], when left to pick from its size:
+/// one per [`MIN_CHUNK_BYTES`], up to the number of threads the machine can run at once
+#[cfg(not(target_arch = "wasm32"))]
+pub fn auto_intra_file_parallelism(file_size: u64) -> usize {
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    ((file_size / MIN_CHUNK_BYTES) as usize).clamp(1, threads)
+}
+
+// Searches the bytes of a chunk of the file, along with the context on either side, only handing over instances that start in the chunk
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(clippy::too_many_arguments)]
+fn search_chunk(
+    path: &Path,
+    phrases: &[Phrase],
+    options: &SearchOptions,
+    encoding: Option<Encoding>,
+    chunk: Range<u64>,
+    size: u64,
+    throttle: Option<&Throttle>,
+    mut on_entry: impl FnMut(ReportEntry) -> ControlFlow<()>
+) -> Result<(), std::io::Error> {
+    use std::io::{Seek, SeekFrom};
+    let overlap = options.context_size as u64;
+    let start = chunk.start.saturating_sub(overlap);
+    let start = start - start % MAX_BYTES_PER_CHARACTER as u64;
+    let end = (chunk.end + overlap).min(size);
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = std::io::BufReader::new(ThrottledReader::new(file.take(end - start), throttle));
+    let _ = search_reader_with(phrases, options, encoding, &mut reader, |entry| {
+        let entry = ReportEntry {
+            instance: PhraseInstance {
+                file_pos: entry.instance.file_pos + start as usize,
+                end_pos: entry.instance.end_pos + start as usize,
+                line: entry.instance.line.filter(|_| start == 0),
+                column: entry.instance.column.filter(|_| start == 0),
+                ..entry.instance
+            },
+            ..entry
+        };
+        match chunk.contains(&(entry.instance.file_pos as u64)) {
+            true => on_entry(entry),
+            false => ControlFlow::Continue(())
+        }
+    })?;
+    Ok(())
+}
+
+// Counts the bytes read through it
+#[cfg(not(target_arch = "wasm32"))]
+struct CountingReader<R> {
+    inner: R,
+    count: u64
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+/// How the bytes of an instance were read to match
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct Interpretation {
+    pub bytes_per_character: u32,
+    pub endianness: Option<Endianness>,     // None for 1 byte per character
+    pub codepoint_diff: i32
+}
+
+impl std::fmt::Display for Interpretation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.endianness {
+            None => write!(f, "{}-byte", self.bytes_per_character)?,
+            Some(Endianness::Little) => write!(f, "{}-byte little-endian", self.bytes_per_character)?,
+            Some(Endianness::Big) => write!(f, "{}-byte big-endian", self.bytes_per_character)?
+        }
+        write!(f, " with a diff of {}", self.codepoint_diff)
+    }
+}
+
+/// How many instances were found with an interpretation
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct InterpretationCount {
+    #[serde(flatten)]
+    pub interpretation: Interpretation,
+    pub count: usize
+}
+
+/// Counts instances by the interpretation they were found with, most common first, with ties going to the diff closest to 0.
+/// Multi-byte instances are taken as little-endian unless `encoding` says otherwise, as that's what finders try.
+pub fn tally_interpretations<'a>(
+    instances: impl IntoIterator<Item=&'a PhraseInstance>,
+    encoding: Option<Encoding>
+) -> Vec<InterpretationCount> {
+    let endianness = encoding.map(|encoding| encoding.endianness).unwrap_or_default();
+    let mut counts: std::collections::HashMap<Interpretation, usize> = std::collections::HashMap::new();
+    for instance in instances {
+        let interpretation = Interpretation {
+            bytes_per_character: instance.bytes_per_character,
+            endianness: if instance.bytes_per_character > 1 { Some(endianness) } else { None },
+            codepoint_diff: instance.codepoint_diff
+        };
+        *counts.entry(interpretation).or_default() += 1;
+    }
+    let mut counts: Vec<InterpretationCount> = counts
+        .into_iter()
+        .map(|(interpretation, count)| InterpretationCount { interpretation, count })
+        .collect();
+    counts.sort_by_key(|count| {
+        let Interpretation { bytes_per_character, endianness, codepoint_diff } = count.interpretation;
+        (Reverse(count.count), codepoint_diff.unsigned_abs(), codepoint_diff, bytes_per_character, endianness == Some(Endianness::Big))
+    });
+    counts
+}
+
+/// A phrase instance along with the phrase it matched and its score.
+/// Serialized along with `len_bytes`, computed from the instance. See [`PhraseInstance::len_bytes`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ReportEntryRepr", into = "ReportEntryRepr")]
+pub struct ReportEntry {
+    pub instance: PhraseInstance,
+    pub phrase: PhraseRef,
+    pub score: i64,
+    pub context_printability: f32   // Fraction of the context the instance was found in that's printable. See context_printability.
+}
+
+// Serialized form of a ReportEntry. Entries written before context_printability was recorded are taken to be printable,
+// so filtering on it doesn't drop them.
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct ReportEntryRepr {
+    instance: PhraseInstance,
+    phrase: PhraseRef,
+    score: i64,
+    #[serde(default)]
+    len_bytes: usize,
+    #[serde(default = "fully_printable")]
+    context_printability: f32
+}
+
+fn fully_printable() -> f32 { 1.0 }
+
+impl From<ReportEntryRepr> for ReportEntry {
+    fn from(repr: ReportEntryRepr) -> Self {
+        let ReportEntryRepr { instance, phrase, score, len_bytes: _, context_printability } = repr;
+        Self { instance, phrase, score, context_printability }
+    }
+}
+
+impl From<ReportEntry> for ReportEntryRepr {
+    fn from(entry: ReportEntry) -> Self {
+        Self {
+            len_bytes: entry.len_bytes(),
+            instance: entry.instance,
+            phrase: entry.phrase,
+            score: entry.score,
+            context_printability: entry.context_printability
+        }
+    }
+}
+
+impl JsonSchema for ReportEntry {
+    fn schema_name() -> String { "ReportEntry".to_owned() }
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        ReportEntryRepr::json_schema(gen)
+    }
+}
+
+impl ReportEntry {
+
+    /// Bytes the instance spans in the file. See [`PhraseInstance::len_bytes`].
+    pub fn len_bytes(&self) -> usize {
+        self.instance.len_bytes()
+    }
+
+    /// Bytes of memory the entry takes up, roughly: its own size and the strings naming its phrase
+    pub fn approx_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + self.phrase.id.len() + self.phrase.text.len()
+    }
+}
+
+/// Names the phrase an instance matched, so clients don't need to look up `phrase_index`
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct PhraseRef {
+    pub id: String,     // See Phrase::id
+    pub text: String    // Tokens separated by spaces
+}
+
+impl PhraseRef {
+    pub fn new(phrase: &Phrase) -> Self {
+        Self {
+            id: phrase.id(),
+            text: phrase.to_string()
+        }
+    }
+}
+
+/// Searches a reader for `phrases`, scoring every instance found.
+/// Fails with InvalidInput if the options or encoding are invalid.
+pub fn search_scored<R: Read>(
+    phrases: &[Phrase],
+    options: &SearchOptions,
+    encoding: Option<Encoding>,
+    reader: &mut R
+) -> Result<Vec<ReportEntry>, std::io::Error> {
+    let mut entries = Vec::new();
+    let _ = search_scored_with(phrases, options, encoding, reader, |entry| {
+        entries.push(entry);
+        ControlFlow::Continue(())
+    }).map_err(invalid_options)?;
+    Ok(entries)
+}
+
+/// Same as [`search_scored`], but hands each entry to `on_entry` as it's found instead of collecting them.
+/// Stops reading as soon as `on_entry` breaks, returning the break. Fails without reading if the options or encoding are invalid.
+/// A read that fails ends the input like EOF does. See [`search_reader_with`] to have it fail instead.
+pub fn search_scored_with<R: Read>(
+    phrases: &[Phrase],
+    options: &SearchOptions,
+    encoding: Option<Encoding>,
+    reader: &mut R,
+    on_entry: impl FnMut(ReportEntry) -> ControlFlow<()>
+) -> Result<ControlFlow<()>, FinderConfigError> {
+    let mut finder = build_scoring_finder(phrases, options, encoding, reader)?;
+    Ok(score_instances(&mut finder, phrases, encoding, on_entry))
+}
+
+/// Same as [`search_scored_with`], but fails with the error a read failed with, once the entries found before it are handed over.
+/// Fails with InvalidInput if the options or encoding are invalid.
+pub fn search_reader_with<R: Read>(
+    phrases: &[Phrase],
+    options: &SearchOptions,
+    encoding: Option<Encoding>,
+    reader: &mut R,
+    on_entry: impl FnMut(ReportEntry) -> ControlFlow<()>
+) -> Result<ControlFlow<()>, std::io::Error> {
+    let mut finder = build_scoring_finder(phrases, options, encoding, reader).map_err(invalid_options)?;
+    let flow = score_instances(&mut finder, phrases, encoding, on_entry);
+    match finder.read_error() {
+        Some(err) => Err(std::io::Error::new(err.kind(), err.to_string())),
+        None => Ok(flow)
+    }
+}
+
+fn build_scoring_finder<'a, R: Read>(
+    phrases: &[Phrase],
+    options: &SearchOptions,
+    encoding: Option<Encoding>,
+    reader: &'a mut R
+) -> Result<Finder<'a, R>, FinderConfigError> {
+    FinderBuilder::new()
+        .context_size(options.context_size)
+        .window_size(options.window_size)
+        .encoding(encoding)
+        .build(phrases, reader)
+}
+
+// Scores the instances the finder finds, handing each to on_entry until it breaks
+fn score_instances<R: Read>(
+    finder: &mut Finder<R>,
+    phrases: &[Phrase],
+    encoding: Option<Encoding>,
+    mut on_entry: impl FnMut(ReportEntry) -> ControlFlow<()>
+) -> ControlFlow<()> {
+    let big_endian = matches!(encoding, Some(Encoding { bytes_per_character: 2, endianness: Endianness::Big }));
+    while let Some(group) = finder.next() {
+        let context_start = finder.get_context_range().start;
+        for instance in group.0 {
+            let phrase = &phrases[instance.phrase_index];
+            let context = finder.get_context_bytes();
+            let input_len = finder.get_context_input_bytes().len();
+
+            // Scoring reads characters as little-endian, so pairs are swapped in line with the instance
+            let (quality, context_printability) = if big_endian {
+                let offset = (instance.file_pos - context_start) % 2;
+                let mut swapped = context[offset..].to_vec();
+                swapped.chunks_exact_mut(2).for_each(|pair| pair.swap(0, 1));
+                (
+                    MatchQuality::measure(&instance, phrase, &swapped, context_start + offset),
+                    context_printability(&instance, &swapped[..input_len.saturating_sub(offset)], context_start + offset)
+                )
+            }
+            else {
+                (
+                    MatchQuality::measure(&instance, phrase, context, context_start),
+                    context_printability(&instance, &context[..input_len], context_start)
+                )
+            };
+            let entry = ReportEntry { instance, phrase: PhraseRef::new(phrase), score: quality.score(), context_printability };
+            if on_entry(entry).is_break() {
+                return ControlFlow::Break(());
+            }
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+// Options or an encoding a finder can't be built with, as an error reading a file with them
+fn invalid_options(err: FinderConfigError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, err)
+}
+
+
+/// Characteristics of a phrase instance that determine how plausible it is.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MatchQuality {
+    pub matched_chars: usize,       // Total characters across all tokens in the phrase
+    pub token_count: usize,         // Number of tokens in the phrase
+    pub codepoint_diff: i32,        // Diff the instance was found with
+    pub adjacent_printable: usize,  // Characters next to the match that are printable under the diff
+    pub adjacent_total: usize       // Characters next to the match that were inspected
+}
+
+impl MatchQuality {
+
+    /// Measures an instance found in `context`, where `context_start` is the file position of `context[0]`.
+    /// Inspects up to [`ADJACENT_CHARS`] characters before the match, and after the token the match starts with.
+    pub fn measure(instance: &PhraseInstance, phrase: &Phrase, context: &[u8], context_start: usize) -> Self {
+        let bpc = instance.bytes_per_character as usize;
+        let diff = instance.codepoint_diff;
+        let start = instance.file_pos - context_start;
+
+        // Finds the longest token that the match starts with
+        let first_token_len = phrase.tokens
+            .iter()
+            .filter(|token| {
+                let end = start + token.0.len() * bpc;
+                end <= context.len() && Text::from_slice(&context[start..end], diff, bpc as u32) == **token
+            })
+            .map(|token| token.0.len())
+            .max()
+            .unwrap_or(0);
+
+        // Counts printable characters on either side
+        let mut adjacent_printable = 0;
+        let mut adjacent_total = 0;
+        let before = (0..ADJACENT_CHARS)
+            .map_while(|i| start.checked_sub((i + 1) * bpc));
+        let after_start = start + first_token_len * bpc;
+        let after = (0..ADJACENT_CHARS)
+            .map(|i| after_start + i * bpc)
+            .take_while(|idx| idx + bpc <= context.len());
+        for idx in before.chain(after) {
+            adjacent_total += 1;
+            if is_printable(&context[idx..idx + bpc], diff) {
+                adjacent_printable += 1;
+            }
+        }
+
+        Self {
+            matched_chars: phrase.tokens.iter().map(|token| token.0.len()).sum(),
+            token_count: phrase.tokens.len(),
+            codepoint_diff: diff,
+            adjacent_printable,
+            adjacent_total
+        }
+    }
+
+    /// Score of the match. Higher is better.
+    ///
+    /// * 10 points per matched character, since long phrases rarely match by chance.
+    /// * 5 points per token, since every token must agree on the same diff.
+    /// * 20 points for a diff of 0, losing 1 point per unit of |diff| down to 0 points.
+    /// * Up to 40 points scaled by the fraction of adjacent characters that are printable.
+    ///   Instances with nothing adjacent to inspect get no points here.
+    pub fn score(&self) -> i64 {
+        let chars_score = 10 * self.matched_chars as i64;
+        let token_score = 5 * self.token_count as i64;
+        let diff_score = 20 - (self.codepoint_diff.unsigned_abs() as i64).min(20);
+        let adjacent_score = match self.adjacent_total {
+            0 => 0,
+            total => 40 * self.adjacent_printable as i64 / total as i64
+        };
+        chars_score + token_score + diff_score + adjacent_score
+    }
+}
+
+/// Fraction of the characters in `context` that are printable ASCII or whitespace when read with the instance's diff and width,
+/// from 0 to 1, where `context_start` is the file position of `context[0]`.
+/// Characters are lined up with the instance, so a match in text scores high, and one in binary noise scores low.
+/// Multi-byte characters are read as little-endian. Returns 0 if the context holds no whole character.
+pub fn context_printability(instance: &PhraseInstance, context: &[u8], context_start: usize) -> f32 {
+    let bpc = instance.bytes_per_character as usize;
+    let offset = (instance.file_pos - context_start) % bpc;
+    let chars = context[offset.min(context.len())..].chunks_exact(bpc);
+    let total = chars.len();
+    let printable = chars.filter(|bytes| is_printable(bytes, instance.codepoint_diff)).count();
+    match total {
+        0 => 0.0,
+        total => printable as f32 / total as f32
+    }
+}
+
+// True if the little-endian character in bytes decodes to printable ASCII or whitespace under the diff
+fn is_printable(bytes: &[u8], codepoint_diff: i32) -> bool {
+    let raw = bytes
+        .iter()
+        .rev()
+        .fold(0u32, |acc, byte| (acc << 8) | *byte as u32);
+    let codepoint = raw as i64 - codepoint_diff as i64;
+    (32..=126).contains(&codepoint) || codepoint == '\n' as i64 || codepoint == '\r' as i64 || codepoint == '\t' as i64
+}
+
+
+#[test]
+fn test_score_ordering() {
+    let base = MatchQuality {
+        matched_chars: 8,
+        token_count: 2,
+        codepoint_diff: 0,
+        adjacent_printable: 16,
+        adjacent_total: 16
+    };
+    let longer = MatchQuality { matched_chars: 12, ..base };
+    let shifted = MatchQuality { codepoint_diff: -5, ..base };
+    let noisy = MatchQuality { adjacent_printable: 2, ..base };
+    let shifted_further = MatchQuality { codepoint_diff: 13, ..base };
+    assert!(longer.score() > base.score());
+    assert!(base.score() > shifted.score());
+    assert!(shifted.score() > shifted_further.score());
+    assert!(base.score() > noisy.score());
+}
+
+#[test]
+fn test_measure_adjacent() {
+    let phrase = Phrase::from_strs(&["word"]);
+    let instance = |file_pos| PhraseInstance {
+        phrase_index: 0,
+        file_pos,
+        end_pos: file_pos + 4,
+        codepoint_diff: 0,
+        bytes_per_character: 1,
+        line: None,
+        column: None
+    };
+    let text = MatchQuality::measure(&instance(107), &phrase, b"a good word here", 100);
+    let noise = MatchQuality::measure(&instance(104), &phrase, b"\x01\x02\x03\x04word\x05\x06\x07\x08", 100);
+    assert_eq!(12, text.adjacent_printable);
+    assert_eq!(12, text.adjacent_total);
+    assert_eq!(0, noise.adjacent_printable);
+    assert_eq!(8, noise.adjacent_total);
+    assert!(text.score() > noise.score());
+}
+
+#[test]
+fn test_ranked() {
+    let phrase = Phrase::from_strs(&["word"]);
+    let entry = |file_pos, score| ReportEntry {
+        instance: PhraseInstance {
+            phrase_index: 0,
+            file_pos,
+            end_pos: file_pos + 4,
+            codepoint_diff: 0,
+            bytes_per_character: 1,
+            line: None,
+            column: None
+        },
+        phrase: PhraseRef::new(&phrase),
+        score,
+        context_printability: 1.0
+    };
+    let report = SearchReport {
+        files: vec![
+            FileSearchResult {
+                path: PathBuf::from("a.txt"),
+                entries: vec![
+                    entry(0, 10),
+                    entry(5, 30)
+                ],
+                changed_during_scan: false,
+                interpretations: Vec::new(),
+                dominant_interpretation: None
+            },
+            FileSearchResult {
+                path: PathBuf::from("b.txt"),
+                entries: vec![
+                    entry(3, 50),
+                    entry(1, 50)
+                ],
+                changed_during_scan: false,
+                interpretations: Vec::new(),
+                dominant_interpretation: None
+            }
+        ],
+        ..SearchReport::new(vec![phrase.clone()], SearchOptions::default(), 0, None)
+    };
+    let ranked = report.ranked();
+    let order: Vec<(&Path, usize)> = ranked.files
+        .iter()
+        .flat_map(|file| file.entries.iter().map(|entry| (file.path.as_path(), entry.instance.file_pos)))
+        .collect();
+    assert_eq!(
+        vec![
+            (Path::new("b.txt"), 1),
+            (Path::new("b.txt"), 3),
+            (Path::new("a.txt"), 5),
+            (Path::new("a.txt"), 0)
+        ],
+        order
+    );
+}
+
+#[test]
+fn test_context_printability() {
+    let options = SearchOptions { context_size: 64, window_size: 32 };
+    let phrases = [Phrase::from_strs(&["famine", "where"])];
+
+    // Found in the ASCII fixture
+    let mut input: &[u8] = include_bytes!("test_text_1.txt");
+    let text = search_scored(&phrases, &options, None, &mut input).unwrap();
+    assert_eq!(1, text.len());
+    assert_eq!(1.0, text[0].context_printability);
+
+    // Planted in random bytes
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut noise: Vec<u8> = (0..256)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 56) as u8
+        })
+        .collect();
+    noise.splice(128..128, b"famine where".iter().copied());
+    let binary = search_scored(&phrases, &options, None, &mut noise.as_slice()).unwrap();
+    assert_eq!(1, binary.len());
+    assert!(binary[0].context_printability < 0.6, "{}", binary[0].context_printability);
+
+    // Characters are lined up with the instance
+    let instance = PhraseInstance {
+        phrase_index: 0,
+        file_pos: 101,
+        end_pos: 105,
+        codepoint_diff: 0,
+        bytes_per_character: 2,
+        line: None,
+        column: None
+    };
+    assert_eq!(1.0, context_printability(&instance, b"\x01a\0b\0c\0", 100));
+    assert_eq!(0.0, context_printability(&instance, b"a\0b\0c\0", 100));
+    assert_eq!(0.0, context_printability(&instance, b"", 100));
+}
+
+#[test]
+fn test_report_entry_written_before_len_and_printability() {
+    use crate::{FileRef, ResultSink};
+    let phrase = Phrase::from_strs(&["famine", "where"]);
+    let old = serde_json::json!({
+        "instance": { "phrase_index": 0, "file_pos": 288, "end_pos": 300, "codepoint_diff": 0, "bytes_per_character": 1 },
+        "phrase": PhraseRef::new(&phrase),
+        "score": 10
+    });
+    let entry: ReportEntry = serde_json::from_value(old).unwrap();
+    assert_eq!(1.0, entry.context_printability);
+    let written = serde_json::to_value(&entry).unwrap();
+    assert_eq!(12, written["len_bytes"]);
+    assert_eq!(1.0, written["context_printability"]);
+
+    // Kept by printability filters, like entries found in text
+    let mut report = SearchReport::new(vec![phrase], SearchOptions::default(), 0, None);
+    let _ = report.on_match(&FileRef { path: Path::new("a.txt"), encoding: None }, &entry);
+    assert_eq!(1, report.min_printability(0.9).files[0].entries.len());
+}
+
+#[test]
+fn test_search_invalid_options() {
+    // Options a finder can't be built with are an error, not a panic
+    let phrases = [Phrase::from_strs(&["famine", "where"])];
+    let fixture = "src/searcher/test_text_1.txt";
+    for options in [SearchOptions { context_size: 0, window_size: 0 }, SearchOptions { context_size: 30, window_size: 8 }] {
+        assert!(!options.is_valid());
+        let err = search_file_with(fixture, &phrases, &options, None, None, |_| ControlFlow::Continue(())).unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidInput, err.kind());
+        assert!(search_file_chunked(fixture, &phrases, &options, None, 2, None, |_| ControlFlow::Continue(())).is_err());
+        assert!(search_scored_with(&phrases, &options, None, &mut &b"famine where"[..], |_| ControlFlow::Continue(())).is_err());
+    }
+}
+
+#[test]
+fn test_search_read_errors() {
+
+    // Fails for good once `input` runs out
+    struct FailingReader<'a> { input: &'a [u8] }
+    impl Read for FailingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.input.read(buf)? {
+                0 => Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe)),
+                read => Ok(read)
+            }
+        }
+    }
+
+    // Entries found before the failure are handed over, then the error is returned
+    let phrases = [Phrase::from_strs(&["famine", "where"])];
+    let options = SearchOptions { context_size: 64, window_size: 32 };
+    let mut entries = Vec::new();
+    let mut reader = FailingReader { input: include_bytes!("test_text_1.txt") };
+    let err = search_reader_with(&phrases, &options, None, &mut reader, |entry| {
+        entries.push(entry);
+        ControlFlow::Continue(())
+    }).unwrap_err();
+    assert_eq!(std::io::ErrorKind::BrokenPipe, err.kind());
+    assert_eq!(vec![288], entries.iter().map(|entry| entry.instance.file_pos).collect::<Vec<_>>());
+
+    // A cancelled scan fails, rather than passing for a file that changed during it
+    let fixture = "src/searcher/test_text_1.txt";
+    let throttle = Throttle::new(None);
+    throttle.cancel();
+    assert!(search_file_with(fixture, &phrases, &options, None, Some(&throttle), |_| ControlFlow::Continue(())).is_err());
+    assert!(search_file_chunked(fixture, &phrases, &options, None, 2, Some(&throttle), |_| ControlFlow::Continue(())).is_err());
+    assert!(!search_file_with(fixture, &phrases, &options, None, None, |_| ControlFlow::Continue(())).unwrap());
+}
+
+#[test]
+fn test_search_file_appended_during_scan() {
+    use std::io::Write;
+
+    let phrases = [Phrase::from_strs(&["famine", "where"])];
+    let options = SearchOptions { context_size: 64, window_size: 32 };
+    let contents = include_bytes!("test_text_1.txt").repeat(64);
+    let path = std::env::temp_dir().join(format!("text-searcher-appended-{}.txt", std::process::id()));
+    std::fs::write(&path, &contents).unwrap();
+
+    // More instances of the phrase are appended once the first is found
+    let mut entries = Vec::new();
+    let changed = search_file_with(&path, &phrases, &options, None, None, |entry| {
+        if entries.is_empty() {
+            let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(&b" famine where ".repeat(64)).unwrap();
+        }
+        entries.push(entry);
+        ControlFlow::Continue(())
+    }).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // Only the original contents are searched
+    assert!(changed);
+    let mut original = contents.as_slice();
+    let original = FileSearchResult::search_reader(&path, &phrases, &options, None, &mut original).unwrap();
+    assert_eq!(original.entries, entries);
+
+    // Files left alone aren't marked
+    let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/src/searcher/test_text_1.txt");
+    assert!(!FileSearchResult::search(fixture, &phrases, &options, None).unwrap().changed_during_scan);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_search_file_without_size() {
+
+    // Files in /proc report a size of 0, but still have contents
+    let phrases = [Phrase::from_strs(&["State:"])];
+    let options = SearchOptions { context_size: 64, window_size: 32 };
+    let result = FileSearchResult::search("/proc/self/status", &phrases, &options, None).unwrap();
+    assert_eq!(1, result.entries.len());
+    assert!(!result.changed_during_scan);
+}
+
+#[test]
+fn test_search_file_chunked() {
+    let phrases = [Phrase::from_strs(&["famine", "where"]), Phrase::from_strs(&["sum", "count"])];
+    let options = SearchOptions { context_size: 64, window_size: 32 };
+    let chunks = 8;
+    let size = 512 * 1024;
+    let chunk_len = size / chunks;
+
+    // Filler that can't match, with phrases planted across, just before and just after each chunk boundary
+    let mut contents = b"..:..;".repeat(size / 6 + 1);
+    contents.truncate(size);
+    let mut planted = Vec::new();
+    for boundary in (1..chunks).map(|chunk| chunk * chunk_len) {
+        for (pos, text) in [(boundary - 6, "famine where"), (boundary - 40, "sum my count"), (boundary + 3, "famine where")] {
+            contents[pos..pos + text.len()].copy_from_slice(text.as_bytes());
+            planted.push(pos);
+        }
+    }
+    planted.sort();
+    let path = std::env::temp_dir().join(format!("text-searcher-chunked-{}.bin", std::process::id()));
+    std::fs::write(&path, &contents).unwrap();
+
+    let encoding = Some(Encoding { bytes_per_character: 1, endianness: Endianness::Little });
+    let collect = |chunks: usize| -> Vec<ReportEntry> {
+        let mut entries = Vec::new();
+        let changed = search_file_chunked(&path, &phrases, &options, encoding, chunks, None, |entry| {
+            entries.push(entry);
+            ControlFlow::Continue(())
+        });
+        assert!(!changed.unwrap());
+        entries
+    };
+    let single = FileSearchResult::search(&path, &phrases, &options, encoding).unwrap().entries;
+    let chunked = collect(chunks);
+
+    // Breaking stops the scan, without waiting on the chunks after
+    let mut handed = 0;
+    let changed = search_file_chunked(&path, &phrases, &options, encoding, chunks, None, |_| {
+        handed += 1;
+        match handed {
+            2 => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(())
+        }
+    });
+    assert!(!changed.unwrap());
+    assert_eq!(2, handed);
+    std::fs::remove_file(&path).unwrap();
+
+    // Same instances and scores, only without lines past the first chunk
+    assert_eq!(planted, single.iter().map(|entry| entry.instance.file_pos).collect::<Vec<usize>>());
+    assert_eq!(single.len(), chunked.len());
+    for (single, chunked) in single.iter().zip(&chunked) {
+        let without_line = PhraseInstance { line: None, column: None, ..single.instance.clone() };
+        assert_eq!(without_line, PhraseInstance { line: None, column: None, ..chunked.instance.clone() });
+        assert_eq!((single.score, single.context_printability), (chunked.score, chunked.context_printability));
+    }
+    assert_eq!(single[0].instance.line, chunked[0].instance.line);
+    assert_eq!(1, auto_intra_file_parallelism(size as u64));
+}
+
+#[test]
+fn test_dominant_interpretation() {
+    let options = SearchOptions { context_size: 64, window_size: 32 };
+    let search = |phrases: &[Phrase], mut input: &[u8], encoding| FileSearchResult::search_reader("input", phrases, &options, encoding, &mut input).unwrap();
+
+    // UTF-16 LE
+    let phrases = [Phrase::from_strs(&["famine", "where"])];
+
+    // Options a finder can't be built with are refused instead of panicking
+    let invalid = SearchOptions { context_size: 30, window_size: 8 };
+    let refused = FileSearchResult::search_reader("input", &phrases, &invalid, None, &mut &b"famine where"[..]).unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidInput, refused.kind());
+    assert_eq!(std::io::ErrorKind::InvalidInput, search_scored(&phrases, &invalid, None, &mut &b""[..]).unwrap_err().kind());
+    let result = search(&phrases, include_bytes!("test_text_1_utf16le.txt"), None);
+    let little_endian = Interpretation { bytes_per_character: 2, endianness: Some(Endianness::Little), codepoint_diff: 0 };
+    assert_eq!(Some(little_endian), result.dominant_interpretation);
+    assert_eq!(result.entries.len(), result.interpretations[0].count);
+    assert_eq!("2-byte little-endian with a diff of 0", little_endian.to_string());
+
+    // Rotated by 13
+    let phrases = [Phrase::from_strs(&["within", "sunken", "deep"]), Phrase::from_strs(&["sum", "count"])];
+    let rotated: Vec<u8> = include_bytes!("test_text_2.txt").iter().map(|b| b + 13).collect();
+    let result = search(&phrases, &rotated, None);
+    let dominant = result.dominant_interpretation.unwrap();
+    assert_eq!((1, None, 13), (dominant.bytes_per_character, dominant.endianness, dominant.codepoint_diff));
+    assert_eq!(2, result.interpretations[0].count);
+
+    // Ties go to the diff closest to 0
+    let instance = |codepoint_diff| PhraseInstance {
+        phrase_index: 0,
+        file_pos: 0,
+        end_pos: 4,
+        codepoint_diff,
+        bytes_per_character: 1,
+        line: None,
+        column: None
+    };
+    let instances = [instance(5), instance(-1), instance(5), instance(-1), instance(0)];
+    let counts = tally_interpretations(&instances, None);
+    assert_eq!(vec![(-1, 2), (5, 2), (0, 1)], counts.iter().map(|count| (count.interpretation.codepoint_diff, count.count)).collect::<Vec<_>>());
+}
@@ -0,0 +1,255 @@
+use std::io::{self, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::SyncSender;
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+
+use crate::{Encoding, FileSearchResult, ReportEntry, SearchReport, tally_interpretations};
+
+/// Source being searched, as passed to a [`ResultSink`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FileRef<'a> {
+    pub path: &'a Path,                 // Path of the file, or name of the source
+    pub encoding: Option<Encoding>      // Encoding the source is searched with, if known
+}
+
+/// Receives search results as they're found, instead of once every source has been searched.
+/// For every source, `on_match` is called for each entry, followed by either `on_file_done` or `on_error`.
+pub trait ResultSink {
+
+    /// Called with each entry found. Breaking stops searching the current file, which is then done.
+    fn on_match(&mut self, file: &FileRef, entry: &ReportEntry) -> ControlFlow<()>;
+
+    /// Called once a file has been searched, or was stopped early
+    fn on_file_done(&mut self, file: &FileRef, changed_during_scan: bool);
+
+    /// Called if a file couldn't be searched. Entries it already sent may be incomplete.
+    fn on_error(&mut self, file: &FileRef, error: &io::Error);
+}
+
+/// Collects results into the report, one [`FileSearchResult`] per file searched.
+/// Files that fail to be searched are left out.
+/// Once an entry would take the report past its `max_bytes`, it and every later entry of its phrase in that file are dropped and counted.
+impl ResultSink for SearchReport {
+    fn on_match(&mut self, file: &FileRef, entry: &ReportEntry) -> ControlFlow<()> {
+        let bytes = entry.approx_bytes();
+        if self.count_dropped(file.path, &entry.phrase) {
+            return ControlFlow::Continue(());
+        }
+        match self.max_bytes {
+            Some(max_bytes) if self.approx_bytes + bytes > max_bytes => self.start_dropping(file.path, &entry.phrase),
+            _ => {
+                self.approx_bytes += bytes;
+                self.current_file(file.path).entries.push(entry.clone());
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn on_file_done(&mut self, file: &FileRef, changed_during_scan: bool) {
+        let result = self.current_file(file.path);
+        let interpretations = tally_interpretations(result.entries.iter().map(|entry| &entry.instance), file.encoding);
+        result.changed_during_scan = changed_during_scan;
+        result.dominant_interpretation = interpretations.first().map(|count| count.interpretation);
+        result.interpretations = interpretations;
+    }
+
+    fn on_error(&mut self, file: &FileRef, _error: &io::Error) {
+        if self.files.last().is_some_and(|result| result.path == file.path) {
+            self.files.pop();
+        }
+    }
+}
+
+impl SearchReport {
+
+    // Result of the file being collected, started if it's a new one
+    fn current_file(&mut self, path: &Path) -> &mut FileSearchResult {
+        if self.files.last().is_none_or(|result| result.path != path) {
+            self.files.push(FileSearchResult {
+                path: path.to_path_buf(),
+                entries: Vec::new(),
+                changed_during_scan: false,
+                interpretations: Vec::new(),
+                dominant_interpretation: None
+            });
+        }
+        self.files.last_mut().expect("File was just pushed")
+    }
+}
+
+/// Something a [`ChannelSink`] sends
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SinkEvent {
+    Match { path: PathBuf, entry: ReportEntry },
+    FileDone { path: PathBuf, changed_during_scan: bool },
+    Error { path: PathBuf, message: String }
+}
+
+/// Sends results over a channel as they're found, such as to a task streaming them as server-sent events.
+/// A bounded channel holds the search back while it's full. Once the receiver is dropped, every file is stopped early.
+#[derive(Debug, Clone)]
+pub struct ChannelSink {
+    sender: SyncSender<SinkEvent>
+}
+
+impl ChannelSink {
+    pub fn new(sender: SyncSender<SinkEvent>) -> Self {
+        Self { sender }
+    }
+}
+
+impl ResultSink for ChannelSink {
+    fn on_match(&mut self, file: &FileRef, entry: &ReportEntry) -> ControlFlow<()> {
+        let event = SinkEvent::Match { path: file.path.to_path_buf(), entry: entry.clone() };
+        match self.sender.send(event) {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(_) => ControlFlow::Break(())
+        }
+    }
+
+    fn on_file_done(&mut self, file: &FileRef, changed_during_scan: bool) {
+        let _ = self.sender.send(SinkEvent::FileDone { path: file.path.to_path_buf(), changed_during_scan });
+    }
+
+    fn on_error(&mut self, file: &FileRef, error: &io::Error) {
+        let _ = self.sender.send(SinkEvent::Error { path: file.path.to_path_buf(), message: error.to_string() });
+    }
+}
+
+/// Writes a line of CSV for every entry as it's found, after a header line.
+/// Writing stops at the first error, which [`Self::finish`] returns.
+pub struct CsvSink<W: Write> {
+    writer: csv::Writer<W>,
+    error: Option<io::Error>
+}
+
+impl<W: Write> CsvSink<W> {
+
+    /// Columns written, in order
+    pub const HEADER: &'static str = "path,phrase_id,phrase,file_pos,end_pos,codepoint_diff,bytes_per_character,line,column,score";
+
+    pub fn new(writer: W) -> Result<Self, io::Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(Self::HEADER.split(','))?;
+        Ok(Self { writer, error: None })
+    }
+
+    /// Flushes the writer and gives it back, or returns the first error writing hit
+    pub fn finish(self) -> Result<W, io::Error> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.writer.into_inner().map_err(|err| io::Error::new(err.error().kind(), err.error().to_string()))
+    }
+
+    fn write_entry(&mut self, file: &FileRef, entry: &ReportEntry) -> Result<(), csv::Error> {
+        let instance = &entry.instance;
+        let optional = |value: Option<usize>| value.map(|value| value.to_string()).unwrap_or_default();
+        self.writer.write_record([
+            file.path.to_string_lossy().into_owned(),
+            entry.phrase.id.clone(),
+            entry.phrase.text.clone(),
+            instance.file_pos.to_string(),
+            instance.end_pos.to_string(),
+            instance.codepoint_diff.to_string(),
+            instance.bytes_per_character.to_string(),
+            optional(instance.line),
+            optional(instance.column),
+            entry.score.to_string()
+        ])
+    }
+}
+
+impl<W: Write> ResultSink for CsvSink<W> {
+    fn on_match(&mut self, file: &FileRef, entry: &ReportEntry) -> ControlFlow<()> {
+        if self.error.is_some() {
+            return ControlFlow::Break(());
+        }
+        match self.write_entry(file, entry) {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(err) => {
+                self.error = Some(err.into());
+                ControlFlow::Break(())
+            }
+        }
+    }
+
+    fn on_file_done(&mut self, _file: &FileRef, _changed_during_scan: bool) {}
+
+    fn on_error(&mut self, _file: &FileRef, _error: &io::Error) {}
+}
+
+
+
+#[test]
+fn test_csv_sink() {
+    use crate::{Phrase, PhraseRef, PhraseInstance};
+    let phrase = Phrase::from_strs(&["famine", "where"]);
+    let entry = ReportEntry {
+        instance: PhraseInstance {
+            phrase_index: 0,
+            file_pos: 288,
+            end_pos: 300,
+            codepoint_diff: 0,
+            bytes_per_character: 1,
+            line: Some(7),
+            column: Some(12)
+        },
+        phrase: PhraseRef::new(&phrase),
+        score: 5,
+        context_printability: 1.0
+    };
+    let file = FileRef { path: Path::new("dir/a, \"b\".txt"), encoding: None };
+    let mut sink = CsvSink::new(Vec::new()).unwrap();
+    assert!(sink.on_match(&file, &entry).is_continue());
+    sink.on_file_done(&file, false);
+    let csv = String::from_utf8(sink.finish().unwrap()).unwrap();
+    let expected = format!("\"dir/a, \"\"b\"\".txt\",{},famine where,288,300,0,1,7,12,5\n", phrase.id());
+    assert_eq!(format!("{}\n{}", CsvSink::<Vec<u8>>::HEADER, expected), csv);
+}
+
+#[test]
+fn test_search_report_max_bytes() {
+    use crate::{search_scored_with, Phrase, SearchOptions};
+
+    // Repetitive input with more instances of each phrase than fit
+    let input = "hunger where famine where ".repeat(50);
+    let phrases = [Phrase::from_strs(&["hunger"]), Phrase::from_strs(&["famine"])];
+    let options = SearchOptions::default();
+    let search = |max_bytes| {
+        let mut report = SearchReport::new(phrases.to_vec(), options, 0, max_bytes);
+        for name in ["a.txt", "b.txt"] {
+            let file = FileRef { path: Path::new(name), encoding: None };
+            let _ = search_scored_with(&phrases, &options, None, &mut input.as_bytes(), |entry| report.on_match(&file, &entry));
+            report.on_file_done(&file, false);
+        }
+        report
+    };
+    let full = search(None);
+    assert!(!full.truncated);
+    let all = full.files[0].entries.len();
+    let bytes_per_entry = full.files[0].entries[0].approx_bytes();
+    assert_eq!(2 * all * bytes_per_entry, full.approx_bytes);
+
+    // Entries up to the cap are kept as they were, and the rest are counted for each file and phrase
+    let capped = search(Some(10 * bytes_per_entry + 1));
+    assert!(capped.truncated);
+    assert_eq!(10 * bytes_per_entry, capped.approx_bytes);
+    assert_eq!(full.files[0].entries[..10], capped.files[0].entries[..]);
+    assert!(capped.files[1].entries.is_empty());
+    let dropped: Vec<(&str, &str, usize)> = capped.dropped
+        .iter()
+        .map(|dropped| (dropped.path.to_str().unwrap(), dropped.phrase.text.as_str(), dropped.count))
+        .collect();
+    assert_eq!(vec![("a.txt", "hunger", 45), ("a.txt", "famine", 45), ("b.txt", "hunger", 50), ("b.txt", "famine", 50)], dropped);
+
+    // A report read back from JSON keeps counting under the same files and phrases
+    let mut restored: SearchReport = serde_json::from_str(&serde_json::to_string(&capped).unwrap()).unwrap();
+    let file = FileRef { path: Path::new("b.txt"), encoding: None };
+    let _ = restored.on_match(&file, &full.files[1].entries[0]);
+    assert_eq!(4, restored.dropped.len());
+    assert_eq!(51, restored.dropped[2].count);
+}
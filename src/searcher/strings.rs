@@ -0,0 +1,145 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+
+use crate::get_2bytes_at;
+
+/// Most diffs [`find_candidate_strings`] extracts strings under, for each width and alignment
+pub const MAX_CANDIDATE_DIFFS: usize = 8;
+
+/// Most characters in a candidate string. Longer runs are split.
+pub const MAX_CANDIDATE_CHARS: usize = 256;
+
+/// A run of characters that reads like text under some diff and width. See [`find_candidate_strings`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CandidateString {
+    pub text: String,
+    pub pos: usize,                 // Byte offset the run starts at
+    pub len_bytes: usize,
+    pub codepoint_diff: i32,        // Diff the run was decoded with
+    pub bytes_per_character: u32,   // 1, or 2 for little-endian characters
+    pub letters: usize              // Letters in the text, which candidates are ranked by
+}
+
+/// Finds runs of at least `min_len` characters that look like text, to pick phrases from in a file whose encoding isn't known.
+/// Every width (1 and 2 bytes) and alignment is tried. The diffs tried are 0, and those under which a value sits between two letters
+/// like a space does, most often first. Under each, runs of printable ASCII are kept if at least half of their characters are letters.
+/// Returns the `limit` runs with the most letters, ties going to the earliest.
+pub fn find_candidate_strings(bytes: &[u8], min_len: usize, limit: usize) -> Vec<CandidateString> {
+    let mut candidates = Vec::new();
+    for (bytes_per_character, offset) in [(1, 0), (2, 0), (2, 1)] {
+        let bpc = bytes_per_character as usize;
+        let values: Vec<i64> = (offset..bytes.len().saturating_sub(bpc - 1))
+            .step_by(bpc)
+            .map(|idx| match bpc {
+                1 => bytes[idx] as i64,
+                _ => get_2bytes_at(bytes, idx) as i64
+            })
+            .collect();
+        for codepoint_diff in likely_diffs(&values) {
+            for (start, chars) in printable_runs(&values, codepoint_diff, min_len) {
+                let text: String = chars.iter().collect();
+                let letters = chars.iter().filter(|char| char.is_ascii_alphabetic()).count();
+                if letters * 2 >= chars.len() {
+                    candidates.push(CandidateString {
+                        text,
+                        pos: offset + start * bpc,
+                        len_bytes: chars.len() * bpc,
+                        codepoint_diff: codepoint_diff as i32,
+                        bytes_per_character,
+                        letters
+                    });
+                }
+            }
+        }
+    }
+    candidates.sort_by_key(|candidate| (Reverse(candidate.letters), candidate.pos, candidate.bytes_per_character));
+    candidates.truncate(limit);
+    candidates
+}
+
+// Diff 0, then up to MAX_CANDIDATE_DIFFS diffs that at least two values would be a space between letters under, most votes first.
+// Diffs that don't fit in an i32 are left out.
+fn likely_diffs(values: &[i64]) -> Vec<i64> {
+    let is_letter = |value: i64| (65..=90).contains(&value) || (97..=122).contains(&value);
+    let mut votes: HashMap<i64, usize> = HashMap::new();
+    for window in values.windows(3) {
+        let diff = window[1] - ' ' as i64;
+        if diff != 0 && is_letter(window[0] - diff) && is_letter(window[2] - diff) && i32::try_from(diff).is_ok() {
+            *votes.entry(diff).or_default() += 1;
+        }
+    }
+    let mut votes: Vec<(i64, usize)> = votes.into_iter().filter(|(_, votes)| *votes >= 2).collect();
+    votes.sort_by_key(|(diff, votes)| (Reverse(*votes), diff.unsigned_abs(), *diff));
+    std::iter::once(0)
+        .chain(votes.into_iter().map(|(diff, _)| diff))
+        .take(MAX_CANDIDATE_DIFFS)
+        .collect()
+}
+
+// Runs of at least min_len values that are printable ASCII under the diff, by character index, split at MAX_CANDIDATE_CHARS
+fn printable_runs(values: &[i64], codepoint_diff: i64, min_len: usize) -> Vec<(usize, Vec<char>)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut chars = Vec::new();
+    for (idx, value) in values.iter().enumerate() {
+        let char = u8::try_from(value - codepoint_diff).ok().filter(|byte| (32..=126).contains(byte)).map(char::from);
+        match char {
+            Some(char) if chars.len() < MAX_CANDIDATE_CHARS => {
+                if chars.is_empty() {
+                    start = idx;
+                }
+                chars.push(char);
+            },
+            _ => {
+                if chars.len() >= min_len.max(1) {
+                    runs.push((start, std::mem::take(&mut chars)));
+                }
+                chars.clear();
+                if let Some(char) = char {
+                    start = idx;
+                    chars.push(char);
+                }
+            }
+        }
+    }
+    if chars.len() >= min_len.max(1) {
+        runs.push((start, chars));
+    }
+    runs
+}
+
+
+#[test]
+fn test_find_candidate_strings() {
+    // Rotated by 13, the fixture's lines are found under that diff
+    let rotated: Vec<u8> = include_bytes!("test_text_2.txt").iter().map(|b| b + 13).collect();
+    let candidates = find_candidate_strings(&rotated, 4, 10);
+    assert_eq!(10, candidates.len());
+    assert!(candidates.iter().all(|candidate| candidate.codepoint_diff == 13 && candidate.bytes_per_character == 1));
+    let all = find_candidate_strings(&rotated, 4, usize::MAX);
+    let count = all.iter().find(|candidate| candidate.text.contains("sum my count")).unwrap();
+    assert_eq!(13, count.codepoint_diff);
+    assert_eq!(rotated[count.pos] - 13, count.text.as_bytes()[0]);
+
+    // As is 2-byte text, with words between noise
+    let mut wide: Vec<u8> = vec![0x01, 0xff, 0x13];
+    wide.extend("famine where abundance lies".bytes().flat_map(|byte| [byte, 0]));
+    wide.extend([0xfe, 0x02, 0x00, 0x80]);
+    let candidates = find_candidate_strings(&wide, 4, 1);
+    assert_eq!(
+        vec![CandidateString {
+            text: "famine where abundance lies".to_owned(),
+            pos: 3,
+            len_bytes: 54,
+            codepoint_diff: 0,
+            bytes_per_character: 2,
+            letters: 24
+        }],
+        candidates
+    );
+
+    // Noise and short runs aren't candidates
+    assert!(find_candidate_strings(&[0x00, 0x81, 0x02, b'a', b'b', 0x90], 4, 10).is_empty());
+}
@@ -0,0 +1,110 @@
+//! Generators for encoded inputs with known phrase positions, used as an oracle in tests.
+
+use crate::{Phrase, PhraseInstance};
+
+/// Filler character surrounding planted phrases. Repeats, so it can't match a token under any diff.
+pub const FILLER: char = '.';
+
+/// Control characters that can be injected into the filler
+pub const CONTROL_CHARS: [char; 5] = ['\0', '\t', '\n', '\r', '\x1b'];
+
+/// How an ASCII corpus gets encoded
+#[derive(Debug, Clone)]
+pub struct EncodingSpec {
+    pub bytes_per_character: u32,       // 1 or 2
+    pub big_endian: bool,               // Only used when bytes_per_character is 2
+    pub codepoint_diff: i32,            // Added to every character before encoding
+    pub junk_prefix: Vec<u8>,           // Raw bytes placed before the encoded corpus
+    pub control_chars: Vec<(usize, char)> // Filler characters replaced with control characters, by character index
+}
+
+/// Corpus with a phrase planted in it
+#[derive(Debug, Clone)]
+pub struct PlantedCorpus {
+    pub corpus: String,
+    pub phrase_char_pos: usize
+}
+
+/// Encoded input along with the instances a [`crate::Finder`] should find in it
+#[derive(Debug, Clone)]
+pub struct EncodedInput {
+    pub bytes: Vec<u8>,
+    pub expected: Vec<PhraseInstance>
+}
+
+/// Writes the tokens of `phrase` separated by spaces, surrounded by filler.
+pub fn plant_phrase(phrase: &Phrase, filler_before: usize, filler_after: usize) -> PlantedCorpus {
+    let mut corpus: String = std::iter::repeat_n(FILLER, filler_before).collect();
+    corpus.push_str(&phrase.to_string());
+    corpus.extend(std::iter::repeat_n(FILLER, filler_after));
+    PlantedCorpus { corpus, phrase_char_pos: filler_before }
+}
+
+/// Encodes a planted corpus and computes where the phrase (at `phrase_index`) should be reported.
+/// Control characters that would land on the phrase itself, or encode to a negative value, are ignored.
+pub fn encode(planted: &PlantedCorpus, phrase_index: usize, spec: &EncodingSpec) -> EncodedInput {
+    let phrase_range = planted.phrase_char_pos..planted.corpus.len() - trailing_filler(&planted.corpus);
+    let mut chars: Vec<char> = planted.corpus.chars().collect();
+    for (idx, control) in &spec.control_chars {
+        let encodable = *control as i32 + spec.codepoint_diff >= 0;
+        if *idx < chars.len() && !phrase_range.contains(idx) && encodable {
+            chars[*idx] = *control;
+        }
+    }
+
+    let mut bytes = spec.junk_prefix.clone();
+    for char in chars {
+        let value = (char as i32 + spec.codepoint_diff) as u32;
+        match (spec.bytes_per_character, spec.big_endian) {
+            (1, _) => bytes.push(value as u8),
+            (2, false) => bytes.extend_from_slice(&(value as u16).to_le_bytes()),
+            (2, true) => bytes.extend_from_slice(&(value as u16).to_be_bytes()),
+            (bpc, _) => panic!("Unsupported bytes_per_character {}", bpc)
+        }
+    }
+
+    let file_pos = expected_file_pos(planted.phrase_char_pos, spec);
+    let (line, column) = match expected_line_start(&bytes[..file_pos], spec) {
+        Some((line, line_start)) => (Some(line), Some(file_pos - line_start + 1)),
+        None => (None, None)
+    };
+    EncodedInput {
+        bytes,
+        expected: vec![PhraseInstance {
+            phrase_index,
+            file_pos,
+            end_pos: file_pos + phrase_range.len() * spec.bytes_per_character as usize,
+            codepoint_diff: spec.codepoint_diff,
+            bytes_per_character: spec.bytes_per_character,
+            line,
+            column
+        }]
+    }
+}
+
+/// File position the finder reports for the character at `char_pos`.
+/// Big-endian input is found as little-endian starting one byte later, pairing each character
+/// with the high byte of the character after it.
+pub fn expected_file_pos(char_pos: usize, spec: &EncodingSpec) -> usize {
+    let bpc = spec.bytes_per_character as usize;
+    let be_offset = if bpc == 2 && spec.big_endian { 1 } else { 0 };
+    spec.junk_prefix.len() + char_pos * bpc + be_offset
+}
+
+/// 1-based line the finder reports after the bytes `before`, and the position that line starts at.
+/// Any byte that decodes to '\n' under the diff breaks a line, including ones in the junk prefix.
+/// Lines are only reported for 1 byte per character.
+pub fn expected_line_start(before: &[u8], spec: &EncodingSpec) -> Option<(usize, usize)> {
+    if spec.bytes_per_character != 1 {
+        return None;
+    }
+    let newline = u8::try_from('\n' as i32 + spec.codepoint_diff).ok();
+    let is_newline = |byte: &u8| Some(*byte) == newline;
+    let line = before.iter().filter(|byte| is_newline(byte)).count() + 1;
+    let line_start = before.iter().rposition(is_newline).map_or(0, |idx| idx + 1);
+    Some((line, line_start))
+}
+
+fn trailing_filler(corpus: &str) -> usize {
+    corpus.chars().rev().take_while(|c| *c == FILLER).count()
+}
@@ -1,16 +1,55 @@
 use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
 use std::fmt::{self, Write};
 
 /// A "String" as a sequence of u32s
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Text(pub Vec<u32>);
 impl Text {
+
+    pub fn from_codepoints(codepoints: Vec<u32>) -> Self {
+        Self(codepoints)
+    }
+
+    /// Appends a codepoint to the end of the text
+    pub fn push(&mut self, codepoint: u32) {
+        self.0.push(codepoint);
+    }
+
+    /// Whether every codepoint is a Unicode scalar value, so none are surrogates or out of range
+    pub fn is_valid_unicode(&self) -> bool {
+        self.0.iter().all(|codepoint| char::from_u32(*codepoint).is_some())
+    }
+
+    /// Whether every codepoint is ASCII
+    pub fn is_ascii(&self) -> bool {
+        self.0.iter().all(|codepoint| *codepoint < 0x80)
+    }
+
+    /// The text as a string, if every codepoint is ASCII. Unlike `to_string`, nothing is replaced,
+    /// and nothing is allocated for texts that aren't ASCII.
+    pub fn to_ascii_string(&self) -> Option<String> {
+        match self.is_ascii() {
+            true => Some(self.0.iter().map(|codepoint| *codepoint as u8 as char).collect()),
+            false => None
+        }
+    }
+
+    /// Codepoint of the character at `byte_offset` in the bytes the text was decoded from, with `bytes_per_char` bytes per character.
+    /// Offsets within a character round down to it. None past the end, or if `bytes_per_char` is 0.
+    pub fn char_at_byte(&self, byte_offset: usize, bytes_per_char: u32) -> Option<u32> {
+        let char_index = byte_offset.checked_div(bytes_per_char as usize)?;
+        self.0.get(char_index).copied()
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(str: &str) -> Self {
-        let vec = str
+        str
             .chars()
             .map(|c| c as u32 )
-            .collect();
-        Self(vec)
+            .collect()
     }
 
     pub fn from_slice(slice: &[u8], codepoint_diff: i32, bytes_per_char: u32) -> Self {
@@ -27,7 +66,7 @@ impl Text {
             let num = (*num as i32 - codepoint_diff) as u32;
             vec.push(num);
         }
-        Self(vec)
+        Self::from_codepoints(vec)
     }
 
     pub fn from_slice_2bytes(slice: &[u8], codepoint_diff: i32) -> Self {
@@ -37,24 +76,30 @@ impl Text {
             let num = (num as i32 - codepoint_diff) as u32;
             vec.push(num);
         }
-        Self(vec)
+        Self::from_codepoints(vec)
     }
 
+    /// Chains texts together, inserting `separator` between each of them if specified.
+    pub fn concat(texts: &[Text], separator: Option<u32>) -> Self {
+        let chars: usize = texts.iter().map(|text| text.0.len()).sum();
+        let separators = if separator.is_some() { texts.len().saturating_sub(1) } else { 0 };
+        let mut vec = Vec::with_capacity(chars + separators);
+        for (index, text) in texts.iter().enumerate() {
+            if let (Some(separator), true) = (separator, index > 0) {
+                vec.push(separator);
+            }
+            vec.extend_from_slice(&text.0);
+        }
+        Self::from_codepoints(vec)
+    }
+
+    // Writes every character, non-ASCII included, replacing control characters and invalid codepoints
     fn write_chars(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for char_u32 in &self.0 {
-            let char_u32 = *char_u32;
-            let char = match char::try_from(char_u32) {
-                Ok(char) => char,
-                Err(_) => '?'
-            };
-            if char_u32 >= 32 && char_u32 <= 126 {
-                f.write_char(char)?;
-            }
-            else {
-                match char {
-                    '\n' | '\r' | '\t' | '\0' => f.write_char(' ')?,
-                    _ => f.write_char('?')?
-                }
+            match char::from_u32(*char_u32) {
+                Some('\n' | '\r' | '\t' | '\0') => f.write_char(' ')?,
+                Some(char) if !char.is_control() => f.write_char(char)?,
+                _ => f.write_char('?')?
             }
         }
         Ok(())
@@ -83,6 +128,12 @@ impl<'de> Deserialize<'de> for Text {
     }
 }
 
+// Serialized as a string, so documented as one
+impl JsonSchema for Text {
+    fn schema_name() -> String { "Text".to_owned() }
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema { String::json_schema(gen) }
+}
+
 impl fmt::Display for Text {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.write_chars(f)?;
@@ -92,4 +143,93 @@ impl fmt::Display for Text {
 
 impl AsRef<[u32]> for Text {
     fn as_ref(&self) -> &[u32] { &self.0 }
-}
\ No newline at end of file
+}
+
+impl FromIterator<u32> for Text {
+    fn from_iter<I: IntoIterator<Item=u32>>(iter: I) -> Self {
+        Self::from_codepoints(iter.into_iter().collect())
+    }
+}
+
+impl Extend<u32> for Text {
+    fn extend<I: IntoIterator<Item=u32>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+#[test]
+fn test_to_ascii_string() {
+    assert_eq!(Some("famine where".to_owned()), Text::from_str("famine where").to_ascii_string());
+    assert_eq!(Some(String::new()), Text::from_str("").to_ascii_string());
+    assert_eq!(None, Text::from_str("café").to_ascii_string());
+    assert_eq!(None, Text::from_slice_1byte(&[b'a', 0xff], 0).to_ascii_string());
+
+    // Control characters are kept as they are
+    assert_eq!(Some("a\nb".to_owned()), Text::from_str("a\nb").to_ascii_string());
+
+    // Codepoints can be changed in place, without anything going stale
+    let mut text = Text::from_str("famine");
+    text.0[0] = 'é' as u32;
+    assert!(!text.is_ascii());
+    assert_eq!(None, text.to_ascii_string());
+}
+
+#[test]
+fn test_char_at_byte() {
+    let wide: Vec<u8> = "famine".bytes().flat_map(|byte| [byte, 0]).collect();
+    let text = Text::from_slice(&wide, 0, 2);
+    assert_eq!(Some('f' as u32), text.char_at_byte(0, 2));
+    assert_eq!(Some('m' as u32), text.char_at_byte(4, 2));
+    assert_eq!(Some('m' as u32), text.char_at_byte(5, 2));
+    assert_eq!(Some('e' as u32), text.char_at_byte(10, 2));
+    assert_eq!(None, text.char_at_byte(12, 2));
+    assert_eq!(Some('e' as u32), Text::from_str("famine").char_at_byte(5, 1));
+    assert_eq!(None, text.char_at_byte(0, 0));
+}
+
+#[test]
+fn test_is_valid_unicode() {
+    assert!(Text::from_str("café 東京 🦀").is_valid_unicode());
+    assert!(Text::from_codepoints(vec![0x10ffff]).is_valid_unicode());
+    assert!(!Text::from_codepoints(vec![97, 0xd800]).is_valid_unicode());
+    assert!(!Text::from_codepoints(vec![0x110000]).is_valid_unicode());
+
+    // Decoding with the wrong diff can leave the range
+    assert!(!Text::from_slice_1byte(b"a", 98).is_valid_unicode());
+}
+
+#[test]
+fn test_text_from_iter() {
+    let text: Text = "famine".chars().map(|c| c as u32).collect();
+    assert_eq!(Text::from_str("famine"), text);
+    assert_eq!(Some("famine"), text.to_ascii_string().as_deref());
+    let tokens = [Text::from_str("famine"), Text::from_str("where")];
+    let joined: Text = tokens.iter().flat_map(|token| token.0.iter().copied()).collect();
+    assert_eq!(Text::from_str("faminewhere"), joined);
+}
+
+#[test]
+fn test_text_push() {
+    let mut text = Text::from_str("fam");
+    text.push('i' as u32);
+    text.extend("ne".chars().map(|char| char as u32));
+    assert_eq!(Text::from_str("famine"), text);
+    assert_eq!(Some("famine"), text.to_ascii_string().as_deref());
+    text.push('é' as u32);
+    assert_eq!(None, text.to_ascii_string().as_deref());
+
+    // Pushing grows the codepoints in place, keeping their allocation
+    let mut text = Text::from_codepoints(Vec::with_capacity(1024));
+    let codepoints = text.0.as_ptr();
+    (0..1024).for_each(|codepoint| text.push(codepoint));
+    text.extend(std::iter::empty());
+    assert_eq!(codepoints, text.0.as_ptr());
+    assert_eq!((0..1024).collect::<Vec<u32>>(), text.0);
+}
+
+#[test]
+fn test_text_display_unicode() {
+    assert_eq!("café 東京 🦀 ¿qué?", Text::from_str("café 東京 🦀 ¿qué?").to_string());
+    assert_eq!("a b c?d?", Text::from_codepoints(vec![97, 10, 98, 9, 99, 0x1b, 100, 0x85]).to_string());
+    assert_eq!("??", Text::from_codepoints(vec![0xd800, 0x110000]).to_string());
+}
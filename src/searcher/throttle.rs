@@ -0,0 +1,193 @@
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Longest a [`Throttle`] sleeps before checking whether it was cancelled
+pub const MAX_THROTTLE_SLEEP: Duration = Duration::from_millis(50);
+
+/// Caps how fast bytes are read, shared by every reader of a scan so the cap covers all of them together.
+/// Readers wait until the bytes read so far are within the cap, measured from when the throttle was made.
+/// Without a cap, only counts what was read.
+#[derive(Debug)]
+pub struct Throttle {
+    max_bytes_per_second: Option<u64>,
+    started: Instant,
+    bytes_read: AtomicU64,
+    cancel: CancelHandle
+}
+
+/// Cancels the throttles made with it, from any thread. Clones cancel the same throttles.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+
+    /// Stops readers of every throttle made with the handle waiting, and fails their reads from then on
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Bytes read through a [`Throttle`], and over how long
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Throughput {
+    pub bytes_read: u64,
+    pub elapsed: Duration
+}
+
+impl Throughput {
+
+    /// Both reads together, as if one followed the other
+    pub fn and(self, other: Throughput) -> Self {
+        Self {
+            bytes_read: self.bytes_read + other.bytes_read,
+            elapsed: self.elapsed + other.elapsed
+        }
+    }
+
+    /// Effective bytes read per second. None if no time has passed.
+    pub fn bytes_per_second(&self) -> Option<u64> {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => Some((self.bytes_read as f64 / secs) as u64),
+            _ => None
+        }
+    }
+}
+
+impl Throttle {
+
+    /// Throttle capped at `max_bytes_per_second`, or that only counts bytes if it's `None`.
+    /// A cap of 0 is the same as no cap.
+    pub fn new(max_bytes_per_second: Option<u64>) -> Self {
+        Self::with_cancel(max_bytes_per_second, CancelHandle::default())
+    }
+
+    /// Same as [`Self::new`], cancelled along with everything else `cancel` was given to
+    pub fn with_cancel(max_bytes_per_second: Option<u64>, cancel: CancelHandle) -> Self {
+        Self {
+            max_bytes_per_second: max_bytes_per_second.filter(|&max| max > 0),
+            started: Instant::now(),
+            bytes_read: AtomicU64::new(0),
+            cancel
+        }
+    }
+
+    pub fn max_bytes_per_second(&self) -> Option<u64> { self.max_bytes_per_second }
+
+    pub fn throughput(&self) -> Throughput {
+        Throughput {
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            elapsed: self.started.elapsed()
+        }
+    }
+
+    /// Counts `bytes` as read, then sleeps until they're within the cap, waking every [`MAX_THROTTLE_SLEEP`] to check for cancellation
+    pub fn consume(&self, bytes: u64) {
+        let total = self.bytes_read.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        let Some(max) = self.max_bytes_per_second else { return };
+        let due = Duration::from_secs_f64(total as f64 / max as f64);
+        while !self.is_cancelled() {
+            let elapsed = self.started.elapsed();
+            if elapsed >= due {
+                break;
+            }
+            std::thread::sleep((due - elapsed).min(MAX_THROTTLE_SLEEP));
+        }
+    }
+
+    /// Stops readers waiting, and fails their reads from then on
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+}
+
+/// Error reads fail with once their [`Throttle`] is cancelled
+pub fn cancelled_error() -> io::Error {
+    io::Error::other("Throttled read cancelled")
+}
+
+/// Reads through a [`Throttle`], if there is one. Goes under a buffer, so the throttle is consulted per chunk rather than per byte.
+/// Reads fail with [`io::ErrorKind::Other`] once the throttle is cancelled.
+pub struct ThrottledReader<'a, R: Read> {
+    inner: R,
+    throttle: Option<&'a Throttle>
+}
+
+impl<'a, R: Read> ThrottledReader<'a, R> {
+    pub fn new(inner: R, throttle: Option<&'a Throttle>) -> Self {
+        Self { inner, throttle }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(throttle) = self.throttle else { return self.inner.read(buf) };
+        if throttle.is_cancelled() {
+            return Err(cancelled_error());
+        }
+        let read = self.inner.read(buf)?;
+        throttle.consume(read as u64);
+        Ok(read)
+    }
+}
+
+
+#[test]
+fn test_throttled_reader() {
+    use std::io::BufReader;
+    use crate::{Finder, Phrase};
+
+    // Results are the same, but take at least as long as the cap allows
+    let input: &[u8] = &include_bytes!("test_text_2.txt").repeat(64);
+    let phrases = [Phrase::from_strs(&["sum", "count"])];
+    let throttle = Throttle::new(Some(256 * 1024));
+    let mut reader = BufReader::with_capacity(4096, ThrottledReader::new(input, Some(&throttle)));
+    let throttled = Finder::new(&phrases, 64, 32, &mut reader).count();
+    let throughput = throttle.throughput();
+    assert_eq!(Finder::new(&phrases, 64, 32, &mut &input[..]).count(), throttled);
+    assert_eq!(input.len() as u64, throughput.bytes_read);
+    let minimum = Duration::from_secs_f64(input.len() as f64 / (256.0 * 1024.0));
+    assert!(throughput.elapsed >= minimum, "{:?} < {:?}", throughput.elapsed, minimum);
+    assert!(throughput.bytes_per_second().unwrap() <= 256 * 1024);
+
+    // Unthrottled readers only count
+    let counter = Throttle::new(None);
+    let mut reader = ThrottledReader::new(input, Some(&counter));
+    io::copy(&mut reader, &mut io::sink()).unwrap();
+    assert_eq!(input.len() as u64, counter.throughput().bytes_read);
+}
+
+#[test]
+fn test_throttle_cancel() {
+    // A read that would wait for seconds returns soon after it's cancelled
+    let throttle = Throttle::new(Some(1024));
+    let input = [0u8; 4096];
+    let started = Instant::now();
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            std::thread::sleep(Duration::from_millis(100));
+            throttle.cancel();
+        });
+        let mut reader = ThrottledReader::new(&input[..], Some(&throttle));
+        let mut buf = [0u8; 4096];
+        assert_eq!(4096, reader.read(&mut buf).unwrap());
+        assert_eq!(io::ErrorKind::Other, reader.read(&mut buf).unwrap_err().kind());
+    });
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    // A handle cancels every throttle made with it
+    let cancel = CancelHandle::default();
+    let throttles = [Throttle::with_cancel(None, cancel.clone()), Throttle::with_cancel(Some(1024), cancel.clone())];
+    cancel.cancel();
+    assert!(throttles.iter().all(Throttle::is_cancelled));
+    assert!(!Throttle::new(None).is_cancelled());
+}
@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+
+use crate::{Encoder, Encoding, MatchOptions, MatchPolicy, Phrase, TokenInstance};
+use super::{match_phrase, search_encoded, search_multibyte, WidthMask};
+
+/// Why a phrase didn't match a window
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    /// A token isn't in the window under any diff or width
+    TokenNotFound,
+    /// Every token is in the window, but not under the same diff as the longest
+    DiffMismatch,
+    /// Every token is in the window, but not with the same width as the longest
+    BpcMismatch,
+    /// Every token is in the window under the same diff, but not in the order the phrase requires
+    OutOfOrder,
+    /// Every token is in the window under the same diff, but only where they overlap each other
+    Overlapping,
+    /// One of the phrase's exclusions is in the window
+    Excluded,
+    /// The phrase is in the window, but isn't preceded by its anchor
+    AnchorMismatch
+}
+
+/// What a [`crate::Finder`] was comparing at a point in its input
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TraceEvent {
+    pub file_pos: usize,                                    // File position of the start of the window
+    pub window_bytes: Vec<u8>,                              // Bytes in the window
+    pub candidate_diffs_considered: Vec<i32>,               // Diffs any token was found with in the window, sorted
+    pub rejected_reason_per_phrase: Vec<Option<RejectReason>>   // Parallel to the phrases. None for those that matched.
+}
+
+/// The most recent trace events, up to a capacity.
+/// Useful for attaching what led up to a missing match to a report.
+#[derive(Debug, Clone, Default)]
+pub struct TraceRing {
+    events: VecDeque<TraceEvent>,
+    capacity: usize
+}
+
+impl TraceRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity
+        }
+    }
+
+    /// Adds an event, dropping the oldest if full
+    pub fn push(&mut self, event: TraceEvent) {
+        if self.capacity == 0 { return; }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Events from oldest to newest
+    pub fn events(&self) -> impl Iterator<Item=&TraceEvent> {
+        self.events.iter()
+    }
+
+    pub fn into_vec(self) -> Vec<TraceEvent> {
+        self.events.into()
+    }
+
+    /// Callback for [`crate::Finder::trace`] that pushes every event to `ring`, which can be read once the finder is done
+    pub fn recorder(ring: &Arc<Mutex<TraceRing>>) -> impl FnMut(TraceEvent) + Send + 'static {
+        let ring = ring.clone();
+        move |event| ring.lock().unwrap().push(event)
+    }
+}
+
+// Works out why the phrase doesn't match the window, ignoring its anchor.
+// Also returns the diffs its tokens were found with. None if the phrase matches.
+pub(crate) fn diagnose_phrase<E: Encoder>(
+    phrase: &Phrase,
+    window: &[u8],
+    options: &MatchOptions,
+    encoder: Option<&E>
+) -> (Option<RejectReason>, Vec<i32>) {
+    let request_widths = WidthMask::from_widths(&options.widths);
+    let widths = request_widths.and(WidthMask::from_widths(&phrase.widths));
+    let find = |token: &[u32], diff: Option<i32>, encoding: Option<Encoding>| -> Option<TokenInstance> {
+        match encoder {
+            Some(encoder) => search_encoded(token, window, encoder).map(|(found, _)| found),
+            None => search_multibyte(token, window, diff, &options.diff_range, encoding, widths)
+        }
+    };
+    let found: Vec<Option<TokenInstance>> = phrase.tokens
+        .iter()
+        .map(|token| find(&token.0, None, options.encoding))
+        .collect();
+    let mut diffs: Vec<i32> = found.iter().flatten().map(|found| found.codepoint_diff).collect();
+    diffs.sort();
+    diffs.dedup();
+
+    let matched = match_phrase(phrase, window, &options.diff_range, options.match_policy, options.encoding, request_widths, encoder);
+    if matched.is_some() {
+        return (None, diffs);
+    }
+    if found.is_empty() || found.iter().any(Option::is_none) {
+        return (Some(RejectReason::TokenNotFound), diffs);
+    }
+
+    // Every token was found. Checks whether they agree with the diff and width of the longest, which matching anchors on.
+    let longest = (0..phrase.tokens.len())
+        .max_by_key(|idx| (phrase.tokens[*idx].0.len(), std::cmp::Reverse(*idx)))
+        .expect("Phrase has tokens");
+    let first = found[longest].expect("Every token was found");
+    let same_width = Encoding {
+        bytes_per_character: first.bytes_per_character,
+        endianness: options.encoding.map(|encoding| encoding.endianness).unwrap_or_default()
+    };
+    for (_, token) in phrase.tokens.iter().enumerate().filter(|(idx, _)| *idx != longest) {
+        if find(&token.0, Some(first.codepoint_diff), Some(same_width)).is_some() {
+            continue;
+        }
+        let reason = match find(&token.0, None, Some(same_width)) {
+            Some(_) => RejectReason::DiffMismatch,
+            None => RejectReason::BpcMismatch
+        };
+        return (Some(reason), diffs);
+    }
+    let excluded = phrase.not
+        .iter()
+        .any(|token| find(&token.0, Some(first.codepoint_diff), Some(same_width)).is_some());
+    let reason = match (excluded, options.match_policy) {
+        (true, _) => RejectReason::Excluded,
+        (false, MatchPolicy::Ordered) => RejectReason::OutOfOrder,
+        (false, MatchPolicy::AnyOrder) => RejectReason::Overlapping
+    };
+    (Some(reason), diffs)
+}
+
+
+#[test]
+fn test_trace_diff_mismatch() {
+    use crate::Finder;
+    let padding = ".".repeat(64);
+    let input = format!("{}the quick gpy{}", padding, padding);     // "fox" shifted by 1
+    let mut reader = input.as_bytes();
+    let phrases = [Phrase::from_strs(&["quick", "fox"]), Phrase::from_strs(&["quick"])];
+    let ring = Arc::new(Mutex::new(TraceRing::new(256)));
+    let mut finder = Finder::new(&phrases, 64, 32, &mut reader);
+    finder.trace(1, TraceRing::recorder(&ring));
+    assert_eq!(1, finder.count());
+    let events = ring.lock().unwrap();
+
+    // Once the window holds both tokens, the phrase is rejected for their diffs
+    let rejected: Vec<&TraceEvent> = events
+        .events()
+        .filter(|event| event.rejected_reason_per_phrase[0] == Some(RejectReason::DiffMismatch))
+        .collect();
+    assert_eq!(45, rejected[0].file_pos);
+    assert_eq!(b"quick gpy", &rejected[0].window_bytes[23..]);
+    assert_eq!(vec![0, 1], rejected[0].candidate_diffs_considered);
+    assert_eq!(None, rejected[0].rejected_reason_per_phrase[1]);
+    assert!(events.events().all(|event| event.rejected_reason_per_phrase[0].is_some()));
+
+    // Only the most recent events are kept
+    let mut ring = TraceRing::new(2);
+    rejected.iter().take(3).for_each(|event| ring.push((*event).clone()));
+    assert_eq!(vec![46, 47], ring.into_vec().iter().map(|event| event.file_pos).collect::<Vec<_>>());
+}
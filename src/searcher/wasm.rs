@@ -0,0 +1,90 @@
+use std::ops::ControlFlow;
+use serde::{Serialize, Deserialize};
+
+use crate::{search_scored_with, Encoding, FinderConfigError, Phrase, ReportEntry, SearchOptions, MAX_BYTES_PER_CHARACTER};
+
+/// Options for searching a buffer already in memory
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SliceSearchOptions {
+    #[serde(flatten)]
+    pub sizes: Option<SearchOptions>,   // Sized from the phrases with SearchOptions::auto_size if not specified
+    #[serde(default)]
+    pub encoding: Option<Encoding>      // Tries every width if not specified
+}
+
+/// Results of searching a buffer, along with the sizes it was searched with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SliceSearchResult {
+    pub options: SearchOptions,
+    pub entries: Vec<ReportEntry>
+}
+
+/// Searches a buffer for `phrases`, scoring every instance found
+pub fn search_slice(bytes: &[u8], phrases: &[Phrase], options: &SliceSearchOptions) -> Result<SliceSearchResult, FinderConfigError> {
+    let sizes = options.sizes.unwrap_or_else(|| SearchOptions::auto_size(phrases, MAX_BYTES_PER_CHARACTER, usize::MAX));
+    let mut entries = Vec::new();
+    let mut reader = bytes;
+    let _ = search_scored_with(phrases, &sizes, options.encoding, &mut reader, |entry| {
+        entries.push(entry);
+        ControlFlow::Continue(())
+    })?;
+    Ok(SliceSearchResult { options: sizes, entries })
+}
+
+/// JSON layer of the wasm `search` export. `phrases` is an array of phrases, and `options` is
+/// [`SliceSearchOptions`] or null. Returns a [`SliceSearchResult`], or a message describing what was invalid.
+pub fn search_json(bytes: &[u8], phrases: serde_json::Value, options: serde_json::Value) -> Result<serde_json::Value, String> {
+    let phrases: Vec<Phrase> = serde_json::from_value(phrases).map_err(|err| err.to_string())?;
+    let options: SliceSearchOptions = match options {
+        serde_json::Value::Null => SliceSearchOptions::default(),
+        options => serde_json::from_value(options).map_err(|err| err.to_string())?
+    };
+    let result = search_slice(bytes, &phrases, &options).map_err(|err| err.to_string())?;
+    serde_json::to_value(result).map_err(|err| err.to_string())
+}
+
+/// Searches `bytes` from JavaScript. See [`search_json`].
+#[cfg(feature = "wasm")]
+#[wasm_bindgen::prelude::wasm_bindgen(js_name = search)]
+pub fn search_wasm(
+    bytes: &[u8],
+    phrases: wasm_bindgen::JsValue,
+    options: wasm_bindgen::JsValue
+) -> Result<wasm_bindgen::JsValue, wasm_bindgen::JsValue> {
+    use serde::Serialize;
+    let to_json = |value: wasm_bindgen::JsValue| -> Result<serde_json::Value, wasm_bindgen::JsValue> {
+        if value.is_undefined() || value.is_null() {
+            return Ok(serde_json::Value::Null);
+        }
+        serde_wasm_bindgen::from_value(value).map_err(Into::into)
+    };
+    let result = search_json(bytes, to_json(phrases)?, to_json(options)?)?;
+    result
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(Into::into)
+}
+
+
+#[test]
+fn test_search_json() {
+    let bytes = include_bytes!("test_text_1.txt");
+    let phrases = serde_json::json!([["famine", "where"]]);
+
+    // Sized from the phrases when no options are given
+    let result = search_json(bytes, phrases.clone(), serde_json::Value::Null).unwrap();
+    let result: SliceSearchResult = serde_json::from_value(result).unwrap();
+    assert_eq!(SearchOptions { context_size: 52, window_size: 26 }, result.options);
+    assert_eq!(1, result.entries.len());
+    assert_eq!(288, result.entries[0].instance.file_pos);
+    assert_eq!("famine where", result.entries[0].phrase.text);
+
+    // Explicit sizes and encoding
+    let options = serde_json::json!({ "context_size": 64, "window_size": 32, "encoding": { "bytes_per_character": 1, "endianness": "little" } });
+    let result = search_json(bytes, phrases.clone(), options).unwrap();
+    assert_eq!(288, result["entries"][0]["instance"]["file_pos"]);
+    assert_eq!(7, result["entries"][0]["instance"]["line"]);
+
+    // Invalid input is reported rather than panicking
+    assert!(search_json(bytes, serde_json::json!("famine"), serde_json::Value::Null).is_err());
+    assert!(search_json(bytes, phrases, serde_json::json!({ "context_size": 30, "window_size": 8 })).is_err());
+}
@@ -0,0 +1,99 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{Finder, FinderBuilder, FinderConfigError, Phrase, PhraseInstanceGroup};
+
+/// Wraps a [`Finder`], seeking back to the start of the input and searching again when asked to with [`Self::restart`].
+/// Reaching the end of the input only ends the current pass, so an instance still being written to a live file isn't
+/// cut short by an automatic restart. Yields [`PhraseInstanceGroup::wrapped_around`] first after every restart,
+/// up to `max_restarts` times.
+pub struct WatchdogFinder<'a, R: Read + Seek> {
+    finder: Option<Finder<'a, R>>,  // Current pass over the input. None once the reader failed to seek.
+    builder: FinderBuilder,         // Builds the finder for every pass
+    phrases: Vec<Phrase>,           // Phrases to search for
+    restarts: usize,                // Number of times the input was searched again
+    max_restarts: usize,            // Restarts allowed
+    wrapped: bool                   // Whether the sentinel for the last restart is yet to be yielded
+}
+
+impl<'a, R: Read + Seek> WatchdogFinder<'a, R> {
+
+    /// Searches `reader` from its current position, with finders built by `builder`
+    pub fn new(
+        builder: FinderBuilder,
+        phrases: &[Phrase],
+        max_restarts: usize,
+        reader: &'a mut R
+    ) -> Result<Self, FinderConfigError> {
+        let finder = builder.clone().build(phrases, reader)?;
+        Ok(Self {
+            finder: Some(finder),
+            builder,
+            phrases: phrases.to_vec(),
+            restarts: 0,
+            max_restarts,
+            wrapped: false
+        })
+    }
+
+    /// Number of times the input was searched again from the start
+    pub fn restarts(&self) -> usize { self.restarts }
+
+    /// Seeks back to the start of the input and searches it again, usually once the current pass has ended.
+    /// Returns false if out of restarts, or if the reader failed to seek, which finishes the finder for good.
+    pub fn restart(&mut self) -> bool {
+        if self.restarts == self.max_restarts {
+            return false;
+        }
+        let Some(finder) = self.finder.take() else { return false };
+        let reader = finder.into_reader();
+        if reader.seek(SeekFrom::Start(0)).is_err() {
+            return false;
+        }
+        let finder = self.builder.clone().build(&self.phrases, reader);
+        self.finder = Some(finder.expect("Configuration was validated by the first build"));
+        self.restarts += 1;
+        self.wrapped = true;
+        true
+    }
+}
+
+impl<'a, R: Read + Seek> Iterator for WatchdogFinder<'a, R> {
+    type Item = PhraseInstanceGroup;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if std::mem::take(&mut self.wrapped) {
+            return Some(PhraseInstanceGroup::wrapped_around());
+        }
+        self.finder.as_mut()?.next()
+    }
+}
+
+
+#[test]
+fn test_watchdog_finder() {
+    use std::io::Cursor;
+    let padding = ".".repeat(64);
+    let input = format!("{}the quick brown fox{}", padding, padding);
+    let mut reader = Cursor::new(input.into_bytes());
+    let phrases = [Phrase::from_strs(&["quick", "fox"])];
+    let builder = FinderBuilder::new().context_size(64);
+    let mut finder = WatchdogFinder::new(builder, &phrases, 2, &mut reader).unwrap();
+    let positions = |finder: &mut WatchdogFinder<_>| -> Vec<Option<usize>> {
+        finder
+            .by_ref()
+            .map(|group| group.0.first().map(|instance| instance.file_pos))
+            .collect()
+    };
+
+    // The end of the input only ends the pass, until a restart is asked for
+    assert_eq!(vec![Some(68)], positions(&mut finder));
+    assert!(positions(&mut finder).is_empty());
+    assert_eq!(0, finder.restarts());
+    assert!(finder.restart());
+    assert_eq!(vec![None, Some(68)], positions(&mut finder));
+    assert!(finder.restart());
+    assert_eq!(vec![None, Some(68)], positions(&mut finder));
+    assert!(!finder.restart());
+    assert!(positions(&mut finder).is_empty());
+    assert_eq!(2, finder.restarts());
+}
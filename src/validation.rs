@@ -0,0 +1,92 @@
+use std::path::Path;
+
+use serde::Serialize;
+use schemars::JsonSchema;
+use text_searcher_rust::PhraseRef;
+
+use crate::finder_service::encode_path;
+
+/// Where the validation pass over the loaded state is at. See FinderService::start_validation.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ValidationStatus {
+    NotRun,
+    InProgress { started_at: u64 },
+    Done(ValidationReport)
+}
+
+/// What a validation pass found stale in the loaded state, and what it fixed
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct ValidationReport {
+    pub started_at: u64,                        // Seconds since the Unix epoch
+    pub finished_at: u64,
+    pub files_checked: usize,
+    pub phrases_checked: usize,
+    pub missing_files: Vec<FileProblem>,        // Tracked files that don't exist
+    pub unreadable_files: Vec<FileProblem>,     // Tracked files that exist, but can't be opened
+    pub invalid_phrases: Vec<InvalidPhrase>,    // Phrases that fail validation with the current limits, or aren't valid Unicode
+    pub persist_error: Option<String>,          // Why the persist file can't be written, if it can't
+    pub fixed: Option<ValidationFixes>          // What was fixed, if fixing was enabled
+}
+
+/// A tracked file that failed validation
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct FileProblem {
+    pub path: String,
+    pub encoded_path: String,   // See finder_service::encode_path
+    pub error: String
+}
+
+impl FileProblem {
+    pub fn new(path: &Path, error: impl ToString) -> Self {
+        Self {
+            path: path.to_string_lossy().into_owned(),
+            encoded_path: encode_path(path),
+            error: error.to_string()
+        }
+    }
+}
+
+/// A phrase that failed validation
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct InvalidPhrase {
+    pub phrase: PhraseRef,
+    pub reason: String
+}
+
+/// Fixes applied after a validation pass. Both are logged as operations, so they can be undone.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct ValidationFixes {
+    pub pruned_files: usize,    // Missing files no longer tracked
+    pub dropped_phrases: usize  // Invalid phrases removed
+}
+
+impl ValidationReport {
+
+    /// Whether the pass found anything wrong
+    pub fn has_problems(&self) -> bool {
+        !self.missing_files.is_empty()
+            || !self.unreadable_files.is_empty()
+            || !self.invalid_phrases.is_empty()
+            || self.persist_error.is_some()
+    }
+
+    /// One line summary, for the log
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{} of {} files missing, {} unreadable, {} of {} phrases invalid",
+            self.missing_files.len(),
+            self.files_checked,
+            self.unreadable_files.len(),
+            self.invalid_phrases.len(),
+            self.phrases_checked
+        );
+        if let Some(err) = &self.persist_error {
+            summary.push_str(&format!(", persist file not writable: {}", err));
+        }
+        if let Some(fixed) = &self.fixed {
+            summary.push_str(&format!(". Pruned {} files and dropped {} phrases", fixed.pruned_files, fixed.dropped_phrases));
+        }
+        summary
+    }
+}